@@ -0,0 +1,79 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compares the dispatch strategies of `DeviceManager` with hundreds of
+//! virtio-mmio-like devices. Run with `cargo +nightly bench`.
+
+#![feature(test)]
+
+extern crate test;
+
+use std::sync::Arc;
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axdevice_base::{BaseDeviceOps, DeviceManager, DispatchStrategy, EmuDeviceType};
+use axerrno::AxResult;
+use test::{Bencher, black_box};
+
+const DEVICES: usize = 512;
+const BASE: usize = 0x1000_0000;
+const SIZE: usize = 0x200;
+
+struct Transport(GuestPhysAddrRange);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for Transport {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.0
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(addr.as_usize())
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+}
+
+fn bench_reads(b: &mut Bencher, strategy: DispatchStrategy) {
+    let manager = DeviceManager::with_dispatch_strategy(strategy);
+    for i in 0..DEVICES {
+        let range = GuestPhysAddrRange::from_start_size((BASE + i * SIZE).into(), SIZE);
+        manager.register(Arc::new(Transport(range))).unwrap();
+    }
+    // Visit the devices in a scattered order, as notifications of many
+    // queues would.
+    let addrs: Vec<GuestPhysAddr> = (0..DEVICES)
+        .map(|i| (BASE + (i * 97 % DEVICES) * SIZE + 0x50).into())
+        .collect();
+    b.iter(|| {
+        for &addr in &addrs {
+            black_box(manager.handle_read(addr, AccessWidth::Dword).unwrap());
+        }
+    });
+}
+
+#[bench]
+fn sorted(b: &mut Bencher) {
+    bench_reads(b, DispatchStrategy::Sorted);
+}
+
+#[bench]
+fn page_index(b: &mut Bencher) {
+    bench_reads(b, DispatchStrategy::PageIndex);
+}
@@ -0,0 +1,374 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interfaces between device models and their host-side backends.
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+
+use axerrno::{AxResult, ax_err};
+
+/// A one-shot callback invoked by a backend when it has capacity again.
+pub type CapacityCallback = Box<dyn FnOnce() + Send>;
+
+/// Flow control between a device model and its byte-sink or packet backend.
+///
+/// A device consults [`can_accept`](Self::can_accept) before taking data from
+/// the guest. If the backend is full, the device flow-controls the guest using
+/// its own hardware mechanism (e.g. a 16550 UART keeps `LSR.THRE` cleared, a
+/// network device stops consuming TX descriptors) and registers a callback with
+/// [`on_capacity_available`](Self::on_capacity_available) to resume once the
+/// backend has drained. This way a slow backend slows down the guest instead of
+/// silently dropping its data.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// fn transmit(&self, byte: u8) {
+///     if !self.backend.can_accept(1) {
+///         self.lsr.clear(LSR_THRE);
+///         let lsr = self.lsr.clone();
+///         self.backend
+///             .on_capacity_available(Box::new(move || lsr.set(LSR_THRE)));
+///         return;
+///     }
+///     self.backend.write(&[byte]);
+/// }
+/// ```
+pub trait Backpressure {
+    /// Returns whether the backend can currently accept `len` more units
+    /// (bytes or packets, depending on the backend) without dropping any.
+    fn can_accept(&self, len: usize) -> bool;
+
+    /// Registers `callback` to be invoked once when the backend has capacity
+    /// available again.
+    ///
+    /// If capacity is already available, the backend may invoke the callback
+    /// immediately. Registering a new callback replaces a pending one.
+    fn on_capacity_available(&self, callback: CapacityCallback);
+}
+
+/// A callback invoked by a [`BlockBackend`] with the data of a completed
+/// asynchronous read.
+pub type BlockReadCallback = Box<dyn FnOnce(AxResult<Vec<u8>>) + Send>;
+
+/// A callback invoked by a [`BlockBackend`] with the result of a completed
+/// asynchronous write.
+pub type BlockWriteCallback = Box<dyn FnOnce(AxResult) + Send>;
+
+/// A block storage backend addressed by logical block address (LBA).
+///
+/// Storage device models (SD/MMC, virtio-blk, AHCI) translate guest requests
+/// into calls on this trait, so that the same backend (a host file, a
+/// partition, a RAM disk) can serve any of them.
+///
+/// Device models submit requests through
+/// [`read_blocks_async`](Self::read_blocks_async) and
+/// [`write_blocks_async`](Self::write_blocks_async). Backends doing host I/O
+/// asynchronously override them, and the device models report completion to
+/// the guest when the callback runs (e.g. through an [`AccessCompleter`])
+/// instead of stalling the vCPU. Callbacks may run before the submitting call
+/// returns, so device models must not hold locks taken by the callback while
+/// submitting.
+///
+/// [`AccessCompleter`]: crate::AccessCompleter
+pub trait BlockBackend: Send + Sync {
+    /// Returns the size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Returns the number of blocks of the backend.
+    fn num_blocks(&self) -> u64;
+
+    /// Reads blocks starting at `lba` into `buf`, whose length must be a
+    /// multiple of [`block_size`](Self::block_size).
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> AxResult;
+
+    /// Writes blocks starting at `lba` from `buf`, whose length must be a
+    /// multiple of [`block_size`](Self::block_size).
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> AxResult;
+
+    /// Makes all completed writes durable.
+    fn flush(&self) -> AxResult;
+
+    /// Starts reading `len` bytes of blocks starting at `lba`, calling `done`
+    /// with the data once the read completes.
+    ///
+    /// The default implementation reads synchronously with
+    /// [`read_blocks`](Self::read_blocks).
+    fn read_blocks_async(&self, lba: u64, len: usize, done: BlockReadCallback) {
+        let mut buf = vec![0; len];
+        done(self.read_blocks(lba, &mut buf).map(|()| buf));
+    }
+
+    /// Starts writing `data` to the blocks starting at `lba`, calling `done`
+    /// once the write completes.
+    ///
+    /// The default implementation writes synchronously with
+    /// [`write_blocks`](Self::write_blocks).
+    fn write_blocks_async(&self, lba: u64, data: Vec<u8>, done: BlockWriteCallback) {
+        done(self.write_blocks(lba, &data));
+    }
+
+    /// Returns whether the backend rejects writes.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// A callback invoked by a [`NetBackend`] with each frame received from the
+/// host network.
+pub type RxCallback = Box<dyn Fn(&[u8]) + Send + Sync>;
+
+/// A network packet backend exchanging Ethernet frames with the host.
+///
+/// Network device models (virtio-net, emulated NICs) hand guest frames to
+/// [`send`](Self::send) and receive host frames through the callback
+/// registered with [`set_rx_callback`](Self::set_rx_callback). Backends that
+/// can run out of transmit capacity should also implement [`Backpressure`].
+pub trait NetBackend: Send + Sync {
+    /// Sends one Ethernet frame to the host network.
+    ///
+    /// Returns `Err(AxError::WouldBlock)` if the backend cannot take the frame
+    /// right now.
+    fn send(&self, frame: &[u8]) -> AxResult;
+
+    /// Registers the callback invoked with each received frame, replacing any
+    /// previously registered one.
+    fn set_rx_callback(&self, callback: RxCallback);
+
+    /// Returns whether the link is up.
+    fn link_up(&self) -> bool;
+
+    /// Returns the MAC address assigned to the guest interface.
+    fn mac(&self) -> [u8; 6];
+
+    /// Returns the maximum transmission unit in bytes.
+    fn mtu(&self) -> usize {
+        1500
+    }
+}
+
+/// An input event, in the evdev encoding shared by virtio-input and Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// The event type, e.g. [`EV_KEY`](Self::EV_KEY).
+    pub kind: u16,
+    /// The key, button or axis the event is about.
+    pub code: u16,
+    /// The new state: 1 for a key press and 0 for a release, or the axis
+    /// position or motion.
+    pub value: u32,
+}
+
+impl InputEvent {
+    /// Ends a group of events reported together.
+    pub const EV_SYN: u16 = 0x00;
+    /// A key or button changed state.
+    pub const EV_KEY: u16 = 0x01;
+    /// A relative axis moved, e.g. a mouse.
+    pub const EV_REL: u16 = 0x02;
+    /// An absolute axis moved, e.g. a tablet.
+    pub const EV_ABS: u16 = 0x03;
+    /// A LED changed state, reported by the guest.
+    pub const EV_LED: u16 = 0x11;
+
+    /// The size of an encoded event.
+    pub(crate) const SIZE: usize = 8;
+
+    /// Creates an event of type `kind`.
+    pub const fn new(kind: u16, code: u16, value: u32) -> Self {
+        Self { kind, code, value }
+    }
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..2].copy_from_slice(&self.kind.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.code.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn decode(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            kind: u16::from_le_bytes([bytes[0], bytes[1]]),
+            code: u16::from_le_bytes([bytes[2], bytes[3]]),
+            value: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        }
+    }
+}
+
+/// The range of an absolute axis of an [`InputBackend`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InputAbsInfo {
+    /// The smallest position.
+    pub min: u32,
+    /// The largest position.
+    pub max: u32,
+    /// The noise the guest filters out.
+    pub fuzz: u32,
+    /// The dead zone around the center.
+    pub flat: u32,
+    /// The resolution, in units per millimeter.
+    pub res: u32,
+}
+
+/// A callback invoked by an [`InputBackend`] with each group of events.
+pub type InputCallback = Box<dyn Fn(&[InputEvent]) + Send + Sync>;
+
+/// A host source of input events, such as the keyboard and pointer of a
+/// remote console served by the management plane.
+///
+/// Input device models (virtio-input) receive the injected events through the
+/// callback registered with [`set_event_callback`](Self::set_event_callback)
+/// and advertise the events the backend reports to the guest.
+pub trait InputBackend: Send + Sync {
+    /// Registers the callback invoked with each group of injected events,
+    /// replacing any previously registered one.
+    ///
+    /// A group holds the events of one input change, such as the two axes of
+    /// a pointer motion; the device ends it with an `EV_SYN` event.
+    fn set_event_callback(&self, callback: InputCallback);
+
+    /// Returns the name of the input device shown to the guest.
+    fn name(&self) -> String;
+
+    /// Returns the codes of the events of type `kind` the backend may
+    /// inject, e.g. the key codes for [`InputEvent::EV_KEY`].
+    fn event_codes(&self, kind: u16) -> Vec<u16>;
+
+    /// Returns the range of the absolute axis `code`.
+    fn abs_info(&self, _code: u16) -> Option<InputAbsInfo> {
+        None
+    }
+
+    /// Called with the status events written by the guest, such as keyboard
+    /// LED changes. The default implementation ignores them.
+    fn on_status(&self, _event: InputEvent) {}
+}
+
+/// A host source of random bytes, such as a hardware TRNG or the host kernel's
+/// entropy pool.
+pub trait EntropySource: Send + Sync {
+    /// Fills `buf` with random bytes, returning the number of bytes filled.
+    ///
+    /// The source may fill fewer bytes than requested if it is temporarily
+    /// depleted.
+    fn fill(&self, buf: &mut [u8]) -> AxResult<usize>;
+}
+
+/// A TPM command processor, such as a software TPM or a passthrough to the
+/// host TPM.
+///
+/// Device models only provide the transport (e.g. [`TpmTisDevice`]); all
+/// command processing and cryptography is left to the backend.
+///
+/// [`TpmTisDevice`]: crate::TpmTisDevice
+pub trait TpmBackend: Send + Sync {
+    /// Executes the TPM command `command` issued from `locality`, returning the
+    /// complete response, header included.
+    fn execute(&self, locality: u8, command: &[u8]) -> AxResult<Vec<u8>>;
+}
+
+/// A handle to a file opened on a [`FsBackend`].
+pub type FsHandle = u64;
+
+/// Attributes of a file or directory on a [`FsBackend`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FsAttr {
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The POSIX permission bits of the file.
+    pub mode: u32,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
+    /// The last modification time, in nanoseconds since the Unix epoch.
+    pub mtime_ns: u64,
+}
+
+/// An entry of a directory listed by [`FsBackend::readdir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsDirEntry {
+    /// The name of the entry, without any path component.
+    pub name: String,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
+}
+
+/// A host filesystem share exported to the guest, e.g. through 9pfs or
+/// virtio-fs.
+///
+/// All paths are relative to the root of the share. Device models must pass
+/// guest-provided paths through [`scope_path`] before handing them to the
+/// backend, so that a guest can never name a file outside of the share.
+pub trait FsBackend: Send + Sync {
+    /// Opens the file at `path`, for writing if `write` is set.
+    fn open(&self, path: &str, write: bool) -> AxResult<FsHandle>;
+
+    /// Closes a handle returned by [`open`](Self::open).
+    fn close(&self, handle: FsHandle) -> AxResult;
+
+    /// Reads from the file at byte `offset` into `buf`, returning the number
+    /// of bytes read.
+    fn read(&self, handle: FsHandle, offset: u64, buf: &mut [u8]) -> AxResult<usize>;
+
+    /// Writes `buf` to the file at byte `offset`, returning the number of
+    /// bytes written.
+    fn write(&self, handle: FsHandle, offset: u64, buf: &[u8]) -> AxResult<usize>;
+
+    /// Lists the entries of the directory at `path`.
+    fn readdir(&self, path: &str) -> AxResult<Vec<FsDirEntry>>;
+
+    /// Returns the attributes of the file or directory at `path`.
+    fn attr(&self, path: &str) -> AxResult<FsAttr>;
+
+    /// Returns whether the share is exported read-only.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Normalizes a guest-provided `path` so that it stays within the root of a
+/// [`FsBackend`] share.
+///
+/// Empty and `.` components are dropped and `..` components are resolved. The
+/// returned path is relative to the share root, without leading or trailing
+/// separators (the root itself is the empty string).
+///
+/// # Returns
+///
+/// - `Ok(path)`: The normalized path.
+/// - `Err(AxError::PermissionDenied)`: The path escapes the share root.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::scope_path;
+///
+/// assert_eq!(scope_path("/etc/./ssh//../hosts").unwrap(), "etc/hosts");
+/// assert!(scope_path("a/../../etc/passwd").is_err());
+/// ```
+pub fn scope_path(path: &str) -> AxResult<String> {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    return ax_err!(PermissionDenied, "path escapes the share root");
+                }
+            }
+            _ => components.push(component),
+        }
+    }
+    Ok(components.join("/"))
+}
@@ -0,0 +1,146 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guest memory control and a reference balloon device.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, RegValue};
+
+/// Operations to grow or shrink the memory of a guest, implemented by the
+/// hypervisor.
+///
+/// Memory overcommit policies (ballooning, free page reporting, hotplug) are
+/// implemented by devices driving this trait, instead of by reaching into
+/// hypervisor internals.
+pub trait MemoryControlOps: Send + Sync {
+    /// Gives the guest physical `range` back to the guest, backing it with host
+    /// memory again.
+    fn request_add(&self, range: GuestPhysAddrRange) -> AxResult;
+
+    /// Takes the guest physical `range` away from the guest, allowing the host
+    /// to reclaim its backing memory.
+    fn request_remove(&self, range: GuestPhysAddrRange) -> AxResult;
+}
+
+/// The page size used by [`BalloonDevice`].
+pub const BALLOON_PAGE_SIZE: usize = 0x1000;
+
+/// A minimal memory balloon device driving [`MemoryControlOps`].
+///
+/// The host sets a target number of pages with [`set_target`](Self::set_target).
+/// The guest driver polls the target and inflates the balloon by handing pages
+/// to the host, or deflates it by taking pages back. All registers are 32 bits
+/// wide:
+///
+/// | Offset | Name          | Access | Description                              |
+/// |--------|---------------|--------|------------------------------------------|
+/// | 0x00   | `TARGET`      | RO     | Number of pages the host wants ballooned. |
+/// | 0x04   | `ACTUAL`      | RO     | Number of pages currently ballooned.     |
+/// | 0x08   | `INFLATE_PFN` | WO     | Hands the page at this PFN to the host.  |
+/// | 0x0c   | `DEFLATE_PFN` | WO     | Takes the page at this PFN back.         |
+///
+/// Reads from write-only registers return zero. A failed [`MemoryControlOps`]
+/// request is returned as the error of the triggering write, and the page is
+/// not accounted.
+pub struct BalloonDevice {
+    range: GuestPhysAddrRange,
+    memory: Arc<dyn MemoryControlOps>,
+    target: AtomicUsize,
+    actual: AtomicUsize,
+}
+
+impl BalloonDevice {
+    const REG_TARGET: usize = 0x00;
+    const REG_ACTUAL: usize = 0x04;
+    const REG_INFLATE_PFN: usize = 0x08;
+    const REG_DEFLATE_PFN: usize = 0x0c;
+
+    /// Creates a balloon device whose registers are mapped at `base`.
+    pub fn new(base: GuestPhysAddr, memory: Arc<dyn MemoryControlOps>) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, BALLOON_PAGE_SIZE),
+            memory,
+            target: AtomicUsize::new(0),
+            actual: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the number of pages the guest should hand to the host.
+    pub fn set_target(&self, pages: usize) {
+        self.target.store(pages, Ordering::Release);
+    }
+
+    /// Returns the number of pages the guest should hand to the host.
+    pub fn target(&self) -> usize {
+        self.target.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of pages currently handed to the host.
+    pub fn actual(&self) -> usize {
+        self.actual.load(Ordering::Acquire)
+    }
+
+    fn page_range(pfn: usize) -> AxResult<GuestPhysAddrRange> {
+        let start = pfn.checked_mul(BALLOON_PAGE_SIZE);
+        match start.and_then(|start| {
+            GuestPhysAddrRange::try_from_start_size(start.into(), BALLOON_PAGE_SIZE)
+        }) {
+            Some(range) => Ok(range),
+            None => ax_err!(InvalidInput, "balloon PFN out of range"),
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for BalloonDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let val = match self.range.offset_of(addr) {
+            Some(Self::REG_TARGET) => self.target(),
+            Some(Self::REG_ACTUAL) => self.actual(),
+            _ => 0,
+        };
+        Ok(RegValue::new(val, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        match self.range.offset_of(addr) {
+            Some(Self::REG_INFLATE_PFN) => {
+                self.memory.request_remove(Self::page_range(val)?)?;
+                self.actual.fetch_add(1, Ordering::AcqRel);
+            }
+            Some(Self::REG_DEFLATE_PFN) => {
+                self.memory.request_add(Self::page_range(val)?)?;
+                let _ = self
+                    .actual
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                        Some(n.saturating_sub(1))
+                    });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
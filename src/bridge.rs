@@ -0,0 +1,345 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bridges forwarding a range of one bus to a child bus.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxResult, ax_err};
+
+use crate::{
+    AccessCompleter, AccessContext, AccessOutcome, BaseDeviceOps, CapabilitySet, CatchUpPolicy,
+    ClockSource, DeviceAddrRangeExt, DeviceManager, DeviceManifest, DeviceStateHeader, DomainEvent,
+    EmuDeviceType, GuestMemoryAccessor, MemoryLayoutChange, PowerState, RawDeviceAddr,
+    RegisterDump, TimerService, TimerToken,
+};
+
+/// A device claiming a range on a parent bus and forwarding the accesses to
+/// it into a child bus, for hierarchical platforms such as a system bus
+/// feeding a peripheral bus.
+///
+/// An access at offset `n` in the bridge range is dispatched to the child bus
+/// at `child_base + n`, so that the devices behind the bridge are registered
+/// at their addresses on the peripheral bus, which may differ from where the
+/// bridge maps them. Accesses missing all child devices fail as on the parent
+/// bus, and are completed by the parent's
+/// [`UnhandledAccessPolicy`](crate::UnhandledAccessPolicy).
+///
+/// Lifecycle hooks, notifications and injected services are forwarded to the
+/// child bus, so a whole sub-bus is paused, reset or saved through the bridge,
+/// and replaced at once by registering another bridge in its place. Region
+/// changes of the child devices are not forwarded: region identifiers are only
+/// unique per device, so the hypervisor sets a region handler on the child bus
+/// itself. The saved state of the bridge is the state of every child device,
+/// tagged with its range.
+pub struct BusBridgeDevice<R> {
+    range: R,
+    child_base: usize,
+    child: DeviceManager<R>,
+}
+
+impl<R: DeviceAddrRangeExt + Copy + 'static> BusBridgeDevice<R> {
+    /// The version of the state layout saved by
+    /// [`save_state`](BaseDeviceOps::save_state).
+    const STATE_VERSION: u32 = 1;
+
+    /// Creates a bridge claiming `range` on the parent bus and forwarding it
+    /// to an empty child bus starting at `child_base`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the range is empty or its
+    /// translation overflows the child address space.
+    pub fn new(range: R, child_base: usize) -> AxResult<Self> {
+        let size = range.size();
+        if size == 0 || child_base.checked_add(size - 1).is_none() {
+            return ax_err!(InvalidInput, "invalid bus bridge window");
+        }
+        Ok(Self {
+            range,
+            child_base,
+            child: DeviceManager::new(),
+        })
+    }
+
+    /// Returns the child bus, to register the devices behind the bridge.
+    pub fn child(&self) -> &DeviceManager<R> {
+        &self.child
+    }
+
+    /// Translates an address on the parent bus to the child bus.
+    fn translate(&self, addr: R::Addr) -> AxResult<R::Addr> {
+        let Some(offset) = self.range.offset_of(addr) else {
+            return ax_err!(BadAddress, "access outside of the bus bridge window");
+        };
+        match R::Addr::from_raw(self.child_base + offset) {
+            Some(addr) => Ok(addr),
+            None => ax_err!(BadAddress, "bus bridge address not representable"),
+        }
+    }
+
+    /// Parses the per-device states saved by
+    /// [`save_state`](BaseDeviceOps::save_state).
+    fn decode_states(mut payload: &[u8]) -> AxResult<Vec<(R, Vec<u8>)>> {
+        let mut states = Vec::new();
+        while !payload.is_empty() {
+            let Some((head, rest)) = payload.split_at_checked(20) else {
+                return ax_err!(InvalidData, "truncated bus bridge device state");
+            };
+            let start = u64::from_le_bytes(head[0..8].try_into().unwrap()) as usize;
+            let end = u64::from_le_bytes(head[8..16].try_into().unwrap()) as usize;
+            let Some(range) = R::from_raw_bounds(start, end) else {
+                return ax_err!(InvalidData, "bad range in bus bridge state");
+            };
+            let len = u32::from_le_bytes(head[16..20].try_into().unwrap()) as usize;
+            let Some((state, rest)) = rest.split_at_checked(len) else {
+                return ax_err!(InvalidData, "truncated bus bridge device state");
+            };
+            states.push((range, state.to_vec()));
+            payload = rest;
+        }
+        Ok(states)
+    }
+}
+
+impl<R: DeviceAddrRangeExt + Copy + 'static> BaseDeviceOps<R> for BusBridgeDevice<R> {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> R {
+        self.range
+    }
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.child.handle_read(self.translate(addr)?, width)
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.child.handle_write(self.translate(addr)?, width, val)
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        self.child
+            .handle_read_ctx(self.translate(addr)?, width, ctx)
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        self.child
+            .handle_write_ctx(self.translate(addr)?, width, val, ctx)
+    }
+
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        self.child
+            .handle_read_bulk(self.translate(addr)?, width, count, stride, buf)
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult {
+        self.child
+            .handle_write_bulk(self.translate(addr)?, width, count, stride, buf)
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        self.child.handle_read_async(self.translate(addr)?, width)
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        self.child
+            .handle_write_async(self.translate(addr)?, width, val)
+    }
+
+    fn handle_call(&self, addr: R::Addr, args: &[usize]) -> AxResult<[usize; 4]> {
+        self.child.handle_call(self.translate(addr)?, args)
+    }
+
+    fn peek(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.child.peek(self.translate(addr)?, width)
+    }
+
+    fn poke(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.child.poke(self.translate(addr)?, width, val)
+    }
+
+    fn on_time_jump(&self, delta_ns: u64, policy: CatchUpPolicy) {
+        for device in self.child.devices() {
+            device.on_time_jump(delta_ns, policy);
+        }
+    }
+
+    fn reset(&self) -> AxResult {
+        self.child.reset_all()
+    }
+
+    fn pause(&self) -> AxResult {
+        self.child.pause_all()
+    }
+
+    fn resume(&self) -> AxResult {
+        self.child.resume_all()
+    }
+
+    fn shutdown(&self) -> AxResult {
+        self.child.shutdown_all()
+    }
+
+    /// Returns the shallowest power state of the child devices, or
+    /// [`PowerState::D0`] if there is none.
+    fn power_state(&self) -> PowerState {
+        self.child
+            .devices()
+            .iter()
+            .map(|device| device.power_state())
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn set_power_state(&self, state: PowerState) -> AxResult {
+        self.child
+            .devices()
+            .iter()
+            .try_for_each(|device| device.set_power_state(state))
+    }
+
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        let mut payload = Vec::new();
+        for (range, state) in self.child.save_all()? {
+            let (start, end) = range.raw_bounds();
+            payload.extend_from_slice(&(start as u64).to_le_bytes());
+            payload.extend_from_slice(&(end as u64).to_le_bytes());
+            payload.extend_from_slice(&(state.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&state);
+        }
+        Ok(DeviceStateHeader::encode(Self::STATE_VERSION, &payload))
+    }
+
+    /// Returns `Err(AxError::InvalidData)` if the state is malformed or was
+    /// saved with another set of child devices.
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        let (header, payload) = DeviceStateHeader::decode(state)?;
+        if header.version != Self::STATE_VERSION {
+            return ax_err!(InvalidData, "unsupported bus bridge state version");
+        }
+        self.child.load_all(&Self::decode_states(payload)?)
+    }
+
+    fn on_domain_event(&self, event: DomainEvent) -> AxResult {
+        self.child
+            .devices()
+            .iter()
+            .try_for_each(|device| device.on_domain_event(event))
+    }
+
+    fn on_vcpu_added(&self, index: usize) -> AxResult {
+        self.child
+            .devices()
+            .iter()
+            .try_for_each(|device| device.on_vcpu_added(index))
+    }
+
+    fn on_memory_layout_changed(&self, change: MemoryLayoutChange) {
+        for device in self.child.devices() {
+            device.on_memory_layout_changed(change);
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        self.child.set_dma_accessor(accessor)
+    }
+
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        self.child.set_completer(completer)
+    }
+
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        self.child.set_timer_service(service)
+    }
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        self.child.set_clock_source(clock)
+    }
+
+    fn on_timer(&self, token: TimerToken) -> bool {
+        self.child.fire_timer(token)
+    }
+
+    /// Returns the registers of the child devices within the bridge window,
+    /// with offsets from the start of the window.
+    fn dump_registers(&self) -> Vec<RegisterDump> {
+        let size = self.range.size();
+        self.child
+            .devices()
+            .iter()
+            .filter_map(|device| {
+                let (start, _) = device.address_range().raw_bounds();
+                let offset = start.checked_sub(self.child_base)?;
+                (offset < size).then(|| (offset, device.dump_registers()))
+            })
+            .flat_map(|(offset, regs)| {
+                regs.into_iter().map(move |mut reg| {
+                    reg.offset += offset;
+                    reg
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the capabilities of any of the child devices.
+    fn capabilities(&self) -> CapabilitySet {
+        self.child
+            .devices()
+            .iter()
+            .fold(CapabilitySet::empty(), |caps, device| {
+                caps | device.capabilities()
+            })
+    }
+
+    /// Returns a generic bus bridge manifest, followed by the compatible
+    /// strings of the child devices.
+    fn manifest(&self) -> DeviceManifest {
+        let mut manifest = DeviceManifest::new("Bus bridge").with_compatible("simple-bus");
+        for (_, device) in self.child.manifest() {
+            manifest.compatible.extend(device.compatible);
+        }
+        manifest
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Optional device capabilities discovered at registration.
+
+use core::ops::BitOr;
+
+/// An optional subsystem a device participates in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Capability {
+    /// The device state can be saved and restored.
+    Snapshot,
+    /// The device can be reset to its power-on state.
+    Reset,
+    /// The device handles accesses wider than a single register.
+    BulkAccess,
+    /// The device completes requests asynchronously.
+    AsyncIo,
+    /// Parts of the device can be mapped directly into the guest.
+    DirectMap,
+    /// The device can be removed from a running VM.
+    HotUnplug,
+    /// The device supports side-effect-free debugger accesses through
+    /// [`BaseDeviceOps::peek`](crate::BaseDeviceOps::peek) and
+    /// [`BaseDeviceOps::poke`](crate::BaseDeviceOps::poke).
+    Peek,
+    /// The device handles the values of guest accesses in the guest byte order
+    /// itself, so its
+    /// [`DeviceManager`](crate::DeviceManager) passes them unconverted (see
+    /// [`ByteOrder`](crate::ByteOrder)).
+    GuestByteOrder,
+}
+
+impl Capability {
+    const ALL: [Capability; 8] = [
+        Capability::Snapshot,
+        Capability::Reset,
+        Capability::BulkAccess,
+        Capability::AsyncIo,
+        Capability::DirectMap,
+        Capability::HotUnplug,
+        Capability::Peek,
+        Capability::GuestByteOrder,
+    ];
+
+    const fn bit(self) -> u32 {
+        1 << self as u8
+    }
+}
+
+/// A set of [`Capability`] values.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::{Capability, CapabilitySet};
+///
+/// let caps = Capability::Snapshot | Capability::Reset;
+/// assert!(caps.contains(Capability::Reset));
+/// assert!(!caps.contains(Capability::HotUnplug));
+/// assert_eq!(caps.iter().count(), 2);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CapabilitySet(u32);
+
+impl CapabilitySet {
+    /// Returns the empty set.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns the set with `cap` added.
+    pub const fn with(self, cap: Capability) -> Self {
+        Self(self.0 | cap.bit())
+    }
+
+    /// Adds `cap` to the set.
+    pub fn insert(&mut self, cap: Capability) {
+        self.0 |= cap.bit();
+    }
+
+    /// Returns whether the set contains `cap`.
+    pub const fn contains(&self, cap: Capability) -> bool {
+        self.0 & cap.bit() != 0
+    }
+
+    /// Returns whether the set is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the capabilities in the set.
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        Capability::ALL
+            .into_iter()
+            .filter(|cap| self.contains(*cap))
+    }
+}
+
+impl From<Capability> for CapabilitySet {
+    fn from(cap: Capability) -> Self {
+        Self::empty().with(cap)
+    }
+}
+
+impl FromIterator<Capability> for CapabilitySet {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::empty(), Self::with)
+    }
+}
+
+impl BitOr for CapabilitySet {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOr<Capability> for CapabilitySet {
+    type Output = Self;
+
+    fn bitor(self, rhs: Capability) -> Self {
+        self.with(rhs)
+    }
+}
+
+impl BitOr for Capability {
+    type Output = CapabilitySet;
+
+    fn bitor(self, rhs: Self) -> CapabilitySet {
+        CapabilitySet::from(self).with(rhs)
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Clock and reset controller emulation driving [`Domain`] events.
+
+use alloc::vec::Vec;
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, Domain, DomainEvent, EmuDeviceType, RegValue};
+
+struct ClockResetState {
+    clk_enable: u32,
+    rst_assert: u32,
+}
+
+/// A clock and reset controller whose clock and reset lines each feed a
+/// [`Domain`] of consumer devices.
+///
+/// All registers are 32 bits wide, with one bit per line:
+///
+/// | Offset | Name      | Access | Description                          |
+/// |--------|-----------|--------|--------------------------------------|
+/// | 0x00   | `CLK_EN`  | RW     | Bit `n` set: clock `n` is running.   |
+/// | 0x04   | `RST`     | RW     | Bit `n` set: reset `n` is asserted.  |
+/// | 0x08   | `NUM_CLK` | RO     | Number of clock lines.               |
+/// | 0x0c   | `NUM_RST` | RO     | Number of reset lines.               |
+///
+/// Clearing a `CLK_EN` bit delivers [`DomainEvent::ClockGate`] to the domain of
+/// that clock and setting it again delivers [`DomainEvent::ClockUngate`].
+/// Setting a `RST` bit delivers [`DomainEvent::Reset`]; deasserting a reset has
+/// no effect on the consumers. All clocks start running and all resets start
+/// deasserted.
+pub struct ClockResetControllerBase {
+    range: GuestPhysAddrRange,
+    clocks: Vec<Domain<GuestPhysAddrRange>>,
+    resets: Vec<Domain<GuestPhysAddrRange>>,
+    state: Mutex<ClockResetState>,
+}
+
+impl ClockResetControllerBase {
+    const REG_CLK_EN: usize = 0x00;
+    const REG_RST: usize = 0x04;
+    const REG_NUM_CLK: usize = 0x08;
+    const REG_NUM_RST: usize = 0x0c;
+
+    /// The maximum number of clock or reset lines.
+    pub const MAX_LINES: usize = 32;
+
+    /// Creates a controller at `base` with one clock line per domain in
+    /// `clocks` and one reset line per domain in `resets`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if there
+    /// are more than [`Self::MAX_LINES`] clocks or resets.
+    pub fn new(
+        base: GuestPhysAddr,
+        clocks: Vec<Domain<GuestPhysAddrRange>>,
+        resets: Vec<Domain<GuestPhysAddrRange>>,
+    ) -> AxResult<Self> {
+        if clocks.len() > Self::MAX_LINES || resets.len() > Self::MAX_LINES {
+            return ax_err!(InvalidInput, "too many clock or reset lines");
+        }
+        Ok(Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x10),
+            state: Mutex::new(ClockResetState {
+                clk_enable: Self::line_mask(clocks.len()),
+                rst_assert: 0,
+            }),
+            clocks,
+            resets,
+        })
+    }
+
+    /// Returns the domain fed by clock line `index`.
+    pub fn clock(&self, index: usize) -> Option<&Domain<GuestPhysAddrRange>> {
+        self.clocks.get(index)
+    }
+
+    /// Returns the domain fed by reset line `index`.
+    pub fn reset(&self, index: usize) -> Option<&Domain<GuestPhysAddrRange>> {
+        self.resets.get(index)
+    }
+
+    fn line_mask(count: usize) -> u32 {
+        (((1u64) << count) - 1) as u32
+    }
+
+    /// Delivers `event` to the domains whose bit is set in `lines`, returning
+    /// the first error.
+    fn notify(domains: &[Domain<GuestPhysAddrRange>], lines: u32, event: DomainEvent) -> AxResult {
+        let mut result = Ok(());
+        for (index, domain) in domains.iter().enumerate() {
+            if lines & (1 << index) != 0
+                && let Err(err) = domain.apply(event)
+            {
+                warn!(
+                    "domain {} failed to handle {:?}: {:?}",
+                    domain.name(),
+                    event,
+                    err
+                );
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for ClockResetControllerBase {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let state = self.state.lock();
+        let val = match self.range.offset_of(addr) {
+            Some(Self::REG_CLK_EN) => state.clk_enable as usize,
+            Some(Self::REG_RST) => state.rst_assert as usize,
+            Some(Self::REG_NUM_CLK) => self.clocks.len(),
+            Some(Self::REG_NUM_RST) => self.resets.len(),
+            _ => 0,
+        };
+        Ok(RegValue::new(val, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        // Consumers are notified with the lock held, so that concurrent writes
+        // are delivered in the same order they are latched.
+        let mut state = self.state.lock();
+        match self.range.offset_of(addr) {
+            Some(Self::REG_CLK_EN) => {
+                let new = val as u32 & Self::line_mask(self.clocks.len());
+                let old = core::mem::replace(&mut state.clk_enable, new);
+                let gated = Self::notify(&self.clocks, old & !new, DomainEvent::ClockGate);
+                let ungated = Self::notify(&self.clocks, new & !old, DomainEvent::ClockUngate);
+                gated.and(ungated)
+            }
+            Some(Self::REG_RST) => {
+                let new = val as u32 & Self::line_mask(self.resets.len());
+                let old = core::mem::replace(&mut state.rst_assert, new);
+                Self::notify(&self.resets, new & !old, DomainEvent::Reset)
+            }
+            _ => Ok(()),
+        }
+    }
+}
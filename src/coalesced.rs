@@ -0,0 +1,109 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Coalesced writes to notification registers.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use axaddrspace::device::{AccessWidth, DeviceAddrRange};
+use axerrno::AxResult;
+use spin::Mutex;
+
+use crate::DeviceAddrRangeExt;
+
+/// A guest write logged in a [`CoalescedWriteRing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalescedWrite<A> {
+    /// The written address.
+    pub addr: A,
+    /// The access width.
+    pub width: AccessWidth,
+    /// The written value.
+    pub val: usize,
+}
+
+/// A ring of guest writes that the framework logs on behalf of a device,
+/// without calling the device synchronously.
+///
+/// Doorbell registers such as virtio queue notify are written far more often
+/// than the device needs to react to each write. A device owning such
+/// registers creates a ring covering them and returns it from
+/// [`BaseDeviceOps::coalesced_writes`]; [`DeviceManager`] then appends writes
+/// to these ranges to the ring, and the device processes them later with
+/// [`drain`](Self::drain), e.g. from its worker thread.
+///
+/// When the ring is full, the write is delivered synchronously through
+/// `handle_write`. Devices should drain the ring at the start of
+/// `handle_write` so that the ordering of writes is preserved.
+///
+/// [`BaseDeviceOps::coalesced_writes`]: crate::BaseDeviceOps::coalesced_writes
+/// [`DeviceManager`]: crate::DeviceManager
+pub struct CoalescedWriteRing<R: DeviceAddrRange> {
+    ranges: Vec<R>,
+    ring: Mutex<VecDeque<CoalescedWrite<R::Addr>>>,
+    capacity: usize,
+}
+
+impl<R: DeviceAddrRangeExt> CoalescedWriteRing<R> {
+    /// Creates a ring of `capacity` writes, coalescing the writes to `ranges`.
+    pub fn new(ranges: Vec<R>, capacity: usize) -> Self {
+        Self {
+            ranges,
+            ring: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Returns whether writes of `width` bytes at `addr` are coalesced.
+    pub fn covers(&self, addr: R::Addr, width: AccessWidth) -> bool {
+        self.ranges
+            .iter()
+            .any(|range| range.contains_access(addr, width))
+    }
+
+    /// Appends `write` to the ring, or returns `false` if the ring is full.
+    pub fn try_push(&self, write: CoalescedWrite<R::Addr>) -> bool {
+        let mut ring = self.ring.lock();
+        if ring.len() >= self.capacity {
+            return false;
+        }
+        ring.push_back(write);
+        true
+    }
+
+    /// Returns the number of pending writes.
+    pub fn len(&self) -> usize {
+        self.ring.lock().len()
+    }
+
+    /// Returns whether there are no pending writes.
+    pub fn is_empty(&self) -> bool {
+        self.ring.lock().is_empty()
+    }
+
+    /// Removes the pending writes and passes them to `f`, oldest first.
+    ///
+    /// Stops at the first error returned by `f` and returns it; the failed
+    /// write is consumed, the following ones stay pending.
+    pub fn drain(&self, mut f: impl FnMut(CoalescedWrite<R::Addr>) -> AxResult) -> AxResult {
+        // Pop one write at a time so that the guest can keep logging writes
+        // while they are processed.
+        loop {
+            let Some(write) = self.ring.lock().pop_front() else {
+                return Ok(());
+            };
+            f(write)?;
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deferred completion of guest accesses.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axerrno::AxResult;
+
+/// Identifies a guest access whose completion was deferred by a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CompletionToken(u64);
+
+impl CompletionToken {
+    /// Allocates a new token, unique for the lifetime of the hypervisor.
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the raw value of the token.
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// The outcome of an access through [`BaseDeviceOps::handle_read_async`] or
+/// [`BaseDeviceOps::handle_write_async`].
+///
+/// [`BaseDeviceOps::handle_read_async`]: crate::BaseDeviceOps::handle_read_async
+/// [`BaseDeviceOps::handle_write_async`]: crate::BaseDeviceOps::handle_write_async
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessOutcome {
+    /// The access completed. For reads this holds the value read, for writes
+    /// it is 0.
+    Completed(usize),
+    /// The access is in progress. The device reports its result to the
+    /// [`AccessCompleter`] with the same token.
+    Pending(CompletionToken),
+}
+
+/// Receives the results of accesses that devices completed asynchronously.
+///
+/// The hypervisor implements this trait and injects it with
+/// [`BaseDeviceOps::set_completer`]. On an [`AccessOutcome::Pending`] outcome
+/// it parks the accessing vCPU, and resumes it when
+/// [`complete`](Self::complete) is called with the token, storing the value of
+/// a read into the destination register.
+///
+/// [`BaseDeviceOps::set_completer`]: crate::BaseDeviceOps::set_completer
+pub trait AccessCompleter: Send + Sync {
+    /// Completes the access identified by `token` with `result`, the value
+    /// read (or 0 for writes) or the error of the access.
+    fn complete(&self, token: CompletionToken, result: AxResult<usize>);
+}
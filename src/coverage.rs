@@ -0,0 +1,232 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Register coverage tracking for device model completeness.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use axaddrspace::device::AccessWidth;
+use axerrno::AxResult;
+use spin::Mutex;
+
+use crate::{
+    AccessContext, AccessKind, AccessOutcome, BaseDeviceOps, DeviceAddrRangeExt, bulk_element_addr,
+    forward::forward_base_device_ops,
+};
+
+/// A kind of guest access to a device register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CoveragePoint {
+    /// The offset of the access from the start of the device range.
+    pub offset: usize,
+    /// The access width.
+    pub width: AccessWidth,
+    /// Whether the access was a read or a write.
+    pub kind: AccessKind,
+}
+
+/// How often a [`CoveragePoint`] was exercised.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CoverageCount {
+    /// The number of accesses.
+    pub hits: u64,
+    /// The number of those accesses the device failed.
+    pub errors: u64,
+}
+
+/// A wrapper recording which registers of a device have been exercised.
+///
+/// Every access is recorded as a [`CoveragePoint`] (offset, width, direction),
+/// so that device authors can compare the report against the register map of
+/// the device and see which registers were never touched by a driver or a
+/// test. Like [`JournaledDevice`](crate::JournaledDevice), coverage is opt-in:
+/// wrap the device before registering it.
+pub struct CoveredDevice<R, D> {
+    inner: D,
+    range: R,
+    points: Mutex<BTreeMap<CoveragePoint, CoverageCount>>,
+}
+
+impl<R: DeviceAddrRangeExt, D: BaseDeviceOps<R>> CoveredDevice<R, D> {
+    /// Wraps `inner`, recording coverage of its address range.
+    pub fn new(inner: D) -> Self {
+        Self {
+            range: inner.address_range(),
+            inner,
+            points: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns the exercised points with their counts, sorted by offset.
+    pub fn report(&self) -> Vec<(CoveragePoint, CoverageCount)> {
+        self.points
+            .lock()
+            .iter()
+            .map(|(point, count)| (*point, *count))
+            .collect()
+    }
+
+    /// Returns the points of `expected` that have never been exercised.
+    pub fn missing(&self, expected: impl IntoIterator<Item = CoveragePoint>) -> Vec<CoveragePoint> {
+        let points = self.points.lock();
+        expected
+            .into_iter()
+            .filter(|point| !points.contains_key(point))
+            .collect()
+    }
+
+    /// Discards all recorded coverage.
+    pub fn clear(&self) {
+        self.points.lock().clear();
+    }
+
+    /// Records each element of a successful bulk access, or the failure of a
+    /// failed one at its first address.
+    fn record_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        kind: AccessKind,
+        count: usize,
+        stride: isize,
+        ok: bool,
+    ) {
+        if !ok {
+            return self.record(addr, width, kind, false);
+        }
+        for index in 0..count {
+            if let Ok(addr) = bulk_element_addr(addr, index, stride) {
+                self.record(addr, width, kind, true);
+            }
+        }
+    }
+
+    fn record(&self, addr: R::Addr, width: AccessWidth, kind: AccessKind, ok: bool) {
+        // Accesses outside the range are never routed to the device.
+        let Some(offset) = self.range.offset_of(addr) else {
+            return;
+        };
+        let mut points = self.points.lock();
+        let count = points
+            .entry(CoveragePoint {
+                offset,
+                width,
+                kind,
+            })
+            .or_default();
+        count.hits += 1;
+        count.errors += !ok as u64;
+    }
+}
+
+impl<R: DeviceAddrRangeExt + 'static, D: BaseDeviceOps<R>> BaseDeviceOps<R>
+    for CoveredDevice<R, D>
+{
+    forward_base_device_ops!(
+        R,
+        inner => identity, handle_call, peek, poke, lifecycle, services, introspection
+    );
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        let result = self.inner.handle_read(addr, width);
+        self.record(addr, width, AccessKind::Read, result.is_ok());
+        result
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        let result = self.inner.handle_write(addr, width, val);
+        self.record(addr, width, AccessKind::Write, result.is_ok());
+        result
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        let result = self.inner.handle_read_ctx(addr, width, ctx);
+        self.record(addr, width, AccessKind::Read, result.is_ok());
+        result
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        let result = self.inner.handle_write_ctx(addr, width, val, ctx);
+        self.record(addr, width, AccessKind::Write, result.is_ok());
+        result
+    }
+
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        let result = self.inner.handle_read_bulk(addr, width, count, stride, buf);
+        self.record_bulk(addr, width, AccessKind::Read, count, stride, result.is_ok());
+        result
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult {
+        let result = self
+            .inner
+            .handle_write_bulk(addr, width, count, stride, buf);
+        self.record_bulk(
+            addr,
+            width,
+            AccessKind::Write,
+            count,
+            stride,
+            result.is_ok(),
+        );
+        result
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        let result = self.inner.handle_read_async(addr, width);
+        self.record(addr, width, AccessKind::Read, result.is_ok());
+        result
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        let result = self.inner.handle_write_async(addr, width, val);
+        self.record(addr, width, AccessKind::Write, result.is_ok());
+        result
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Address lookup strategies of the device manager.
+
+use alloc::{vec, vec::Vec};
+
+/// How a [`DeviceManager`](crate::DeviceManager) finds the device owning an
+/// accessed address, chosen when the manager is built with
+/// [`DeviceManager::with_dispatch_strategy`](crate::DeviceManager::with_dispatch_strategy).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchStrategy {
+    /// A binary search of the devices sorted by address, in `O(log n)`.
+    #[default]
+    Sorted,
+    /// A hash index of the 4 KiB pages covered by the devices, found in
+    /// constant time, for VMs with hundreds of devices such as virtio-mmio
+    /// transports.
+    ///
+    /// The index is rebuilt when a device is registered or unregistered, in
+    /// time proportional to the number of pages covered. Devices spanning
+    /// more than [`MAX_INDEXED_PAGES`](Self::MAX_INDEXED_PAGES) pages, and
+    /// addresses missing all devices, fall back to the binary search.
+    PageIndex,
+}
+
+impl DispatchStrategy {
+    /// The largest number of pages of a device indexed by
+    /// [`PageIndex`](Self::PageIndex).
+    pub const MAX_INDEXED_PAGES: usize = 64;
+}
+
+const PAGE_SHIFT: u32 = 12;
+
+/// An open-addressing hash table from page numbers to the index of the first
+/// device entry covering the page.
+pub(crate) struct PageIndex {
+    slots: Vec<Option<(usize, usize)>>,
+    shift: u32,
+}
+
+impl PageIndex {
+    /// Builds the index of the device entries with the raw bounds `ranges`,
+    /// sorted by address.
+    pub(crate) fn build(ranges: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let mut pages: Vec<(usize, usize)> = Vec::new();
+        for (entry, (start, end)) in ranges.into_iter().enumerate() {
+            let (first, last) = (start >> PAGE_SHIFT, (end - 1) >> PAGE_SHIFT);
+            if last - first >= DispatchStrategy::MAX_INDEXED_PAGES {
+                continue;
+            }
+            for page in first..=last {
+                // A page shared with the previous entry keeps pointing to it.
+                if pages.last().is_none_or(|&(last, _)| last != page) {
+                    pages.push((page, entry));
+                }
+            }
+        }
+        // Keep the table at most half full, so that probes stay short.
+        let bits = (pages.len() * 2)
+            .next_power_of_two()
+            .trailing_zeros()
+            .max(1);
+        let mut index = Self {
+            slots: vec![None; 1 << bits],
+            shift: u64::BITS - bits,
+        };
+        for (page, entry) in pages {
+            let mut slot = index.slot(page);
+            while index.slots[slot].is_some() {
+                slot = (slot + 1) & (index.slots.len() - 1);
+            }
+            index.slots[slot] = Some((page, entry));
+        }
+        index
+    }
+
+    /// Returns the index of the first device entry covering the page of the
+    /// raw address `raw`, if the page is indexed.
+    pub(crate) fn get(&self, raw: usize) -> Option<usize> {
+        let page = raw >> PAGE_SHIFT;
+        let mut slot = self.slot(page);
+        loop {
+            match self.slots[slot] {
+                Some((indexed, entry)) if indexed == page => return Some(entry),
+                Some(_) => slot = (slot + 1) & (self.slots.len() - 1),
+                None => return None,
+            }
+        }
+    }
+
+    fn slot(&self, page: usize) -> usize {
+        ((page as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> self.shift) as usize
+    }
+}
@@ -0,0 +1,366 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guest memory access, scatter-gather buffers and DMA address translation
+//! for DMA-capable devices.
+
+use alloc::{vec, vec::Vec};
+
+use axaddrspace::GuestPhysAddr;
+use axerrno::{AxResult, ax_err};
+
+use crate::RegionAccess;
+
+/// Access to the guest RAM of a VM, injected into DMA-capable devices through
+/// [`BaseDeviceOps::set_dma_accessor`](crate::BaseDeviceOps::set_dma_accessor).
+///
+/// Implementations must check bounds: an access that is not entirely within
+/// guest RAM fails with `Err(AxError::BadAddress)` without transferring any
+/// data. Multi-byte helpers use little-endian byte order.
+pub trait GuestMemoryAccessor: Send + Sync {
+    /// Reads `buf.len()` bytes of guest memory starting at `addr`.
+    fn read(&self, addr: GuestPhysAddr, buf: &mut [u8]) -> AxResult;
+
+    /// Writes `data` to guest memory starting at `addr`.
+    fn write(&self, addr: GuestPhysAddr, data: &[u8]) -> AxResult;
+
+    /// Copies `len` bytes of guest memory from `src` to `dst`. The ranges may
+    /// overlap.
+    ///
+    /// The default implementation bounces the data through a stack buffer.
+    fn copy(&self, dst: GuestPhysAddr, src: GuestPhysAddr, len: usize) -> AxResult {
+        const CHUNK: usize = 256;
+        let mut buf = [0u8; CHUNK];
+        if dst.as_usize() <= src.as_usize() {
+            let mut done = 0;
+            while done < len {
+                let n = CHUNK.min(len - done);
+                self.read(src + done, &mut buf[..n])?;
+                self.write(dst + done, &buf[..n])?;
+                done += n;
+            }
+        } else {
+            let mut left = len;
+            while left > 0 {
+                let n = CHUNK.min(left);
+                left -= n;
+                self.read(src + left, &mut buf[..n])?;
+                self.write(dst + left, &buf[..n])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with the `len` bytes of guest memory starting at `addr`.
+    ///
+    /// Implementations backed by a host mapping of guest RAM should override
+    /// this to pass the mapped memory directly, avoiding a copy. The default
+    /// implementation reads the memory into a temporary buffer.
+    fn with_slice(
+        &self,
+        addr: GuestPhysAddr,
+        len: usize,
+        f: &mut dyn FnMut(&[u8]) -> AxResult,
+    ) -> AxResult {
+        let mut buf = vec![0; len];
+        self.read(addr, &mut buf)?;
+        f(&buf)
+    }
+
+    /// Calls `f` with the `len` bytes of guest memory starting at `addr`, for
+    /// `f` to fill in.
+    ///
+    /// Like [`with_slice`](Self::with_slice), mapped implementations should
+    /// override this. The default implementation passes a zeroed temporary
+    /// buffer and writes it back if `f` succeeds.
+    fn with_slice_mut(
+        &self,
+        addr: GuestPhysAddr,
+        len: usize,
+        f: &mut dyn FnMut(&mut [u8]) -> AxResult,
+    ) -> AxResult {
+        let mut buf = vec![0; len];
+        f(&mut buf)?;
+        self.write(addr, &buf)
+    }
+
+    /// Reads a `u16` at `addr`.
+    fn read_u16(&self, addr: GuestPhysAddr) -> AxResult<u16> {
+        let mut buf = [0; 2];
+        self.read(addr, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Reads a `u32` at `addr`.
+    fn read_u32(&self, addr: GuestPhysAddr) -> AxResult<u32> {
+        let mut buf = [0; 4];
+        self.read(addr, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// Reads a `u64` at `addr`.
+    fn read_u64(&self, addr: GuestPhysAddr) -> AxResult<u64> {
+        let mut buf = [0; 8];
+        self.read(addr, &mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Writes a `u16` at `addr`.
+    fn write_u16(&self, addr: GuestPhysAddr, val: u16) -> AxResult {
+        self.write(addr, &val.to_le_bytes())
+    }
+
+    /// Writes a `u32` at `addr`.
+    fn write_u32(&self, addr: GuestPhysAddr, val: u32) -> AxResult {
+        self.write(addr, &val.to_le_bytes())
+    }
+
+    /// Writes a `u64` at `addr`.
+    fn write_u64(&self, addr: GuestPhysAddr, val: u64) -> AxResult {
+        self.write(addr, &val.to_le_bytes())
+    }
+}
+
+/// One contiguous range of a [`GuestBufferList`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestBuffer {
+    /// The guest physical start address.
+    pub addr: GuestPhysAddr,
+    /// The length in bytes.
+    pub len: usize,
+}
+
+/// A scatter-gather list of guest physical buffers, accessed as one
+/// contiguous byte stream.
+///
+/// Every access is checked against the total length of the list before any
+/// data is transferred, and accesses to guest memory go through a
+/// [`GuestMemoryAccessor`], which checks them against guest RAM.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use axdevice_base::GuestBufferList;
+///
+/// let mut sg = GuestBufferList::new();
+/// sg.push(0x8000_0000.into(), 16)?;
+/// sg.push(0x8000_1000.into(), 512)?;
+/// sg.check_len(16 + 512)?;
+///
+/// let (header, data) = sg.split_at(16)?;
+/// let mut hdr = [0; 16];
+/// header.read(&*dma, 0, &mut hdr)?;
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GuestBufferList {
+    buffers: Vec<GuestBuffer>,
+    len: usize,
+}
+
+impl GuestBufferList {
+    /// Creates an empty list.
+    pub const fn new() -> Self {
+        Self {
+            buffers: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Appends the `len` bytes at `addr` to the list. Empty buffers are
+    /// ignored.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the buffer wraps around the
+    /// address space or the total length overflows.
+    pub fn push(&mut self, addr: GuestPhysAddr, len: usize) -> AxResult {
+        if len == 0 {
+            return Ok(());
+        }
+        let (Some(_), Some(total)) = (addr.as_usize().checked_add(len), self.len.checked_add(len))
+        else {
+            return ax_err!(InvalidInput, "guest buffer overflows");
+        };
+        self.buffers.push(GuestBuffer { addr, len });
+        self.len = total;
+        Ok(())
+    }
+
+    /// Returns the total length of the list in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the list holds no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the buffers of the list.
+    pub fn buffers(&self) -> &[GuestBuffer] {
+        &self.buffers
+    }
+
+    /// Returns an iterator over the buffers of the list.
+    pub fn iter(&self) -> impl Iterator<Item = &GuestBuffer> {
+        self.buffers.iter()
+    }
+
+    /// Checks that the list holds at least `len` bytes.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` otherwise.
+    pub fn check_len(&self, len: usize) -> AxResult {
+        if self.len < len {
+            return ax_err!(InvalidInput, "guest buffer list too short");
+        }
+        Ok(())
+    }
+
+    /// Returns the bytes `[offset, offset + len)` of the list as a new list.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the range exceeds the list.
+    pub fn slice(&self, offset: usize, len: usize) -> AxResult<Self> {
+        let mut list = Self::new();
+        for (addr, n) in self.chunks(offset, len)? {
+            list.push(addr, n)?;
+        }
+        Ok(list)
+    }
+
+    /// Splits the list into its first `offset` bytes and the rest.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `offset` exceeds the list.
+    pub fn split_at(&self, offset: usize) -> AxResult<(Self, Self)> {
+        self.check_len(offset)?;
+        Ok((
+            self.slice(0, offset)?,
+            self.slice(offset, self.len - offset)?,
+        ))
+    }
+
+    /// Reads `buf.len()` bytes of the list starting at byte `offset`.
+    pub fn read(&self, mem: &dyn GuestMemoryAccessor, offset: usize, buf: &mut [u8]) -> AxResult {
+        let mut done = 0;
+        for (addr, n) in self.chunks(offset, buf.len())? {
+            mem.read(addr, &mut buf[done..done + n])?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to the list starting at byte `offset`.
+    pub fn write(&self, mem: &dyn GuestMemoryAccessor, offset: usize, data: &[u8]) -> AxResult {
+        let mut done = 0;
+        for (addr, n) in self.chunks(offset, data.len())? {
+            mem.write(addr, &data[done..done + n])?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with each contiguous piece of the bytes `[offset, offset +
+    /// len)` of the list, in order, without copying where the accessor
+    /// supports it (see [`GuestMemoryAccessor::with_slice`]).
+    pub fn for_each_slice(
+        &self,
+        mem: &dyn GuestMemoryAccessor,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(&[u8]) -> AxResult,
+    ) -> AxResult {
+        for (addr, n) in self.chunks(offset, len)? {
+            mem.with_slice(addr, n, &mut f)?;
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with each contiguous piece of the bytes `[offset, offset +
+    /// len)` of the list for it to fill in, in order (see
+    /// [`GuestMemoryAccessor::with_slice_mut`]).
+    pub fn for_each_slice_mut(
+        &self,
+        mem: &dyn GuestMemoryAccessor,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(&mut [u8]) -> AxResult,
+    ) -> AxResult {
+        for (addr, n) in self.chunks(offset, len)? {
+            mem.with_slice_mut(addr, n, &mut f)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the guest ranges covering the bytes `[offset, offset + len)` of
+    /// the list.
+    fn chunks(&self, offset: usize, len: usize) -> AxResult<Vec<(GuestPhysAddr, usize)>> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => {}
+            _ => return ax_err!(InvalidInput, "access beyond guest buffer list"),
+        }
+        let mut chunks = Vec::new();
+        let (mut skip, mut left) = (offset, len);
+        for buf in &self.buffers {
+            if left == 0 {
+                break;
+            }
+            if skip >= buf.len {
+                skip -= buf.len;
+                continue;
+            }
+            let n = (buf.len - skip).min(left);
+            chunks.push((buf.addr + skip, n));
+            skip = 0;
+            left -= n;
+        }
+        Ok(chunks)
+    }
+}
+
+/// The DMA address translation of the devices behind an IOMMU, programmed
+/// through a guest-visible IOMMU such as
+/// [`VirtioIommuDevice`](crate::VirtioIommuDevice).
+///
+/// Endpoints, identified by their IDs on the IOMMU, are attached to
+/// translation domains. The DMA of an attached endpoint is translated through
+/// the mappings of its domain, and faults outside them. The hypervisor
+/// applies the mappings, to a host IOMMU for passed-through devices or to the
+/// [`GuestMemoryAccessor`] of emulated ones.
+///
+/// Errors are reported to the guest: `AxError::NotFound` for an unknown
+/// endpoint or domain, `AxError::AlreadyExists` for a mapping overlapping an
+/// existing one, `AxError::NoMemory` when out of mapping resources and
+/// `AxError::Unsupported` for requests the translator cannot apply.
+pub trait DmaTranslator: Send + Sync {
+    /// Attaches `endpoint` to `domain`, detaching it from its previous
+    /// domain.
+    fn attach(&self, domain: u32, endpoint: u32) -> AxResult;
+
+    /// Detaches `endpoint` from `domain`, blocking its DMA.
+    fn detach(&self, domain: u32, endpoint: u32) -> AxResult;
+
+    /// Maps the I/O virtual addresses `iova..=last` of `domain` to the guest
+    /// physical addresses starting at `paddr`, with the permissions `access`.
+    ///
+    /// The range is inclusive, so that a single mapping may cover the whole
+    /// 64-bit I/O virtual address space.
+    fn map(
+        &self,
+        domain: u32,
+        iova: u64,
+        last: u64,
+        paddr: GuestPhysAddr,
+        access: RegionAccess,
+    ) -> AxResult;
+
+    /// Removes the mappings of `domain` within `iova..=last`.
+    fn unmap(&self, domain: u32, iova: u64, last: u64) -> AxResult;
+}
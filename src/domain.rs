@@ -0,0 +1,103 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Power and clock domains grouping emulated devices.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use axaddrspace::device::DeviceAddrRange;
+use axerrno::AxResult;
+
+use crate::BaseDeviceOps;
+
+/// A domain-wide operation delivered to every member of a [`Domain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DomainEvent {
+    /// The domain is reset. Devices return to their power-on state.
+    Reset,
+    /// The domain is powered down. Devices must stop generating interrupts and
+    /// DMA until [`DomainEvent::Resume`] is delivered.
+    Suspend,
+    /// The domain is powered up again after a [`DomainEvent::Suspend`].
+    Resume,
+    /// The clock feeding the domain is gated. Devices keep their state but make
+    /// no progress (e.g. a UART stops transmitting).
+    ClockGate,
+    /// The clock feeding the domain is ungated again.
+    ClockUngate,
+}
+
+impl DomainEvent {
+    /// Returns whether the event is delivered to members in reverse order.
+    ///
+    /// Events taking a domain down are delivered in reverse membership order,
+    /// so that devices added later (which may depend on earlier ones) go down
+    /// first; all other events are delivered in membership order.
+    pub fn is_reverse_order(&self) -> bool {
+        matches!(self, DomainEvent::Suspend | DomainEvent::ClockGate)
+    }
+}
+
+/// A named group of devices sharing a power or clock domain.
+///
+/// Domains model SoC power and clock domains: a guest-visible power or clock
+/// controller device holds the domains it controls and applies domain-wide
+/// operations to them, which are then routed to each member device through
+/// [`BaseDeviceOps::on_domain_event`].
+pub struct Domain<R: DeviceAddrRange> {
+    name: String,
+    members: Vec<Arc<dyn BaseDeviceOps<R>>>,
+}
+
+impl<R: DeviceAddrRange + 'static> Domain<R> {
+    /// Creates an empty domain with the given name.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            members: Vec::new(),
+        }
+    }
+
+    /// Returns the name of the domain.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds a device to the domain.
+    pub fn add(&mut self, device: Arc<dyn BaseDeviceOps<R>>) {
+        self.members.push(device);
+    }
+
+    /// Returns the member devices, in the order they were added.
+    pub fn members(&self) -> &[Arc<dyn BaseDeviceOps<R>>] {
+        &self.members
+    }
+
+    /// Delivers `event` to all member devices.
+    ///
+    /// See [`DomainEvent::is_reverse_order`] for the delivery order. Delivery
+    /// stops at the first device returning an error, and that error is returned.
+    pub fn apply(&self, event: DomainEvent) -> AxResult {
+        if event.is_reverse_order() {
+            self.members
+                .iter()
+                .rev()
+                .try_for_each(|dev| dev.on_domain_event(event))
+        } else {
+            self.members
+                .iter()
+                .try_for_each(|dev| dev.on_domain_event(event))
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Register dumps for debugging guest hangs.
+
+use core::fmt;
+
+use axaddrspace::device::AccessWidth;
+
+/// The current value of a device register, returned by
+/// [`BaseDeviceOps::dump_registers`](crate::BaseDeviceOps::dump_registers).
+///
+/// # Example
+///
+/// ```rust
+/// use axaddrspace::device::AccessWidth;
+/// use axdevice_base::RegisterDump;
+///
+/// let reg = RegisterDump::new("UARTFR", 0x18, AccessWidth::Word, 0x90);
+/// assert_eq!(alloc::format!("{reg}"), "UARTFR           +0x018 = 0x0090");
+/// # extern crate alloc;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterDump {
+    /// The register name, as in the device datasheet.
+    pub name: &'static str,
+    /// The offset of the register from the start of the device range.
+    pub offset: usize,
+    /// The width of the register.
+    pub width: AccessWidth,
+    /// The current value of the register.
+    pub value: usize,
+}
+
+impl RegisterDump {
+    /// Creates a register dump entry.
+    pub const fn new(name: &'static str, offset: usize, width: AccessWidth, value: usize) -> Self {
+        Self {
+            name,
+            offset,
+            width,
+            value,
+        }
+    }
+}
+
+impl fmt::Display for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<16} +{:#05x} = {:#0digits$x}",
+            self.name,
+            self.offset,
+            self.value,
+            digits = 2 + 2 * self.width.size()
+        )
+    }
+}
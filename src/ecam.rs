@@ -0,0 +1,516 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PCI configuration addresses and the ECAM configuration window.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axaddrspace::{
+    GuestPhysAddr, GuestPhysAddrRange,
+    device::{AccessWidth, DeviceAddr, DeviceAddrRange},
+};
+use axerrno::{AxError, AxResult, ax_err};
+
+use crate::{
+    AccessCompleter, AccessContext, BaseDeviceOps, BasePciDeviceOps, CapabilitySet, CatchUpPolicy,
+    ClockSource, DeviceAddrRangeExt, DeviceManager, DeviceManifest, DeviceStateHeader, DomainEvent,
+    EmuDeviceType, GuestMemoryAccessor, MemoryLayoutChange, PowerState, RawDeviceAddr, RegionId,
+    RegionUpdateHandler, RegionUpdateSink, RegisterDump, TimerService, TimerToken, width_mask,
+};
+
+/// The bus, device and function numbers of a PCI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciBdf {
+    /// The bus number.
+    pub bus: u8,
+    /// The device number, below 32.
+    pub device: u8,
+    /// The function number, below 8.
+    pub function: u8,
+}
+
+impl PciBdf {
+    /// Creates a BDF, or returns `None` if `device` or `function` is out of
+    /// range.
+    pub const fn new(bus: u8, device: u8, function: u8) -> Option<Self> {
+        if device < 32 && function < 8 {
+            Some(Self {
+                bus,
+                device,
+                function,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the identifier under which an [`EcamWindow`] reports `region`
+    /// of this function: the 16-bit bus/device/function number in the upper
+    /// half, and the low 16 bits of `region` in the lower half.
+    pub const fn region_id(self, region: RegionId) -> RegionId {
+        let bdf = (self.bus as u32) << 8 | (self.device as u32) << 3 | self.function as u32;
+        RegionId::new(bdf << 16 | (region.as_u32() & 0xffff))
+    }
+
+    /// Splits an identifier built by [`region_id`](Self::region_id) into the
+    /// function and its region.
+    pub const fn split_region_id(id: RegionId) -> (Self, RegionId) {
+        let bdf = id.as_u32() >> 16;
+        let bdf = Self {
+            bus: (bdf >> 8) as u8,
+            device: ((bdf >> 3) & 0x1f) as u8,
+            function: (bdf & 0x7) as u8,
+        };
+        (bdf, RegionId::new(id.as_u32() & 0xffff))
+    }
+}
+
+/// An address in the configuration space of a PCI function.
+///
+/// The raw value of the address ([`RawDeviceAddr::to_raw`]) is its offset in
+/// an ECAM window starting at bus 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciConfigAddr {
+    /// The addressed function.
+    pub bdf: PciBdf,
+    /// The register offset in the function's configuration space, below 4096.
+    pub offset: u16,
+}
+
+impl PciConfigAddr {
+    /// The size of the configuration space of a function in an ECAM window.
+    pub const FUNCTION_SIZE: usize = 0x1000;
+
+    /// Decodes an offset in an ECAM window whose first bus is bus 0.
+    ///
+    /// Returns `None` if the offset is beyond bus 255.
+    pub fn from_ecam_offset(offset: usize) -> Option<Self> {
+        if offset >= 256 << 20 {
+            return None;
+        }
+        Some(Self {
+            bdf: PciBdf {
+                bus: (offset >> 20) as u8,
+                device: ((offset >> 15) & 0x1f) as u8,
+                function: ((offset >> 12) & 0x7) as u8,
+            },
+            offset: (offset & 0xfff) as u16,
+        })
+    }
+
+    /// Returns the offset of the address in an ECAM window whose first bus is
+    /// bus 0.
+    pub fn ecam_offset(self) -> usize {
+        (self.bdf.bus as usize) << 20
+            | (self.bdf.device as usize) << 15
+            | (self.bdf.function as usize) << 12
+            | self.offset as usize
+    }
+}
+
+impl DeviceAddr for PciConfigAddr {}
+
+impl RawDeviceAddr for PciConfigAddr {
+    fn to_raw(self) -> usize {
+        self.ecam_offset()
+    }
+
+    fn from_raw(raw: usize) -> Option<Self> {
+        Self::from_ecam_offset(raw)
+    }
+}
+
+/// An inclusive range of PCI configuration addresses, usually the whole
+/// configuration space of one function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciConfigRange {
+    /// The first address of the range.
+    pub start: PciConfigAddr,
+    /// The last address of the range.
+    pub end: PciConfigAddr,
+}
+
+impl PciConfigRange {
+    /// Returns the range covering the whole configuration space of `bdf`.
+    pub const fn function(bdf: PciBdf) -> Self {
+        Self {
+            start: PciConfigAddr { bdf, offset: 0 },
+            end: PciConfigAddr {
+                bdf,
+                offset: PciConfigAddr::FUNCTION_SIZE as u16 - 1,
+            },
+        }
+    }
+}
+
+impl DeviceAddrRange for PciConfigRange {
+    type Addr = PciConfigAddr;
+
+    fn contains(&self, addr: PciConfigAddr) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+impl DeviceAddrRangeExt for PciConfigRange {
+    fn raw_bounds(&self) -> (usize, usize) {
+        (self.start.to_raw(), self.end.to_raw() + 1)
+    }
+
+    fn from_raw_bounds(start: usize, end: usize) -> Option<Self> {
+        if start >= end {
+            return None;
+        }
+        Some(Self {
+            start: PciConfigAddr::from_raw(start)?,
+            end: PciConfigAddr::from_raw(end - 1)?,
+        })
+    }
+}
+
+/// An ECAM (PCIe enhanced configuration access mechanism) window, routing
+/// guest configuration accesses to the registered PCI functions.
+///
+/// Functions implement [`BasePciDeviceOps`] over the
+/// [`PciConfigRange`] of their BDF and are registered with
+/// [`register`](Self::register). Accesses to functions that are not present
+/// read as all ones and ignore writes, as a master abort does on real
+/// hardware.
+///
+/// Lifecycle hooks, notifications and injected services are forwarded to all
+/// functions. Region changes of the functions, such as BAR reprogramming, are
+/// reported through the window's own region sink, identified with
+/// [`PciBdf::region_id`]. The window reports the registers, capabilities and
+/// compatible strings of all of them. Its saved state is the state of every
+/// function, tagged with its BDF.
+pub struct EcamWindow {
+    range: GuestPhysAddrRange,
+    start_bus: u8,
+    functions: DeviceManager<PciConfigRange>,
+}
+
+impl EcamWindow {
+    /// Creates an ECAM window at `base` decoding buses `start_bus` to
+    /// `end_bus` inclusive.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `end_bus` is below `start_bus`.
+    pub fn new(base: GuestPhysAddr, start_bus: u8, end_bus: u8) -> AxResult<Self> {
+        if end_bus < start_bus {
+            return ax_err!(InvalidInput, "invalid ECAM bus range");
+        }
+        let size = ((end_bus - start_bus) as usize + 1) << 20;
+        Ok(Self {
+            range: GuestPhysAddrRange::from_start_size(base, size),
+            start_bus,
+            functions: DeviceManager::new(),
+        })
+    }
+
+    /// Registers a PCI function.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the function's bus is outside
+    /// the window, and otherwise the same errors as
+    /// [`DeviceManager::register`].
+    pub fn register(&self, function: Arc<dyn BasePciDeviceOps>) -> AxResult {
+        if self
+            .decode_bus(function.address_range().start.bdf.bus)
+            .is_none()
+        {
+            return ax_err!(InvalidInput, "PCI function outside of the ECAM window");
+        }
+        self.functions.register(function)
+    }
+
+    /// Unregisters the function `bdf`, returning it.
+    pub fn unregister(&self, bdf: PciBdf) -> Option<Arc<dyn BasePciDeviceOps>> {
+        self.functions
+            .unregister(PciConfigRange::function(bdf).start)
+    }
+
+    /// Returns the registered functions.
+    pub fn functions(&self) -> &DeviceManager<PciConfigRange> {
+        &self.functions
+    }
+
+    /// Decodes a guest physical address in the window.
+    pub fn decode(&self, addr: GuestPhysAddr) -> Option<PciConfigAddr> {
+        let offset = self.range.offset_of(addr)?;
+        PciConfigAddr::from_ecam_offset(offset + ((self.start_bus as usize) << 20))
+    }
+
+    /// The version of the state layout saved by
+    /// [`save_state`](BaseDeviceOps::save_state).
+    const STATE_VERSION: u32 = 1;
+
+    fn decode_bus(&self, bus: u8) -> Option<u8> {
+        let rel = bus.checked_sub(self.start_bus)?;
+        ((rel as usize) << 20 < self.range.size()).then_some(rel)
+    }
+
+    fn decode_access(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<PciConfigAddr> {
+        match self.decode(addr) {
+            Some(config)
+                if (config.offset as usize) + width.size() <= PciConfigAddr::FUNCTION_SIZE =>
+            {
+                Ok(config)
+            }
+            _ => ax_err!(InvalidInput, "invalid ECAM access"),
+        }
+    }
+
+    /// Parses the per-function states saved by
+    /// [`save_state`](BaseDeviceOps::save_state).
+    fn decode_states(mut payload: &[u8]) -> AxResult<Vec<(PciConfigRange, Vec<u8>)>> {
+        let mut states = Vec::new();
+        while !payload.is_empty() {
+            let Some((head, rest)) = payload.split_at_checked(7) else {
+                return ax_err!(InvalidData, "truncated ECAM function state");
+            };
+            let Some(bdf) = PciBdf::new(head[0], head[1], head[2]) else {
+                return ax_err!(InvalidData, "bad BDF in ECAM state");
+            };
+            let len = u32::from_le_bytes(head[3..7].try_into().unwrap()) as usize;
+            let Some((state, rest)) = rest.split_at_checked(len) else {
+                return ax_err!(InvalidData, "truncated ECAM function state");
+            };
+            states.push((PciConfigRange::function(bdf), state.to_vec()));
+            payload = rest;
+        }
+        Ok(states)
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for EcamWindow {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let config = self.decode_access(addr, width)?;
+        match self.functions.handle_read(config, width) {
+            Err(AxError::NotFound) => Ok(width_mask(width)),
+            result => result,
+        }
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        let config = self.decode_access(addr, width)?;
+        match self.functions.handle_write(config, width, val) {
+            Err(AxError::NotFound) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: GuestPhysAddr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        let config = self.decode_access(addr, width)?;
+        match self.functions.handle_read_ctx(config, width, ctx) {
+            Err(AxError::NotFound) => Ok(width_mask(width)),
+            result => result,
+        }
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: GuestPhysAddr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        let config = self.decode_access(addr, width)?;
+        match self.functions.handle_write_ctx(config, width, val, ctx) {
+            Err(AxError::NotFound) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn on_time_jump(&self, delta_ns: u64, policy: CatchUpPolicy) {
+        for function in self.functions.devices() {
+            function.on_time_jump(delta_ns, policy);
+        }
+    }
+
+    fn reset(&self) -> AxResult {
+        self.functions.reset_all()
+    }
+
+    fn pause(&self) -> AxResult {
+        self.functions.pause_all()
+    }
+
+    fn resume(&self) -> AxResult {
+        self.functions.resume_all()
+    }
+
+    fn shutdown(&self) -> AxResult {
+        self.functions.shutdown_all()
+    }
+
+    /// Returns the shallowest power state of the functions, or
+    /// [`PowerState::D0`] if none is registered.
+    fn power_state(&self) -> PowerState {
+        self.functions
+            .devices()
+            .iter()
+            .map(|function| function.power_state())
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn set_power_state(&self, state: PowerState) -> AxResult {
+        self.functions
+            .devices()
+            .iter()
+            .try_for_each(|function| function.set_power_state(state))
+    }
+
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        let mut payload = Vec::new();
+        for (range, state) in self.functions.save_all()? {
+            let bdf = range.start.bdf;
+            payload.extend_from_slice(&[bdf.bus, bdf.device, bdf.function]);
+            payload.extend_from_slice(&(state.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&state);
+        }
+        Ok(DeviceStateHeader::encode(Self::STATE_VERSION, &payload))
+    }
+
+    /// Returns `Err(AxError::InvalidData)` if the state is malformed or was
+    /// saved with another set of functions.
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        let (header, payload) = DeviceStateHeader::decode(state)?;
+        if header.version != Self::STATE_VERSION {
+            return ax_err!(InvalidData, "unsupported ECAM state version");
+        }
+        self.functions.load_all(&Self::decode_states(payload)?)
+    }
+
+    fn on_domain_event(&self, event: DomainEvent) -> AxResult {
+        self.functions
+            .devices()
+            .iter()
+            .try_for_each(|function| function.on_domain_event(event))
+    }
+
+    fn on_vcpu_added(&self, index: usize) -> AxResult {
+        self.functions
+            .devices()
+            .iter()
+            .try_for_each(|function| function.on_vcpu_added(index))
+    }
+
+    fn on_memory_layout_changed(&self, change: MemoryLayoutChange) {
+        for function in self.functions.devices() {
+            function.on_memory_layout_changed(change);
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        self.functions.set_dma_accessor(accessor)
+    }
+
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        self.functions.set_completer(completer)
+    }
+
+    fn set_region_sink(&self, sink: Arc<dyn RegionUpdateSink>) {
+        self.functions
+            .set_region_handler(Arc::new(FunctionRegions(sink)))
+    }
+
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        self.functions.set_timer_service(service)
+    }
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        self.functions.set_clock_source(clock)
+    }
+
+    fn on_timer(&self, token: TimerToken) -> bool {
+        self.functions.fire_timer(token)
+    }
+
+    /// Returns the registers of all functions, with offsets from the start of
+    /// the window.
+    fn dump_registers(&self) -> Vec<RegisterDump> {
+        let base = (self.start_bus as usize) << 20;
+        self.functions
+            .devices()
+            .iter()
+            .flat_map(|function| {
+                let offset = function.address_range().start.ecam_offset() - base;
+                function.dump_registers().into_iter().map(move |mut reg| {
+                    reg.offset += offset;
+                    reg
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the capabilities of any of the functions.
+    fn capabilities(&self) -> CapabilitySet {
+        self.functions
+            .devices()
+            .iter()
+            .fold(CapabilitySet::empty(), |caps, function| {
+                caps | function.capabilities()
+            })
+    }
+
+    /// Returns a generic ECAM host bridge manifest, followed by the
+    /// compatible strings of the functions.
+    fn manifest(&self) -> DeviceManifest {
+        let mut manifest =
+            DeviceManifest::new("PCIe ECAM window").with_compatible("pci-host-ecam-generic");
+        for (_, function) in self.functions.manifest() {
+            manifest.compatible.extend(function.compatible);
+        }
+        manifest
+    }
+}
+
+/// Reports the region changes of the functions of an [`EcamWindow`] through
+/// the window's sink, tagging them with the function.
+struct FunctionRegions(Arc<dyn RegionUpdateSink>);
+
+impl FunctionRegions {
+    fn region_id(device: usize, region: RegionId) -> AxResult<RegionId> {
+        match PciConfigAddr::from_ecam_offset(device) {
+            Some(config) => Ok(config.bdf.region_id(region)),
+            None => ax_err!(InvalidInput, "not a PCI function"),
+        }
+    }
+}
+
+impl RegionUpdateHandler for FunctionRegions {
+    fn remap(&self, device: usize, region: RegionId, new_base: usize, new_size: usize) -> AxResult {
+        self.0
+            .remap(Self::region_id(device, region)?, new_base, new_size)
+    }
+
+    fn enable(&self, device: usize, region: RegionId) -> AxResult {
+        self.0.enable(Self::region_id(device, region)?)
+    }
+
+    fn disable(&self, device: usize, region: RegionId) -> AxResult {
+        self.0.disable(Self::region_id(device, region)?)
+    }
+}
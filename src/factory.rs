@@ -0,0 +1,313 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Creation of devices from their configuration.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use axerrno::{AxResult, ax_err};
+use spin::RwLock;
+
+use crate::{ConfigError, EmuDeviceType, EmulatedDeviceConfig, GuestMemoryAccessor, MmioDevice};
+
+/// The host services available to device factories.
+///
+/// Backends are registered by name and trait object type, and devices look
+/// them up by the name given in their configuration, e.g. the `backend`
+/// parameter.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let deps = DeviceDeps::new()
+///     .with_dma(guest_memory)
+///     .with_backend::<dyn BlockBackend>("disk0", Arc::new(RamDisk::new(...)));
+/// let disk = deps.backend::<dyn BlockBackend>(cfg.get_str("backend").unwrap())?;
+/// ```
+#[derive(Default, Clone)]
+pub struct DeviceDeps {
+    dma: Option<Arc<dyn GuestMemoryAccessor>>,
+    backends: BTreeMap<String, Arc<dyn Any + Send + Sync>>,
+}
+
+impl DeviceDeps {
+    /// Creates an empty set of dependencies.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the guest memory accessor.
+    pub fn with_dma(mut self, accessor: Arc<dyn GuestMemoryAccessor>) -> Self {
+        self.dma = Some(accessor);
+        self
+    }
+
+    /// Registers `backend` under `name`, replacing any backend of that name.
+    pub fn with_backend<T: ?Sized + Send + Sync + 'static>(
+        mut self,
+        name: impl Into<String>,
+        backend: Arc<T>,
+    ) -> Self {
+        self.backends.insert(name.into(), Arc::new(backend));
+        self
+    }
+
+    /// Returns the guest memory accessor.
+    ///
+    /// Returns `Err(AxError::NotFound)` if none was set.
+    pub fn dma(&self) -> AxResult<Arc<dyn GuestMemoryAccessor>> {
+        match &self.dma {
+            Some(accessor) => Ok(accessor.clone()),
+            None => ax_err!(NotFound, "no guest memory accessor"),
+        }
+    }
+
+    /// Returns the backend registered under `name`.
+    ///
+    /// Returns `Err(AxError::NotFound)` if there is no such backend, and
+    /// `Err(AxError::InvalidInput)` if it is not of type `T`.
+    pub fn backend<T: ?Sized + Send + Sync + 'static>(&self, name: &str) -> AxResult<Arc<T>> {
+        let Some(backend) = self.backends.get(name) else {
+            return ax_err!(NotFound, "no backend of that name");
+        };
+        match backend.downcast_ref::<Arc<T>>() {
+            Some(backend) => Ok(backend.clone()),
+            None => ax_err!(InvalidInput, "backend of another type"),
+        }
+    }
+}
+
+/// Creates devices of one type from their configuration.
+///
+/// Functions and closures with the signature of [`create`](Self::create)
+/// implement this trait.
+pub trait DeviceFactory: Send + Sync {
+    /// Creates a device from `cfg`, using the host services of `deps`.
+    fn create(&self, cfg: &EmulatedDeviceConfig, deps: &DeviceDeps) -> AxResult<MmioDevice>;
+
+    /// Checks whether a device can be created from `cfg`, see
+    /// [`ValidateConfig`](crate::ValidateConfig). The default implementation
+    /// accepts all configurations.
+    fn validate(&self, cfg: &EmulatedDeviceConfig) -> Result<(), ConfigError> {
+        let _ = cfg;
+        Ok(())
+    }
+}
+
+impl<F> DeviceFactory for F
+where
+    F: Fn(&EmulatedDeviceConfig, &DeviceDeps) -> AxResult<MmioDevice> + Send + Sync,
+{
+    fn create(&self, cfg: &EmulatedDeviceConfig, deps: &DeviceDeps) -> AxResult<MmioDevice> {
+        self(cfg, deps)
+    }
+}
+
+/// A registry of [`DeviceFactory`]s keyed by [`EmuDeviceType`], creating the
+/// devices of a VM from its configuration.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use axdevice_base::{DeviceRegistry, EmuDeviceType};
+///
+/// let registry = DeviceRegistry::new();
+/// registry.register(EmuDeviceType::Console, Arc::new(Pl011::create))?;
+/// for device in registry.create_all(&vm_config.emu_devices, &deps)? {
+///     mmio.register(device)?;
+/// }
+/// ```
+pub struct DeviceRegistry {
+    factories: RwLock<Vec<(EmuDeviceType, Arc<dyn DeviceFactory>)>>,
+}
+
+impl DeviceRegistry {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            factories: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Registers `factory` to create the devices of type `ty`.
+    ///
+    /// Returns `Err(AxError::AlreadyExists)` if a factory is already
+    /// registered for `ty`.
+    pub fn register(&self, ty: EmuDeviceType, factory: Arc<dyn DeviceFactory>) -> AxResult {
+        let mut factories = self.factories.write();
+        if factories.iter().any(|(other, _)| *other == ty) {
+            return ax_err!(AlreadyExists, "device type already has a factory");
+        }
+        factories.push((ty, factory));
+        Ok(())
+    }
+
+    /// Returns the factory registered for `ty`, if any.
+    pub fn factory(&self, ty: EmuDeviceType) -> Option<Arc<dyn DeviceFactory>> {
+        self.factories
+            .read()
+            .iter()
+            .find(|(other, _)| *other == ty)
+            .map(|(_, factory)| factory.clone())
+    }
+
+    /// Validates `cfg` and creates the device it describes, with the factory
+    /// registered for its [`emu_type`](EmulatedDeviceConfig::emu_type).
+    ///
+    /// Returns `Err(AxError::NotFound)` if no factory is registered for the
+    /// type, `Err(AxError::InvalidInput)` if the factory rejects the
+    /// configuration, and the errors of [`DeviceFactory::create`].
+    pub fn create(&self, cfg: &EmulatedDeviceConfig, deps: &DeviceDeps) -> AxResult<MmioDevice> {
+        let ty = EmuDeviceType::from_usize(cfg.emu_type);
+        let Some(factory) = self.factory(ty) else {
+            return ax_err!(NotFound, "no factory for the device type");
+        };
+        if let Err(err) = factory.validate(cfg) {
+            warn!("invalid configuration of device {:?}: {}", cfg.name, err);
+            return Err(err.into());
+        }
+        factory.create(cfg, deps)
+    }
+
+    /// Creates the devices described by `cfgs`, in order.
+    ///
+    /// Returns the error of the first device that cannot be created.
+    pub fn create_all(
+        &self,
+        cfgs: &[EmulatedDeviceConfig],
+        deps: &DeviceDeps,
+    ) -> AxResult<Vec<MmioDevice>> {
+        cfgs.iter().map(|cfg| self.create(cfg, deps)).collect()
+    }
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A device factory registered at link time with [`register_device_type!`].
+///
+/// [`register_device_type!`]: crate::register_device_type
+#[cfg(feature = "static-registry")]
+#[derive(Clone, Copy)]
+pub struct StaticDeviceFactory {
+    /// The device type created by the factory.
+    pub ty: EmuDeviceType,
+    /// Creates a device, see [`DeviceFactory::create`].
+    pub create: fn(&EmulatedDeviceConfig, &DeviceDeps) -> AxResult<MmioDevice>,
+    /// Validates a configuration, see [`DeviceFactory::validate`].
+    pub validate: fn(&EmulatedDeviceConfig) -> Result<(), ConfigError>,
+}
+
+#[cfg(feature = "static-registry")]
+impl StaticDeviceFactory {
+    /// Creates a factory for `ty` accepting all configurations.
+    pub const fn new(
+        ty: EmuDeviceType,
+        create: fn(&EmulatedDeviceConfig, &DeviceDeps) -> AxResult<MmioDevice>,
+    ) -> Self {
+        Self {
+            ty,
+            create,
+            validate: |_| Ok(()),
+        }
+    }
+
+    /// Sets the configuration check of the factory.
+    pub const fn with_validate(
+        mut self,
+        validate: fn(&EmulatedDeviceConfig) -> Result<(), ConfigError>,
+    ) -> Self {
+        self.validate = validate;
+        self
+    }
+}
+
+#[cfg(feature = "static-registry")]
+impl DeviceFactory for StaticDeviceFactory {
+    fn create(&self, cfg: &EmulatedDeviceConfig, deps: &DeviceDeps) -> AxResult<MmioDevice> {
+        (self.create)(cfg, deps)
+    }
+
+    fn validate(&self, cfg: &EmulatedDeviceConfig) -> Result<(), ConfigError> {
+        (self.validate)(cfg)
+    }
+}
+
+/// The factories registered with [`register_device_type!`] by all crates
+/// linked into the hypervisor.
+///
+/// The entries are collected by the linker into the `linkme_DEVICE_FACTORIES`
+/// section, which custom linker scripts must keep.
+///
+/// [`register_device_type!`]: crate::register_device_type
+#[cfg(feature = "static-registry")]
+#[linkme::distributed_slice]
+pub static DEVICE_FACTORIES: [StaticDeviceFactory];
+
+#[cfg(feature = "static-registry")]
+impl DeviceRegistry {
+    /// Registers all factories of [`DEVICE_FACTORIES`].
+    ///
+    /// Returns `Err(AxError::AlreadyExists)` if a device type has several
+    /// factories, or already has one in this registry.
+    pub fn register_static(&self) -> AxResult {
+        for factory in DEVICE_FACTORIES {
+            self.register(factory.ty, Arc::new(*factory))?;
+        }
+        Ok(())
+    }
+}
+
+/// Registers a device factory at link time, so that linking the crate
+/// defining the device makes its type constructible by
+/// [`DeviceRegistry::register_static`].
+///
+/// The factory is a function with the signature of
+/// [`DeviceFactory::create`], optionally followed by a configuration check
+/// with the signature of [`ValidateConfig::validate_config`].
+///
+/// Requires the `static-registry` feature.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use axdevice_base::{EmuDeviceType, register_device_type};
+///
+/// register_device_type!(EmuDeviceType::Console, Pl011::create, Pl011::validate_config);
+/// ```
+///
+/// [`ValidateConfig::validate_config`]: crate::ValidateConfig::validate_config
+#[cfg(feature = "static-registry")]
+#[macro_export]
+macro_rules! register_device_type {
+    ($ty:expr, $create:expr $(,)?) => {
+        $crate::register_device_type!(@entry $crate::StaticDeviceFactory::new($ty, $create));
+    };
+    ($ty:expr, $create:expr, $validate:expr $(,)?) => {
+        $crate::register_device_type!(
+            @entry $crate::StaticDeviceFactory::new($ty, $create).with_validate($validate)
+        );
+    };
+    (@entry $factory:expr) => {
+        const _: () = {
+            #[$crate::__private::linkme::distributed_slice($crate::DEVICE_FACTORIES)]
+            #[linkme(crate = $crate::__private::linkme)]
+            static FACTORY: $crate::StaticDeviceFactory = $factory;
+        };
+    };
+}
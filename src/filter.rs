@@ -0,0 +1,59 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bus-level policies on the accesses dispatched to devices.
+
+use axaddrspace::device::{AccessWidth, DeviceAddrRange};
+
+use crate::{AccessKind, BaseDeviceOps};
+
+/// The decision of an [`AccessFilter`] on a guest access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    /// The access proceeds, to the next filter and then to the device.
+    Allow,
+    /// The access fails with `AxError::PermissionDenied` without reaching the
+    /// device.
+    Deny,
+    /// A write proceeds with this value instead. A read completes with this
+    /// value without reaching the device.
+    Rewrite(usize),
+}
+
+/// A policy applied by a [`DeviceManager`] to the guest accesses it
+/// dispatches, before the device handles them.
+///
+/// Filters enforce rules centrally without wrapping or modifying the device
+/// models, e.g. "this VM may not set the bus-master bit of a PCI command
+/// register" by rewriting the writes to it. Install a filter with
+/// [`DeviceManager::add_access_filter`]. Filters are called from the accessing
+/// vCPU in the order they were added, with the values in the byte order of
+/// the device, and the first one denying the access or rewriting a read
+/// decides its outcome. A rewritten write is passed on with its new value.
+///
+/// [`DeviceManager`]: crate::DeviceManager
+/// [`DeviceManager::add_access_filter`]: crate::DeviceManager::add_access_filter
+pub trait AccessFilter<R: DeviceAddrRange>: Send + Sync {
+    /// Called before `device` handles an access.
+    ///
+    /// `value` is the value to be written, and 0 for reads.
+    fn filter(
+        &self,
+        device: &dyn BaseDeviceOps<R>,
+        addr: R::Addr,
+        width: AccessWidth,
+        value: usize,
+        kind: AccessKind,
+    ) -> FilterAction;
+}
@@ -0,0 +1,239 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated NOR flash device with a persistence backend.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, RegValue};
+
+/// The value of an erased flash byte.
+const ERASED: u8 = 0xff;
+
+/// Persistent storage backing the contents of a [`FlashDevice`].
+///
+/// The hypervisor implements this to keep flash contents (e.g. firmware
+/// variables) across VM restarts, for example in a host file or a partition.
+pub trait PersistentStore: Send + Sync {
+    /// Fills `buf` with the stored image.
+    ///
+    /// If nothing has been stored yet, the store should fill `buf` with `0xff`,
+    /// the value of erased flash.
+    fn load(&self, buf: &mut [u8]) -> AxResult;
+
+    /// Persists `data` at byte `offset` of the image.
+    fn store(&self, offset: usize, data: &[u8]) -> AxResult;
+}
+
+/// A NOR flash device with sector erase and program semantics.
+///
+/// The flash array is mapped at the start of the device range and can be read
+/// directly. Writes to the array program it: like real NOR flash, programming
+/// can only clear bits, so the new content is `old & val`; setting bits back to
+/// one requires erasing the whole sector. Every change is written through to
+/// the [`PersistentStore`].
+///
+/// A page of 32-bit control registers follows the array:
+///
+/// | Offset        | Name           | Access | Description                       |
+/// |---------------|----------------|--------|-----------------------------------|
+/// | `size + 0x00` | `STATUS`       | RW1C   | Bit 0: ready, bit 1: write to a protected sector, bit 2: store failure. |
+/// | `size + 0x04` | `ERASE`        | WO     | Erases the sector with this index. |
+/// | `size + 0x08` | `SECTOR_SIZE`  | RO     | Size of a sector in bytes.        |
+/// | `size + 0x0c` | `SECTOR_COUNT` | RO     | Number of sectors.                |
+///
+/// Operations complete synchronously, so `STATUS` always reports ready; guests
+/// that poll for completion after each operation work unchanged. Writes and
+/// erases hitting a write-protected sector are ignored and set the protection
+/// error bit, as on real hardware.
+pub struct FlashDevice {
+    range: GuestPhysAddrRange,
+    sector_size: usize,
+    data: Mutex<Vec<u8>>,
+    protected: Mutex<Vec<Range<usize>>>,
+    status: AtomicU32,
+    store: Arc<dyn PersistentStore>,
+}
+
+impl FlashDevice {
+    const CTRL_SIZE: usize = 0x1000;
+    const REG_STATUS: usize = 0x00;
+    const REG_ERASE: usize = 0x04;
+    const REG_SECTOR_SIZE: usize = 0x08;
+    const REG_SECTOR_COUNT: usize = 0x0c;
+
+    /// `STATUS` bit: the device is ready for the next operation.
+    pub const STATUS_READY: u32 = 1 << 0;
+    /// `STATUS` bit: a write or erase hit a write-protected sector.
+    pub const STATUS_PROTECT_ERR: u32 = 1 << 1;
+    /// `STATUS` bit: the persistent store failed to save a change.
+    pub const STATUS_STORE_ERR: u32 = 1 << 2;
+
+    /// Creates a flash device at `base` with `sector_count` sectors of
+    /// `sector_size` bytes, loading its contents from `store`.
+    pub fn new(
+        base: GuestPhysAddr,
+        sector_size: usize,
+        sector_count: usize,
+        store: Arc<dyn PersistentStore>,
+    ) -> AxResult<Self> {
+        let Some(size) = sector_size.checked_mul(sector_count) else {
+            return ax_err!(InvalidInput, "flash size overflow");
+        };
+        let Some(range) = GuestPhysAddrRange::try_from_start_size(base, size + Self::CTRL_SIZE)
+        else {
+            return ax_err!(InvalidInput, "flash range overflow");
+        };
+        let mut data = vec![ERASED; size];
+        store.load(&mut data)?;
+        Ok(Self {
+            range,
+            sector_size,
+            data: Mutex::new(data),
+            protected: Mutex::new(Vec::new()),
+            status: AtomicU32::new(0),
+            store,
+        })
+    }
+
+    /// Returns the size of the flash array in bytes.
+    pub fn size(&self) -> usize {
+        self.range.size() - Self::CTRL_SIZE
+    }
+
+    /// Write-protects or unprotects the given range of sector indices.
+    pub fn set_write_protect(&self, sectors: Range<usize>, protect: bool) {
+        let mut protected = self.protected.lock();
+        protected.retain(|r| r.end <= sectors.start || sectors.end <= r.start);
+        if protect {
+            protected.push(sectors);
+        }
+    }
+
+    /// Returns whether the sector with index `sector` is write-protected.
+    pub fn is_write_protected(&self, sector: usize) -> bool {
+        self.protected.lock().iter().any(|r| r.contains(&sector))
+    }
+
+    fn set_status(&self, bits: u32) {
+        self.status.fetch_or(bits, Ordering::AcqRel);
+    }
+
+    fn persist(&self, offset: usize, data: &[u8]) {
+        if self.store.store(offset, data).is_err() {
+            self.set_status(Self::STATUS_STORE_ERR);
+        }
+    }
+
+    fn program(&self, offset: usize, val: RegValue) {
+        let end = offset + val.width().size();
+        // An unaligned write may straddle two sectors; both must be writable.
+        let mut sectors = offset / self.sector_size..=(end - 1) / self.sector_size;
+        if sectors.any(|sector| self.is_write_protected(sector)) {
+            self.set_status(Self::STATUS_PROTECT_ERR);
+            return;
+        }
+        let mut data = self.data.lock();
+        let cells = &mut data[offset..end];
+        for (cell, byte) in cells.iter_mut().zip(val.to_bytes()) {
+            *cell &= byte;
+        }
+        self.persist(offset, cells);
+    }
+
+    fn erase(&self, sector: usize) {
+        if sector >= self.size() / self.sector_size {
+            return;
+        }
+        if self.is_write_protected(sector) {
+            self.set_status(Self::STATUS_PROTECT_ERR);
+            return;
+        }
+        let start = sector * self.sector_size;
+        let mut data = self.data.lock();
+        let cells = &mut data[start..start + self.sector_size];
+        cells.fill(ERASED);
+        self.persist(start, cells);
+    }
+
+    fn read_ctrl(&self, reg: usize) -> usize {
+        match reg {
+            Self::REG_STATUS => (self.status.load(Ordering::Acquire) | Self::STATUS_READY) as usize,
+            Self::REG_SECTOR_SIZE => self.sector_size,
+            Self::REG_SECTOR_COUNT => self.size() / self.sector_size,
+            _ => 0,
+        }
+    }
+
+    fn write_ctrl(&self, reg: usize, val: usize) {
+        match reg {
+            Self::REG_STATUS => {
+                self.status.fetch_and(!(val as u32), Ordering::AcqRel);
+            }
+            Self::REG_ERASE => self.erase(val),
+            _ => {}
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for FlashDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        if !self.range.contains_access(addr, width) {
+            return ax_err!(BadAddress);
+        }
+        let offset = addr.as_usize() - self.range.start.as_usize();
+        let val = if offset < self.size() {
+            let data = self.data.lock();
+            match data.get(offset..offset + width.size()) {
+                Some(bytes) => RegValue::from_bytes(bytes).unwrap().zero_extend(),
+                None => return ax_err!(BadAddress),
+            }
+        } else {
+            self.read_ctrl(offset - self.size())
+        };
+        Ok(RegValue::new(val, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        if !self.range.contains_access(addr, width) {
+            return ax_err!(BadAddress);
+        }
+        let offset = addr.as_usize() - self.range.start.as_usize();
+        if offset < self.size() {
+            if offset + width.size() > self.size() {
+                return ax_err!(BadAddress);
+            }
+            self.program(offset, RegValue::new(val, width));
+        } else {
+            self.write_ctrl(offset - self.size(), val);
+        }
+        Ok(())
+    }
+}
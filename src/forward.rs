@@ -0,0 +1,320 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Method forwarding for wrapper devices.
+
+/// Implements [`BaseDeviceOps`](crate::BaseDeviceOps) methods of a wrapper
+/// device by calling the same method of the wrapped device.
+///
+/// Used inside the `impl BaseDeviceOps<R>` block of a wrapper, as
+/// `forward_base_device_ops!(R, inner => method, ...)`, where `R` is the range
+/// type parameter and `inner` the field holding the wrapped device. Each entry
+/// is either a method name or one of the groups below, so that a hook added to
+/// the trait only needs to be added to its group to reach every wrapper:
+///
+/// - `identity`: `emu_type`, `address_range`, `holes`.
+/// - `lifecycle`: the power, lifecycle, snapshot and notification hooks, from
+///   `on_time_jump` to `on_memory_layout_changed`.
+/// - `services`: the `set_*` injection methods and `on_timer`.
+/// - `introspection`: `abi_version`, `coalesced_writes`, `stats`,
+///   `dump_registers`, `as_irq_chip`, `capabilities` and `manifest`.
+///
+/// Methods the wrapper intercepts are left out and implemented by hand.
+macro_rules! forward_base_device_ops {
+    ($r:ident, $inner:ident => $($method:ident),+ $(,)?) => {
+        $(forward_base_device_ops!(@ $r, $inner, $method);)+
+    };
+
+    (@ $r:ident, $inner:ident, identity) => {
+        forward_base_device_ops!($r, $inner => emu_type, address_range, holes);
+    };
+    (@ $r:ident, $inner:ident, lifecycle) => {
+        forward_base_device_ops!(
+            $r, $inner => on_time_jump, reset, pause, resume, shutdown, power_state,
+            set_power_state, save_state, load_state, on_domain_event, on_vcpu_added,
+            on_memory_layout_changed,
+        );
+    };
+    (@ $r:ident, $inner:ident, services) => {
+        forward_base_device_ops!(
+            $r, $inner => set_dma_accessor, set_completer, set_region_sink, set_timer_service,
+            set_clock_source, on_timer,
+        );
+    };
+    (@ $r:ident, $inner:ident, introspection) => {
+        forward_base_device_ops!(
+            $r, $inner => abi_version, coalesced_writes, stats, dump_registers, as_irq_chip,
+            capabilities, manifest,
+        );
+    };
+
+    (@ $r:ident, $inner:ident, emu_type) => {
+        fn emu_type(&self) -> $crate::EmuDeviceType {
+            self.$inner.emu_type()
+        }
+    };
+    (@ $r:ident, $inner:ident, address_range) => {
+        fn address_range(&self) -> $r {
+            self.$inner.address_range()
+        }
+    };
+    (@ $r:ident, $inner:ident, holes) => {
+        fn holes(&self) -> ::alloc::vec::Vec<$r> {
+            self.$inner.holes()
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_read) => {
+        fn handle_read(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+        ) -> ::axerrno::AxResult<usize> {
+            self.$inner.handle_read(addr, width)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_write) => {
+        fn handle_write(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+            val: usize,
+        ) -> ::axerrno::AxResult {
+            self.$inner.handle_write(addr, width, val)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_read_ctx) => {
+        fn handle_read_ctx(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+            ctx: $crate::AccessContext,
+        ) -> ::axerrno::AxResult<usize> {
+            self.$inner.handle_read_ctx(addr, width, ctx)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_write_ctx) => {
+        fn handle_write_ctx(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+            val: usize,
+            ctx: $crate::AccessContext,
+        ) -> ::axerrno::AxResult {
+            self.$inner.handle_write_ctx(addr, width, val, ctx)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_read_bulk) => {
+        fn handle_read_bulk(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+            count: usize,
+            stride: isize,
+            buf: &mut [u8],
+        ) -> ::axerrno::AxResult
+        where
+            $r::Addr: $crate::RawDeviceAddr,
+        {
+            self.$inner.handle_read_bulk(addr, width, count, stride, buf)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_write_bulk) => {
+        fn handle_write_bulk(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+            count: usize,
+            stride: isize,
+            buf: &[u8],
+        ) -> ::axerrno::AxResult
+        where
+            $r::Addr: $crate::RawDeviceAddr,
+        {
+            self.$inner.handle_write_bulk(addr, width, count, stride, buf)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_read_async) => {
+        fn handle_read_async(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+        ) -> ::axerrno::AxResult<$crate::AccessOutcome> {
+            self.$inner.handle_read_async(addr, width)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_write_async) => {
+        fn handle_write_async(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+            val: usize,
+        ) -> ::axerrno::AxResult<$crate::AccessOutcome> {
+            self.$inner.handle_write_async(addr, width, val)
+        }
+    };
+    (@ $r:ident, $inner:ident, handle_call) => {
+        fn handle_call(&self, addr: $r::Addr, args: &[usize]) -> ::axerrno::AxResult<[usize; 4]> {
+            self.$inner.handle_call(addr, args)
+        }
+    };
+    (@ $r:ident, $inner:ident, peek) => {
+        fn peek(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+        ) -> ::axerrno::AxResult<usize> {
+            self.$inner.peek(addr, width)
+        }
+    };
+    (@ $r:ident, $inner:ident, poke) => {
+        fn poke(
+            &self,
+            addr: $r::Addr,
+            width: ::axaddrspace::device::AccessWidth,
+            val: usize,
+        ) -> ::axerrno::AxResult {
+            self.$inner.poke(addr, width, val)
+        }
+    };
+    (@ $r:ident, $inner:ident, abi_version) => {
+        fn abi_version(&self) -> u32 {
+            self.$inner.abi_version()
+        }
+    };
+    (@ $r:ident, $inner:ident, on_time_jump) => {
+        fn on_time_jump(&self, delta_ns: u64, policy: $crate::CatchUpPolicy) {
+            self.$inner.on_time_jump(delta_ns, policy)
+        }
+    };
+    (@ $r:ident, $inner:ident, reset) => {
+        fn reset(&self) -> ::axerrno::AxResult {
+            self.$inner.reset()
+        }
+    };
+    (@ $r:ident, $inner:ident, pause) => {
+        fn pause(&self) -> ::axerrno::AxResult {
+            self.$inner.pause()
+        }
+    };
+    (@ $r:ident, $inner:ident, resume) => {
+        fn resume(&self) -> ::axerrno::AxResult {
+            self.$inner.resume()
+        }
+    };
+    (@ $r:ident, $inner:ident, shutdown) => {
+        fn shutdown(&self) -> ::axerrno::AxResult {
+            self.$inner.shutdown()
+        }
+    };
+    (@ $r:ident, $inner:ident, power_state) => {
+        fn power_state(&self) -> $crate::PowerState {
+            self.$inner.power_state()
+        }
+    };
+    (@ $r:ident, $inner:ident, set_power_state) => {
+        fn set_power_state(&self, state: $crate::PowerState) -> ::axerrno::AxResult {
+            self.$inner.set_power_state(state)
+        }
+    };
+    (@ $r:ident, $inner:ident, save_state) => {
+        fn save_state(&self) -> ::axerrno::AxResult<::alloc::vec::Vec<u8>> {
+            self.$inner.save_state()
+        }
+    };
+    (@ $r:ident, $inner:ident, load_state) => {
+        fn load_state(&self, state: &[u8]) -> ::axerrno::AxResult {
+            self.$inner.load_state(state)
+        }
+    };
+    (@ $r:ident, $inner:ident, on_domain_event) => {
+        fn on_domain_event(&self, event: $crate::DomainEvent) -> ::axerrno::AxResult {
+            self.$inner.on_domain_event(event)
+        }
+    };
+    (@ $r:ident, $inner:ident, on_vcpu_added) => {
+        fn on_vcpu_added(&self, index: usize) -> ::axerrno::AxResult {
+            self.$inner.on_vcpu_added(index)
+        }
+    };
+    (@ $r:ident, $inner:ident, on_memory_layout_changed) => {
+        fn on_memory_layout_changed(&self, change: $crate::MemoryLayoutChange) {
+            self.$inner.on_memory_layout_changed(change)
+        }
+    };
+    (@ $r:ident, $inner:ident, set_dma_accessor) => {
+        fn set_dma_accessor(
+            &self,
+            accessor: ::alloc::sync::Arc<dyn $crate::GuestMemoryAccessor>,
+        ) {
+            self.$inner.set_dma_accessor(accessor)
+        }
+    };
+    (@ $r:ident, $inner:ident, set_completer) => {
+        fn set_completer(&self, completer: ::alloc::sync::Arc<dyn $crate::AccessCompleter>) {
+            self.$inner.set_completer(completer)
+        }
+    };
+    (@ $r:ident, $inner:ident, set_region_sink) => {
+        fn set_region_sink(&self, sink: ::alloc::sync::Arc<dyn $crate::RegionUpdateSink>) {
+            self.$inner.set_region_sink(sink)
+        }
+    };
+    (@ $r:ident, $inner:ident, set_timer_service) => {
+        fn set_timer_service(&self, service: ::alloc::sync::Arc<dyn $crate::TimerService>) {
+            self.$inner.set_timer_service(service)
+        }
+    };
+    (@ $r:ident, $inner:ident, set_clock_source) => {
+        fn set_clock_source(&self, clock: ::alloc::sync::Arc<dyn $crate::ClockSource>) {
+            self.$inner.set_clock_source(clock)
+        }
+    };
+    (@ $r:ident, $inner:ident, on_timer) => {
+        fn on_timer(&self, token: $crate::TimerToken) -> bool {
+            self.$inner.on_timer(token)
+        }
+    };
+    (@ $r:ident, $inner:ident, coalesced_writes) => {
+        fn coalesced_writes(&self) -> Option<&$crate::CoalescedWriteRing<$r>> {
+            self.$inner.coalesced_writes()
+        }
+    };
+    (@ $r:ident, $inner:ident, stats) => {
+        fn stats(&self) -> Option<$crate::DeviceStats<$r>> {
+            self.$inner.stats()
+        }
+    };
+    (@ $r:ident, $inner:ident, dump_registers) => {
+        fn dump_registers(&self) -> ::alloc::vec::Vec<$crate::RegisterDump> {
+            self.$inner.dump_registers()
+        }
+    };
+    (@ $r:ident, $inner:ident, as_irq_chip) => {
+        fn as_irq_chip(&self) -> Option<&dyn $crate::VirtualIrqChip> {
+            self.$inner.as_irq_chip()
+        }
+    };
+    (@ $r:ident, $inner:ident, capabilities) => {
+        fn capabilities(&self) -> $crate::CapabilitySet {
+            self.$inner.capabilities()
+        }
+    };
+    (@ $r:ident, $inner:ident, manifest) => {
+        fn manifest(&self) -> $crate::DeviceManifest {
+            self.$inner.manifest()
+        }
+    };
+}
+
+pub(crate) use forward_base_device_ops;
@@ -0,0 +1,319 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A fixed-mode framebuffer in guest RAM with dirty-tile tracking.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    BaseDeviceOps, Capability, CapabilitySet, DeviceAddrRangeExt, EmuDeviceType,
+    GuestMemoryAccessor, RegValue,
+};
+
+/// A rectangle of a framebuffer, in pixels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    /// The left edge.
+    pub x: u32,
+    /// The top edge.
+    pub y: u32,
+    /// The width.
+    pub width: u32,
+    /// The height.
+    pub height: u32,
+}
+
+/// A host-side display consumer of a [`FramebufferDevice`], such as a VNC
+/// server or a window on the host.
+pub trait DisplayConsumer: Send + Sync {
+    /// Called from the guest vCPU when it reports changes to the framebuffer.
+    ///
+    /// The consumer collects the changed tiles with
+    /// [`FramebufferDevice::take_dirty`] and copies them with
+    /// [`FramebufferDevice::read_rect`], typically from its own thread.
+    fn on_damage(&self);
+}
+
+struct FramebufferState {
+    /// The value written to `FB_LO`/`FB_HI`.
+    fb_reg: u64,
+    /// The guest address of the framebuffer, once enabled by the guest.
+    fb: Option<GuestPhysAddr>,
+    /// The damage rectangle being reported, as written to `DAMAGE_XY` and
+    /// `DAMAGE_WH`.
+    damage: DirtyRect,
+    /// One bit per tile, row by row.
+    dirty: Vec<u64>,
+}
+
+/// A simple framebuffer device with a fixed mode, whose pixels live in guest
+/// RAM so that the guest draws without trapping.
+///
+/// The guest allocates `stride * height` bytes of RAM and registers their
+/// address. After drawing, it reports the changed rectangle with the damage
+/// registers. The device keeps the damage as a bitmap of
+/// [`TILE`](Self::TILE)-pixel square tiles and notifies the
+/// [`DisplayConsumer`], which only copies the changed tiles out of guest RAM
+/// through the [`GuestMemoryAccessor`] injected with
+/// [`set_dma_accessor`](BaseDeviceOps::set_dma_accessor). Registers:
+///
+/// | Offset | Name        | Access | Description                                  |
+/// |--------|-------------|--------|----------------------------------------------|
+/// | 0x00   | `FB_LO`     | RW     | Bits 31:12 of the framebuffer address; bit 0 enables the display. |
+/// | 0x04   | `FB_HI`     | RW     | Bits 63:32 of the framebuffer address.       |
+/// | 0x08   | `WIDTH`     | RO     | Width in pixels.                             |
+/// | 0x0c   | `HEIGHT`    | RO     | Height in pixels.                            |
+/// | 0x10   | `STRIDE`    | RO     | Bytes per row.                               |
+/// | 0x14   | `FORMAT`    | RO     | DRM fourcc of the pixels, `XR24` (XRGB8888). |
+/// | 0x18   | `DAMAGE_XY` | RW     | Left edge in bits 15:0, top edge in bits 31:16. |
+/// | 0x1c   | `DAMAGE_WH` | RW     | Width in bits 15:0, height in bits 31:16.    |
+/// | 0x20   | `DAMAGE`    | WO     | Any write marks the damage rectangle dirty.  |
+///
+/// The guest writes `FB_HI` first, and the write to `FB_LO` takes effect;
+/// enabling the display marks the whole framebuffer dirty.
+pub struct FramebufferDevice {
+    range: GuestPhysAddrRange,
+    width: u32,
+    height: u32,
+    state: Mutex<FramebufferState>,
+    consumer: Mutex<Option<Arc<dyn DisplayConsumer>>>,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+}
+
+impl FramebufferDevice {
+    const REG_FB_LO: usize = 0x00;
+    const REG_FB_HI: usize = 0x04;
+    const REG_WIDTH: usize = 0x08;
+    const REG_HEIGHT: usize = 0x0c;
+    const REG_STRIDE: usize = 0x10;
+    const REG_FORMAT: usize = 0x14;
+    const REG_DAMAGE_XY: usize = 0x18;
+    const REG_DAMAGE_WH: usize = 0x1c;
+    const REG_DAMAGE: usize = 0x20;
+
+    /// `FB_LO` bit: the display is enabled.
+    pub const FB_ENABLE: u64 = 1 << 0;
+
+    /// The DRM fourcc of the XRGB8888 pixel format.
+    pub const FORMAT_XRGB8888: u32 = u32::from_le_bytes(*b"XR24");
+
+    /// The bytes per pixel.
+    pub const BYTES_PER_PIXEL: usize = 4;
+
+    /// The size in pixels of the square tiles dirt is tracked in.
+    pub const TILE: u32 = 64;
+
+    /// Creates a `width` x `height` framebuffer device at `base`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if a dimension is 0 or does not
+    /// fit the 16-bit damage registers.
+    pub fn new(base: GuestPhysAddr, width: u32, height: u32) -> AxResult<Self> {
+        if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return ax_err!(InvalidInput, "invalid framebuffer mode");
+        }
+        let tiles = (width.div_ceil(Self::TILE) * height.div_ceil(Self::TILE)) as usize;
+        Ok(Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x1000),
+            width,
+            height,
+            state: Mutex::new(FramebufferState {
+                fb_reg: 0,
+                fb: None,
+                damage: DirtyRect::default(),
+                dirty: vec![0; tiles.div_ceil(64)],
+            }),
+            consumer: Mutex::new(None),
+            dma: Mutex::new(None),
+        })
+    }
+
+    /// Returns the bytes per row of the framebuffer.
+    pub fn stride(&self) -> usize {
+        self.width as usize * Self::BYTES_PER_PIXEL
+    }
+
+    /// Returns the guest address of the framebuffer enabled by the guest, if
+    /// any.
+    pub fn framebuffer(&self) -> Option<GuestPhysAddr> {
+        self.state.lock().fb
+    }
+
+    /// Sets the display consumer notified of damage, or removes it if `None`.
+    pub fn set_consumer(&self, consumer: Option<Arc<dyn DisplayConsumer>>) {
+        *self.consumer.lock() = consumer;
+    }
+
+    /// Returns the dirty tiles, clipped to the framebuffer, and marks them
+    /// clean.
+    pub fn take_dirty(&self) -> Vec<DirtyRect> {
+        let mut state = self.state.lock();
+        let columns = self.width.div_ceil(Self::TILE);
+        let mut rects = Vec::new();
+        for (word_index, word) in state.dirty.iter_mut().enumerate() {
+            let mut bits = core::mem::take(word);
+            while bits != 0 {
+                let tile = (word_index * 64) as u32 + bits.trailing_zeros();
+                bits &= bits - 1;
+                let (x, y) = ((tile % columns) * Self::TILE, (tile / columns) * Self::TILE);
+                rects.push(DirtyRect {
+                    x,
+                    y,
+                    width: Self::TILE.min(self.width - x),
+                    height: Self::TILE.min(self.height - y),
+                });
+            }
+        }
+        rects
+    }
+
+    /// Copies the pixels of `rect` out of guest RAM into `buf`, row by row
+    /// without padding.
+    ///
+    /// Returns `Err(AxError::BadState)` if the display is disabled or no DMA
+    /// accessor has been injected, and `Err(AxError::InvalidInput)` if `rect`
+    /// is not within the framebuffer or `buf` does not match its size.
+    pub fn read_rect(&self, rect: DirtyRect, buf: &mut [u8]) -> AxResult {
+        let (Some(fb), Some(mem)) = (self.framebuffer(), self.dma.lock().clone()) else {
+            return ax_err!(BadState, "framebuffer disabled or without DMA accessor");
+        };
+        let row_len = rect.width as usize * Self::BYTES_PER_PIXEL;
+        if rect
+            .x
+            .checked_add(rect.width)
+            .is_none_or(|right| right > self.width)
+            || rect
+                .y
+                .checked_add(rect.height)
+                .is_none_or(|bottom| bottom > self.height)
+            || buf.len() != row_len * rect.height as usize
+        {
+            return ax_err!(InvalidInput, "bad framebuffer rectangle");
+        }
+        if row_len == 0 {
+            return Ok(());
+        }
+        for (row, line) in buf.chunks_exact_mut(row_len).enumerate() {
+            let offset =
+                (rect.y as usize + row) * self.stride() + rect.x as usize * Self::BYTES_PER_PIXEL;
+            mem.read(fb + offset, line)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the tiles intersecting `rect`, clipped to the framebuffer, dirty.
+    fn mark_dirty(&self, state: &mut FramebufferState, rect: DirtyRect) {
+        let right = rect.x.saturating_add(rect.width).min(self.width);
+        let bottom = rect.y.saturating_add(rect.height).min(self.height);
+        if rect.x >= right || rect.y >= bottom {
+            return;
+        }
+        let columns = self.width.div_ceil(Self::TILE);
+        for row in rect.y / Self::TILE..bottom.div_ceil(Self::TILE) {
+            for column in rect.x / Self::TILE..right.div_ceil(Self::TILE) {
+                let tile = (row * columns + column) as usize;
+                state.dirty[tile / 64] |= 1 << (tile % 64);
+            }
+        }
+    }
+
+    fn notify(&self) {
+        if let Some(consumer) = self.consumer.lock().clone() {
+            consumer.on_damage();
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for FramebufferDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let state = self.state.lock();
+        let val = match self.range.offset_of(addr) {
+            Some(Self::REG_FB_LO) => state.fb_reg as u32,
+            Some(Self::REG_FB_HI) => (state.fb_reg >> 32) as u32,
+            Some(Self::REG_WIDTH) => self.width,
+            Some(Self::REG_HEIGHT) => self.height,
+            Some(Self::REG_STRIDE) => self.stride() as u32,
+            Some(Self::REG_FORMAT) => Self::FORMAT_XRGB8888,
+            Some(Self::REG_DAMAGE_XY) => state.damage.x | state.damage.y << 16,
+            Some(Self::REG_DAMAGE_WH) => state.damage.width | state.damage.height << 16,
+            _ => 0,
+        };
+        Ok(RegValue::new(val as usize, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        let mut state = self.state.lock();
+        let val = val as u32;
+        let (low, high) = (val & 0xffff, val >> 16);
+        match self.range.offset_of(addr) {
+            Some(Self::REG_FB_HI) => {
+                state.fb_reg = (state.fb_reg & 0xffff_ffff) | (val as u64) << 32;
+            }
+            Some(Self::REG_FB_LO) => {
+                state.fb_reg = (state.fb_reg & !0xffff_ffff) | val as u64;
+                state.fb = (state.fb_reg & Self::FB_ENABLE != 0)
+                    .then(|| GuestPhysAddr::from(state.fb_reg as usize & !0xfff));
+                if state.fb.is_some() {
+                    let whole = DirtyRect {
+                        x: 0,
+                        y: 0,
+                        width: self.width,
+                        height: self.height,
+                    };
+                    self.mark_dirty(&mut state, whole);
+                    drop(state);
+                    self.notify();
+                }
+            }
+            Some(Self::REG_DAMAGE_XY) => (state.damage.x, state.damage.y) = (low, high),
+            Some(Self::REG_DAMAGE_WH) => (state.damage.width, state.damage.height) = (low, high),
+            Some(Self::REG_DAMAGE) => {
+                let damage = state.damage;
+                self.mark_dirty(&mut state, damage);
+                drop(state);
+                self.notify();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> AxResult {
+        let mut state = self.state.lock();
+        state.fb_reg = 0;
+        state.fb = None;
+        state.damage = DirtyRect::default();
+        state.dirty.fill(0);
+        Ok(())
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        Capability::Reset.into()
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Addressing of firmware interfaces called through SMC, HVC or SBI `ecall`.
+
+use axaddrspace::device::{DeviceAddr, DeviceAddrRange};
+
+use crate::{DeviceAddrRangeExt, RawDeviceAddr};
+
+/// Identifies a firmware function called by the guest.
+///
+/// On Arm this is the SMCCC function identifier passed in `w0`. On RISC-V it
+/// is the SBI extension ID passed in `a7`; the SBI function ID in `a6` is
+/// passed to the device as the first call argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HypercallId(pub u32);
+
+impl HypercallId {
+    /// Creates a function identifier from its raw value.
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl DeviceAddr for HypercallId {}
+
+impl RawDeviceAddr for HypercallId {
+    fn to_raw(self) -> usize {
+        self.0 as usize
+    }
+
+    fn from_raw(raw: usize) -> Option<Self> {
+        u32::try_from(raw).ok().map(Self)
+    }
+}
+
+/// An inclusive range of firmware function identifiers, usually the functions
+/// of one SMCCC service or one SBI extension.
+///
+/// # Example
+///
+/// ```rust
+/// use axaddrspace::device::DeviceAddrRange;
+/// use axdevice_base::{HypercallId, HypercallRange};
+///
+/// // The SMC32 PSCI functions.
+/// let psci = HypercallRange::new(HypercallId(0x8400_0000), HypercallId(0x8400_001f));
+/// assert!(psci.contains(HypercallId(0x8400_0003)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HypercallRange {
+    /// The first function of the range.
+    pub start: HypercallId,
+    /// The last function of the range.
+    pub end: HypercallId,
+}
+
+impl HypercallRange {
+    /// Creates the range of functions from `start` to `end`, inclusive.
+    pub const fn new(start: HypercallId, end: HypercallId) -> Self {
+        Self { start, end }
+    }
+
+    /// Creates the range holding the single function `id`.
+    pub const fn single(id: HypercallId) -> Self {
+        Self { start: id, end: id }
+    }
+}
+
+impl DeviceAddrRange for HypercallRange {
+    type Addr = HypercallId;
+
+    fn contains(&self, addr: HypercallId) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+impl DeviceAddrRangeExt for HypercallRange {
+    fn raw_bounds(&self) -> (usize, usize) {
+        (self.start.to_raw(), self.end.to_raw() + 1)
+    }
+
+    fn from_raw_bounds(start: usize, end: usize) -> Option<Self> {
+        if start >= end {
+            return None;
+        }
+        Some(Self {
+            start: HypercallId::from_raw(start)?,
+            end: HypercallId::from_raw(end - 1)?,
+        })
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! I2C controller emulation with attached slave devices.
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::AxResult;
+use spin::Mutex;
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, RegValue, SlaveRegistry};
+
+/// A device attached to an emulated I2C (or SMBus) bus.
+pub trait I2cSlave: Send + Sync {
+    /// Called when the controller addresses this slave with a START or
+    /// repeated START condition. `read` is the direction of the transfer.
+    ///
+    /// Returning an error NACKs the address.
+    fn start(&self, read: bool) -> AxResult {
+        let _ = read;
+        Ok(())
+    }
+
+    /// Receives one byte written by the controller.
+    ///
+    /// Returning an error NACKs the byte.
+    fn write(&self, byte: u8) -> AxResult;
+
+    /// Returns the next byte read by the controller.
+    fn read(&self) -> u8;
+
+    /// Called on a STOP condition ending the transfer.
+    fn stop(&self) {}
+}
+
+/// An I2C bus, mapping 7-bit or 10-bit slave addresses to slave devices.
+pub type I2cBus = SlaveRegistry<u16, dyn I2cSlave>;
+
+struct I2cState {
+    con: u32,
+    tar: u32,
+    enable: bool,
+    intr_mask: u32,
+    raw_intr: u32,
+    abort_source: u32,
+    rx_fifo: VecDeque<u8>,
+    active: Option<(Arc<dyn I2cSlave>, bool)>,
+}
+
+/// An I2C controller exposing a subset of the DesignWare `DW_apb_i2c`
+/// register interface in master mode.
+///
+/// Transfers are executed synchronously as the guest writes `IC_DATA_CMD`, so
+/// the transmit FIFO is always empty and received bytes are available in the
+/// receive FIFO right away. A missing slave or a NACK aborts the transfer and
+/// is reported through `IC_RAW_INTR_STAT.TX_ABRT` and `IC_TX_ABRT_SOURCE`, as
+/// on hardware. Interrupt status bits are maintained, but there is no
+/// interrupt line, so guest drivers must poll.
+pub struct I2cControllerBase {
+    range: GuestPhysAddrRange,
+    bus: Arc<I2cBus>,
+    state: Mutex<I2cState>,
+}
+
+impl I2cControllerBase {
+    const IC_CON: usize = 0x00;
+    const IC_TAR: usize = 0x04;
+    const IC_DATA_CMD: usize = 0x10;
+    const IC_INTR_STAT: usize = 0x2c;
+    const IC_INTR_MASK: usize = 0x30;
+    const IC_RAW_INTR_STAT: usize = 0x34;
+    const IC_CLR_INTR: usize = 0x40;
+    const IC_CLR_TX_ABRT: usize = 0x54;
+    const IC_CLR_STOP_DET: usize = 0x60;
+    const IC_ENABLE: usize = 0x6c;
+    const IC_STATUS: usize = 0x70;
+    const IC_TXFLR: usize = 0x74;
+    const IC_RXFLR: usize = 0x78;
+    const IC_TX_ABRT_SOURCE: usize = 0x80;
+    const IC_ENABLE_STATUS: usize = 0x9c;
+    const IC_COMP_PARAM_1: usize = 0xf4;
+    const IC_COMP_VERSION: usize = 0xf8;
+    const IC_COMP_TYPE: usize = 0xfc;
+
+    const FIFO_DEPTH: usize = 16;
+
+    const CON_10BITADDR_MASTER: u32 = 1 << 4;
+    const CMD_READ: usize = 1 << 8;
+    const CMD_STOP: usize = 1 << 9;
+    const CMD_RESTART: usize = 1 << 10;
+
+    const INTR_RX_OVER: u32 = 1 << 1;
+    const INTR_RX_FULL: u32 = 1 << 2;
+    const INTR_TX_EMPTY: u32 = 1 << 4;
+    const INTR_TX_ABRT: u32 = 1 << 6;
+    const INTR_ACTIVITY: u32 = 1 << 8;
+    const INTR_STOP_DET: u32 = 1 << 9;
+
+    const ABRT_7B_ADDR_NOACK: u32 = 1 << 0;
+    const ABRT_10ADDR1_NOACK: u32 = 1 << 1;
+    const ABRT_TXDATA_NOACK: u32 = 1 << 3;
+
+    /// Creates a controller at `base` driving the slaves attached to `bus`.
+    pub fn new(base: GuestPhysAddr, bus: Arc<I2cBus>) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x100),
+            bus,
+            state: Mutex::new(I2cState {
+                con: 0,
+                tar: 0,
+                enable: false,
+                intr_mask: 0,
+                raw_intr: 0,
+                abort_source: 0,
+                rx_fifo: VecDeque::with_capacity(Self::FIFO_DEPTH),
+                active: None,
+            }),
+        }
+    }
+
+    /// Returns the bus of the controller.
+    pub fn bus(&self) -> &Arc<I2cBus> {
+        &self.bus
+    }
+
+    fn raw_intr(state: &I2cState) -> u32 {
+        let mut raw = state.raw_intr | Self::INTR_TX_EMPTY;
+        if !state.rx_fifo.is_empty() {
+            raw |= Self::INTR_RX_FULL;
+        }
+        if state.active.is_some() {
+            raw |= Self::INTR_ACTIVITY;
+        }
+        raw
+    }
+
+    fn end_transfer(state: &mut I2cState) {
+        if let Some((slave, _)) = state.active.take() {
+            slave.stop();
+            state.raw_intr |= Self::INTR_STOP_DET;
+        }
+    }
+
+    fn abort(state: &mut I2cState, source: u32) {
+        Self::end_transfer(state);
+        state.raw_intr |= Self::INTR_TX_ABRT;
+        state.abort_source |= source;
+    }
+
+    fn data_cmd(&self, state: &mut I2cState, cmd: usize) {
+        // The hardware flushes the TX FIFO until the abort is cleared.
+        if !state.enable || state.raw_intr & Self::INTR_TX_ABRT != 0 {
+            return;
+        }
+        let read = cmd & Self::CMD_READ != 0;
+        let need_start = match &state.active {
+            Some((_, dir)) => *dir != read || cmd & Self::CMD_RESTART != 0,
+            None => true,
+        };
+        if need_start {
+            let (addr, noack) = if state.con & Self::CON_10BITADDR_MASTER != 0 {
+                (state.tar & 0x3ff, Self::ABRT_10ADDR1_NOACK)
+            } else {
+                (state.tar & 0x7f, Self::ABRT_7B_ADDR_NOACK)
+            };
+            let Some(slave) = self.bus.get(addr as u16) else {
+                return Self::abort(state, noack);
+            };
+            if slave.start(read).is_err() {
+                return Self::abort(state, noack);
+            }
+            state.active = Some((slave, read));
+        }
+        let (slave, _) = state.active.as_ref().unwrap();
+        if read {
+            let byte = slave.read();
+            if state.rx_fifo.len() < Self::FIFO_DEPTH {
+                state.rx_fifo.push_back(byte);
+            } else {
+                state.raw_intr |= Self::INTR_RX_OVER;
+            }
+        } else if slave.write(cmd as u8).is_err() {
+            return Self::abort(state, Self::ABRT_TXDATA_NOACK);
+        }
+        if cmd & Self::CMD_STOP != 0 {
+            Self::end_transfer(state);
+        }
+    }
+
+    fn read_reg(&self, reg: usize) -> u32 {
+        let mut state = self.state.lock();
+        match reg {
+            Self::IC_CON => state.con,
+            Self::IC_TAR => state.tar,
+            Self::IC_DATA_CMD => state.rx_fifo.pop_front().unwrap_or(0) as u32,
+            Self::IC_INTR_STAT => Self::raw_intr(&state) & state.intr_mask,
+            Self::IC_INTR_MASK => state.intr_mask,
+            Self::IC_RAW_INTR_STAT => Self::raw_intr(&state),
+            Self::IC_CLR_INTR => {
+                state.raw_intr = 0;
+                state.abort_source = 0;
+                0
+            }
+            Self::IC_CLR_TX_ABRT => {
+                state.raw_intr &= !Self::INTR_TX_ABRT;
+                state.abort_source = 0;
+                0
+            }
+            Self::IC_CLR_STOP_DET => {
+                state.raw_intr &= !Self::INTR_STOP_DET;
+                0
+            }
+            Self::IC_ENABLE | Self::IC_ENABLE_STATUS => state.enable as u32,
+            Self::IC_STATUS => {
+                // TFE | TFNF, plus RFNE and ACTIVITY.
+                let mut status = 0b110;
+                if !state.rx_fifo.is_empty() {
+                    status |= 1 << 3;
+                }
+                if state.active.is_some() {
+                    status |= 1;
+                }
+                status
+            }
+            Self::IC_TXFLR => 0,
+            Self::IC_RXFLR => state.rx_fifo.len() as u32,
+            Self::IC_TX_ABRT_SOURCE => state.abort_source,
+            Self::IC_COMP_PARAM_1 => {
+                let depth = (Self::FIFO_DEPTH - 1) as u32;
+                (depth << 16) | (depth << 8)
+            }
+            Self::IC_COMP_VERSION => 0x3230_312a,
+            Self::IC_COMP_TYPE => 0x4457_0140,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&self, reg: usize, val: usize) {
+        let mut state = self.state.lock();
+        match reg {
+            Self::IC_CON => state.con = val as u32,
+            Self::IC_TAR => state.tar = val as u32,
+            Self::IC_DATA_CMD => self.data_cmd(&mut state, val),
+            Self::IC_INTR_MASK => state.intr_mask = val as u32,
+            Self::IC_ENABLE => {
+                state.enable = val & 1 != 0;
+                if !state.enable {
+                    Self::end_transfer(&mut state);
+                    state.rx_fifo.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for I2cControllerBase {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let val = match self.range.offset_of(addr) {
+            Some(reg) => self.read_reg(reg),
+            None => 0,
+        };
+        Ok(RegValue::new(val as usize, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        if let Some(reg) = self.range.offset_of(addr) {
+            self.write_reg(reg, val);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fault injection for testing guest drivers and hypervisor error paths.
+
+use alloc::sync::Arc;
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use axaddrspace::device::{AccessWidth, DeviceAddrRange};
+use axerrno::{AxError, AxResult};
+use spin::Mutex;
+
+use crate::{
+    AccessCompleter, AccessContext, AccessOutcome, BaseDeviceOps, CompletionToken, RawDeviceAddr,
+    bulk_element_value, forward::forward_base_device_ops,
+};
+
+#[derive(Default)]
+struct InjectorState {
+    accesses: u64,
+    fail_at: Option<(u64, AxError)>,
+    corrupt_mask: usize,
+}
+
+/// Forwards completions to the real completer unless they are dropped.
+struct DroppingCompleter {
+    inner: Arc<dyn AccessCompleter>,
+    drop: Arc<AtomicBool>,
+}
+
+impl AccessCompleter for DroppingCompleter {
+    fn complete(&self, token: CompletionToken, result: AxResult<usize>) {
+        if !self.drop.load(Ordering::Relaxed) {
+            self.inner.complete(token, result);
+        }
+    }
+}
+
+/// A wrapper injecting faults into the accesses handled by a device.
+///
+/// The injector can fail a given access with a chosen error, corrupt the
+/// values read from the device, and drop the asynchronous completions the
+/// device reports (see [`AccessCompleter`]), which lets tests exercise the
+/// error paths of guest drivers and of the hypervisor. With no fault
+/// programmed, the wrapper is transparent.
+///
+/// A bulk access of `count` elements counts as `count` accesses, and fails as
+/// a whole if one of them is the access to fail.
+pub struct ErrorInjector<R, D> {
+    inner: D,
+    state: Mutex<InjectorState>,
+    drop_completions: Arc<AtomicBool>,
+    _range: PhantomData<fn() -> R>,
+}
+
+impl<R: DeviceAddrRange, D: BaseDeviceOps<R>> ErrorInjector<R, D> {
+    /// Wraps `inner`, with no fault programmed.
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(InjectorState::default()),
+            drop_completions: Arc::new(AtomicBool::new(false)),
+            _range: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Makes the `n`-th access from now (1-based) fail with `error`, without
+    /// reaching the device.
+    pub fn fail_nth(&self, n: u64, error: AxError) {
+        let mut state = self.state.lock();
+        state.fail_at = Some((state.accesses + n.max(1), error));
+    }
+
+    /// Flips the bits of `mask` in every value read from the device, or stops
+    /// corrupting reads if `mask` is 0.
+    pub fn corrupt_reads(&self, mask: usize) {
+        self.state.lock().corrupt_mask = mask;
+    }
+
+    /// Sets whether the asynchronous completions reported by the device are
+    /// dropped.
+    pub fn drop_completions(&self, drop: bool) {
+        self.drop_completions.store(drop, Ordering::Relaxed);
+    }
+
+    /// Returns the number of accesses seen by the injector.
+    pub fn accesses(&self) -> u64 {
+        self.state.lock().accesses
+    }
+
+    /// Removes all programmed faults.
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        state.fail_at = None;
+        state.corrupt_mask = 0;
+        self.drop_completions.store(false, Ordering::Relaxed);
+    }
+
+    /// Counts an access, returning the error to inject, if any.
+    fn check(&self) -> AxResult {
+        self.check_n(1)
+    }
+
+    /// Counts `n` accesses, returning the error to inject if one of them is
+    /// the access to fail.
+    fn check_n(&self, n: u64) -> AxResult {
+        let mut state = self.state.lock();
+        let first = state.accesses + 1;
+        state.accesses += n;
+        match state.fail_at {
+            Some((at, error)) if (first..=state.accesses).contains(&at) => {
+                state.fail_at = None;
+                Err(error)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn corrupt(&self, val: usize) -> usize {
+        val ^ self.state.lock().corrupt_mask
+    }
+}
+
+impl<R: DeviceAddrRange + 'static, D: BaseDeviceOps<R>> BaseDeviceOps<R> for ErrorInjector<R, D> {
+    forward_base_device_ops!(
+        R,
+        inner => identity, peek, poke, lifecycle, set_dma_accessor, set_region_sink,
+        set_timer_service, set_clock_source, on_timer, introspection
+    );
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.check()?;
+        self.inner
+            .handle_read(addr, width)
+            .map(|val| self.corrupt(val))
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.check()?;
+        self.inner.handle_write(addr, width, val)
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        self.check()?;
+        self.inner
+            .handle_read_ctx(addr, width, ctx)
+            .map(|val| self.corrupt(val))
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        self.check()?;
+        self.inner.handle_write_ctx(addr, width, val, ctx)
+    }
+
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        self.check_n(count as u64)?;
+        self.inner
+            .handle_read_bulk(addr, width, count, stride, buf)?;
+        for element in buf.chunks_exact_mut(width.size()) {
+            let val = self.corrupt(bulk_element_value(element));
+            element.copy_from_slice(&(val as u64).to_le_bytes()[..width.size()]);
+        }
+        Ok(())
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        self.check_n(count as u64)?;
+        self.inner
+            .handle_write_bulk(addr, width, count, stride, buf)
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        self.check()?;
+        self.inner
+            .handle_read_async(addr, width)
+            .map(|outcome| match outcome {
+                AccessOutcome::Completed(val) => AccessOutcome::Completed(self.corrupt(val)),
+                pending => pending,
+            })
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        self.check()?;
+        self.inner.handle_write_async(addr, width, val)
+    }
+
+    fn handle_call(&self, addr: R::Addr, args: &[usize]) -> AxResult<[usize; 4]> {
+        self.check()?;
+        self.inner.handle_call(addr, args)
+    }
+
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        self.inner.set_completer(Arc::new(DroppingCompleter {
+            inner: completer,
+            drop: self.drop_completions.clone(),
+        }))
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The interface of emulated interrupt controllers.
+
+use axerrno::AxResult;
+
+/// An emulated interrupt controller, such as a vGIC, vPLIC or vIOAPIC.
+///
+/// Besides handling the guest accesses to its registers through
+/// [`BaseDeviceOps`], an interrupt controller device implements this trait
+/// and returns itself from [`BaseDeviceOps::as_irq_chip`], so that the
+/// hypervisor can find it with [`DeviceManager::irq_chip`] and route device
+/// interrupts into it.
+///
+/// Interrupt numbers are those of the controller, e.g. GIC INTIDs.
+///
+/// [`BaseDeviceOps`]: crate::BaseDeviceOps
+/// [`BaseDeviceOps::as_irq_chip`]: crate::BaseDeviceOps::as_irq_chip
+/// [`DeviceManager::irq_chip`]: crate::DeviceManager::irq_chip
+pub trait VirtualIrqChip: Send + Sync {
+    /// Sets the input line of `irq` to `level`. Edge-triggered interrupts
+    /// are raised on a transition to `true`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `irq` is not implemented by
+    /// the controller.
+    fn inject(&self, irq: usize, level: bool) -> AxResult;
+
+    /// Signals the end of the handling of `irq`, as if the guest had written
+    /// the end-of-interrupt register.
+    fn eoi(&self, irq: usize) -> AxResult;
+
+    /// Routes `irq` to the vCPU of index `vcpu`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `irq` is not implemented or
+    /// cannot be routed to `vcpu`.
+    fn set_affinity(&self, irq: usize, vcpu: usize) -> AxResult;
+
+    /// Returns the highest priority interrupt pending for the vCPU of index
+    /// `vcpu`, if any.
+    fn pending(&self, vcpu: usize) -> Option<usize>;
+}
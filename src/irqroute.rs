@@ -0,0 +1,170 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Routing of device interrupt lines to guest interrupts.
+
+use alloc::{string::String, vec::Vec};
+
+use axerrno::{AxResult, ax_err};
+
+use crate::{EmulatedDeviceConfig, MsiMessage};
+
+/// The guest interrupt a device interrupt line is routed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum IrqTarget {
+    /// An input of the guest interrupt controller (a GSI, GIC SPI or PLIC
+    /// source).
+    Gsi(u32),
+    /// A message signalled interrupt.
+    Msi(MsiMessage),
+}
+
+/// An entry of an [`IrqRoutingTable`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IrqRoute {
+    /// The name of the source device, as in [`EmulatedDeviceConfig::name`].
+    pub device: String,
+    /// The interrupt line of the device, 0 for devices with a single line.
+    pub line: u32,
+    /// The guest interrupt the line is routed to.
+    pub target: IrqTarget,
+    /// Whether the route is masked, dropping the interrupts of the line.
+    #[serde(default)]
+    pub masked: bool,
+}
+
+/// A table routing the interrupt lines of devices to guest interrupts.
+///
+/// Several lines may be routed to the same target, to model shared interrupts.
+/// The table is usually part of the VM configuration; without one,
+/// [`from_configs`](Self::from_configs) derives the implicit routing of each
+/// device's [`irq_id`](EmulatedDeviceConfig::irq_id).
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::{IrqRoute, IrqRoutingTable, IrqTarget};
+///
+/// let mut table = IrqRoutingTable::new();
+/// table.add(IrqRoute {
+///     device: "uart0".into(),
+///     line: 0,
+///     target: IrqTarget::Gsi(33),
+///     masked: false,
+/// })?;
+/// assert_eq!(table.route("uart0", 0), Some(IrqTarget::Gsi(33)));
+///
+/// table.set_masked("uart0", 0, true)?;
+/// assert_eq!(table.route("uart0", 0), None);
+/// # Ok::<(), axerrno::AxError>(())
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct IrqRoutingTable {
+    routes: Vec<IrqRoute>,
+}
+
+impl IrqRoutingTable {
+    /// Creates an empty routing table.
+    pub const fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Creates a table routing line 0 of each device to the GSI given by its
+    /// `irq_id`. Devices with an `irq_id` of 0 have no interrupt and are
+    /// skipped.
+    pub fn from_configs(configs: &[EmulatedDeviceConfig]) -> Self {
+        Self {
+            routes: configs
+                .iter()
+                .filter(|config| config.irq_id != 0)
+                .map(|config| IrqRoute {
+                    device: config.name.clone(),
+                    line: 0,
+                    target: IrqTarget::Gsi(config.irq_id as u32),
+                    masked: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// Adds a route.
+    ///
+    /// Returns `Err(AxError::AlreadyExists)` if the line is already routed.
+    pub fn add(&mut self, route: IrqRoute) -> AxResult {
+        if self.find(&route.device, route.line).is_some() {
+            return ax_err!(AlreadyExists, "interrupt line already routed");
+        }
+        self.routes.push(route);
+        Ok(())
+    }
+
+    /// Removes and returns the route of `line` of `device`, if any.
+    pub fn remove(&mut self, device: &str, line: u32) -> Option<IrqRoute> {
+        let index = self.find(device, line)?;
+        Some(self.routes.remove(index))
+    }
+
+    /// Routes `line` of `device` to `target`, keeping its mask, and returns
+    /// the previous target.
+    ///
+    /// Returns `Err(AxError::NotFound)` if the line is not routed.
+    pub fn remap(&mut self, device: &str, line: u32, target: IrqTarget) -> AxResult<IrqTarget> {
+        let index = self.index_of(device, line)?;
+        Ok(core::mem::replace(&mut self.routes[index].target, target))
+    }
+
+    /// Masks or unmasks the route of `line` of `device`.
+    ///
+    /// Returns `Err(AxError::NotFound)` if the line is not routed.
+    pub fn set_masked(&mut self, device: &str, line: u32, masked: bool) -> AxResult {
+        let index = self.index_of(device, line)?;
+        self.routes[index].masked = masked;
+        Ok(())
+    }
+
+    /// Returns the target of `line` of `device`, or `None` if the line is
+    /// not routed or masked.
+    pub fn route(&self, device: &str, line: u32) -> Option<IrqTarget> {
+        let route = &self.routes[self.find(device, line)?];
+        (!route.masked).then_some(route.target)
+    }
+
+    /// Returns the lines routed to `target`, as `(device, line)` pairs,
+    /// including masked ones.
+    pub fn sources(&self, target: IrqTarget) -> impl Iterator<Item = (&str, u32)> {
+        self.routes
+            .iter()
+            .filter(move |route| route.target == target)
+            .map(|route| (route.device.as_str(), route.line))
+    }
+
+    /// Returns all routes, in insertion order.
+    pub fn routes(&self) -> &[IrqRoute] {
+        &self.routes
+    }
+
+    fn find(&self, device: &str, line: u32) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|route| route.device == device && route.line == line)
+    }
+
+    fn index_of(&self, device: &str, line: u32) -> AxResult<usize> {
+        match self.find(device, line) {
+            Some(index) => Ok(index),
+            None => ax_err!(NotFound, "interrupt line not routed"),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access journaling for crash diagnostics of a single device.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use axaddrspace::device::{AccessWidth, DeviceAddrRange};
+use axerrno::{AxError, AxResult};
+use spin::Mutex;
+
+use crate::{
+    AccessContext, AccessKind, AccessOutcome, BaseDeviceOps, RawDeviceAddr, UnhandledAccessPolicy,
+    bulk_element_addr, bulk_element_value, forward::forward_base_device_ops,
+};
+
+/// A single guest access recorded by a [`JournaledDevice`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessRecord<A> {
+    /// The accessed address.
+    pub addr: A,
+    /// The access width.
+    pub width: AccessWidth,
+    /// Whether the access was a read or a write.
+    pub kind: AccessKind,
+    /// The value read from or written to the device. For failed reads this is 0.
+    pub value: usize,
+    /// The error returned by the device, if any.
+    pub error: Option<AxError>,
+}
+
+/// A wrapper that keeps a journal of the last accesses handled by a device.
+///
+/// The journal is a fixed-size ring holding the most recent `capacity` accesses
+/// together with their values. Whenever the wrapped device fails an access, the
+/// whole journal is dumped to the log as warnings, so that a bug report against
+/// a device model comes with the exact register sequence that triggered the
+/// failure. Unhandled accesses (see
+/// [`UnhandledAccessPolicy::is_unhandled`]) are journaled but not dumped, as
+/// guests routinely probe registers a device does not implement.
+///
+/// Journaling is opt-in: wrap the device before registering it with the
+/// hypervisor.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use axdevice_base::JournaledDevice;
+///
+/// let uart = Arc::new(JournaledDevice::new(Uart16550::new(...), 64));
+/// ```
+pub struct JournaledDevice<R: DeviceAddrRange, D> {
+    inner: D,
+    journal: Mutex<VecDeque<AccessRecord<R::Addr>>>,
+    capacity: usize,
+}
+
+impl<R: DeviceAddrRange, D: BaseDeviceOps<R>> JournaledDevice<R, D> {
+    /// Wraps `inner`, keeping a journal of its last `capacity` accesses.
+    pub fn new(inner: D, capacity: usize) -> Self {
+        Self {
+            inner,
+            journal: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns the journaled accesses, oldest first.
+    pub fn records(&self) -> Vec<AccessRecord<R::Addr>> {
+        self.journal.lock().iter().copied().collect()
+    }
+
+    /// Discards all journaled accesses.
+    pub fn clear(&self) {
+        self.journal.lock().clear();
+    }
+
+    fn record_read(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        result: AxResult<usize>,
+    ) -> AxResult<usize> {
+        self.record(AccessRecord {
+            addr,
+            width,
+            kind: AccessKind::Read,
+            value: *result.as_ref().unwrap_or(&0),
+            error: result.err(),
+        });
+        result
+    }
+
+    fn record_write(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        result: AxResult,
+    ) -> AxResult {
+        self.record(AccessRecord {
+            addr,
+            width,
+            kind: AccessKind::Write,
+            value: val,
+            error: result.err(),
+        });
+        result
+    }
+
+    /// Journals each element of a successful bulk access, or the failure of
+    /// a failed one at its first address.
+    fn record_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        kind: AccessKind,
+        stride: isize,
+        buf: &[u8],
+        result: AxResult,
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        if let Err(error) = result {
+            self.record(AccessRecord {
+                addr,
+                width,
+                kind,
+                value: 0,
+                error: Some(error),
+            });
+            return result;
+        }
+        for (index, element) in buf.chunks_exact(width.size()).enumerate() {
+            if let Ok(addr) = bulk_element_addr(addr, index, stride) {
+                self.record(AccessRecord {
+                    addr,
+                    width,
+                    kind,
+                    value: bulk_element_value(element),
+                    error: None,
+                });
+            }
+        }
+        result
+    }
+
+    fn record(&self, record: AccessRecord<R::Addr>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut journal = self.journal.lock();
+        if journal.len() == self.capacity {
+            journal.pop_front();
+        }
+        journal.push_back(record);
+        match record.error {
+            Some(error) if !UnhandledAccessPolicy::is_unhandled(error) => {
+                let records: Vec<_> = journal.iter().copied().collect();
+                drop(journal);
+                self.dump(&records);
+            }
+            Some(error) => debug!(
+                "{:?} device did not handle {:?} {:?} {:?}: {error:?}",
+                self.inner.emu_type(),
+                record.kind,
+                record.addr,
+                record.width
+            ),
+            None => {}
+        }
+    }
+
+    fn dump(&self, records: &[AccessRecord<R::Addr>]) {
+        warn!(
+            "{:?} device failed, last {} accesses (oldest first):",
+            self.inner.emu_type(),
+            records.len()
+        );
+        for record in records {
+            warn!(
+                "  {:?} {:?} {:?} = {:#x} -> {:?}",
+                record.kind, record.addr, record.width, record.value, record.error
+            );
+        }
+    }
+}
+
+impl<R: DeviceAddrRange + 'static, D: BaseDeviceOps<R>> BaseDeviceOps<R> for JournaledDevice<R, D> {
+    forward_base_device_ops!(
+        R,
+        inner => identity, handle_call, peek, poke, lifecycle, services, introspection
+    );
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        let result = self.inner.handle_read(addr, width);
+        self.record_read(addr, width, result)
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        let result = self.inner.handle_write(addr, width, val);
+        self.record_write(addr, width, val, result)
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        let result = self.inner.handle_read_ctx(addr, width, ctx);
+        self.record_read(addr, width, result)
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        let result = self.inner.handle_write_ctx(addr, width, val, ctx);
+        self.record_write(addr, width, val, result)
+    }
+
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        let result = self.inner.handle_read_bulk(addr, width, count, stride, buf);
+        self.record_bulk(addr, width, AccessKind::Read, stride, buf, result)
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        let result = self
+            .inner
+            .handle_write_bulk(addr, width, count, stride, buf);
+        self.record_bulk(addr, width, AccessKind::Write, stride, buf, result)
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        let result = self.inner.handle_read_async(addr, width);
+        self.record(AccessRecord {
+            addr,
+            width,
+            kind: AccessKind::Read,
+            value: match result {
+                Ok(AccessOutcome::Completed(val)) => val,
+                _ => 0,
+            },
+            error: result.err(),
+        });
+        result
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        let result = self.inner.handle_write_async(addr, width, val);
+        self.record(AccessRecord {
+            addr,
+            width,
+            kind: AccessKind::Write,
+            value: val,
+            error: result.err(),
+        });
+        result
+    }
+}
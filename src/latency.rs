@@ -0,0 +1,42 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Latency hints for the scheduling of device accesses.
+
+/// How latency-sensitive the guest accesses to a region are.
+///
+/// The hypervisor scheduler uses the class to prioritize the exits it has to
+/// handle, e.g. those of a control-loop guest driving an emulated CAN
+/// controller over the bulk work of a storage device.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum LatencyClass {
+    /// Accesses that must be handled before any other device work.
+    RealTime,
+    /// Accesses without particular latency requirements.
+    #[default]
+    Normal,
+    /// Throughput-oriented accesses that may be delayed in favour of others.
+    Bulk,
+}
+
+/// Receives the latency class of the guest accesses dispatched by a
+/// [`DeviceManager`](crate::DeviceManager).
+///
+/// The hypervisor scheduler implements this trait and installs it with
+/// [`DeviceManager::set_latency_observer`](crate::DeviceManager::set_latency_observer).
+pub trait LatencyObserver: Send + Sync {
+    /// Called from the accessing vCPU before a guest access to the raw
+    /// address `addr` is dispatched, with the class of the region it hits.
+    fn on_access(&self, addr: usize, class: LatencyClass);
+}
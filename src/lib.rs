@@ -26,10 +26,131 @@
 //! - [`EmuDeviceType`]: Enumeration representing the type of emulator devices
 //!   (re-exported from `axvmconfig` crate).
 //! - [`EmulatedDeviceConfig`]: Configuration structure for device initialization.
+//! - [`ValidateConfig`]: Device checks rejecting bad configurations at VM build
+//!   time with a [`ConfigError`].
 //! - Trait aliases for specific device types:
 //!   - [`BaseMmioDeviceOps`]: For MMIO (Memory-Mapped I/O) devices.
 //!   - [`BaseSysRegDeviceOps`]: For system register devices.
 //!   - [`BasePortDeviceOps`]: For port I/O devices.
+//!   - [`BasePciDeviceOps`]: For PCI functions behind an [`EcamWindow`].
+//!   - [`BaseHypercallDeviceOps`]: For firmware interfaces such as PSCI or SBI.
+//! - [`MmioDevice`], [`SysRegDevice`], [`PortDevice`], [`HypercallDevice`]:
+//!   Shared handles to devices of each kind.
+//! - [`prelude`]: Re-exports of the items needed by almost every device model.
+//! - [`Capability`]: Optional subsystems a device declares participation in
+//!   through [`BaseDeviceOps::capabilities`].
+//! - [`BaseMultiSpaceDeviceOps`]: Devices claiming ranges in several address
+//!   spaces, registered per space through [`SpaceView`]s.
+//! - [`DeviceRegistry`]: [`DeviceFactory`]s keyed by [`EmuDeviceType`], creating
+//!   the devices of a VM from its configuration.
+//! - [`RegisterDump`]: Register values reported by devices for debugging.
+//! - [`PowerState`]: Device power states, set by ACPI/PM emulation, with the
+//!   [`LowPowerAccess`] policy for accesses to devices in low-power states.
+//! - [`DeviceManager`]: A device table routing guest accesses to the device
+//!   owning the accessed address, found with a [`DispatchStrategy`].
+//! - [`BusBridgeDevice`]: A device forwarding a range of one bus to a child
+//!   [`DeviceManager`], with address translation.
+//! - [`HostDeviceManager`]: The device managers of all VMs keyed by [`VmId`],
+//!   for finding devices, collecting statistics and quiescing across VMs.
+//! - [`UnhandledAccessPolicy`]: Whether accesses missing all devices, hitting
+//!   device [holes](BaseDeviceOps::holes) or unimplemented registers fault or
+//!   read as zero, set per [`DeviceManager`] and per region.
+//! - [`LatencyClass`]: How latency-sensitive the accesses to a region are,
+//!   reported to the hypervisor scheduler through a [`LatencyObserver`].
+//! - [`DeviceManifest`]: Device documentation metadata for generated machine
+//!   descriptions.
+//! - [`DeviceStateHeader`]: Versioned framing of device state saved for
+//!   snapshots and live migration.
+//! - [`DeviceAddrRangeExt`]: Range arithmetic (intersection, subtraction, splitting,
+//!   alignment and access containment checks) over device address ranges.
+//! - [`CatchUpPolicy`]: How timer-like devices handle time jumps after a VM pause
+//!   or restore.
+//! - [`TimerService`]: Timed callbacks for devices such as watchdogs and RTCs,
+//!   injected through [`BaseDeviceOps::set_timer_service`].
+//! - [`ClockSource`]: Host monotonic and wall-clock time, with per-guest
+//!   wall-clock offsets through [`GuestClock`].
+//! - [`PvClockDevice`]: A paravirtual clock publishing [`PvClockInfo`] to a
+//!   shared guest page, for low-overhead and migration-safe timekeeping.
+//! - [`StealTimeDevice`]: A paravirtual device publishing the per-vCPU steal
+//!   time and preemption hints of [`SchedulerInfoOps`] to shared guest memory.
+//! - [`RegValue`]: Register values keyed by [`AccessWidth`], with byte conversion,
+//!   extension and sub-word merging helpers.
+//! - [`ByteOrder`]: The byte order of guest accesses, converted by a
+//!   [`DeviceManager`] for big-endian guests.
+//! - [`QueueSet`]: Per-queue state, enable flags and notification affinity for
+//!   multi-queue devices.
+//! - [`TransactionalRegion`]: Registers whose writes are staged and committed
+//!   atomically by a trigger bit, with rollback on validation failure.
+//! - [`GuestMemoryAccessor`]: Bounds-checked guest RAM access for DMA-capable
+//!   devices, injected through [`BaseDeviceOps::set_dma_accessor`].
+//! - [`GuestBufferList`]: Scatter-gather lists of guest buffers, accessed
+//!   through a [`GuestMemoryAccessor`].
+//! - [`DmaTranslator`]: DMA address translation domains and mappings, programmed
+//!   by the guest through the [`VirtioIommuDevice`].
+//! - [`SplitQueue`]: The device side of a split virtqueue, walking descriptor
+//!   chains into [`GuestBufferList`]s.
+//! - [`PciConfigSpace`]: The standard PCI configuration space header, with
+//!   write masks and BAR sizing.
+//! - [`MsixTable`]: The MSI-X table and pending bit array of a PCI function.
+//! - [`EcamWindow`]: The PCIe ECAM configuration window, routing configuration
+//!   accesses to [`BasePciDeviceOps`] functions.
+//! - [`VirtioMmioRegs`]: The virtio-mmio transport register block, embedded by
+//!   virtio device models.
+//! - [`RegionUpdateSink`]: Propagation of guest-programmed region changes, such
+//!   as PCI BAR reprogramming, to the hypervisor's [`RegionUpdateHandler`],
+//!   keyed by the reporting device.
+//! - [`VirtualIrqChip`]: The interface of interrupt controller devices, through
+//!   which the hypervisor routes device interrupts.
+//! - [`IrqRoutingTable`]: Routing of device interrupt lines to guest GSIs or
+//!   MSI messages, with per-route masking.
+//! - [`Backpressure`]: Flow control between device models and their backends.
+//! - [`BlockBackend`]: Block storage backends addressed by LBA, serving the
+//!   [`SdhciBase`] SD host controller.
+//! - [`NetBackend`]: Network packet backends exchanging Ethernet frames, serving
+//!   the multi-queue [`VirtioNetDevice`].
+//! - [`EntropySource`]: Host entropy sources, used by the rate-limited
+//!   [`TrngDevice`] and [`VirtioRngDevice`].
+//! - [`FsBackend`]: Host filesystem shares, exported to the guest by the
+//!   [`VirtioFsDevice`].
+//! - [`InputBackend`]: Host input event sources, such as a remote console,
+//!   injecting key and pointer events through the [`VirtioInputDevice`].
+//! - [`Domain`]: Named groups of devices sharing a power or clock domain.
+//! - [`MemoryControlOps`]: Guest memory grow/shrink requests, driven by devices
+//!   such as the reference [`BalloonDevice`].
+//! - [`FlashDevice`]: An emulated NOR flash persisted through a [`PersistentStore`].
+//! - [`FramebufferDevice`]: A fixed-mode framebuffer in guest RAM, tracking
+//!   the tiles the guest reports dirty for a host [`DisplayConsumer`].
+//! - [`I2cControllerBase`]: A DesignWare-like I2C controller routing transfers to
+//!   [`I2cSlave`] devices attached through a [`SlaveRegistry`].
+//! - [`SpiControllerBase`]: An SPI controller routing transfers to [`SpiSlave`]
+//!   devices by chip select.
+//! - [`ClockResetControllerBase`]: A clock and reset controller gating and
+//!   resetting the [`Domain`]s fed by its lines.
+//! - [`MailboxDevice`]: A doorbell mailbox forwarding guest commands to a
+//!   hypervisor-side [`MailboxHandler`].
+//! - [`TpmTisDevice`]: The TPM TIS transport, delegating command processing to a
+//!   [`TpmBackend`].
+//! - [`CoveredDevice`]: An opt-in wrapper recording which register offsets,
+//!   widths and directions have been exercised.
+//! - [`NaturalWidthAdapter`]: A wrapper splitting guest accesses of any width
+//!   into accesses of the single register width a device implements.
+//! - [`DeviceTracer`]: A hook observing all accesses dispatched by a
+//!   [`DeviceManager`], or those of an address range as a shadow subscriber,
+//!   with the compact binary [`TraceRecord`] format.
+//! - [`AccessFilter`]: A bus-level policy allowing, denying or rewriting the
+//!   accesses dispatched by a [`DeviceManager`] before they reach the devices.
+//! - [`replay`]: Replays a recorded access trace against a device and reports
+//!   the reads whose results changed, for regression tests of device models.
+//! - [`StatsDevice`]: An opt-in wrapper maintaining the access statistics of a
+//!   device, reported through [`BaseDeviceOps::stats`].
+//! - [`ErrorInjector`]: A wrapper failing or corrupting accesses to a device,
+//!   for testing error paths.
+//! - [`ThrottledDevice`]: A wrapper rate-limiting the accesses to a device with
+//!   a token bucket.
+//! - [`PermissionCheckedDevice`]: A wrapper rejecting accesses that the
+//!   [`RegionAccess`] of the accessed region does not permit.
+//! - [`JournaledDevice`]: An opt-in wrapper that journals the last accesses of a
+//!   device and dumps them to the log when the device fails.
 //!
 //! # Usage
 //!
@@ -69,8 +190,10 @@
 //!
 //! # Feature Flags
 //!
-//! This crate currently has no optional feature flags. All functionality is available
-//! by default.
+//! - `static-registry`: Enables link-time registration of device factories with
+//!   `register_device_type!`.
+//! - `testing`: Enables the `testing` module, with a scriptable mock device,
+//!   assertion and fuzzing helpers for device model tests.
 
 #![no_std]
 #![feature(trait_alias)]
@@ -83,17 +206,156 @@
 #![warn(missing_docs)]
 
 extern crate alloc;
+#[macro_use]
+extern crate log;
 
-use alloc::{string::String, sync::Arc, vec::Vec};
+mod backend;
+mod balloon;
+mod bridge;
+mod capability;
+mod clock;
+mod coalesced;
+mod completion;
+mod coverage;
+mod dispatch;
+mod dma;
+mod domain;
+mod dump;
+mod ecam;
+mod factory;
+mod filter;
+mod flash;
+mod forward;
+mod framebuffer;
+mod hypercall;
+mod i2c;
+mod inject;
+mod irqchip;
+mod irqroute;
+mod journal;
+mod latency;
+mod mailbox;
+mod manager;
+mod manifest;
+mod msix;
+mod multispace;
+mod params;
+mod pci;
+mod permission;
+mod power;
+pub mod prelude;
+mod pvclock;
+mod queue;
+mod range;
+mod reg;
+mod region;
+mod replay;
+mod rng;
+mod sdhci;
+mod spi;
+mod state;
+mod stats;
+mod steal;
+mod subbus;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod throttle;
+mod time;
+mod tpm;
+mod trace;
+mod txn;
+mod unhandled;
+mod validate;
+mod virtio_fs;
+mod virtio_input;
+mod virtio_iommu;
+mod virtio_mmio;
+mod virtio_net;
+mod virtio_rng;
+mod virtqueue;
+mod vm;
+mod width;
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
 use core::any::Any;
 
 use axaddrspace::{
     GuestPhysAddrRange,
     device::{AccessWidth, DeviceAddrRange, PortRange, SysRegAddrRange},
 };
-use axerrno::AxResult;
+use axerrno::{AxError, AxResult, ax_err};
 
 pub use axvmconfig::EmulatedDeviceType as EmuDeviceType;
+pub use backend::{
+    Backpressure, BlockBackend, BlockReadCallback, BlockWriteCallback, CapacityCallback,
+    EntropySource, FsAttr, FsBackend, FsDirEntry, FsHandle, InputAbsInfo, InputBackend,
+    InputCallback, InputEvent, NetBackend, RxCallback, TpmBackend, scope_path,
+};
+pub use balloon::{BALLOON_PAGE_SIZE, BalloonDevice, MemoryControlOps};
+pub use bridge::BusBridgeDevice;
+pub use capability::{Capability, CapabilitySet};
+pub use clock::ClockResetControllerBase;
+pub use coalesced::{CoalescedWrite, CoalescedWriteRing};
+pub use completion::{AccessCompleter, AccessOutcome, CompletionToken};
+pub use coverage::{CoverageCount, CoveragePoint, CoveredDevice};
+pub use dispatch::DispatchStrategy;
+pub use dma::{DmaTranslator, GuestBuffer, GuestBufferList, GuestMemoryAccessor};
+pub use domain::{Domain, DomainEvent};
+pub use dump::RegisterDump;
+pub use ecam::{EcamWindow, PciBdf, PciConfigAddr, PciConfigRange};
+#[cfg(feature = "static-registry")]
+pub use factory::{DEVICE_FACTORIES, StaticDeviceFactory};
+pub use factory::{DeviceDeps, DeviceFactory, DeviceRegistry};
+pub use filter::{AccessFilter, FilterAction};
+pub use flash::{FlashDevice, PersistentStore};
+pub use framebuffer::{DirtyRect, DisplayConsumer, FramebufferDevice};
+pub use hypercall::{HypercallId, HypercallRange};
+pub use i2c::{I2cBus, I2cControllerBase, I2cSlave};
+pub use inject::ErrorInjector;
+pub use irqchip::VirtualIrqChip;
+pub use irqroute::{IrqRoute, IrqRoutingTable, IrqTarget};
+pub use journal::{AccessRecord, JournaledDevice};
+pub use latency::{LatencyClass, LatencyObserver};
+pub use mailbox::{MailboxDevice, MailboxHandler};
+pub use manager::DeviceManager;
+pub use manifest::DeviceManifest;
+pub use msix::{MsiMessage, MsixTable};
+pub use multispace::{
+    BaseMultiSpaceDeviceOps, SpaceView, UnifiedAddr, UnifiedAddrRange, space_views,
+};
+pub use params::{ConfigValue, RegionAccess, RegionConfig, RegionSpace};
+pub use pci::{PciBar, PciBarChange, PciConfigSpace};
+pub use permission::PermissionCheckedDevice;
+pub use power::{LowPowerAccess, PowerState};
+pub use pvclock::{PvClockDevice, PvClockInfo};
+pub use queue::{Queue, QueueSet};
+pub use range::{DeviceAddrRangeExt, RawDeviceAddr};
+pub use reg::{ByteOrder, RegValue, width_mask};
+pub use region::{DeviceRegionSink, RegionId, RegionUpdateHandler, RegionUpdateSink};
+pub use replay::{ReplayMismatch, TraceRecorder, decode_trace, replay};
+pub use rng::TrngDevice;
+pub use sdhci::SdhciBase;
+pub use spi::{SpiBus, SpiControllerBase, SpiSlave};
+pub use state::DeviceStateHeader;
+pub use stats::{AccessStats, DeviceStats, StatsDevice};
+pub use steal::{SchedulerInfoOps, StealTimeDevice, StealTimeInfo};
+pub use subbus::SlaveRegistry;
+pub use throttle::{ThrottleResponse, ThrottledDevice};
+pub use time::{CatchUpPolicy, ClockSource, GuestClock, TimerService, TimerToken};
+pub use tpm::TpmTisDevice;
+pub use trace::{DeviceTracer, TraceRecord};
+pub use txn::TransactionalRegion;
+pub use unhandled::UnhandledAccessPolicy;
+pub use validate::{ConfigError, ValidateConfig};
+pub use virtio_fs::VirtioFsDevice;
+pub use virtio_input::VirtioInputDevice;
+pub use virtio_iommu::VirtioIommuDevice;
+pub use virtio_mmio::{VirtioMmioDevice, VirtioMmioRegs, VirtioQueueConfig};
+pub use virtio_net::{NetModeration, VirtioNetDevice};
+pub use virtio_rng::VirtioRngDevice;
+pub use virtqueue::{DescChain, SplitQueue};
+pub use vm::{HostDeviceManager, VmId};
+pub use width::NaturalWidthAdapter;
 
 /// Represents the configuration of an emulated device for a virtual machine.
 ///
@@ -109,6 +371,9 @@ pub use axvmconfig::EmulatedDeviceType as EmuDeviceType;
 /// - `irq_id`: The interrupt line number for device interrupts.
 /// - `emu_type`: Numeric identifier for the device type.
 /// - `cfg_list`: Device-specific configuration parameters.
+/// - `guest_profile`: The guest OS family the device should be compatible with.
+/// - `params`: Named, typed device parameters.
+/// - `regions`: Additional address ranges of the device.
 ///
 /// # Example
 ///
@@ -122,6 +387,7 @@ pub use axvmconfig::EmulatedDeviceType as EmuDeviceType;
 ///     irq_id: 33,
 ///     emu_type: 1,
 ///     cfg_list: vec![115200], // baud rate
+///     ..Default::default()
 /// };
 /// ```
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
@@ -166,6 +432,192 @@ pub struct EmulatedDeviceConfig {
     /// specific device type. For example, a UART device might use this to
     /// specify baud rate, while a virtio device might use it for queue sizes.
     pub cfg_list: Vec<usize>,
+
+    /// The guest OS family the device should be compatible with.
+    ///
+    /// Devices may consult this to choose defaults that suit the guest's
+    /// drivers. Defaults to [`GuestProfile::Linux`] when omitted from the
+    /// configuration.
+    #[serde(default)]
+    pub guest_profile: GuestProfile,
+
+    /// Named device parameters.
+    ///
+    /// These replace the positional [`cfg_list`](Self::cfg_list) for new
+    /// devices, and are read with the typed getters such as
+    /// [`get_u64`](Self::get_u64) and [`get_str`](Self::get_str). Legacy
+    /// configurations can be converted with
+    /// [`import_cfg_list`](Self::import_cfg_list).
+    #[serde(default)]
+    pub params: BTreeMap<String, ConfigValue>,
+
+    /// Additional address ranges of the device, e.g. the BARs of a PCI device
+    /// or the I/O ports of a device that also has an MMIO window.
+    ///
+    /// See [`address_ranges`](Self::address_ranges).
+    #[serde(default)]
+    pub regions: Vec<RegionConfig>,
+}
+
+/// A hint about the guest OS family running in a virtual machine.
+///
+/// One device model can serve several guest ecosystems by choosing compatible
+/// defaults based on this hint, e.g. offering a transitional rather than a
+/// modern virtio interface, or enabling quirks in UART FIFO behavior that a
+/// particular guest driver relies on. The hint never changes the set of
+/// registers a device implements, only their default configuration.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum GuestProfile {
+    /// Linux guests.
+    #[default]
+    Linux,
+    /// Zephyr RTOS guests.
+    Zephyr,
+    /// Other real-time operating systems with minimal drivers.
+    RtosGeneric,
+    /// Windows guests.
+    Windows,
+}
+
+/// The major version of the device ABI defined by this crate.
+///
+/// The major version is bumped whenever the contract of [`BaseDeviceOps`] changes
+/// in an incompatible way, e.g. when the semantics of an existing method or of a
+/// default method implementation change.
+pub const DEVICE_ABI_MAJOR: u16 = 1;
+
+/// The minor version of the device ABI defined by this crate.
+///
+/// The minor version is bumped whenever new methods with backward-compatible
+/// default implementations are added to [`BaseDeviceOps`].
+///
+/// Version 1.1 adds the context, bulk and asynchronous access handlers, the
+/// lifecycle, power and snapshot hooks, service injection and the
+/// introspection methods.
+pub const DEVICE_ABI_MINOR: u16 = 1;
+
+/// The device ABI version defined by this crate, encoded as
+/// `(DEVICE_ABI_MAJOR << 16) | DEVICE_ABI_MINOR`.
+///
+/// Devices report the version they were built against through
+/// [`BaseDeviceOps::abi_version`], and the hypervisor checks it with
+/// [`check_abi_version`] when the device is registered.
+pub const DEVICE_ABI_VERSION: u32 = ((DEVICE_ABI_MAJOR as u32) << 16) | DEVICE_ABI_MINOR as u32;
+
+/// Checks whether a device built against the ABI `version` can be driven by this
+/// crate.
+///
+/// A device is accepted if its major version equals [`DEVICE_ABI_MAJOR`] and its
+/// minor version is not newer than [`DEVICE_ABI_MINOR`].
+///
+/// # Returns
+///
+/// - `Ok(())`: The device is compatible.
+/// - `Err(AxError::Unsupported)`: The device was built against an incompatible ABI.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::{DEVICE_ABI_VERSION, check_abi_version};
+///
+/// assert!(check_abi_version(DEVICE_ABI_VERSION).is_ok());
+/// // Devices built against an older minor version are still accepted.
+/// assert!(check_abi_version(DEVICE_ABI_VERSION - 1).is_ok());
+/// assert!(check_abi_version(DEVICE_ABI_VERSION + 1).is_err());
+/// assert!(check_abi_version(DEVICE_ABI_VERSION + (1 << 16)).is_err());
+/// ```
+pub fn check_abi_version(version: u32) -> AxResult {
+    let major = (version >> 16) as u16;
+    let minor = version as u16;
+    if major != DEVICE_ABI_MAJOR || minor > DEVICE_ABI_MINOR {
+        return ax_err!(Unsupported, "incompatible device ABI version");
+    }
+    Ok(())
+}
+
+/// A change of the guest-physical memory layout of a VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLayoutChange {
+    /// A RAM region was added, e.g. by memory hotplug or balloon deflation.
+    Added(GuestPhysAddrRange),
+    /// A RAM region was removed, e.g. by memory unplug or balloon inflation.
+    Removed(GuestPhysAddrRange),
+    /// A RAM region was resized in place.
+    Resized {
+        /// The region before the change.
+        old: GuestPhysAddrRange,
+        /// The region after the change.
+        new: GuestPhysAddrRange,
+    },
+}
+
+/// The direction of a guest access to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AccessKind {
+    /// The guest reads from the device.
+    Read,
+    /// The guest writes to the device.
+    Write,
+}
+
+/// The agent performing an access to a device.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessOrigin {
+    /// A guest vCPU, trapped by the hypervisor.
+    #[default]
+    Vcpu,
+    /// The hypervisor itself, e.g. a debugger or a state inspection tool.
+    Hypervisor,
+}
+
+/// Information about the agent performing an access, passed to
+/// [`BaseDeviceOps::handle_read_ctx`] and [`BaseDeviceOps::handle_write_ctx`].
+///
+/// This lets devices with per-CPU semantics (banked interrupt controller
+/// registers, per-CPU timers) tell the accessing vCPUs apart.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct AccessContext {
+    /// The index of the vCPU performing the access.
+    pub vcpu_id: usize,
+    /// Whether the access is made from the secure state (Arm TrustZone).
+    pub is_secure: bool,
+    /// The guest program counter of the accessing instruction, if known.
+    pub pc: Option<usize>,
+    /// The agent performing the access.
+    pub origin: AccessOrigin,
+}
+
+impl AccessContext {
+    /// Creates the context of a non-secure access by vCPU `vcpu_id`.
+    pub const fn new(vcpu_id: usize) -> Self {
+        Self {
+            vcpu_id,
+            is_secure: false,
+            pc: None,
+            origin: AccessOrigin::Vcpu,
+        }
+    }
+
+    /// Sets whether the access is made from the secure state.
+    pub const fn with_secure(mut self, is_secure: bool) -> Self {
+        self.is_secure = is_secure;
+        self
+    }
+
+    /// Sets the guest program counter of the accessing instruction.
+    pub const fn with_pc(mut self, pc: usize) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+
+    /// Sets the agent performing the access.
+    pub const fn with_origin(mut self, origin: AccessOrigin) -> Self {
+        self.origin = origin;
+        self
+    }
 }
 
 /// The core trait that all emulated devices must implement.
@@ -203,6 +655,18 @@ pub trait BaseDeviceOps<R: DeviceAddrRange>: Any {
     /// accesses to the appropriate device handler.
     fn address_range(&self) -> R;
 
+    /// Returns the ranges within [`address_range`](Self::address_range) that
+    /// the device does not decode, such as reserved gaps in its register map
+    /// that real hardware answers with a bus error.
+    ///
+    /// A [`DeviceManager`] reads the holes once, at registration, and does not
+    /// dispatch accesses touching them: they are unhandled, and completed
+    /// according to its [`UnhandledAccessPolicy`]. The default implementation
+    /// returns no holes.
+    fn holes(&self) -> Vec<R> {
+        Vec::new()
+    }
+
     /// Handles a read operation on the emulated device.
     ///
     /// # Arguments
@@ -240,6 +704,469 @@ pub trait BaseDeviceOps<R: DeviceAddrRange>: Any {
     /// Implementations should only use the lower bits of `val` corresponding
     /// to the specified `width`.
     fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult;
+
+    /// Handles a read operation, with information about the accessing vCPU.
+    ///
+    /// Hypervisors that know the accessing vCPU call this method rather than
+    /// [`handle_read`](Self::handle_read). Devices with per-CPU semantics
+    /// override it; the default implementation ignores `ctx` and calls
+    /// [`handle_read`](Self::handle_read).
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        let _ = ctx;
+        self.handle_read(addr, width)
+    }
+
+    /// Handles a write operation, with information about the accessing vCPU.
+    ///
+    /// The default implementation ignores `ctx` and calls
+    /// [`handle_write`](Self::handle_write). See
+    /// [`handle_read_ctx`](Self::handle_read_ctx).
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        let _ = ctx;
+        self.handle_write(addr, width, val)
+    }
+
+    /// Handles `count` consecutive reads, as issued by x86 `REP INS` or a
+    /// guest copying out of a device window.
+    ///
+    /// Element `i` is read at `addr + i * stride` and stored little-endian at
+    /// `buf[i * width.size()..]`. A stride of 0 reads the same register
+    /// repeatedly, as port I/O string instructions do. Devices backed by
+    /// memory-like storage (e.g. framebuffers) override this to service the
+    /// whole burst at once; the default implementation loops over
+    /// [`handle_read`](Self::handle_read).
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `buf` is not `count` elements
+    /// long, and `Err(AxError::BadAddress)` if an element address is not
+    /// representable. Stops at the first failing element, leaving the
+    /// previous elements read.
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        read_bulk_each(addr, width, count, stride, buf, |addr, width| {
+            self.handle_read(addr, width)
+        })
+    }
+
+    /// Handles `count` consecutive writes, as issued by x86 `REP OUTS` or a
+    /// guest copying into a device window.
+    ///
+    /// Element `i` is taken little-endian from `buf[i * width.size()..]` and
+    /// written at `addr + i * stride`. The default implementation loops over
+    /// [`handle_write`](Self::handle_write). See
+    /// [`handle_read_bulk`](Self::handle_read_bulk) for the errors.
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        write_bulk_each(addr, width, count, stride, buf, |addr, width, val| {
+            self.handle_write(addr, width, val)
+        })
+    }
+
+    /// Handles a read operation that the device may complete asynchronously.
+    ///
+    /// Devices backed by slow host I/O override this to start the request and
+    /// return [`AccessOutcome::Pending`], then report the result to the
+    /// [`AccessCompleter`] injected with [`set_completer`](Self::set_completer).
+    /// Such devices advertise [`Capability::AsyncIo`]. The default
+    /// implementation completes synchronously through
+    /// [`handle_read`](Self::handle_read).
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        self.handle_read(addr, width).map(AccessOutcome::Completed)
+    }
+
+    /// Handles a write operation that the device may complete asynchronously.
+    ///
+    /// The default implementation completes synchronously through
+    /// [`handle_write`](Self::handle_write). See
+    /// [`handle_read_async`](Self::handle_read_async).
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        self.handle_write(addr, width, val)
+            .map(|()| AccessOutcome::Completed(0))
+    }
+
+    /// Handles a guest call of the firmware function `addr` with the argument
+    /// registers `args`, returning the values of the result registers.
+    ///
+    /// This is the entry point of [`BaseHypercallDeviceOps`] devices; other
+    /// devices keep the default implementation, which returns
+    /// `Err(AxError::Unsupported)`.
+    fn handle_call(&self, addr: R::Addr, args: &[usize]) -> AxResult<[usize; 4]> {
+        let _ = (addr, args);
+        ax_err!(Unsupported, "device does not handle calls")
+    }
+
+    /// Reads a register for a debugger, without side effects.
+    ///
+    /// Unlike [`handle_read`](Self::handle_read), this must not change the
+    /// device state, e.g. reading an interrupt status register must not clear
+    /// it, and reading a FIFO must not pop it. Devices implementing this
+    /// method declare [`Capability::Peek`]. The default implementation
+    /// returns `Err(AxError::Unsupported)`.
+    fn peek(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        let _ = (addr, width);
+        ax_err!(Unsupported, "device does not support debugger reads")
+    }
+
+    /// Writes a register for a debugger, without side effects.
+    ///
+    /// Unlike [`handle_write`](Self::handle_write), this only stores `val`:
+    /// it must not trigger the actions of the register, e.g. start a transfer
+    /// or raise an interrupt. See [`peek`](Self::peek). The default
+    /// implementation returns `Err(AxError::Unsupported)`.
+    fn poke(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        let _ = (addr, width, val);
+        ax_err!(Unsupported, "device does not support debugger writes")
+    }
+
+    /// Returns the device ABI version this device was built against.
+    ///
+    /// The hypervisor checks the returned value with [`check_abi_version`] when
+    /// the device is registered, so that a device relying on an incompatible
+    /// contract is rejected up front instead of misbehaving at runtime.
+    ///
+    /// The default implementation returns [`DEVICE_ABI_VERSION`] of the crate
+    /// version the device was compiled against, and should not be overridden.
+    /// Within a single build it always matches, so the check only rejects
+    /// devices compiled separately against another version of this crate,
+    /// e.g. device plugins loaded by the hypervisor.
+    fn abi_version(&self) -> u32 {
+        DEVICE_ABI_VERSION
+    }
+
+    /// Notifies the device that guest time jumped forward by `delta_ns`
+    /// nanoseconds without the VM running, e.g. after a host pause or a
+    /// snapshot restore.
+    ///
+    /// Timer-like devices (RTCs, periodic timers, watchdogs) should use this to
+    /// re-arm their timers according to `policy` instead of replaying every
+    /// missed period. The default implementation does nothing.
+    fn on_time_jump(&self, delta_ns: u64, policy: CatchUpPolicy) {
+        let _ = (delta_ns, policy);
+    }
+
+    /// Resets the device to its power-on state, e.g. on guest reboot.
+    ///
+    /// The hypervisor only resets a device while the VM is paused, so no
+    /// access handler runs concurrently. The default implementation does
+    /// nothing.
+    fn reset(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// Quiesces the device, e.g. when the VM is paused.
+    ///
+    /// The hypervisor stops all vCPUs of the VM before pausing its devices, so
+    /// no access handler is in flight, and none is called until
+    /// [`resume`](Self::resume). The device must stop its own background
+    /// activity (timers, backend completions, interrupt injection) before
+    /// returning. The default implementation does nothing.
+    fn pause(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// Resumes a device paused by [`pause`](Self::pause). The default
+    /// implementation does nothing.
+    fn resume(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// Shuts the device down before the VM is destroyed, releasing backend
+    /// resources.
+    ///
+    /// No access handler is in flight, and no method other than `drop` is
+    /// called afterwards. The default implementation does nothing.
+    fn shutdown(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// Returns the current power state of the device. The default
+    /// implementation returns [`PowerState::D0`].
+    fn power_state(&self) -> PowerState {
+        PowerState::D0
+    }
+
+    /// Transitions the device to `state`, as requested by the guest through
+    /// ACPI or PCI power management.
+    ///
+    /// Devices entering [`PowerState::D3Cold`] lose their context, and come
+    /// back to D0 as if [`reset`](Self::reset). The default implementation
+    /// only accepts [`PowerState::D0`], and returns
+    /// `Err(AxError::Unsupported)` for other states.
+    fn set_power_state(&self, state: PowerState) -> AxResult {
+        if !state.is_active() {
+            return ax_err!(Unsupported, "device does not support power management");
+        }
+        Ok(())
+    }
+
+    /// Saves the internal state of the device, for VM snapshots and live
+    /// migration.
+    ///
+    /// The state should start with a [`DeviceStateHeader`]. It is only saved
+    /// while the device is paused. Devices implementing this should also report
+    /// [`Capability::Snapshot`]. The default implementation returns
+    /// `Err(AxError::Unsupported)`, so that a VM with devices that cannot be
+    /// saved is not silently snapshotted.
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        ax_err!(Unsupported, "device does not support snapshots")
+    }
+
+    /// Restores state saved by [`save_state`](Self::save_state).
+    ///
+    /// The state is only restored while the device is paused. The default
+    /// implementation returns `Err(AxError::Unsupported)`.
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        let _ = state;
+        ax_err!(Unsupported, "device does not support snapshots")
+    }
+
+    /// Handles a domain-wide operation on a [`Domain`] this device belongs to.
+    ///
+    /// The default implementation maps [`DomainEvent::Reset`],
+    /// [`DomainEvent::Suspend`] and [`DomainEvent::Resume`] to
+    /// [`reset`](Self::reset), [`pause`](Self::pause) and
+    /// [`resume`](Self::resume), and ignores clock events.
+    fn on_domain_event(&self, event: DomainEvent) -> AxResult {
+        match event {
+            DomainEvent::Reset => self.reset(),
+            DomainEvent::Suspend => self.pause(),
+            DomainEvent::Resume => self.resume(),
+            DomainEvent::ClockGate | DomainEvent::ClockUngate => Ok(()),
+        }
+    }
+
+    /// Notifies the device that vCPU `index` was hot-added to the running VM.
+    ///
+    /// Devices with per-vCPU state (interrupt controllers, per-CPU timers,
+    /// banked registers) should grow their banks and affinity tables here. An
+    /// error vetoes the hot-add. The default implementation does nothing.
+    fn on_vcpu_added(&self, index: usize) -> AxResult {
+        let _ = index;
+        Ok(())
+    }
+
+    /// Notifies the device that the guest-physical memory layout changed.
+    ///
+    /// Devices caching guest memory translations, pinned buffers or validated
+    /// ring addresses must revalidate them here, as they may no longer point
+    /// to guest RAM. The default implementation does nothing.
+    fn on_memory_layout_changed(&self, change: MemoryLayoutChange) {
+        let _ = change;
+    }
+
+    /// Provides the device with access to guest RAM.
+    ///
+    /// The framework calls this when the device is registered (see
+    /// [`DeviceManager::set_dma_accessor`]). DMA-capable devices keep the
+    /// accessor and use it for all guest memory accesses. The default
+    /// implementation drops it.
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        let _ = accessor;
+    }
+
+    /// Provides the device with the receiver of asynchronously completed
+    /// accesses.
+    ///
+    /// The framework calls this when the device is registered (see
+    /// [`DeviceManager::set_completer`]). Devices returning
+    /// [`AccessOutcome::Pending`] keep the completer; the default
+    /// implementation drops it.
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        let _ = completer;
+    }
+
+    /// Provides the device with the receiver of changes to its
+    /// guest-programmed regions.
+    ///
+    /// The framework calls this when the device is registered (see
+    /// [`DeviceManager::set_region_handler`]), with a sink of the device's
+    /// own. Devices with relocatable regions, such as PCI BARs, keep the sink
+    /// and report every change to it; the default implementation drops it.
+    fn set_region_sink(&self, sink: Arc<dyn RegionUpdateSink>) {
+        let _ = sink;
+    }
+
+    /// Provides the device with the service scheduling its timers.
+    ///
+    /// The framework calls this when the device is registered (see
+    /// [`DeviceManager::set_timer_service`]). Devices needing timed callbacks
+    /// keep the service; the default implementation drops it.
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        let _ = service;
+    }
+
+    /// Handles the expiry of a timer scheduled with the injected
+    /// [`TimerService`], returning whether `token` belongs to the device.
+    ///
+    /// The default implementation returns `false`.
+    fn on_timer(&self, token: TimerToken) -> bool {
+        let _ = token;
+        false
+    }
+
+    /// Provides the device with the clock it reads time from.
+    ///
+    /// The framework calls this when the device is registered (see
+    /// [`DeviceManager::set_clock_source`]). Devices exposing time to the
+    /// guest keep the clock; the default implementation drops it.
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        let _ = clock;
+    }
+
+    /// Returns the ring in which the framework logs writes to the device's
+    /// notification registers, instead of calling
+    /// [`handle_write`](Self::handle_write).
+    ///
+    /// See [`CoalescedWriteRing`]. The default implementation returns `None`,
+    /// so that all writes are delivered synchronously.
+    fn coalesced_writes(&self) -> Option<&CoalescedWriteRing<R>> {
+        None
+    }
+
+    /// Returns the access statistics of the device, if it maintains any.
+    ///
+    /// Devices are usually wrapped in a [`StatsDevice`] to maintain them. The
+    /// default implementation returns `None`.
+    fn stats(&self) -> Option<DeviceStats<R>> {
+        None
+    }
+
+    /// Returns the current values of the device registers, for debugging.
+    ///
+    /// Implementations must not have side effects on the device, e.g. reading
+    /// a register must not clear it. The hypervisor prints the dumps of all
+    /// devices with [`DeviceManager::dump`]. The default implementation
+    /// returns no register.
+    fn dump_registers(&self) -> Vec<RegisterDump> {
+        Vec::new()
+    }
+
+    /// Returns the device as an interrupt controller, if it is one.
+    ///
+    /// See [`VirtualIrqChip`]. The default implementation returns `None`.
+    fn as_irq_chip(&self) -> Option<&dyn VirtualIrqChip> {
+        None
+    }
+
+    /// Returns the optional subsystems the device participates in.
+    ///
+    /// The hypervisor queries this once at registration. The default
+    /// implementation returns the empty set.
+    fn capabilities(&self) -> CapabilitySet {
+        CapabilitySet::empty()
+    }
+
+    /// Returns whether [`capabilities`](Self::capabilities) contains `cap`.
+    fn supports(&self, cap: Capability) -> bool {
+        self.capabilities().contains(cap)
+    }
+
+    /// Returns the documentation metadata of the device.
+    ///
+    /// The default implementation only names the model after
+    /// [`emu_type`](Self::emu_type).
+    fn manifest(&self) -> DeviceManifest {
+        DeviceManifest::new(alloc::format!("{:?}", self.emu_type()))
+    }
+}
+
+/// Returns the address of element `index` of a bulk access starting at
+/// `addr`.
+pub(crate) fn bulk_element_addr<A: RawDeviceAddr>(
+    addr: A,
+    index: usize,
+    stride: isize,
+) -> AxResult<A> {
+    (index as isize)
+        .checked_mul(stride)
+        .and_then(|delta| addr.to_raw().checked_add_signed(delta))
+        .and_then(A::from_raw)
+        .ok_or(AxError::BadAddress)
+}
+
+/// Returns the value of a bulk access element, stored little-endian.
+pub(crate) fn bulk_element_value(element: &[u8]) -> usize {
+    let mut bytes = [0; 8];
+    bytes[..element.len()].copy_from_slice(element);
+    u64::from_le_bytes(bytes) as usize
+}
+
+/// Performs a bulk read as `count` scalar reads with `read`, as the default
+/// [`BaseDeviceOps::handle_read_bulk`] does.
+pub(crate) fn read_bulk_each<A: RawDeviceAddr>(
+    addr: A,
+    width: AccessWidth,
+    count: usize,
+    stride: isize,
+    buf: &mut [u8],
+    mut read: impl FnMut(A, AccessWidth) -> AxResult<usize>,
+) -> AxResult {
+    let size = width.size();
+    if count.checked_mul(size) != Some(buf.len()) {
+        return ax_err!(InvalidInput, "bulk buffer does not match the element count");
+    }
+    for (index, element) in buf.chunks_exact_mut(size).enumerate() {
+        let val = read(bulk_element_addr(addr, index, stride)?, width)?;
+        element.copy_from_slice(&(val as u64).to_le_bytes()[..size]);
+    }
+    Ok(())
+}
+
+/// Performs a bulk write as `count` scalar writes with `write`, as the
+/// default [`BaseDeviceOps::handle_write_bulk`] does.
+pub(crate) fn write_bulk_each<A: RawDeviceAddr>(
+    addr: A,
+    width: AccessWidth,
+    count: usize,
+    stride: isize,
+    buf: &[u8],
+    mut write: impl FnMut(A, AccessWidth, usize) -> AxResult,
+) -> AxResult {
+    let size = width.size();
+    if count.checked_mul(size) != Some(buf.len()) {
+        return ax_err!(InvalidInput, "bulk buffer does not match the element count");
+    }
+    for (index, element) in buf.chunks_exact(size).enumerate() {
+        write(
+            bulk_element_addr(addr, index, stride)?,
+            width,
+            bulk_element_value(element),
+        )?;
+    }
+    Ok(())
 }
 
 /// Attempts to downcast a device to a specific type and apply a function to it.
@@ -334,5 +1261,43 @@ pub trait BaseSysRegDeviceOps = BaseDeviceOps<SysRegAddrRange>;
 /// Port I/O devices are only used on x86/x86_64 architectures.
 pub trait BasePortDeviceOps = BaseDeviceOps<PortRange>;
 
+/// Trait alias for PCI function operations.
+///
+/// This is a convenience alias for [`BaseDeviceOps`] with [`PciConfigRange`]
+/// as the address range type. PCI functions handle the accesses to their
+/// configuration space, routed to them by an [`EcamWindow`]; their BARs are
+/// registered as separate MMIO or port I/O devices.
+pub trait BasePciDeviceOps = BaseDeviceOps<PciConfigRange>;
+
+/// Trait alias for firmware interface operations.
+///
+/// This is a convenience alias for [`BaseDeviceOps`] with [`HypercallRange`]
+/// as the address range type. Firmware interface emulators, such as Arm PSCI
+/// over SMC/HVC or RISC-V SBI extensions, claim a range of function
+/// identifiers and handle the guest calls in
+/// [`handle_call`](BaseDeviceOps::handle_call).
+pub trait BaseHypercallDeviceOps = BaseDeviceOps<HypercallRange>;
+
+/// A shared handle to an MMIO device.
+pub type MmioDevice = Arc<dyn BaseMmioDeviceOps>;
+
+/// A shared handle to a system register device.
+pub type SysRegDevice = Arc<dyn BaseSysRegDeviceOps>;
+
+/// A shared handle to a port I/O device.
+pub type PortDevice = Arc<dyn BasePortDeviceOps>;
+
+/// A shared handle to a PCI function.
+pub type PciDevice = Arc<dyn BasePciDeviceOps>;
+
+/// A shared handle to a firmware interface.
+pub type HypercallDevice = Arc<dyn BaseHypercallDeviceOps>;
+
+#[doc(hidden)]
+#[cfg(feature = "static-registry")]
+pub mod __private {
+    pub use linkme;
+}
+
 #[cfg(test)]
 mod test;
@@ -0,0 +1,152 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mailbox device for guest-to-hypervisor service requests.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, RegValue};
+
+/// The hypervisor-side service behind a [`MailboxDevice`].
+pub trait MailboxHandler: Send + Sync {
+    /// Handles `command` issued by the guest.
+    ///
+    /// `payload` holds the request written by the guest, and may be updated
+    /// in place with the response. The returned value is exposed to the guest
+    /// in the `RESULT` register. An error sets the `ERROR` status bit instead.
+    fn handle(&self, command: u32, payload: &mut [u8]) -> AxResult<u32>;
+}
+
+struct MailboxState {
+    command: u32,
+    status: u32,
+    result: u32,
+    payload: Vec<u8>,
+}
+
+/// A doorbell mailbox forwarding guest commands to a [`MailboxHandler`].
+///
+/// The guest writes a request into the payload window, then writes the command
+/// code to `COMMAND`. The command is handled synchronously, so `STATUS.DONE` (or
+/// `STATUS.ERROR`) is already set when the guest polls it.
+///
+/// | Offset  | Name           | Access | Description                                  |
+/// |---------|----------------|--------|----------------------------------------------|
+/// | 0x00    | `COMMAND`      | RW     | Writing issues the command (the doorbell).   |
+/// | 0x04    | `STATUS`       | RW1C   | See the `STATUS_*` bits.                     |
+/// | 0x08    | `RESULT`       | RO     | Value returned by the handler.               |
+/// | 0x0c    | `PAYLOAD_SIZE` | RO     | Size of the payload window in bytes.         |
+/// | 0x100.. | `PAYLOAD`      | RW     | Request and response payload, any width.     |
+pub struct MailboxDevice {
+    range: GuestPhysAddrRange,
+    handler: Arc<dyn MailboxHandler>,
+    state: Mutex<MailboxState>,
+}
+
+impl MailboxDevice {
+    const REG_COMMAND: usize = 0x00;
+    const REG_STATUS: usize = 0x04;
+    const REG_RESULT: usize = 0x08;
+    const REG_PAYLOAD_SIZE: usize = 0x0c;
+    const PAYLOAD_OFFSET: usize = 0x100;
+
+    /// `STATUS` bit: the last command completed successfully.
+    pub const STATUS_DONE: u32 = 1 << 0;
+    /// `STATUS` bit: the last command failed.
+    pub const STATUS_ERROR: u32 = 1 << 1;
+
+    /// Creates a mailbox at `base` with a payload window of `payload_size`
+    /// bytes, forwarding commands to `handler`.
+    pub fn new(base: GuestPhysAddr, payload_size: usize, handler: Arc<dyn MailboxHandler>) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, Self::PAYLOAD_OFFSET + payload_size),
+            handler,
+            state: Mutex::new(MailboxState {
+                command: 0,
+                status: 0,
+                result: 0,
+                payload: vec![0; payload_size],
+            }),
+        }
+    }
+
+    fn doorbell(&self, state: &mut MailboxState, command: u32) {
+        state.command = command;
+        match self.handler.handle(command, &mut state.payload) {
+            Ok(result) => {
+                state.result = result;
+                state.status = Self::STATUS_DONE;
+            }
+            Err(err) => {
+                warn!("mailbox command {command:#x} failed: {err:?}");
+                state.result = 0;
+                state.status = Self::STATUS_ERROR;
+            }
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for MailboxDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        if !self.range.contains_access(addr, width) {
+            return ax_err!(BadAddress);
+        }
+        let offset = addr.as_usize() - self.range.start.as_usize();
+        let state = self.state.lock();
+        let val = match offset {
+            Self::REG_COMMAND => state.command as usize,
+            Self::REG_STATUS => state.status as usize,
+            Self::REG_RESULT => state.result as usize,
+            Self::REG_PAYLOAD_SIZE => state.payload.len(),
+            offset if offset >= Self::PAYLOAD_OFFSET => {
+                let start = offset - Self::PAYLOAD_OFFSET;
+                let bytes = &state.payload[start..start + width.size()];
+                RegValue::from_bytes(bytes).unwrap().zero_extend()
+            }
+            _ => 0,
+        };
+        Ok(RegValue::new(val, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        if !self.range.contains_access(addr, width) {
+            return ax_err!(BadAddress);
+        }
+        let offset = addr.as_usize() - self.range.start.as_usize();
+        let mut state = self.state.lock();
+        match offset {
+            Self::REG_COMMAND => self.doorbell(&mut state, val as u32),
+            Self::REG_STATUS => state.status &= !(val as u32),
+            offset if offset >= Self::PAYLOAD_OFFSET => {
+                let start = offset - Self::PAYLOAD_OFFSET;
+                state.payload[start..start + width.size()]
+                    .copy_from_slice(RegValue::new(val, width).to_bytes());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
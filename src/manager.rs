@@ -0,0 +1,1210 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Device registration and address routing.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, AtomicU64, Ordering},
+};
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxResult, ax_err};
+use spin::RwLock;
+
+use crate::{
+    AccessCompleter, AccessContext, AccessFilter, AccessKind, AccessOutcome, BaseDeviceOps,
+    ByteOrder, Capability, ClockSource, CoalescedWrite, DeviceAddrRangeExt, DeviceManifest,
+    DeviceRegionSink, DeviceTracer, DispatchStrategy, FilterAction, GuestMemoryAccessor,
+    LatencyClass, LatencyObserver, LowPowerAccess, RawDeviceAddr, RegionUpdateHandler,
+    TimerService, TimerToken, UnhandledAccessPolicy, bulk_element_addr, bulk_element_value,
+    check_abi_version, dispatch::PageIndex, width_mask,
+};
+
+/// A shadow subscriber and the range it watches.
+type Shadow<R> = (R, Arc<dyn DeviceTracer<R>>);
+
+struct Entry<R> {
+    seq: u64,
+    start: usize,
+    end: usize,
+    /// The raw bounds of the [holes](BaseDeviceOps::holes) of the device.
+    holes: Vec<(usize, usize)>,
+    device: Arc<dyn BaseDeviceOps<R>>,
+}
+
+impl<R> Entry<R> {
+    /// Returns whether `[start, end)` touches a hole of the device.
+    fn hits_hole(&self, start: usize, end: usize) -> bool {
+        self.holes
+            .iter()
+            .any(|&(hole_start, hole_end)| start < hole_end && hole_start < end)
+    }
+}
+
+/// A table of devices keyed by their address ranges, dispatching guest
+/// accesses to the device owning the accessed address.
+///
+/// Devices are checked when they are registered: their ABI version must be
+/// compatible (see [`check_abi_version`]) and their range must not overlap any
+/// registered device. The table is kept sorted by address, so lookups are a
+/// binary search.
+///
+/// Devices are called without the table locked, so a device may register or
+/// unregister devices from its access handlers.
+///
+/// The lifecycle helpers ([`reset_all`](Self::reset_all) and friends) visit
+/// devices in registration order when bringing them up, and in reverse
+/// registration order when taking them down, so that devices registered later
+/// (which may depend on earlier ones) go down first.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use axdevice_base::DeviceManager;
+///
+/// let mmio = DeviceManager::new();
+/// mmio.register(Arc::new(Uart16550::new(...)))?;
+/// let val = mmio.handle_read(addr, width)?;
+/// ```
+pub struct DeviceManager<R> {
+    entries: RwLock<Vec<Entry<R>>>,
+    strategy: DispatchStrategy,
+    /// The page index of the entries, with [`DispatchStrategy::PageIndex`].
+    pages: RwLock<Option<PageIndex>>,
+    next_seq: AtomicU64,
+    dma: RwLock<Option<Arc<dyn GuestMemoryAccessor>>>,
+    completer: RwLock<Option<Arc<dyn AccessCompleter>>>,
+    region_handler: RwLock<Option<Arc<dyn RegionUpdateHandler>>>,
+    timers: RwLock<Option<Arc<dyn TimerService>>>,
+    clock: RwLock<Option<Arc<dyn ClockSource>>>,
+    tracer: RwLock<Option<Arc<dyn DeviceTracer<R>>>>,
+    shadows: RwLock<Vec<Shadow<R>>>,
+    filters: RwLock<Vec<Arc<dyn AccessFilter<R>>>>,
+    low_power: AtomicU8,
+    byte_order: AtomicU8,
+    unhandled: RwLock<UnhandledAccessPolicy>,
+    unhandled_regions: RwLock<Vec<(R, UnhandledAccessPolicy)>>,
+    latency_regions: RwLock<Vec<(R, LatencyClass)>>,
+    latency_observer: RwLock<Option<Arc<dyn LatencyObserver>>>,
+}
+
+impl<R: DeviceAddrRangeExt + 'static> DeviceManager<R> {
+    /// Creates an empty device manager.
+    pub const fn new() -> Self {
+        Self::with_dispatch_strategy(DispatchStrategy::Sorted)
+    }
+
+    /// Creates an empty device manager finding devices with `strategy`.
+    pub const fn with_dispatch_strategy(strategy: DispatchStrategy) -> Self {
+        Self {
+            entries: RwLock::new(Vec::new()),
+            strategy,
+            pages: RwLock::new(None),
+            next_seq: AtomicU64::new(0),
+            dma: RwLock::new(None),
+            completer: RwLock::new(None),
+            region_handler: RwLock::new(None),
+            timers: RwLock::new(None),
+            clock: RwLock::new(None),
+            tracer: RwLock::new(None),
+            shadows: RwLock::new(Vec::new()),
+            filters: RwLock::new(Vec::new()),
+            low_power: AtomicU8::new(LowPowerAccess::Allow as u8),
+            byte_order: AtomicU8::new(ByteOrder::Little as u8),
+            unhandled: RwLock::new(UnhandledAccessPolicy::Fault),
+            unhandled_regions: RwLock::new(Vec::new()),
+            latency_regions: RwLock::new(Vec::new()),
+            latency_observer: RwLock::new(None),
+        }
+    }
+
+    /// Returns the lookup strategy of the manager.
+    pub fn dispatch_strategy(&self) -> DispatchStrategy {
+        self.strategy
+    }
+
+    /// Sets the guest memory accessor injected into devices, and injects it
+    /// into all registered devices.
+    ///
+    /// Devices registered afterwards receive it at registration.
+    pub fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.write() = Some(accessor.clone());
+        for device in self.devices() {
+            device.set_dma_accessor(accessor.clone());
+        }
+    }
+
+    /// Sets the receiver of asynchronously completed accesses, and injects it
+    /// into all registered devices.
+    ///
+    /// Devices registered afterwards receive it at registration.
+    pub fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        *self.completer.write() = Some(completer.clone());
+        for device in self.devices() {
+            device.set_completer(completer.clone());
+        }
+    }
+
+    /// Sets the receiver of region changes, and injects a
+    /// [`DeviceRegionSink`] reporting to it into each registered device, so
+    /// that changes are keyed by the device reporting them.
+    ///
+    /// Devices registered afterwards receive their sink at registration.
+    pub fn set_region_handler(&self, handler: Arc<dyn RegionUpdateHandler>) {
+        *self.region_handler.write() = Some(handler.clone());
+        for device in self.devices() {
+            Self::inject_region_sink(&device, handler.clone());
+        }
+    }
+
+    /// Sets the service scheduling device timers, and injects it into all
+    /// registered devices.
+    ///
+    /// Devices registered afterwards receive it at registration.
+    pub fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        *self.timers.write() = Some(service.clone());
+        for device in self.devices() {
+            device.set_timer_service(service.clone());
+        }
+    }
+
+    /// Sets the clock devices read time from, and injects it into all
+    /// registered devices.
+    ///
+    /// Devices registered afterwards receive it at registration.
+    pub fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        *self.clock.write() = Some(clock.clone());
+        for device in self.devices() {
+            device.set_clock_source(clock.clone());
+        }
+    }
+
+    /// Sets how guest accesses to devices that are not in
+    /// [`PowerState::D0`](crate::PowerState::D0) are handled. The default is
+    /// [`LowPowerAccess::Allow`].
+    ///
+    /// The policy applies to all guest access paths, but not to debugger
+    /// accesses ([`peek`](Self::peek), [`poke`](Self::poke)) nor calls.
+    pub fn set_low_power_access(&self, policy: LowPowerAccess) {
+        self.low_power.store(policy as u8, Ordering::Relaxed);
+    }
+
+    /// Sets the byte order of the guest. The default is
+    /// [`ByteOrder::Little`].
+    ///
+    /// The values of big-endian guest accesses are converted to little-endian
+    /// before they are dispatched, and the values read are converted back, so
+    /// that device models work on little-endian values whatever the guest.
+    /// Devices declaring [`Capability::GuestByteOrder`] handle the guest byte
+    /// order themselves and get the values unconverted.
+    ///
+    /// The conversion applies to all guest access paths, but not to debugger
+    /// accesses ([`peek`](Self::peek), [`poke`](Self::poke)) nor calls.
+    /// Tracers see the values of the devices. Reads completed later through
+    /// the [`AccessCompleter`] are not converted: the hypervisor converts them
+    /// with [`ByteOrder::convert`].
+    pub fn set_guest_byte_order(&self, order: ByteOrder) {
+        self.byte_order.store(order as u8, Ordering::Relaxed);
+    }
+
+    /// Returns the byte order of the guest.
+    pub fn guest_byte_order(&self) -> ByteOrder {
+        if self.byte_order.load(Ordering::Relaxed) == ByteOrder::Big as u8 {
+            ByteOrder::Big
+        } else {
+            ByteOrder::Little
+        }
+    }
+
+    /// Sets how unhandled guest reads and writes are completed, unless a
+    /// policy is set for the accessed region with
+    /// [`set_region_unhandled_policy`](Self::set_region_unhandled_policy). The
+    /// default is [`UnhandledAccessPolicy::Fault`].
+    ///
+    /// The policy applies to [`handle_read`](Self::handle_read),
+    /// [`handle_write`](Self::handle_write) and their `_ctx` and `_async`
+    /// variants.
+    pub fn set_unhandled_policy(&self, policy: UnhandledAccessPolicy) {
+        *self.unhandled.write() = policy;
+    }
+
+    /// Sets how unhandled guest reads and writes within `range` are
+    /// completed, e.g. the range of a single device or one of its register
+    /// blocks.
+    ///
+    /// If the ranges of several calls overlap, the latest call takes
+    /// precedence. Setting the policy of a range again replaces its previous
+    /// policy.
+    pub fn set_region_unhandled_policy(&self, range: R, policy: UnhandledAccessPolicy) {
+        let mut regions = self.unhandled_regions.write();
+        regions.retain(|(region, _)| region.raw_bounds() != range.raw_bounds());
+        regions.push((range, policy));
+    }
+
+    /// Removes the policy set for `range` with
+    /// [`set_region_unhandled_policy`](Self::set_region_unhandled_policy),
+    /// returning it.
+    ///
+    /// Only a policy set for exactly `range` is removed.
+    pub fn remove_region_unhandled_policy(&self, range: R) -> Option<UnhandledAccessPolicy> {
+        let mut regions = self.unhandled_regions.write();
+        let index = regions
+            .iter()
+            .position(|(region, _)| region.raw_bounds() == range.raw_bounds())?;
+        Some(regions.remove(index).1)
+    }
+
+    /// Returns the policy for unhandled accesses to `addr`.
+    pub fn unhandled_policy(&self, addr: R::Addr) -> UnhandledAccessPolicy {
+        Self::region_setting(&self.unhandled_regions.read(), addr)
+            .unwrap_or_else(|| *self.unhandled.read())
+    }
+
+    /// Sets the latency class of the guest accesses within `range`.
+    /// Accesses outside all ranges with a class are
+    /// [`LatencyClass::Normal`].
+    ///
+    /// If the ranges of several calls overlap, the latest call takes
+    /// precedence. Setting the class of a range again replaces its previous
+    /// class.
+    pub fn set_region_latency(&self, range: R, class: LatencyClass) {
+        let mut regions = self.latency_regions.write();
+        regions.retain(|(region, _)| region.raw_bounds() != range.raw_bounds());
+        regions.push((range, class));
+    }
+
+    /// Returns the latency class of the guest accesses to `addr`.
+    pub fn latency_class(&self, addr: R::Addr) -> LatencyClass {
+        Self::region_setting(&self.latency_regions.read(), addr).unwrap_or_default()
+    }
+
+    /// Installs `observer` to receive the latency class of every guest read
+    /// and write before it is dispatched, or removes it if `None`.
+    ///
+    /// Debugger accesses and calls are not reported.
+    pub fn set_latency_observer(&self, observer: Option<Arc<dyn LatencyObserver>>) {
+        *self.latency_observer.write() = observer;
+    }
+
+    /// Installs `tracer` to observe all dispatched accesses, or disables
+    /// tracing if `None`.
+    ///
+    /// Accesses that are not routed to a device (e.g. to an unmapped address)
+    /// are not traced. Bulk accesses are traced element by element when they
+    /// succeed, and as a single access at their first address when they fail.
+    pub fn set_tracer(&self, tracer: Option<Arc<dyn DeviceTracer<R>>>) {
+        *self.tracer.write() = tracer;
+    }
+
+    /// Subscribes `subscriber` to the accesses within `range`, e.g. for a
+    /// coverage or monitoring tool watching devices it does not own.
+    ///
+    /// After the owning device handled an access within `range`, the
+    /// subscriber receives a copy of it as a [`DeviceTracer`] installed with
+    /// [`set_tracer`](Self::set_tracer) would, but cannot change its outcome.
+    /// A subscriber may subscribe to several ranges, and several subscribers
+    /// to the same range. Subscribers are called with the subscriptions
+    /// locked, so they must not subscribe or unsubscribe.
+    pub fn add_shadow_subscriber(&self, range: R, subscriber: Arc<dyn DeviceTracer<R>>) {
+        self.shadows.write().push((range, subscriber));
+    }
+
+    /// Removes all subscriptions of `subscriber` made with
+    /// [`add_shadow_subscriber`](Self::add_shadow_subscriber), returning
+    /// whether it was subscribed.
+    pub fn remove_shadow_subscriber(&self, subscriber: &Arc<dyn DeviceTracer<R>>) -> bool {
+        let mut shadows = self.shadows.write();
+        let len = shadows.len();
+        shadows.retain(|(_, shadow)| !Arc::ptr_eq(shadow, subscriber));
+        shadows.len() != len
+    }
+
+    /// Adds `filter` after the installed access filters, to allow, deny or
+    /// rewrite the guest accesses before they reach the devices.
+    ///
+    /// Filters see the accesses that are routed to a powered device, after
+    /// their conversion from the guest byte order, and before they are
+    /// coalesced. Bulk accesses are filtered element by element, and fail as
+    /// a whole if any element is denied. Filters are called with the filter
+    /// list locked, so they must not add or remove filters.
+    pub fn add_access_filter(&self, filter: Arc<dyn AccessFilter<R>>) {
+        self.filters.write().push(filter);
+    }
+
+    /// Removes `filter`, added with
+    /// [`add_access_filter`](Self::add_access_filter), returning whether it
+    /// was installed.
+    pub fn remove_access_filter(&self, filter: &Arc<dyn AccessFilter<R>>) -> bool {
+        let mut filters = self.filters.write();
+        let len = filters.len();
+        filters.retain(|installed| !Arc::ptr_eq(installed, filter));
+        filters.len() != len
+    }
+
+    /// Registers `device` at its [`address_range`](BaseDeviceOps::address_range).
+    ///
+    /// Once the device is registered, the guest memory accessor (see
+    /// [`set_dma_accessor`](Self::set_dma_accessor)) is injected into it if
+    /// set, and likewise for the completer (see
+    /// [`set_completer`](Self::set_completer)), a region sink (see
+    /// [`set_region_handler`](Self::set_region_handler)), the timer service (see
+    /// [`set_timer_service`](Self::set_timer_service)) and the clock (see
+    /// [`set_clock_source`](Self::set_clock_source)). A device that is
+    /// refused never sees them.
+    ///
+    /// # Returns
+    ///
+    /// - `Err(AxError::Unsupported)`: The device was built against an
+    ///   incompatible ABI.
+    /// - `Err(AxError::InvalidInput)`: The device range is empty, or one of its
+    ///   [holes](BaseDeviceOps::holes) is not within it.
+    /// - `Err(AxError::AlreadyExists)`: The device range overlaps a registered
+    ///   device.
+    pub fn register(&self, device: Arc<dyn BaseDeviceOps<R>>) -> AxResult {
+        check_abi_version(device.abi_version())?;
+        let (start, end) = device.address_range().raw_bounds();
+        if start >= end {
+            return ax_err!(InvalidInput, "empty device range");
+        }
+        let holes: Vec<_> = device
+            .holes()
+            .iter()
+            .map(DeviceAddrRangeExt::raw_bounds)
+            .collect();
+        if holes
+            .iter()
+            .any(|&(hole_start, hole_end)| hole_start < start || hole_end > end)
+        {
+            return ax_err!(InvalidInput, "device hole outside of the device range");
+        }
+        let mut entries = self.entries.write();
+        let index = entries.partition_point(|entry| entry.end <= start);
+        if entries.get(index).is_some_and(|entry| entry.start < end) {
+            return ax_err!(AlreadyExists, "device range overlaps a registered device");
+        }
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        entries.insert(
+            index,
+            Entry {
+                seq,
+                start,
+                end,
+                holes,
+                device: device.clone(),
+            },
+        );
+        self.reindex(&entries);
+        drop(entries);
+        if let Some(accessor) = self.dma.read().clone() {
+            device.set_dma_accessor(accessor);
+        }
+        if let Some(completer) = self.completer.read().clone() {
+            device.set_completer(completer);
+        }
+        if let Some(handler) = self.region_handler.read().clone() {
+            Self::inject_region_sink(&device, handler);
+        }
+        if let Some(service) = self.timers.read().clone() {
+            device.set_timer_service(service);
+        }
+        if let Some(clock) = self.clock.read().clone() {
+            device.set_clock_source(clock);
+        }
+        Ok(())
+    }
+
+    /// Unregisters and returns the device owning `addr`, if any.
+    pub fn unregister(&self, addr: R::Addr) -> Option<Arc<dyn BaseDeviceOps<R>>> {
+        let mut entries = self.entries.write();
+        let index = self.index_of(&entries, addr.to_raw())?;
+        let entry = entries.remove(index);
+        self.reindex(&entries);
+        Some(entry.device)
+    }
+
+    /// Returns the device owning `addr`, if any.
+    pub fn find(&self, addr: R::Addr) -> Option<Arc<dyn BaseDeviceOps<R>>> {
+        let entries = self.entries.read();
+        self.index_of(&entries, addr.to_raw())
+            .map(|index| entries[index].device.clone())
+    }
+
+    /// Returns all registered devices, sorted by address.
+    pub fn devices(&self) -> Vec<Arc<dyn BaseDeviceOps<R>>> {
+        self.entries
+            .read()
+            .iter()
+            .map(|entry| entry.device.clone())
+            .collect()
+    }
+
+    /// Writes the registers of all devices (see
+    /// [`BaseDeviceOps::dump_registers`]) to `out`, one device after another
+    /// in address order.
+    pub fn dump(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        for device in self.devices() {
+            let (start, end) = device.address_range().raw_bounds();
+            writeln!(out, "{:?} [{start:#x}, {end:#x}):", device.emu_type())?;
+            for reg in device.dump_registers() {
+                writeln!(out, "  {reg}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first registered interrupt controller, i.e. the first
+    /// device whose [`as_irq_chip`](BaseDeviceOps::as_irq_chip) returns
+    /// `Some`.
+    pub fn irq_chip(&self) -> Option<Arc<dyn BaseDeviceOps<R>>> {
+        self.entries
+            .read()
+            .iter()
+            .find(|entry| entry.device.as_irq_chip().is_some())
+            .map(|entry| entry.device.clone())
+    }
+
+    /// Delivers the expiry of the timer `token` to the device that scheduled
+    /// it, returning whether a registered device claimed the token.
+    ///
+    /// The [`TimerService`] calls this when a timer expires.
+    pub fn fire_timer(&self, token: TimerToken) -> bool {
+        self.devices().iter().any(|device| device.on_timer(token))
+    }
+
+    /// Returns the manifests of all registered devices with their ranges,
+    /// sorted by address.
+    pub fn manifest(&self) -> Vec<(R, DeviceManifest)> {
+        self.entries
+            .read()
+            .iter()
+            .map(|entry| (entry.device.address_range(), entry.device.manifest()))
+            .collect()
+    }
+
+    /// Returns the number of registered devices.
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    /// Returns whether no device is registered.
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().is_empty()
+    }
+
+    /// Dispatches a guest read to the device owning `addr`.
+    ///
+    /// Returns `Err(AxError::NotFound)` if no device owns `addr`, and
+    /// `Err(AxError::BadAddress)` if the access runs past the end of the
+    /// device range. Unhandled accesses are completed according to the
+    /// [`unhandled_policy`](Self::unhandled_policy) of `addr`.
+    pub fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.report_latency(addr);
+        let result = self.route(addr, width).and_then(|device| {
+            if !self.powered(&device)? {
+                return Ok(width_mask(width));
+            }
+            let result = self
+                .filter_read(&device, addr, width)
+                .and_then(|rewritten| {
+                    rewritten.map_or_else(|| device.handle_read(addr, width), Ok)
+                });
+            self.trace_read(&device, addr, width, result)
+                .map(|val| self.convert(&device, val, width))
+        });
+        self.resolve_read(addr, width, result)
+    }
+
+    /// Dispatches a guest write to the device owning `addr`.
+    ///
+    /// Writes covered by the device's coalesced write ring (see
+    /// [`BaseDeviceOps::coalesced_writes`]) are logged there instead, unless
+    /// the ring is full.
+    ///
+    /// Returns the same errors as [`handle_read`](Self::handle_read).
+    pub fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.report_latency(addr);
+        let result = self.route(addr, width).and_then(|device| {
+            if !self.powered(&device)? {
+                return Ok(());
+            }
+            let val = self.convert(&device, val, width);
+            let (val, result) = match self.filter_write(&device, addr, width, val) {
+                Ok(val) if Self::coalesce(&device, addr, width, val) => (val, Ok(())),
+                Ok(val) => (val, device.handle_write(addr, width, val)),
+                Err(error) => (val, Err(error)),
+            };
+            self.trace_write(&device, addr, width, val, result)
+        });
+        self.resolve_write(addr, width, val, result)
+    }
+
+    /// Dispatches a guest read to the device owning `addr`, passing the
+    /// access context.
+    ///
+    /// Returns the same errors as [`handle_read`](Self::handle_read).
+    pub fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        self.report_latency(addr);
+        let result = self.route(addr, width).and_then(|device| {
+            if !self.powered(&device)? {
+                return Ok(width_mask(width));
+            }
+            let result = self
+                .filter_read(&device, addr, width)
+                .and_then(|rewritten| {
+                    rewritten.map_or_else(|| device.handle_read_ctx(addr, width, ctx), Ok)
+                });
+            self.trace_read(&device, addr, width, result)
+                .map(|val| self.convert(&device, val, width))
+        });
+        self.resolve_read(addr, width, result)
+    }
+
+    /// Dispatches a guest write to the device owning `addr`, passing the
+    /// access context.
+    ///
+    /// Writes are coalesced as in [`handle_write`](Self::handle_write), in
+    /// which case the context is dropped.
+    ///
+    /// Returns the same errors as [`handle_read`](Self::handle_read).
+    pub fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        self.report_latency(addr);
+        let result = self.route(addr, width).and_then(|device| {
+            if !self.powered(&device)? {
+                return Ok(());
+            }
+            let val = self.convert(&device, val, width);
+            let (val, result) = match self.filter_write(&device, addr, width, val) {
+                Ok(val) if Self::coalesce(&device, addr, width, val) => (val, Ok(())),
+                Ok(val) => (val, device.handle_write_ctx(addr, width, val, ctx)),
+                Err(error) => (val, Err(error)),
+            };
+            self.trace_write(&device, addr, width, val, result)
+        });
+        self.resolve_write(addr, width, val, result)
+    }
+
+    /// Dispatches a guest read that the device may complete asynchronously
+    /// (see [`BaseDeviceOps::handle_read_async`]).
+    ///
+    /// Pending accesses are traced with a value of 0 when they are issued.
+    ///
+    /// Returns the same errors as [`handle_read`](Self::handle_read).
+    pub fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        self.report_latency(addr);
+        let result = self.route(addr, width).and_then(|device| {
+            if !self.powered(&device)? {
+                return Ok(AccessOutcome::Completed(width_mask(width)));
+            }
+            let result =
+                self.filter_read(&device, addr, width)
+                    .and_then(|rewritten| match rewritten {
+                        Some(val) => Ok(AccessOutcome::Completed(val)),
+                        None => device.handle_read_async(addr, width),
+                    });
+            let value = match result {
+                Ok(AccessOutcome::Completed(val)) => val,
+                _ => 0,
+            };
+            let status = result.map(|_| ());
+            self.observe(&*device, addr, width, value, AccessKind::Read, status);
+            result.map(|outcome| match outcome {
+                AccessOutcome::Completed(val) => {
+                    AccessOutcome::Completed(self.convert(&device, val, width))
+                }
+                pending => pending,
+            })
+        });
+        match result {
+            Err(error) => self
+                .resolve_read(addr, width, Err(error))
+                .map(AccessOutcome::Completed),
+            outcome => outcome,
+        }
+    }
+
+    /// Dispatches a guest write that the device may complete asynchronously
+    /// (see [`BaseDeviceOps::handle_write_async`]).
+    ///
+    /// Returns the same errors as [`handle_read`](Self::handle_read).
+    pub fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        self.report_latency(addr);
+        let result = self.route(addr, width).and_then(|device| {
+            if !self.powered(&device)? {
+                return Ok(AccessOutcome::Completed(0));
+            }
+            let val = self.convert(&device, val, width);
+            let (val, result) = match self.filter_write(&device, addr, width, val) {
+                Ok(val) => (val, device.handle_write_async(addr, width, val)),
+                Err(error) => (val, Err(error)),
+            };
+            let status = result.map(|_| ());
+            self.observe(&*device, addr, width, val, AccessKind::Write, status);
+            result
+        });
+        match result {
+            Err(error) => self
+                .resolve_write(addr, width, val, Err(error))
+                .map(|()| AccessOutcome::Completed(0)),
+            outcome => outcome,
+        }
+    }
+
+    /// Dispatches a guest call (see [`BaseDeviceOps::handle_call`]) to the
+    /// device owning the function `addr`.
+    ///
+    /// Returns `Err(AxError::NotFound)` if no device owns `addr`. Calls are
+    /// not traced.
+    pub fn handle_call(&self, addr: R::Addr, args: &[usize]) -> AxResult<[usize; 4]> {
+        self.route(addr, AccessWidth::Byte)?.handle_call(addr, args)
+    }
+
+    /// Reads a register of the device owning `addr` for a debugger (see
+    /// [`BaseDeviceOps::peek`]).
+    ///
+    /// Returns the same errors as [`handle_read`](Self::handle_read), and
+    /// `Err(AxError::Unsupported)` if the device does not support debugger
+    /// accesses. Debugger accesses are not traced.
+    pub fn peek(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.route(addr, width)?.peek(addr, width)
+    }
+
+    /// Writes a register of the device owning `addr` for a debugger (see
+    /// [`BaseDeviceOps::poke`]).
+    ///
+    /// Returns the same errors as [`peek`](Self::peek).
+    pub fn poke(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.route(addr, width)?.poke(addr, width, val)
+    }
+
+    /// Dispatches a bulk guest read (see [`BaseDeviceOps::handle_read_bulk`])
+    /// to the device owning all its elements.
+    ///
+    /// Returns the same errors as [`handle_read`](Self::handle_read), and
+    /// `Err(AxError::BadAddress)` if the elements span several devices.
+    pub fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        self.report_latency(addr);
+        let device = self.route_bulk(addr, width, count, stride)?;
+        if !self.powered(&device)? {
+            buf.fill(0xff);
+            return Ok(());
+        }
+        let result = self
+            .filter_read_bulk(&device, addr, width, count, stride)
+            .and_then(|rewrites| {
+                device.handle_read_bulk(addr, width, count, stride, buf)?;
+                let size = width.size();
+                for (index, val) in rewrites {
+                    buf[index * size..][..size]
+                        .copy_from_slice(&(val as u64).to_le_bytes()[..size]);
+                }
+                Ok(())
+            });
+        self.trace_bulk(&device, addr, width, stride, buf, AccessKind::Read, result)?;
+        if self.converts(&device) {
+            buf.chunks_exact_mut(width.size()).for_each(<[u8]>::reverse);
+        }
+        Ok(())
+    }
+
+    /// Dispatches a bulk guest write (see [`BaseDeviceOps::handle_write_bulk`])
+    /// to the device owning all its elements.
+    ///
+    /// Returns the same errors as [`handle_read_bulk`](Self::handle_read_bulk).
+    pub fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult {
+        self.report_latency(addr);
+        let device = self.route_bulk(addr, width, count, stride)?;
+        if !self.powered(&device)? {
+            return Ok(());
+        }
+        // Converted or filtered values are written from a copy of the buffer.
+        let mut prepared = None;
+        if self.converts(&device) || !self.filters.read().is_empty() {
+            let mut copy = buf.to_vec();
+            if self.converts(&device) {
+                copy.chunks_exact_mut(width.size())
+                    .for_each(<[u8]>::reverse);
+            }
+            prepared = Some(copy);
+        }
+        let result = match prepared.as_mut() {
+            Some(copy) => self.filter_write_bulk(&device, addr, width, stride, copy),
+            None => Ok(()),
+        };
+        let buf = prepared.as_deref().unwrap_or(buf);
+        let result =
+            result.and_then(|()| device.handle_write_bulk(addr, width, count, stride, buf));
+        self.trace_bulk(&device, addr, width, stride, buf, AccessKind::Write, result)
+    }
+
+    /// Resets all devices, in registration order.
+    ///
+    /// Stops at the first device returning an error, and returns that error.
+    pub fn reset_all(&self) -> AxResult {
+        self.for_each_in_order(false, |device| device.reset())
+    }
+
+    /// Pauses all devices, in reverse registration order.
+    ///
+    /// Stops at the first device returning an error, and returns that error.
+    pub fn pause_all(&self) -> AxResult {
+        self.for_each_in_order(true, |device| device.pause())
+    }
+
+    /// Resumes all devices, in registration order.
+    ///
+    /// Stops at the first device returning an error, and returns that error.
+    pub fn resume_all(&self) -> AxResult {
+        self.for_each_in_order(false, |device| device.resume())
+    }
+
+    /// Shuts all devices down, in reverse registration order.
+    ///
+    /// Every device is shut down even if an earlier one fails; the first error
+    /// is returned.
+    pub fn shutdown_all(&self) -> AxResult {
+        let mut result = Ok(());
+        let _ = self.for_each_in_order(true, |device| {
+            result = result.and(device.shutdown());
+            Ok(())
+        });
+        result
+    }
+
+    /// Saves the state of all devices, in registration order.
+    ///
+    /// Fails if any device cannot save its state (see
+    /// [`BaseDeviceOps::save_state`]). The devices should be paused first.
+    pub fn save_all(&self) -> AxResult<Vec<(R, Vec<u8>)>> {
+        self.in_registration_order()
+            .iter()
+            .map(|device| Ok((device.address_range(), device.save_state()?)))
+            .collect()
+    }
+
+    /// Restores the state saved by [`save_all`](Self::save_all), in
+    /// registration order.
+    ///
+    /// Returns `Err(AxError::InvalidData)` if the saved device set does not
+    /// match the registered devices and their ranges. The devices should be
+    /// paused first.
+    pub fn load_all(&self, states: &[(R, Vec<u8>)]) -> AxResult {
+        let devices = self.in_registration_order();
+        if devices.len() != states.len() {
+            return ax_err!(InvalidData, "saved device set does not match");
+        }
+        for (device, (range, state)) in devices.iter().zip(states) {
+            if device.address_range().raw_bounds() != range.raw_bounds() {
+                return ax_err!(InvalidData, "saved device set does not match");
+            }
+            device.load_state(state)?;
+        }
+        Ok(())
+    }
+
+    /// Injects the sink of `device`, keyed by the start of its range.
+    fn inject_region_sink(
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        handler: Arc<dyn RegionUpdateHandler>,
+    ) {
+        let key = device.address_range().raw_bounds().0;
+        device.set_region_sink(Arc::new(DeviceRegionSink::new(key, handler)));
+    }
+
+    /// Returns all registered devices, in registration order.
+    fn in_registration_order(&self) -> Vec<Arc<dyn BaseDeviceOps<R>>> {
+        let entries = self.entries.read();
+        let mut devices: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.seq, entry.device.clone()))
+            .collect();
+        devices.sort_unstable_by_key(|(seq, _)| *seq);
+        devices.into_iter().map(|(_, device)| device).collect()
+    }
+
+    fn for_each_in_order(
+        &self,
+        reverse: bool,
+        f: impl FnMut(&Arc<dyn BaseDeviceOps<R>>) -> AxResult,
+    ) -> AxResult {
+        let mut devices = self.in_registration_order();
+        if reverse {
+            devices.reverse();
+        }
+        devices.iter().try_for_each(f)
+    }
+
+    fn route(&self, addr: R::Addr, width: AccessWidth) -> AxResult<Arc<dyn BaseDeviceOps<R>>> {
+        let entries = self.entries.read();
+        let Some(index) = self.index_of(&entries, addr.to_raw()) else {
+            return ax_err!(NotFound, "no device at the accessed address");
+        };
+        let entry = &entries[index];
+        let end = addr.to_raw().saturating_add(width.size());
+        if end > entry.end {
+            return ax_err!(BadAddress, "access crosses the end of the device range");
+        }
+        if entry.hits_hole(addr.to_raw(), end) {
+            return ax_err!(NotFound, "access to a hole in the device range");
+        }
+        Ok(entry.device.clone())
+    }
+
+    fn trace_read(
+        &self,
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+        result: AxResult<usize>,
+    ) -> AxResult<usize> {
+        let value = *result.as_ref().unwrap_or(&0);
+        let status = result.map(|_| ());
+        self.observe(&**device, addr, width, value, AccessKind::Read, status);
+        result
+    }
+
+    fn trace_write(
+        &self,
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        result: AxResult,
+    ) -> AxResult {
+        self.observe(&**device, addr, width, val, AccessKind::Write, result);
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn trace_bulk(
+        &self,
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+        stride: isize,
+        buf: &[u8],
+        kind: AccessKind,
+        result: AxResult,
+    ) -> AxResult {
+        if self.tracer.read().is_none() && self.shadows.read().is_empty() {
+            return result;
+        }
+        if result.is_err() {
+            self.observe(&**device, addr, width, 0, kind, result);
+            return result;
+        }
+        let size = width.size();
+        for (index, element) in buf.chunks_exact(size).enumerate() {
+            let mut bytes = [0; 8];
+            bytes[..size].copy_from_slice(element);
+            let value = u64::from_le_bytes(bytes) as usize;
+            // The addresses were validated by the device.
+            if let Ok(element_addr) = bulk_element_addr(addr, index, stride) {
+                self.observe(&**device, element_addr, width, value, kind, Ok(()));
+            }
+        }
+        result
+    }
+
+    /// Reports an access handled by `device` to the tracer and the shadow
+    /// subscribers of `addr`.
+    fn observe(
+        &self,
+        device: &dyn BaseDeviceOps<R>,
+        addr: R::Addr,
+        width: AccessWidth,
+        value: usize,
+        kind: AccessKind,
+        result: AxResult,
+    ) {
+        if let Some(tracer) = self.tracer.read().as_ref() {
+            tracer.on_access(device, addr, width, value, kind, result);
+        }
+        for (range, subscriber) in self.shadows.read().iter() {
+            if range.contains(addr) {
+                subscriber.on_access(device, addr, width, value, kind, result);
+            }
+        }
+    }
+
+    fn route_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+    ) -> AxResult<Arc<dyn BaseDeviceOps<R>>> {
+        let device = self.route(addr, width)?;
+        if count > 1 {
+            let last_addr = bulk_element_addr(addr, count - 1, stride)?;
+            let last = self.route(last_addr, width)?;
+            if !core::ptr::addr_eq(Arc::as_ptr(&device), Arc::as_ptr(&last)) {
+                return ax_err!(BadAddress, "bulk access spans several devices");
+            }
+            // Elements between the first and the last may still hit a hole.
+            let entries = self.entries.read();
+            if let Some(index) = self.index_of(&entries, addr.to_raw())
+                && !entries[index].holes.is_empty()
+            {
+                for element in 1..count - 1 {
+                    let start = bulk_element_addr(addr, element, stride)?.to_raw();
+                    if entries[index].hits_hole(start, start + width.size()) {
+                        return ax_err!(NotFound, "bulk access to a hole in the device range");
+                    }
+                }
+            }
+        }
+        Ok(device)
+    }
+
+    /// Returns whether a guest access to `device` must be dispatched, or
+    /// completed without the device because it is in a low-power state (see
+    /// [`set_low_power_access`](Self::set_low_power_access)).
+    fn powered(&self, device: &Arc<dyn BaseDeviceOps<R>>) -> AxResult<bool> {
+        let policy = self.low_power.load(Ordering::Relaxed);
+        if policy == LowPowerAccess::Allow as u8 || device.power_state().is_active() {
+            return Ok(true);
+        }
+        if policy == LowPowerAccess::Refuse as u8 {
+            return ax_err!(BadState, "device is in a low-power state");
+        }
+        Ok(false)
+    }
+
+    /// Applies the access filters to a read of `device`, returning the value
+    /// it completes with if a filter rewrote it.
+    fn filter_read(
+        &self,
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+    ) -> AxResult<Option<usize>> {
+        for filter in self.filters.read().iter() {
+            match filter.filter(&**device, addr, width, 0, AccessKind::Read) {
+                FilterAction::Allow => {}
+                FilterAction::Deny => return ax_err!(PermissionDenied, "read denied by a filter"),
+                FilterAction::Rewrite(val) => return Ok(Some(val)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Applies the access filters to a write of `val` to `device`, returning
+    /// the value to write.
+    fn filter_write(
+        &self,
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+        mut val: usize,
+    ) -> AxResult<usize> {
+        for filter in self.filters.read().iter() {
+            match filter.filter(&**device, addr, width, val, AccessKind::Write) {
+                FilterAction::Allow => {}
+                FilterAction::Deny => return ax_err!(PermissionDenied, "write denied by a filter"),
+                FilterAction::Rewrite(new) => val = new,
+            }
+        }
+        Ok(val)
+    }
+
+    /// Applies the access filters to each element of a bulk read, returning
+    /// the indices and values of the elements rewritten.
+    fn filter_read_bulk(
+        &self,
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+    ) -> AxResult<Vec<(usize, usize)>> {
+        let mut rewrites = Vec::new();
+        if self.filters.read().is_empty() {
+            return Ok(rewrites);
+        }
+        for index in 0..count {
+            let element_addr = bulk_element_addr(addr, index, stride)?;
+            if let Some(val) = self.filter_read(device, element_addr, width)? {
+                rewrites.push((index, val));
+            }
+        }
+        Ok(rewrites)
+    }
+
+    /// Applies the access filters to each element of a bulk write, rewriting
+    /// the elements of `buf` in place.
+    fn filter_write_bulk(
+        &self,
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        let size = width.size();
+        for (index, element) in buf.chunks_exact_mut(size).enumerate() {
+            let element_addr = bulk_element_addr(addr, index, stride)?;
+            let val =
+                self.filter_write(device, element_addr, width, bulk_element_value(element))?;
+            element.copy_from_slice(&(val as u64).to_le_bytes()[..size]);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the values of guest accesses to `device` are converted
+    /// from the guest byte order.
+    fn converts(&self, device: &Arc<dyn BaseDeviceOps<R>>) -> bool {
+        self.guest_byte_order() == ByteOrder::Big && !device.supports(Capability::GuestByteOrder)
+    }
+
+    /// Converts the value of a guest access to `device` between the guest
+    /// byte order and little-endian.
+    fn convert(&self, device: &Arc<dyn BaseDeviceOps<R>>, val: usize, width: AccessWidth) -> usize {
+        if self.converts(device) {
+            ByteOrder::Big.convert(val, width)
+        } else {
+            val
+        }
+    }
+
+    /// Reports the latency class of a guest access to `addr` to the latency
+    /// observer, if any.
+    fn report_latency(&self, addr: R::Addr) {
+        if let Some(observer) = self.latency_observer.read().as_ref() {
+            observer.on_access(addr.to_raw(), self.latency_class(addr));
+        }
+    }
+
+    /// Returns the setting of the latest region in `regions` containing
+    /// `addr`.
+    fn region_setting<T: Copy>(regions: &[(R, T)], addr: R::Addr) -> Option<T> {
+        regions
+            .iter()
+            .rev()
+            .find(|(range, _)| range.contains(addr))
+            .map(|(_, setting)| *setting)
+    }
+
+    /// Completes a failed read according to the unhandled access policy of
+    /// `addr`.
+    fn resolve_read(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        result: AxResult<usize>,
+    ) -> AxResult<usize> {
+        match result {
+            Err(error) if UnhandledAccessPolicy::is_unhandled(error) => self
+                .unhandled_policy(addr)
+                .resolve_read(addr.to_raw(), width, result),
+            result => result,
+        }
+    }
+
+    /// Completes a failed write according to the unhandled access policy of
+    /// `addr`.
+    fn resolve_write(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        result: AxResult,
+    ) -> AxResult {
+        match result {
+            Err(error) if UnhandledAccessPolicy::is_unhandled(error) => self
+                .unhandled_policy(addr)
+                .resolve_write(addr.to_raw(), width, val, result),
+            result => result,
+        }
+    }
+
+    /// Logs the write in the device's coalesced write ring, if it covers the
+    /// address and has room for it.
+    fn coalesce(
+        device: &Arc<dyn BaseDeviceOps<R>>,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> bool {
+        device.coalesced_writes().is_some_and(|ring| {
+            ring.covers(addr, width) && ring.try_push(CoalescedWrite { addr, width, val })
+        })
+    }
+
+    /// Rebuilds the page index after `entries` changed.
+    fn reindex(&self, entries: &[Entry<R>]) {
+        if self.strategy == DispatchStrategy::PageIndex {
+            let ranges = entries.iter().map(|entry| (entry.start, entry.end));
+            *self.pages.write() = Some(PageIndex::build(ranges));
+        }
+    }
+
+    fn index_of(&self, entries: &[Entry<R>], raw: usize) -> Option<usize> {
+        // The entries covering the page of `raw` follow the indexed one.
+        if let Some(first) = self.pages.read().as_ref().and_then(|pages| pages.get(raw))
+            && let Some(offset) = entries[first..]
+                .iter()
+                .take_while(|entry| entry.start <= raw)
+                .position(|entry| raw < entry.end)
+        {
+            return Some(first + offset);
+        }
+        Self::search(entries, raw)
+    }
+
+    fn search(entries: &[Entry<R>], raw: usize) -> Option<usize> {
+        let index = entries.partition_point(|entry| entry.end <= raw);
+        entries
+            .get(index)
+            .is_some_and(|entry| entry.start <= raw)
+            .then_some(index)
+    }
+}
+
+impl<R: DeviceAddrRangeExt + 'static> Default for DeviceManager<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Device documentation metadata for machine descriptions.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::GuestProfile;
+
+/// Human-readable metadata describing a device model, returned by
+/// [`BaseDeviceOps::manifest`](crate::BaseDeviceOps::manifest).
+///
+/// Management tooling collects the manifests of all devices of a VM (see
+/// [`DeviceManager::manifest`](crate::DeviceManager::manifest)) to describe
+/// the emulated machine without parsing device source code.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::{DeviceManifest, GuestProfile};
+///
+/// let manifest = DeviceManifest::new("PL011 UART")
+///     .with_compatible("arm,pl011")
+///     .with_guest_profile(GuestProfile::Linux);
+/// assert_eq!(manifest.compatible, ["arm,pl011"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DeviceManifest {
+    /// The model name of the device.
+    pub model: String,
+    /// Device-tree style compatible strings, most specific first.
+    pub compatible: Vec<String>,
+    /// The guest profiles the device model has been validated with. Empty if
+    /// not declared.
+    pub guest_profiles: Vec<GuestProfile>,
+}
+
+impl DeviceManifest {
+    /// Creates a manifest for the device model `model`.
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            compatible: Vec::new(),
+            guest_profiles: Vec::new(),
+        }
+    }
+
+    /// Adds a compatible string.
+    pub fn with_compatible(mut self, compatible: impl Into<String>) -> Self {
+        self.compatible.push(compatible.into());
+        self
+    }
+
+    /// Adds a supported guest profile.
+    pub fn with_guest_profile(mut self, profile: GuestProfile) -> Self {
+        self.guest_profiles.push(profile);
+        self
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MSI-X table and pending bit array emulation.
+
+use alloc::{vec, vec::Vec};
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+/// A message signalled interrupt: a `data` write to `address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MsiMessage {
+    /// The address the message is written to.
+    pub address: u64,
+    /// The message data.
+    pub data: u32,
+}
+
+#[derive(Clone, Copy)]
+struct MsixEntry {
+    words: [u32; 4],
+    pending: bool,
+}
+
+impl MsixEntry {
+    const MASKED: Self = Self {
+        words: [0, 0, 0, MsixTable::VECTOR_MASKED],
+        pending: false,
+    };
+
+    fn message(&self) -> MsiMessage {
+        MsiMessage {
+            address: (self.words[1] as u64) << 32 | self.words[0] as u64,
+            data: self.words[2],
+        }
+    }
+
+    fn masked(&self) -> bool {
+        self.words[3] & MsixTable::VECTOR_MASKED != 0
+    }
+}
+
+struct MsixState {
+    entries: Vec<MsixEntry>,
+    enabled: bool,
+    function_masked: bool,
+}
+
+impl MsixState {
+    fn deliverable(&self, entry: &MsixEntry) -> bool {
+        self.enabled && !self.function_masked && !entry.masked()
+    }
+
+    fn release_pending(&mut self) -> Vec<MsiMessage> {
+        let mut messages = Vec::new();
+        for index in 0..self.entries.len() {
+            let entry = self.entries[index];
+            if entry.pending && self.deliverable(&entry) {
+                self.entries[index].pending = false;
+                messages.push(entry.message());
+            }
+        }
+        messages
+    }
+}
+
+/// The MSI-X table and pending bit array (PBA) of a PCI function.
+///
+/// The device maps the table and the PBA into one of its BARs and forwards
+/// the guest accesses to [`read_table`](Self::read_table),
+/// [`write_table`](Self::write_table) and [`read_pba`](Self::read_pba). The
+/// enable and function mask bits of the MSI-X capability are mirrored with
+/// [`set_control`](Self::set_control).
+///
+/// Interrupts are raised with [`trigger`](Self::trigger), which returns the
+/// message to deliver, or latches the pending bit while the vector is masked.
+/// Methods that unmask vectors return the pending messages released by the
+/// change. Delivering the messages is left to the device.
+pub struct MsixTable {
+    state: Mutex<MsixState>,
+}
+
+impl MsixTable {
+    /// The maximum number of vectors of a function.
+    pub const MAX_VECTORS: usize = 2048;
+    /// The size of a table entry in bytes.
+    pub const ENTRY_SIZE: usize = 16;
+    /// Vector control bit: the vector is masked.
+    pub const VECTOR_MASKED: u32 = 1;
+
+    /// Creates a table of `num_vectors` vectors, all masked.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `num_vectors` is 0 or above
+    /// [`MAX_VECTORS`](Self::MAX_VECTORS).
+    pub fn new(num_vectors: usize) -> AxResult<Self> {
+        if num_vectors == 0 || num_vectors > Self::MAX_VECTORS {
+            return ax_err!(InvalidInput, "invalid MSI-X vector count");
+        }
+        Ok(Self {
+            state: Mutex::new(MsixState {
+                entries: vec![MsixEntry::MASKED; num_vectors],
+                enabled: false,
+                function_masked: false,
+            }),
+        })
+    }
+
+    /// Returns the number of vectors.
+    pub fn num_vectors(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    /// Returns the size of the table in bytes.
+    pub fn table_size(&self) -> usize {
+        self.num_vectors() * Self::ENTRY_SIZE
+    }
+
+    /// Returns the size of the PBA in bytes.
+    pub fn pba_size(&self) -> usize {
+        self.num_vectors().div_ceil(64) * 8
+    }
+
+    /// Mirrors the MSI-X Enable and Function Mask bits of the message control
+    /// register, returning the pending messages released by the change.
+    pub fn set_control(&self, enabled: bool, function_masked: bool) -> Vec<MsiMessage> {
+        let mut state = self.state.lock();
+        state.enabled = enabled;
+        state.function_masked = function_masked;
+        state.release_pending()
+    }
+
+    /// Returns the message programmed for `vector`.
+    pub fn message(&self, vector: usize) -> Option<MsiMessage> {
+        self.state
+            .lock()
+            .entries
+            .get(vector)
+            .map(MsixEntry::message)
+    }
+
+    /// Returns whether `vector` has a pending message.
+    pub fn is_pending(&self, vector: usize) -> bool {
+        self.state
+            .lock()
+            .entries
+            .get(vector)
+            .is_some_and(|entry| entry.pending)
+    }
+
+    /// Raises `vector`.
+    ///
+    /// Returns the message to deliver, or `None` if MSI-X is disabled or the
+    /// vector is masked, in which case the pending bit is set when MSI-X is
+    /// enabled.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `vector` is out of range.
+    pub fn trigger(&self, vector: usize) -> AxResult<Option<MsiMessage>> {
+        let mut state = self.state.lock();
+        let Some(&entry) = state.entries.get(vector) else {
+            return ax_err!(InvalidInput, "MSI-X vector out of range");
+        };
+        if state.deliverable(&entry) {
+            return Ok(Some(entry.message()));
+        }
+        if state.enabled {
+            state.entries[vector].pending = true;
+        }
+        Ok(None)
+    }
+
+    /// Handles a guest read of the table at `offset`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the access is not a naturally
+    /// aligned dword or qword inside the table.
+    pub fn read_table(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        let state = self.state.lock();
+        let (index, word) = Self::decode(offset, width, state.entries.len())?;
+        let words = &state.entries[index].words;
+        Ok(match width {
+            AccessWidth::Qword => ((words[word + 1] as u64) << 32 | words[word] as u64) as usize,
+            _ => words[word] as usize,
+        })
+    }
+
+    /// Handles a guest write of the table at `offset`, returning the pending
+    /// message released if the write unmasked a vector.
+    ///
+    /// Returns the same errors as [`read_table`](Self::read_table).
+    pub fn write_table(
+        &self,
+        offset: usize,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<Option<MsiMessage>> {
+        let mut state = self.state.lock();
+        let (index, word) = Self::decode(offset, width, state.entries.len())?;
+        let entry = &mut state.entries[index];
+        entry.words[word] = val as u32;
+        if width == AccessWidth::Qword {
+            entry.words[word + 1] = (val as u64 >> 32) as u32;
+        }
+        // Only the mask bit of the vector control word is writable.
+        entry.words[3] &= Self::VECTOR_MASKED;
+        let entry = *entry;
+        if entry.pending && state.deliverable(&entry) {
+            state.entries[index].pending = false;
+            return Ok(Some(entry.message()));
+        }
+        Ok(None)
+    }
+
+    /// Handles a guest read of the PBA at `offset`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the access is not a naturally
+    /// aligned dword or qword inside the PBA.
+    pub fn read_pba(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        let state = self.state.lock();
+        let size = width.size();
+        if !matches!(width, AccessWidth::Dword | AccessWidth::Qword)
+            || !offset.is_multiple_of(size)
+            || offset
+                .checked_add(size)
+                .is_none_or(|end| end > state.entries.len().div_ceil(64) * 8)
+        {
+            return ax_err!(InvalidInput, "invalid MSI-X PBA access");
+        }
+        let first = offset * 8;
+        let bits = state
+            .entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(size * 8)
+            .filter(|(_, entry)| entry.pending)
+            .fold(0u64, |bits, (index, _)| bits | 1 << (index - first));
+        Ok(bits as usize)
+    }
+
+    /// Masks all vectors and clears their messages and pending bits, and
+    /// disables MSI-X.
+    pub fn reset(&self) {
+        let mut state = self.state.lock();
+        state.entries.fill(MsixEntry::MASKED);
+        state.enabled = false;
+        state.function_masked = false;
+    }
+
+    fn decode(offset: usize, width: AccessWidth, len: usize) -> AxResult<(usize, usize)> {
+        let size = width.size();
+        if !matches!(width, AccessWidth::Dword | AccessWidth::Qword)
+            || !offset.is_multiple_of(size)
+            || offset
+                .checked_add(size)
+                .is_none_or(|end| end > len * Self::ENTRY_SIZE)
+        {
+            return ax_err!(InvalidInput, "invalid MSI-X table access");
+        }
+        Ok((offset / Self::ENTRY_SIZE, offset % Self::ENTRY_SIZE / 4))
+    }
+}
@@ -0,0 +1,478 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Devices claiming ranges in several address spaces.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axaddrspace::{
+    GuestPhysAddr, GuestPhysAddrRange,
+    device::{AccessWidth, DeviceAddrRange, Port, PortRange, SysRegAddr, SysRegAddrRange},
+};
+use axerrno::{AxResult, ax_err};
+
+use crate::{
+    AccessCompleter, BaseDeviceOps, CapabilitySet, CatchUpPolicy, ClockSource, DeviceManifest,
+    DomainEvent, EmuDeviceType, GuestMemoryAccessor, MemoryLayoutChange, PowerState,
+    RegionUpdateSink, RegisterDump, TimerService, TimerToken,
+};
+
+/// An address in any of the address spaces a device can be accessed through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnifiedAddr {
+    /// A guest physical address, accessed through MMIO.
+    Mmio(GuestPhysAddr),
+    /// An I/O port.
+    Port(Port),
+    /// A system register.
+    SysReg(SysRegAddr),
+}
+
+impl From<GuestPhysAddr> for UnifiedAddr {
+    fn from(addr: GuestPhysAddr) -> Self {
+        Self::Mmio(addr)
+    }
+}
+
+impl From<Port> for UnifiedAddr {
+    fn from(port: Port) -> Self {
+        Self::Port(port)
+    }
+}
+
+impl From<SysRegAddr> for UnifiedAddr {
+    fn from(addr: SysRegAddr) -> Self {
+        Self::SysReg(addr)
+    }
+}
+
+/// An address range in any of the address spaces a device can be accessed
+/// through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnifiedAddrRange {
+    /// A range of guest physical addresses, accessed through MMIO.
+    Mmio(GuestPhysAddrRange),
+    /// A range of I/O ports.
+    Port(PortRange),
+    /// A range of system registers.
+    SysReg(SysRegAddrRange),
+}
+
+impl UnifiedAddrRange {
+    /// Returns whether `addr` lies within the range. Addresses of another
+    /// address space are never contained.
+    pub fn contains(&self, addr: UnifiedAddr) -> bool {
+        match (self, addr) {
+            (Self::Mmio(range), UnifiedAddr::Mmio(addr)) => range.contains(addr),
+            (Self::Port(range), UnifiedAddr::Port(port)) => range.contains(port),
+            (Self::SysReg(range), UnifiedAddr::SysReg(addr)) => {
+                DeviceAddrRange::contains(range, addr)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<GuestPhysAddrRange> for UnifiedAddrRange {
+    fn from(range: GuestPhysAddrRange) -> Self {
+        Self::Mmio(range)
+    }
+}
+
+impl From<PortRange> for UnifiedAddrRange {
+    fn from(range: PortRange) -> Self {
+        Self::Port(range)
+    }
+}
+
+impl From<SysRegAddrRange> for UnifiedAddrRange {
+    fn from(range: SysRegAddrRange) -> Self {
+        Self::SysReg(range)
+    }
+}
+
+impl TryFrom<UnifiedAddrRange> for GuestPhysAddrRange {
+    type Error = UnifiedAddrRange;
+
+    fn try_from(range: UnifiedAddrRange) -> Result<Self, Self::Error> {
+        match range {
+            UnifiedAddrRange::Mmio(range) => Ok(range),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<UnifiedAddrRange> for PortRange {
+    type Error = UnifiedAddrRange;
+
+    fn try_from(range: UnifiedAddrRange) -> Result<Self, Self::Error> {
+        match range {
+            UnifiedAddrRange::Port(range) => Ok(range),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<UnifiedAddrRange> for SysRegAddrRange {
+    type Error = UnifiedAddrRange;
+
+    fn try_from(range: UnifiedAddrRange) -> Result<Self, Self::Error> {
+        match range {
+            UnifiedAddrRange::SysReg(range) => Ok(range),
+            other => Err(other),
+        }
+    }
+}
+
+/// A device claiming ranges in several address spaces, such as a PCI host
+/// bridge with both an MMIO ECAM window and the legacy `0xcf8`/`0xcfc` ports.
+///
+/// The device is registered in the device table of each address space through
+/// the [`SpaceView`]s returned by [`space_views`], which all share the single
+/// device object.
+///
+/// The lifecycle, snapshot, notification and service hooks mirror those of
+/// [`BaseDeviceOps`], with the same defaults, and are called through the
+/// views as described in [`SpaceView`].
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use axdevice_base::space_views;
+///
+/// let bridge: Arc<dyn BaseMultiSpaceDeviceOps> = Arc::new(HostBridge::new(...));
+/// for view in space_views::<GuestPhysAddrRange>(&bridge) {
+///     mmio.register(Arc::new(view))?;
+/// }
+/// for view in space_views::<PortRange>(&bridge) {
+///     pio.register(Arc::new(view))?;
+/// }
+/// ```
+pub trait BaseMultiSpaceDeviceOps: Send + Sync {
+    /// Returns the type of the emulated device.
+    fn emu_type(&self) -> EmuDeviceType;
+
+    /// Returns all ranges claimed by the device, in any address space.
+    fn address_ranges(&self) -> Vec<UnifiedAddrRange>;
+
+    /// Handles a read from `addr`, which lies within one of the
+    /// [`address_ranges`](Self::address_ranges).
+    fn handle_read(&self, addr: UnifiedAddr, width: AccessWidth) -> AxResult<usize>;
+
+    /// Handles a write of `val` to `addr`, which lies within one of the
+    /// [`address_ranges`](Self::address_ranges).
+    fn handle_write(&self, addr: UnifiedAddr, width: AccessWidth, val: usize) -> AxResult;
+
+    /// See [`BaseDeviceOps::on_time_jump`].
+    fn on_time_jump(&self, delta_ns: u64, policy: CatchUpPolicy) {
+        let _ = (delta_ns, policy);
+    }
+
+    /// See [`BaseDeviceOps::reset`].
+    fn reset(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// See [`BaseDeviceOps::pause`].
+    fn pause(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// See [`BaseDeviceOps::resume`].
+    fn resume(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// See [`BaseDeviceOps::shutdown`].
+    fn shutdown(&self) -> AxResult {
+        Ok(())
+    }
+
+    /// See [`BaseDeviceOps::power_state`].
+    fn power_state(&self) -> PowerState {
+        PowerState::D0
+    }
+
+    /// See [`BaseDeviceOps::set_power_state`].
+    fn set_power_state(&self, state: PowerState) -> AxResult {
+        if !state.is_active() {
+            return ax_err!(Unsupported, "device does not support power management");
+        }
+        Ok(())
+    }
+
+    /// See [`BaseDeviceOps::save_state`].
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        ax_err!(Unsupported, "device does not support snapshots")
+    }
+
+    /// See [`BaseDeviceOps::load_state`].
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        let _ = state;
+        ax_err!(Unsupported, "device does not support snapshots")
+    }
+
+    /// See [`BaseDeviceOps::on_domain_event`].
+    fn on_domain_event(&self, event: DomainEvent) -> AxResult {
+        match event {
+            DomainEvent::Reset => self.reset(),
+            DomainEvent::Suspend => self.pause(),
+            DomainEvent::Resume => self.resume(),
+            DomainEvent::ClockGate | DomainEvent::ClockUngate => Ok(()),
+        }
+    }
+
+    /// See [`BaseDeviceOps::on_vcpu_added`].
+    fn on_vcpu_added(&self, index: usize) -> AxResult {
+        let _ = index;
+        Ok(())
+    }
+
+    /// See [`BaseDeviceOps::on_memory_layout_changed`].
+    fn on_memory_layout_changed(&self, change: MemoryLayoutChange) {
+        let _ = change;
+    }
+
+    /// See [`BaseDeviceOps::set_dma_accessor`].
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        let _ = accessor;
+    }
+
+    /// See [`BaseDeviceOps::set_completer`].
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        let _ = completer;
+    }
+
+    /// See [`BaseDeviceOps::set_region_sink`].
+    fn set_region_sink(&self, sink: Arc<dyn RegionUpdateSink>) {
+        let _ = sink;
+    }
+
+    /// See [`BaseDeviceOps::set_timer_service`].
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        let _ = service;
+    }
+
+    /// See [`BaseDeviceOps::on_timer`].
+    fn on_timer(&self, token: TimerToken) -> bool {
+        let _ = token;
+        false
+    }
+
+    /// See [`BaseDeviceOps::set_clock_source`].
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        let _ = clock;
+    }
+
+    /// See [`BaseDeviceOps::dump_registers`].
+    fn dump_registers(&self) -> Vec<RegisterDump> {
+        Vec::new()
+    }
+
+    /// See [`BaseDeviceOps::capabilities`].
+    fn capabilities(&self) -> CapabilitySet {
+        CapabilitySet::empty()
+    }
+
+    /// See [`BaseDeviceOps::manifest`].
+    fn manifest(&self) -> DeviceManifest {
+        DeviceManifest::new(alloc::format!("{:?}", self.emu_type()))
+    }
+}
+
+/// One range of a [`BaseMultiSpaceDeviceOps`] device, seen as a device of a
+/// single address space.
+///
+/// The view of the first of the device's
+/// [`address_ranges`](BaseMultiSpaceDeviceOps::address_ranges) is its primary
+/// view. Hooks that must reach the device once however many views are
+/// registered (the lifecycle, power, snapshot and notification hooks, the
+/// region sink and register dumps) are only forwarded from the primary view;
+/// the other views accept them without effect, and save an empty state. The
+/// remaining services, timers, capabilities and the manifest are forwarded
+/// from every view.
+pub struct SpaceView<R> {
+    device: Arc<dyn BaseMultiSpaceDeviceOps>,
+    range: R,
+    primary: bool,
+}
+
+impl<R: Copy> SpaceView<R> {
+    /// Returns the range of the device this view covers.
+    pub fn range(&self) -> R {
+        self.range
+    }
+
+    /// Returns the viewed device.
+    pub fn device(&self) -> &Arc<dyn BaseMultiSpaceDeviceOps> {
+        &self.device
+    }
+
+    /// Returns whether this is the primary view of the device.
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    /// Calls `f` on the device if this is the primary view.
+    fn primary(&self, f: impl FnOnce(&dyn BaseMultiSpaceDeviceOps) -> AxResult) -> AxResult {
+        if self.primary {
+            f(&*self.device)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns a view of `device` for each of its ranges in the address space of
+/// `R`.
+pub fn space_views<R: TryFrom<UnifiedAddrRange>>(
+    device: &Arc<dyn BaseMultiSpaceDeviceOps>,
+) -> Vec<SpaceView<R>> {
+    device
+        .address_ranges()
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, range)| Some((index, R::try_from(range).ok()?)))
+        .map(|(index, range)| SpaceView {
+            device: device.clone(),
+            range,
+            primary: index == 0,
+        })
+        .collect()
+}
+
+impl<R> BaseDeviceOps<R> for SpaceView<R>
+where
+    R: DeviceAddrRange<Addr: Into<UnifiedAddr>> + Copy + Send + Sync + 'static,
+{
+    fn emu_type(&self) -> EmuDeviceType {
+        self.device.emu_type()
+    }
+
+    fn address_range(&self) -> R {
+        self.range
+    }
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.device.handle_read(addr.into(), width)
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.device.handle_write(addr.into(), width, val)
+    }
+
+    fn on_time_jump(&self, delta_ns: u64, policy: CatchUpPolicy) {
+        if self.primary {
+            self.device.on_time_jump(delta_ns, policy);
+        }
+    }
+
+    fn reset(&self) -> AxResult {
+        self.primary(|device| device.reset())
+    }
+
+    fn pause(&self) -> AxResult {
+        self.primary(|device| device.pause())
+    }
+
+    fn resume(&self) -> AxResult {
+        self.primary(|device| device.resume())
+    }
+
+    fn shutdown(&self) -> AxResult {
+        self.primary(|device| device.shutdown())
+    }
+
+    fn power_state(&self) -> PowerState {
+        self.device.power_state()
+    }
+
+    fn set_power_state(&self, state: PowerState) -> AxResult {
+        self.primary(|device| device.set_power_state(state))
+    }
+
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        if self.primary {
+            self.device.save_state()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        if self.primary {
+            self.device.load_state(state)
+        } else if state.is_empty() {
+            Ok(())
+        } else {
+            ax_err!(InvalidData, "state saved for a secondary space view")
+        }
+    }
+
+    fn on_domain_event(&self, event: DomainEvent) -> AxResult {
+        self.primary(|device| device.on_domain_event(event))
+    }
+
+    fn on_vcpu_added(&self, index: usize) -> AxResult {
+        self.primary(|device| device.on_vcpu_added(index))
+    }
+
+    fn on_memory_layout_changed(&self, change: MemoryLayoutChange) {
+        if self.primary {
+            self.device.on_memory_layout_changed(change);
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        self.device.set_dma_accessor(accessor)
+    }
+
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        self.device.set_completer(completer)
+    }
+
+    fn set_region_sink(&self, sink: Arc<dyn RegionUpdateSink>) {
+        if self.primary {
+            self.device.set_region_sink(sink);
+        }
+    }
+
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        self.device.set_timer_service(service)
+    }
+
+    fn on_timer(&self, token: TimerToken) -> bool {
+        self.device.on_timer(token)
+    }
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        self.device.set_clock_source(clock)
+    }
+
+    fn dump_registers(&self) -> Vec<RegisterDump> {
+        if self.primary {
+            self.device.dump_registers()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        self.device.capabilities()
+    }
+
+    fn manifest(&self) -> DeviceManifest {
+        self.device.manifest()
+    }
+}
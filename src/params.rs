@@ -0,0 +1,221 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed device parameters and regions of [`EmulatedDeviceConfig`].
+
+use alloc::{string::String, vec::Vec};
+
+use axaddrspace::{
+    GuestPhysAddrRange,
+    device::{Port, PortRange, SysRegAddr, SysRegAddrRange},
+};
+use axerrno::{AxResult, ax_err};
+
+use crate::{EmulatedDeviceConfig, UnifiedAddrRange};
+
+/// The value of a named device parameter.
+///
+/// Values are deserialized untagged, so that configuration files write them
+/// directly, e.g. `queue_size = 256` or `backend = "tap0"`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ConfigValue {
+    /// A boolean.
+    Bool(bool),
+    /// An integer.
+    Int(i64),
+    /// A string.
+    Str(String),
+    /// An array of values.
+    Array(Vec<ConfigValue>),
+}
+
+/// The address space of a [`RegionConfig`].
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum RegionSpace {
+    /// Guest physical memory, accessed through MMIO.
+    #[default]
+    Mmio,
+    /// I/O ports.
+    Port,
+    /// System registers.
+    SysReg,
+}
+
+/// The guest accesses permitted to a [`RegionConfig`].
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum RegionAccess {
+    /// Reads and writes.
+    #[default]
+    ReadWrite,
+    /// Reads only.
+    ReadOnly,
+    /// Writes only.
+    WriteOnly,
+}
+
+impl RegionAccess {
+    /// Returns whether the guest may read the region.
+    pub const fn can_read(self) -> bool {
+        !matches!(self, Self::WriteOnly)
+    }
+
+    /// Returns whether the guest may write the region.
+    pub const fn can_write(self) -> bool {
+        !matches!(self, Self::ReadOnly)
+    }
+}
+
+/// An address range of a device, in addition to the primary
+/// `base_ipa`/`length` range of [`EmulatedDeviceConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RegionConfig {
+    /// The first address of the region.
+    pub base: usize,
+    /// The size of the region, in addresses.
+    pub size: usize,
+    /// The address space of the region.
+    #[serde(default)]
+    pub space: RegionSpace,
+    /// The guest accesses permitted to the region.
+    #[serde(default)]
+    pub access: RegionAccess,
+}
+
+impl RegionConfig {
+    /// Returns the address range of the region.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the region is empty, or does
+    /// not fit in its address space.
+    pub fn address_range(&self) -> AxResult<UnifiedAddrRange> {
+        let Some(last) = self
+            .size
+            .checked_sub(1)
+            .and_then(|len| self.base.checked_add(len))
+        else {
+            return ax_err!(InvalidInput, "empty or overflowing device region");
+        };
+        Ok(match self.space {
+            RegionSpace::Mmio => {
+                GuestPhysAddrRange::from_start_size(self.base.into(), self.size).into()
+            }
+            RegionSpace::Port => match (u16::try_from(self.base), u16::try_from(last)) {
+                (Ok(start), Ok(end)) => PortRange::new(Port::new(start), Port::new(end)).into(),
+                _ => return ax_err!(InvalidInput, "port region beyond port 0xffff"),
+            },
+            RegionSpace::SysReg => {
+                SysRegAddrRange::new(SysRegAddr::new(self.base), SysRegAddr::new(last)).into()
+            }
+        })
+    }
+}
+
+impl EmulatedDeviceConfig {
+    /// Returns the parameter `key` as an unsigned integer.
+    ///
+    /// Returns `None` if the parameter is missing, not an integer, or
+    /// negative.
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get_i64(key).and_then(|val| u64::try_from(val).ok())
+    }
+
+    /// Returns the parameter `key` as an integer, or `None` if it is missing
+    /// or not an integer.
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        match self.params.get(key)? {
+            ConfigValue::Int(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Returns the parameter `key` as a boolean, or `None` if it is missing
+    /// or not a boolean.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.params.get(key)? {
+            ConfigValue::Bool(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Returns the parameter `key` as a string, or `None` if it is missing or
+    /// not a string.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.params.get(key)? {
+            ConfigValue::Str(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Returns the parameter `key` as an array, or `None` if it is missing or
+    /// not an array.
+    pub fn get_array(&self, key: &str) -> Option<&[ConfigValue]> {
+        match self.params.get(key)? {
+            ConfigValue::Array(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Names the entries of the legacy positional
+    /// [`cfg_list`](Self::cfg_list): entry `i` becomes the integer parameter
+    /// `keys[i]`.
+    ///
+    /// Parameters that are already set are kept, so that a device can accept
+    /// both forms of configuration by calling this before reading its
+    /// parameters. Entries beyond `keys` and keys beyond the list are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use axdevice_base::EmulatedDeviceConfig;
+    ///
+    /// let mut config = EmulatedDeviceConfig {
+    ///     cfg_list: vec![115200, 8],
+    ///     ..Default::default()
+    /// };
+    /// config.import_cfg_list(&["baud_rate", "data_bits"]);
+    /// assert_eq!(config.get_u64("baud_rate"), Some(115200));
+    /// ```
+    pub fn import_cfg_list(&mut self, keys: &[&str]) {
+        for (key, &val) in keys.iter().zip(&self.cfg_list) {
+            self.params
+                .entry((*key).into())
+                .or_insert(ConfigValue::Int(val as i64));
+        }
+    }
+
+    /// Returns the address ranges of the device: the primary MMIO range
+    /// given by `base_ipa` and `length`, unless `length` is 0, followed by
+    /// the ranges of [`regions`](Self::regions).
+    ///
+    /// The result suits
+    /// [`BaseMultiSpaceDeviceOps::address_ranges`](crate::BaseMultiSpaceDeviceOps::address_ranges).
+    /// Returns the same errors as [`RegionConfig::address_range`].
+    pub fn address_ranges(&self) -> AxResult<Vec<UnifiedAddrRange>> {
+        let primary = (self.length != 0).then_some(RegionConfig {
+            base: self.base_ipa,
+            size: self.length,
+            space: RegionSpace::Mmio,
+            access: RegionAccess::ReadWrite,
+        });
+        primary
+            .iter()
+            .chain(&self.regions)
+            .map(RegionConfig::address_range)
+            .collect()
+    }
+}
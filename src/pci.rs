@@ -0,0 +1,465 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PCI configuration space emulation.
+
+use alloc::vec::Vec;
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{RegionId, RegionUpdateSink};
+
+/// A base address register of a [`PciConfigSpace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciBar {
+    /// A 32-bit memory BAR of `size` bytes.
+    Memory32 {
+        /// The size of the region, a power of two of at least 16 bytes.
+        size: u32,
+        /// Whether the region is prefetchable.
+        prefetchable: bool,
+    },
+    /// A 64-bit memory BAR of `size` bytes, occupying two BAR slots.
+    Memory64 {
+        /// The size of the region, a power of two of at least 16 bytes.
+        size: u64,
+        /// Whether the region is prefetchable.
+        prefetchable: bool,
+    },
+    /// An I/O port BAR of `size` ports.
+    Io {
+        /// The size of the region, a power of two of at least 4 ports.
+        size: u32,
+    },
+}
+
+impl PciBar {
+    fn size(&self) -> u64 {
+        match *self {
+            Self::Memory32 { size, .. } | Self::Io { size } => size as u64,
+            Self::Memory64 { size, .. } => size,
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        match *self {
+            Self::Memory32 { prefetchable, .. } => (prefetchable as u32) << 3,
+            Self::Memory64 { prefetchable, .. } => 0x4 | (prefetchable as u32) << 3,
+            Self::Io { .. } => 0x1,
+        }
+    }
+
+    fn flag_bits(&self) -> u64 {
+        match self {
+            Self::Io { .. } => 0x3,
+            _ => 0xf,
+        }
+    }
+}
+
+/// A change of the placement a BAR is decoded at, returned by
+/// [`PciConfigSpace::write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciBarChange {
+    /// The index of the BAR.
+    pub index: usize,
+    /// The base address the BAR is now decoded at, or `None` if it is no
+    /// longer decoded.
+    pub address: Option<u64>,
+}
+
+impl PciBarChange {
+    /// Returns the region of the BAR, [`RegionId::BAR0`] to
+    /// [`RegionId::BAR5`].
+    pub const fn region(&self) -> RegionId {
+        RegionId::new(self.index as u32)
+    }
+}
+
+struct PciConfigState {
+    bytes: [u8; PciConfigSpace::SIZE],
+    write_mask: [u8; PciConfigSpace::SIZE],
+    w1c_mask: [u8; PciConfigSpace::SIZE],
+    bars: [Option<PciBar>; 6],
+    decoded: [Option<u64>; 6],
+    next_cap: usize,
+    last_cap: usize,
+}
+
+impl PciConfigState {
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn write_raw(&mut self, offset: usize, data: &[u8]) {
+        self.bytes[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn set_mask(&mut self, offset: usize, mask: &[u8]) {
+        self.write_mask[offset..offset + mask.len()].copy_from_slice(mask);
+    }
+
+    fn bar_address(&self, index: usize) -> Option<u64> {
+        let bar = self.bars.get(index)?.as_ref()?;
+        let low = self.read_u32(PciConfigSpace::BAR0 + index * 4) as u64;
+        let high = match bar {
+            PciBar::Memory64 { .. } => self.read_u32(PciConfigSpace::BAR0 + index * 4 + 4) as u64,
+            _ => 0,
+        };
+        Some((high << 32 | low) & !bar.flag_bits())
+    }
+
+    /// Returns the address BAR `index` is decoded at, or `None` if the BAR is
+    /// not declared or its decoding is disabled in the command register.
+    fn decoded_address(&self, index: usize) -> Option<u64> {
+        let bar = self.bars.get(index)?.as_ref()?;
+        let command = u16::from_le_bytes([
+            self.bytes[PciConfigSpace::COMMAND],
+            self.bytes[PciConfigSpace::COMMAND + 1],
+        ]);
+        let decode = match bar {
+            PciBar::Io { .. } => PciConfigSpace::COMMAND_IO,
+            _ => PciConfigSpace::COMMAND_MEMORY,
+        };
+        if command & decode == 0 {
+            return None;
+        }
+        self.bar_address(index)
+    }
+
+    /// Returns whether the guest is sizing BAR `index`, i.e. all the address
+    /// bits of one of its dwords are set.
+    fn is_sizing(&self, index: usize) -> bool {
+        let slots = match self.bars[index] {
+            Some(PciBar::Memory64 { .. }) => 2,
+            Some(_) => 1,
+            None => 0,
+        };
+        (0..slots).any(|slot| {
+            let offset = PciConfigSpace::BAR0 + (index + slot) * 4;
+            let mask = u32::from_le_bytes(self.write_mask[offset..offset + 4].try_into().unwrap());
+            mask != 0 && self.read_u32(offset) & mask == mask
+        })
+    }
+}
+
+/// The standard (type 0) PCI configuration space header of an emulated
+/// function.
+///
+/// The header fields, BARs and capability chain are laid out at construction
+/// time; guest accesses through [`read`](Self::read) and
+/// [`write`](Self::write) then honour the per-byte write masks of the
+/// specification: read-only identification fields, write-1-to-clear status
+/// bits, and BAR sizing, where writing all ones to a BAR reads back the
+/// inverted size mask. The device model only has to implement the payload of
+/// its BARs, at the addresses reported by [`write`](Self::write).
+///
+/// # Example
+///
+/// ```rust
+/// use axaddrspace::device::AccessWidth;
+/// use axdevice_base::{PciBar, PciBarChange, PciConfigSpace};
+///
+/// let config = PciConfigSpace::new(0x1af4, 0x1041, 0x02_00_00, 1);
+/// config
+///     .set_bar(0, PciBar::Memory32 { size: 0x1000, prefetchable: false })
+///     .unwrap();
+///
+/// // Sizing the BAR is not reported as a change.
+/// assert!(config.write(0x10, AccessWidth::Dword, 0xffff_ffff).unwrap().is_empty());
+/// assert_eq!(config.read(0x10, AccessWidth::Dword).unwrap(), 0xffff_f000);
+/// config.write(0x10, AccessWidth::Dword, 0xfe00_0000).unwrap();
+/// assert_eq!(config.bar_address(0), Some(0xfe00_0000));
+///
+/// // The BAR is decoded once memory decoding is enabled.
+/// let changes = config.write(0x04, AccessWidth::Word, 0x2).unwrap();
+/// assert_eq!(changes, [PciBarChange { index: 0, address: Some(0xfe00_0000) }]);
+/// ```
+pub struct PciConfigSpace {
+    state: Mutex<PciConfigState>,
+}
+
+impl PciConfigSpace {
+    /// The size of the configuration space.
+    pub const SIZE: usize = 0x100;
+
+    /// The offset of the command register.
+    pub const COMMAND: usize = 0x04;
+    /// The offset of the status register.
+    pub const STATUS: usize = 0x06;
+    /// The offset of the first base address register.
+    pub const BAR0: usize = 0x10;
+    /// The offset of the capabilities pointer.
+    pub const CAPABILITIES_POINTER: usize = 0x34;
+    /// The offset of the interrupt line register.
+    pub const INTERRUPT_LINE: usize = 0x3c;
+
+    /// Command register bit: I/O space decoding is enabled.
+    pub const COMMAND_IO: u16 = 1 << 0;
+    /// Command register bit: memory space decoding is enabled.
+    pub const COMMAND_MEMORY: u16 = 1 << 1;
+    /// Command register bit: the function may act as a bus master.
+    pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+    /// Command register bit: legacy INTx interrupts are disabled.
+    pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+    const COMMAND_WRITE_MASK: u16 = 0x0547;
+    const STATUS_CAPABILITIES: u16 = 1 << 4;
+    const STATUS_W1C_MASK: u16 = 0xf900;
+    const CAPABILITIES_START: usize = 0x40;
+
+    /// Creates the header of a function with the given IDs, 24-bit class code
+    /// (`class << 16 | subclass << 8 | prog_if`) and revision, with no BARs
+    /// and no capabilities.
+    pub fn new(vendor_id: u16, device_id: u16, class_code: u32, revision: u8) -> Self {
+        let mut state = PciConfigState {
+            bytes: [0; Self::SIZE],
+            write_mask: [0; Self::SIZE],
+            w1c_mask: [0; Self::SIZE],
+            bars: [None; 6],
+            decoded: [None; 6],
+            next_cap: Self::CAPABILITIES_START,
+            last_cap: 0,
+        };
+        state.write_raw(0x00, &vendor_id.to_le_bytes());
+        state.write_raw(0x02, &device_id.to_le_bytes());
+        state.write_raw(0x08, &(class_code << 8 | revision as u32).to_le_bytes());
+        state.set_mask(Self::COMMAND, &Self::COMMAND_WRITE_MASK.to_le_bytes());
+        state.w1c_mask[Self::STATUS..Self::STATUS + 2]
+            .copy_from_slice(&Self::STATUS_W1C_MASK.to_le_bytes());
+        // Cache line size, latency timer and interrupt line are plain storage.
+        state.set_mask(0x0c, &[0xff, 0xff]);
+        state.set_mask(Self::INTERRUPT_LINE, &[0xff]);
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Sets the subsystem vendor and subsystem IDs.
+    pub fn set_subsystem(&self, vendor_id: u16, id: u16) {
+        let mut state = self.state.lock();
+        state.write_raw(0x2c, &vendor_id.to_le_bytes());
+        state.write_raw(0x2e, &id.to_le_bytes());
+    }
+
+    /// Sets the legacy interrupt pin (1 = INTA# .. 4 = INTD#, 0 = none).
+    pub fn set_interrupt_pin(&self, pin: u8) {
+        self.state.lock().bytes[0x3d] = pin;
+    }
+
+    /// Declares BAR `index`.
+    ///
+    /// A [`PciBar::Memory64`] also occupies BAR `index + 1`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the size is not a power of two
+    /// or too small, or if the BAR slots are out of range.
+    ///
+    /// Returns `Err(AxError::AlreadyExists)` if a slot is already used.
+    pub fn set_bar(&self, index: usize, bar: PciBar) -> AxResult {
+        let slots = match bar {
+            PciBar::Memory64 { .. } => 2,
+            _ => 1,
+        };
+        let min_size = match bar {
+            PciBar::Io { .. } => 4,
+            _ => 16,
+        };
+        let size = bar.size();
+        if index + slots > 6 || !size.is_power_of_two() || size < min_size {
+            return ax_err!(InvalidInput, "invalid PCI BAR");
+        }
+        let mut state = self.state.lock();
+        if state.bars[index..index + slots].iter().any(Option::is_some)
+            || (index > 0 && matches!(state.bars[index - 1], Some(PciBar::Memory64 { .. })))
+        {
+            return ax_err!(AlreadyExists, "PCI BAR slot already used");
+        }
+        let offset = Self::BAR0 + index * 4;
+        let mask = !(size - 1) & !bar.flag_bits();
+        state.write_raw(offset, &bar.flags().to_le_bytes());
+        state.set_mask(offset, &(mask as u32).to_le_bytes());
+        if slots == 2 {
+            state.write_raw(offset + 4, &[0; 4]);
+            state.set_mask(offset + 4, &((mask >> 32) as u32).to_le_bytes());
+        }
+        state.bars[index] = Some(bar);
+        Ok(())
+    }
+
+    /// Appends a capability with ID `id` to the capability chain, returning
+    /// its offset.
+    ///
+    /// `data` is the body of the capability following the ID and next pointer
+    /// bytes. It is read-only to the guest unless made writable with
+    /// [`set_write_mask`](Self::set_write_mask).
+    ///
+    /// Returns `Err(AxError::NoMemory)` if the configuration space is full.
+    pub fn add_capability(&self, id: u8, data: &[u8]) -> AxResult<usize> {
+        let mut state = self.state.lock();
+        let offset = state.next_cap;
+        let end = offset + 2 + data.len();
+        if end > Self::SIZE {
+            return ax_err!(NoMemory, "PCI capability space exhausted");
+        }
+        state.bytes[offset] = id;
+        state.bytes[offset + 1] = 0;
+        state.write_raw(offset + 2, data);
+        match state.last_cap {
+            0 => state.bytes[Self::CAPABILITIES_POINTER] = offset as u8,
+            last => state.bytes[last + 1] = offset as u8,
+        }
+        let status = u16::from_le_bytes([state.bytes[Self::STATUS], state.bytes[Self::STATUS + 1]])
+            | Self::STATUS_CAPABILITIES;
+        state.write_raw(Self::STATUS, &status.to_le_bytes());
+        state.last_cap = offset;
+        state.next_cap = end.next_multiple_of(4);
+        Ok(offset)
+    }
+
+    /// Makes the bits set in `mask` writable by the guest, for the bytes
+    /// starting at `offset`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the bytes are outside the
+    /// configuration space.
+    pub fn set_write_mask(&self, offset: usize, mask: &[u8]) -> AxResult {
+        if offset
+            .checked_add(mask.len())
+            .is_none_or(|end| end > Self::SIZE)
+        {
+            return ax_err!(InvalidInput, "PCI config offset out of range");
+        }
+        self.state.lock().set_mask(offset, mask);
+        Ok(())
+    }
+
+    /// Overwrites the bytes starting at `offset` regardless of the write
+    /// masks, for fields owned by the device model.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the bytes are outside the
+    /// configuration space.
+    pub fn write_raw(&self, offset: usize, data: &[u8]) -> AxResult {
+        if offset
+            .checked_add(data.len())
+            .is_none_or(|end| end > Self::SIZE)
+        {
+            return ax_err!(InvalidInput, "PCI config offset out of range");
+        }
+        self.state.lock().write_raw(offset, data);
+        Ok(())
+    }
+
+    /// Returns the command register.
+    pub fn command(&self) -> u16 {
+        let state = self.state.lock();
+        u16::from_le_bytes([state.bytes[Self::COMMAND], state.bytes[Self::COMMAND + 1]])
+    }
+
+    /// Returns the guest-programmed base address of BAR `index`, or `None` if
+    /// the BAR is not declared.
+    pub fn bar_address(&self, index: usize) -> Option<u64> {
+        self.state.lock().bar_address(index)
+    }
+
+    /// Reports `changes`, as returned by [`write`](Self::write), to `sink`:
+    /// a BAR now decoded is remapped to its new address with its declared
+    /// size and enabled, and a BAR no longer decoded is disabled.
+    ///
+    /// Stops at and returns the first error of the sink, or
+    /// `Err(AxError::InvalidInput)` if a change names an undeclared BAR.
+    pub fn apply_bar_changes(
+        &self,
+        changes: &[PciBarChange],
+        sink: &dyn RegionUpdateSink,
+    ) -> AxResult {
+        for change in changes {
+            match change.address {
+                Some(address) => {
+                    let Some(bar) = self.state.lock().bars.get(change.index).copied().flatten()
+                    else {
+                        return ax_err!(InvalidInput, "PCI BAR not declared");
+                    };
+                    sink.remap(change.region(), address as usize, bar.size() as usize)?;
+                    sink.enable(change.region())?;
+                }
+                None => sink.disable(change.region())?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a guest read of the configuration space.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the access is outside the
+    /// configuration space.
+    pub fn read(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        Self::check_access(offset, width)?;
+        let state = self.state.lock();
+        let mut bytes = [0; 8];
+        bytes[..width.size()].copy_from_slice(&state.bytes[offset..offset + width.size()]);
+        Ok(u64::from_le_bytes(bytes) as usize)
+    }
+
+    /// Handles a guest write of the configuration space.
+    ///
+    /// Returns the BARs whose decoded placement was changed by the write, so
+    /// that the device can remap, enable or disable their regions. A BAR is
+    /// only decoded while the memory or I/O decoding bit of the command
+    /// register matching its type is set, so BAR updates with decoding
+    /// disabled are only reported once decoding is enabled. Sizing probes
+    /// (writing all ones to a BAR) are not reported either: the BAR keeps its
+    /// placement until the guest writes an address again.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the access is outside the
+    /// configuration space.
+    pub fn write(
+        &self,
+        offset: usize,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<Vec<PciBarChange>> {
+        Self::check_access(offset, width)?;
+        let mut state = self.state.lock();
+        let val = (val as u64).to_le_bytes();
+        for (i, &byte) in val[..width.size()].iter().enumerate() {
+            let pos = offset + i;
+            let mask = state.write_mask[pos];
+            let w1c = state.w1c_mask[pos];
+            state.bytes[pos] = ((state.bytes[pos] & !mask) | (byte & mask)) & !(byte & w1c);
+        }
+        let mut changes = Vec::new();
+        for index in 0..6 {
+            if state.is_sizing(index) {
+                continue;
+            }
+            let address = state.decoded_address(index);
+            if address != state.decoded[index] {
+                state.decoded[index] = address;
+                changes.push(PciBarChange { index, address });
+            }
+        }
+        Ok(changes)
+    }
+
+    fn check_access(offset: usize, width: AccessWidth) -> AxResult {
+        if offset
+            .checked_add(width.size())
+            .is_none_or(|end| end > Self::SIZE)
+        {
+            return ax_err!(InvalidInput, "PCI config access out of range");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Enforcement of the access permissions of device regions.
+
+use alloc::vec::Vec;
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxError, AxResult};
+
+use crate::{
+    AccessContext, AccessOutcome, BaseDeviceOps, DeviceAddrRangeExt, EmulatedDeviceConfig,
+    RawDeviceAddr, RegionAccess, UnifiedAddrRange, bulk_element_addr,
+    forward::forward_base_device_ops,
+};
+
+/// A wrapper rejecting the guest accesses that the [`RegionAccess`] of the
+/// accessed region does not permit, before they reach the device.
+///
+/// An access is checked against every region it touches, so that a
+/// multi-byte access running into a restricted region is rejected; accesses
+/// outside all regions are passed to the device. Rejected accesses fail with
+/// [`AxError::PermissionDenied`] unless another error is set with
+/// [`with_error`](Self::with_error). Debugger accesses and calls are not
+/// checked.
+///
+/// The coalesced write ring of the wrapped device is not exposed, as writes
+/// logged there by [`DeviceManager`](crate::DeviceManager) would bypass the
+/// checks: all writes are delivered to the device through the wrapper.
+pub struct PermissionCheckedDevice<R, D> {
+    inner: D,
+    regions: Vec<(R, RegionAccess)>,
+    error: AxError,
+}
+
+impl<R: DeviceAddrRangeExt, D: BaseDeviceOps<R>> PermissionCheckedDevice<R, D> {
+    /// Wraps `inner`, with no region restricted.
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            regions: Vec::new(),
+            error: AxError::PermissionDenied,
+        }
+    }
+
+    /// Restricts the accesses to `range` to those permitted by `access`.
+    pub fn with_region(mut self, range: R, access: RegionAccess) -> Self {
+        self.regions.push((range, access));
+        self
+    }
+
+    /// Restricts the accesses to the [`regions`](EmulatedDeviceConfig::regions)
+    /// of `config` that lie in the address space of `R`.
+    ///
+    /// Returns the same errors as
+    /// [`RegionConfig::address_range`](crate::RegionConfig::address_range).
+    pub fn with_config(mut self, config: &EmulatedDeviceConfig) -> AxResult<Self>
+    where
+        R: TryFrom<UnifiedAddrRange>,
+    {
+        for region in &config.regions {
+            if let Ok(range) = R::try_from(region.address_range()?) {
+                self.regions.push((range, region.access));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Sets the error rejected accesses fail with.
+    pub fn with_error(mut self, error: AxError) -> Self {
+        self.error = error;
+        self
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns the access permitted to `addr`, if it lies in a restricted
+    /// region.
+    pub fn access_of(&self, addr: R::Addr) -> Option<RegionAccess> {
+        self.regions
+            .iter()
+            .find(|(range, _)| range.contains(addr))
+            .map(|(_, access)| *access)
+    }
+
+    /// Checks an access to the addresses `[addr, addr + width)` against all
+    /// regions they overlap.
+    fn check(&self, addr: R::Addr, width: AccessWidth, write: bool) -> AxResult {
+        let start = addr.to_raw();
+        let end = start.saturating_add(width.size());
+        let denied = self.regions.iter().any(|(range, access)| {
+            let (region_start, region_end) = range.raw_bounds();
+            let permitted = if write {
+                access.can_write()
+            } else {
+                access.can_read()
+            };
+            region_start < end && start < region_end && !permitted
+        });
+        if denied { Err(self.error) } else { Ok(()) }
+    }
+
+    /// Checks every element of a bulk access.
+    fn check_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        write: bool,
+    ) -> AxResult {
+        for index in 0..count {
+            self.check(bulk_element_addr(addr, index, stride)?, width, write)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: DeviceAddrRangeExt + 'static, D: BaseDeviceOps<R>> BaseDeviceOps<R>
+    for PermissionCheckedDevice<R, D>
+{
+    forward_base_device_ops!(
+        R,
+        inner => identity, handle_call, peek, poke, lifecycle, services, abi_version, stats,
+        dump_registers, as_irq_chip, capabilities, manifest
+    );
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.check(addr, width, false)?;
+        self.inner.handle_read(addr, width)
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.check(addr, width, true)?;
+        self.inner.handle_write(addr, width, val)
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        self.check(addr, width, false)?;
+        self.inner.handle_read_ctx(addr, width, ctx)
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        self.check(addr, width, true)?;
+        self.inner.handle_write_ctx(addr, width, val, ctx)
+    }
+
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        self.check_bulk(addr, width, count, stride, false)?;
+        self.inner.handle_read_bulk(addr, width, count, stride, buf)
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult {
+        self.check_bulk(addr, width, count, stride, true)?;
+        self.inner
+            .handle_write_bulk(addr, width, count, stride, buf)
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        self.check(addr, width, false)?;
+        self.inner.handle_read_async(addr, width)
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        self.check(addr, width, true)?;
+        self.inner.handle_write_async(addr, width, val)
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Device power management states.
+
+/// The power state of a device, following the PCI/ACPI device states.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PowerState {
+    /// Fully on.
+    #[default]
+    D0,
+    /// Light sleep, with most of the context preserved.
+    D1,
+    /// Deeper sleep, with less of the context preserved.
+    D2,
+    /// Off, but still powered enough to be enumerated and brought back to D0.
+    D3Hot,
+    /// Off and unpowered; the device context is lost.
+    D3Cold,
+}
+
+impl PowerState {
+    /// Returns whether the device is fully on.
+    pub const fn is_active(self) -> bool {
+        matches!(self, Self::D0)
+    }
+}
+
+/// How a [`DeviceManager`](crate::DeviceManager) handles guest accesses to
+/// devices that are not in [`PowerState::D0`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LowPowerAccess {
+    /// Dispatch the accesses as usual.
+    #[default]
+    Allow,
+    /// Fail the accesses with `Err(AxError::BadState)`.
+    Refuse,
+    /// Complete reads with all ones and ignore writes, without dispatching
+    /// them, as a powered-down bus device would.
+    AllOnes,
+}
@@ -0,0 +1,37 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The items needed by almost every device model.
+//!
+//! ```rust
+//! use axdevice_base::prelude::*;
+//! ```
+//!
+//! This brings the device traits and aliases, the address and range types of
+//! [`axaddrspace`], [`AccessWidth`], the register and range helpers of this
+//! crate, and the [`axerrno`] result types into scope.
+
+pub use axaddrspace::{
+    GuestPhysAddr, GuestPhysAddrRange,
+    device::{AccessWidth, DeviceAddrRange, Port, PortRange, SysRegAddr, SysRegAddrRange},
+};
+pub use axerrno::{AxError, AxResult, ax_err};
+
+pub use crate::{
+    AccessContext, AccessKind, AccessOrigin, BaseDeviceOps, BaseHypercallDeviceOps,
+    BaseMmioDeviceOps, BasePciDeviceOps, BasePortDeviceOps, BaseSysRegDeviceOps,
+    DeviceAddrRangeExt, EmuDeviceType, EmulatedDeviceConfig, HypercallDevice, HypercallId,
+    HypercallRange, MmioDevice, PciBdf, PciConfigAddr, PciConfigRange, PciDevice, PortDevice,
+    RawDeviceAddr, RegValue, SysRegDevice, map_device_of_type, width_mask,
+};
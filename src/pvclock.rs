@@ -0,0 +1,334 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Paravirtual clock published to the guest in shared memory.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{Ordering, fence};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    BaseDeviceOps, Capability, CapabilitySet, ClockSource, DeviceAddrRangeExt, EmuDeviceType,
+    GuestMemoryAccessor, RegValue,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// The time parameters of a pvclock page, laid out as the `pvclock_vcpu_time_info`
+/// structure of kvmclock:
+///
+/// | Offset | Field               | Description                                   |
+/// |--------|---------------------|-----------------------------------------------|
+/// | 0x00   | `version`           | Seqlock counter, odd while an update is made. |
+/// | 0x08   | `tsc_timestamp`     | The counter value at the last update.         |
+/// | 0x10   | `system_time`       | Nanoseconds of monotonic time at that value.  |
+/// | 0x18   | `tsc_to_system_mul` | 32.32 fixed-point counter to ns multiplier.   |
+/// | 0x1c   | `tsc_shift`         | Power of two applied to deltas before.        |
+/// | 0x1d   | `flags`             | [`FLAG_STABLE`](Self::FLAG_STABLE).           |
+///
+/// The guest reads the page without trapping and computes the current time
+/// from its own counter with [`ticks_to_ns`](Self::ticks_to_ns). Updates
+/// follow the seqlock protocol, with [`publish`](Self::publish) on the writer
+/// side and [`read`](Self::read) on the reader side.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PvClockInfo {
+    /// The seqlock counter.
+    pub version: u32,
+    /// The counter value at the last update.
+    pub tsc_timestamp: u64,
+    /// The monotonic time in nanoseconds at `tsc_timestamp`.
+    pub system_time: u64,
+    /// The multiplier converting shifted counter deltas to nanoseconds, as a
+    /// 32.32 fixed-point number.
+    pub tsc_to_system_mul: u32,
+    /// The power of two counter deltas are multiplied by before scaling.
+    pub tsc_shift: i8,
+    /// `FLAG_*` bits.
+    pub flags: u8,
+}
+
+impl PvClockInfo {
+    /// The size of the structure in guest memory.
+    pub const SIZE: usize = 32;
+
+    /// Flag: the counter is synchronized across vCPUs, so the guest does not
+    /// need to guard against time going backwards between them.
+    pub const FLAG_STABLE: u8 = 1 << 0;
+
+    /// Creates the parameters for a counter running at `frequency` Hz, which
+    /// read `ticks` at `system_time` nanoseconds.
+    pub fn new(ticks: u64, frequency: u64, system_time: u64) -> Self {
+        let (tsc_to_system_mul, tsc_shift) = Self::scale(frequency);
+        Self {
+            version: 0,
+            tsc_timestamp: ticks,
+            system_time,
+            tsc_to_system_mul,
+            tsc_shift,
+            flags: Self::FLAG_STABLE,
+        }
+    }
+
+    /// Returns the multiplier and shift converting ticks of a `frequency` Hz
+    /// counter to nanoseconds, with the precision kvmclock uses.
+    pub fn scale(frequency: u64) -> (u32, i8) {
+        let mut shift = 0i8;
+        let mut base = frequency.max(1);
+        let mut scaled = NANOS_PER_SEC;
+        while base > scaled * 2 || base >> 32 != 0 {
+            base >>= 1;
+            shift -= 1;
+        }
+        let mut base = base as u32;
+        while base as u64 <= scaled || scaled >> 32 != 0 {
+            if scaled >> 32 != 0 || base & (1 << 31) != 0 {
+                scaled >>= 1;
+            } else {
+                base <<= 1;
+            }
+            shift += 1;
+        }
+        (((scaled << 32) / base as u64) as u32, shift)
+    }
+
+    /// Returns the monotonic time in nanoseconds when the counter reads
+    /// `ticks`, as computed by the guest.
+    pub fn ticks_to_ns(&self, ticks: u64) -> u64 {
+        let delta = ticks.wrapping_sub(self.tsc_timestamp);
+        let delta = if self.tsc_shift < 0 {
+            delta >> -self.tsc_shift
+        } else {
+            delta << self.tsc_shift
+        };
+        let scaled = (delta as u128 * self.tsc_to_system_mul as u128) >> 32;
+        self.system_time.wrapping_add(scaled as u64)
+    }
+
+    /// Encodes the structure as laid out in guest memory.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.tsc_timestamp.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.system_time.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.tsc_to_system_mul.to_le_bytes());
+        bytes[28] = self.tsc_shift as u8;
+        bytes[29] = self.flags;
+        bytes
+    }
+
+    /// Decodes the structure from its layout in guest memory.
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        Self {
+            version: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            tsc_timestamp: u64_at(8),
+            system_time: u64_at(16),
+            tsc_to_system_mul: u32::from_le_bytes(bytes[24..28].try_into().unwrap()),
+            tsc_shift: bytes[28] as i8,
+            flags: bytes[29],
+        }
+    }
+
+    /// Writes the structure to guest memory at `addr` as a seqlock writer.
+    ///
+    /// The version is made odd before the other fields are written and even
+    /// again after, so that a concurrent [`read`](Self::read) retries instead
+    /// of seeing a torn update. `self.version` is advanced accordingly and
+    /// ends up even; the version already in guest memory is ignored, so the
+    /// guest cannot confuse the writer.
+    pub fn publish(&mut self, mem: &dyn GuestMemoryAccessor, addr: GuestPhysAddr) -> AxResult {
+        self.version |= 1;
+        mem.write_u32(addr, self.version)?;
+        fence(Ordering::Release);
+        mem.write(addr + 8, &self.to_bytes()[8..])?;
+        fence(Ordering::Release);
+        self.version = self.version.wrapping_add(1);
+        mem.write_u32(addr, self.version)
+    }
+
+    /// Reads the structure from guest memory at `addr` as a seqlock reader.
+    ///
+    /// Returns `Err(AxError::WouldBlock)` if an update was in progress or
+    /// completed during the read, in which case the caller should retry.
+    pub fn read(mem: &dyn GuestMemoryAccessor, addr: GuestPhysAddr) -> AxResult<Self> {
+        let version = mem.read_u32(addr)?;
+        fence(Ordering::Acquire);
+        let mut bytes = [0; Self::SIZE];
+        mem.read(addr, &mut bytes)?;
+        fence(Ordering::Acquire);
+        if version & 1 != 0 || mem.read_u32(addr)? != version {
+            return ax_err!(WouldBlock, "pvclock update in progress");
+        }
+        let mut info = Self::from_bytes(&bytes);
+        info.version = version;
+        Ok(info)
+    }
+}
+
+struct PvClockState {
+    /// The value written to `PAGE_LO`/`PAGE_HI`.
+    page_reg: u64,
+    /// The page being updated, once enabled by the guest.
+    page: Option<GuestPhysAddr>,
+    info: PvClockInfo,
+}
+
+/// A paravirtual clock device, giving the guest low-overhead and
+/// migration-safe timekeeping in the style of kvmclock.
+///
+/// The guest registers the address of a [`PvClockInfo`] structure in its RAM,
+/// which the device fills in through the [`GuestMemoryAccessor`] injected with
+/// [`set_dma_accessor`](BaseDeviceOps::set_dma_accessor), from the
+/// [`ClockSource`] injected with
+/// [`set_clock_source`](BaseDeviceOps::set_clock_source). The guest only ever
+/// reads the structure, and computes the time from the counter of the clock
+/// source, which it reads directly (e.g. `CNTVCT_EL0` or the TSC).
+///
+/// The hypervisor calls [`update`](Self::update) whenever the relation between
+/// counter and time changes: after restoring the VM on another host, after
+/// changing the counter offset, or periodically to correct drift. Updates use
+/// the seqlock protocol of [`PvClockInfo::publish`]. Registers:
+///
+/// | Offset | Name      | Access | Description                                  |
+/// |--------|-----------|--------|----------------------------------------------|
+/// | 0x00   | `PAGE_LO` | RW     | Bits 31:5 of the structure address; bit 0 enables the clock. |
+/// | 0x04   | `PAGE_HI` | RW     | Bits 63:32 of the structure address.         |
+///
+/// The structure must be aligned to 32 bytes; the guest writes `PAGE_HI` first,
+/// and the write to `PAGE_LO` takes effect.
+pub struct PvClockDevice {
+    range: GuestPhysAddrRange,
+    state: Mutex<PvClockState>,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+    clock: Mutex<Option<Arc<dyn ClockSource>>>,
+}
+
+impl PvClockDevice {
+    const REG_PAGE_LO: usize = 0x00;
+    const REG_PAGE_HI: usize = 0x04;
+
+    /// `PAGE_LO` bit: the clock is enabled.
+    pub const PAGE_ENABLE: u64 = 1 << 0;
+
+    /// Creates a paravirtual clock device at `base`.
+    pub fn new(base: GuestPhysAddr) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x1000),
+            state: Mutex::new(PvClockState {
+                page_reg: 0,
+                page: None,
+                info: PvClockInfo::default(),
+            }),
+            dma: Mutex::new(None),
+            clock: Mutex::new(None),
+        }
+    }
+
+    /// Returns the address of the structure enabled by the guest, if any.
+    pub fn page(&self) -> Option<GuestPhysAddr> {
+        self.state.lock().page
+    }
+
+    /// Re-reads the clock source and publishes fresh parameters to the guest,
+    /// if it has enabled the clock.
+    ///
+    /// Returns `Err(AxError::BadState)` if no clock source or DMA accessor has
+    /// been injected.
+    pub fn update(&self) -> AxResult {
+        let mut state = self.state.lock();
+        self.publish(&mut state)
+    }
+
+    fn publish(&self, state: &mut PvClockState) -> AxResult {
+        let Some(page) = state.page else {
+            return Ok(());
+        };
+        let (Some(clock), Some(mem)) = (self.clock.lock().clone(), self.dma.lock().clone()) else {
+            return ax_err!(
+                BadState,
+                "pvclock device has no clock source or DMA accessor"
+            );
+        };
+        let (ticks, frequency) = (clock.ticks(), clock.frequency().max(1));
+        let system_time = ticks as u128 * NANOS_PER_SEC as u128 / frequency as u128;
+        let version = state.info.version;
+        state.info = PvClockInfo {
+            version,
+            ..PvClockInfo::new(ticks, frequency, system_time as u64)
+        };
+        state.info.publish(&*mem, page)
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for PvClockDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let page_reg = self.state.lock().page_reg;
+        let val = match self.range.offset_of(addr) {
+            Some(Self::REG_PAGE_LO) => page_reg as u32,
+            Some(Self::REG_PAGE_HI) => (page_reg >> 32) as u32,
+            _ => 0,
+        };
+        Ok(RegValue::new(val as usize, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        let mut state = self.state.lock();
+        let val = val as u32 as u64;
+        match self.range.offset_of(addr) {
+            Some(Self::REG_PAGE_HI) => {
+                state.page_reg = (state.page_reg & 0xffff_ffff) | val << 32;
+                Ok(())
+            }
+            Some(Self::REG_PAGE_LO) => {
+                state.page_reg = (state.page_reg & !0xffff_ffff) | val;
+                state.page = (state.page_reg & Self::PAGE_ENABLE != 0).then(|| {
+                    GuestPhysAddr::from(state.page_reg as usize & !(PvClockInfo::SIZE - 1))
+                });
+                self.publish(&mut state)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn reset(&self) -> AxResult {
+        let mut state = self.state.lock();
+        state.page_reg = 0;
+        state.page = None;
+        Ok(())
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        *self.clock.lock() = Some(clock);
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        Capability::Reset.into()
+    }
+}
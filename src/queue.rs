@@ -0,0 +1,150 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Uniform management of the queues of multi-queue devices.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use spin::{Mutex, MutexGuard};
+
+/// One queue of a [`QueueSet`]: the device-specific state `T`, plus the
+/// enable flag and notification affinity common to all queues.
+pub struct Queue<T> {
+    state: Mutex<T>,
+    enabled: AtomicBool,
+    affinity: AtomicUsize,
+}
+
+impl<T> Queue<T> {
+    const NO_AFFINITY: usize = usize::MAX;
+
+    fn new(state: T) -> Self {
+        Self {
+            state: Mutex::new(state),
+            enabled: AtomicBool::new(false),
+            affinity: AtomicUsize::new(Self::NO_AFFINITY),
+        }
+    }
+
+    /// Locks and returns the state of the queue.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.state.lock()
+    }
+
+    /// Returns whether the queue is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Enables or disables the queue.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+    }
+
+    /// Returns the vCPU that notifications of this queue are steered to, or
+    /// `None` if they may be delivered to any vCPU.
+    pub fn affinity(&self) -> Option<usize> {
+        match self.affinity.load(Ordering::Relaxed) {
+            Self::NO_AFFINITY => None,
+            vcpu => Some(vcpu),
+        }
+    }
+
+    /// Steers notifications of this queue to `vcpu`, or to any vCPU if `None`.
+    pub fn set_affinity(&self, vcpu: Option<usize>) {
+        self.affinity
+            .store(vcpu.unwrap_or(Self::NO_AFFINITY), Ordering::Relaxed);
+    }
+}
+
+impl<T: Default> Queue<T> {
+    /// Disables the queue, clears its affinity and resets its state.
+    pub fn reset(&self) {
+        self.set_enabled(false);
+        self.set_affinity(None);
+        *self.state.lock() = T::default();
+    }
+}
+
+/// A fixed set of queues for multi-queue devices (net, block, crypto, ...).
+///
+/// Each queue carries its own device-specific state `T` behind its own lock,
+/// so that queues can be serviced concurrently, plus the enable flag and
+/// notification affinity that every multi-queue device needs. Keeping them in
+/// one structure avoids parallel per-queue arrays going out of sync.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::QueueSet;
+///
+/// #[derive(Default)]
+/// struct RxQueue {
+///     head: u16,
+/// }
+///
+/// let queues = QueueSet::<RxQueue>::new(4, |_| RxQueue::default());
+/// queues.get(1).unwrap().set_enabled(true);
+/// queues.get(1).unwrap().lock().head = 8;
+/// assert_eq!(queues.enabled().map(|(index, _)| index).collect::<Vec<_>>(), [1]);
+///
+/// queues.reset();
+/// assert_eq!(queues.enabled().count(), 0);
+/// ```
+pub struct QueueSet<T> {
+    queues: Vec<Queue<T>>,
+}
+
+impl<T> QueueSet<T> {
+    /// Creates `count` disabled queues, with the state of queue `i` created by
+    /// `init(i)`.
+    pub fn new(count: usize, init: impl FnMut(usize) -> T) -> Self {
+        Self {
+            queues: (0..count).map(init).map(Queue::new).collect(),
+        }
+    }
+
+    /// Returns the number of queues.
+    pub fn len(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Returns whether the set has no queues.
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Returns the queue at `index`.
+    pub fn get(&self, index: usize) -> Option<&Queue<T>> {
+        self.queues.get(index)
+    }
+
+    /// Returns all queues with their indices, e.g. to snapshot them.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Queue<T>)> {
+        self.queues.iter().enumerate()
+    }
+
+    /// Returns the enabled queues with their indices.
+    pub fn enabled(&self) -> impl Iterator<Item = (usize, &Queue<T>)> {
+        self.iter().filter(|(_, queue)| queue.is_enabled())
+    }
+}
+
+impl<T: Default> QueueSet<T> {
+    /// Resets all queues, as on a device reset.
+    pub fn reset(&self) {
+        self.queues.iter().for_each(Queue::reset);
+    }
+}
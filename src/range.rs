@@ -0,0 +1,232 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Arithmetic helpers for device address ranges.
+//!
+//! The range types in [`axaddrspace::device`] only expose `contains`, and some of
+//! them are half-open ([`GuestPhysAddrRange`]) while others are inclusive
+//! ([`PortRange`], [`SysRegAddrRange`]). The traits in this module provide a
+//! single implementation of the usual range operations on top of a normalized
+//! half-open `[start, end)` view, so that consumers don't have to re-derive the
+//! boundary handling for every range type.
+
+use axaddrspace::{
+    GuestPhysAddr, GuestPhysAddrRange,
+    device::{
+        AccessWidth, DeviceAddr, DeviceAddrRange, Port, PortRange, SysRegAddr, SysRegAddrRange,
+    },
+};
+
+/// A device address that can be converted to and from a raw `usize` value.
+pub trait RawDeviceAddr: DeviceAddr {
+    /// Returns the raw value of the address.
+    fn to_raw(self) -> usize;
+
+    /// Creates an address from a raw value.
+    ///
+    /// Returns `None` if the value is not representable by this address type.
+    fn from_raw(raw: usize) -> Option<Self>;
+}
+
+impl RawDeviceAddr for GuestPhysAddr {
+    fn to_raw(self) -> usize {
+        self.as_usize()
+    }
+
+    fn from_raw(raw: usize) -> Option<Self> {
+        Some(GuestPhysAddr::from_usize(raw))
+    }
+}
+
+impl RawDeviceAddr for Port {
+    fn to_raw(self) -> usize {
+        self.number() as usize
+    }
+
+    fn from_raw(raw: usize) -> Option<Self> {
+        u16::try_from(raw).ok().map(Port::new)
+    }
+}
+
+impl RawDeviceAddr for SysRegAddr {
+    fn to_raw(self) -> usize {
+        self.addr()
+    }
+
+    fn from_raw(raw: usize) -> Option<Self> {
+        Some(SysRegAddr::new(raw))
+    }
+}
+
+/// Range arithmetic over [`DeviceAddrRange`] types.
+///
+/// Implementors only need to provide the conversion to and from raw half-open
+/// bounds; all other operations are provided on top of them. Operations that
+/// produce a range return `None` instead of an empty range, since inclusive range
+/// types cannot represent an empty range.
+///
+/// Some methods are named differently from the inherent methods of
+/// [`memory_addr::AddrRange`] (e.g. [`intersects`](Self::intersects) rather than
+/// `overlaps`), so that they are not shadowed when called on a
+/// [`GuestPhysAddrRange`].
+///
+/// # Example
+///
+/// ```rust
+/// use axaddrspace::{GuestPhysAddrRange, device::AccessWidth};
+/// use axdevice_base::DeviceAddrRangeExt;
+///
+/// let range = GuestPhysAddrRange::from_start_size(0x1000.into(), 0x1000);
+/// let other = GuestPhysAddrRange::from_start_size(0x1800.into(), 0x1000);
+///
+/// let common = range.intersect(&other).unwrap();
+/// assert_eq!(common.raw_bounds(), (0x1800, 0x2000));
+/// assert!(range.contains_access(0x1ffc.into(), AccessWidth::Dword));
+/// assert!(!range.contains_access(0x1ffc.into(), AccessWidth::Qword));
+/// ```
+pub trait DeviceAddrRangeExt: DeviceAddrRange<Addr: RawDeviceAddr> + Sized {
+    /// Returns the bounds of the range as raw half-open `[start, end)` values.
+    fn raw_bounds(&self) -> (usize, usize);
+
+    /// Creates a range from raw half-open `[start, end)` bounds.
+    ///
+    /// Returns `None` if the bounds are empty, reversed, or not representable by
+    /// this range type.
+    fn from_raw_bounds(start: usize, end: usize) -> Option<Self>;
+
+    /// Returns the number of addresses in the range.
+    fn size(&self) -> usize {
+        let (start, end) = self.raw_bounds();
+        end.saturating_sub(start)
+    }
+
+    /// Returns whether the range contains no addresses.
+    fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    /// Returns the offset of `addr` from the start of the range, or `None` if
+    /// the address is outside the range.
+    fn offset_of(&self, addr: Self::Addr) -> Option<usize> {
+        let (start, end) = self.raw_bounds();
+        let raw = addr.to_raw();
+        (start..end).contains(&raw).then(|| raw - start)
+    }
+
+    /// Returns whether the two ranges share at least one address.
+    fn intersects(&self, other: &Self) -> bool {
+        self.intersect(other).is_some()
+    }
+
+    /// Returns whether `other` lies entirely within this range.
+    fn covers(&self, other: &Self) -> bool {
+        let (start, end) = self.raw_bounds();
+        let (other_start, other_end) = other.raw_bounds();
+        other_start < other_end && start <= other_start && other_end <= end
+    }
+
+    /// Returns the addresses shared by both ranges, or `None` if they are disjoint.
+    fn intersect(&self, other: &Self) -> Option<Self> {
+        let (start, end) = self.raw_bounds();
+        let (other_start, other_end) = other.raw_bounds();
+        Self::from_raw_bounds(start.max(other_start), end.min(other_end))
+    }
+
+    /// Removes the addresses of `other` from this range.
+    ///
+    /// Returns the parts of this range below and above `other`, either of which
+    /// may be `None` if it would be empty.
+    fn subtract(&self, other: &Self) -> (Option<Self>, Option<Self>) {
+        let (start, end) = self.raw_bounds();
+        let (other_start, other_end) = other.raw_bounds();
+        if other_start >= other_end || other_end <= start || end <= other_start {
+            return (Self::from_raw_bounds(start, end), None);
+        }
+        (
+            Self::from_raw_bounds(start, other_start),
+            Self::from_raw_bounds(other_end, end),
+        )
+    }
+
+    /// Splits the range into `[start, addr)` and `[addr, end)`.
+    ///
+    /// Returns `None` if either part would be empty, i.e. if `addr` is not
+    /// strictly inside the range.
+    fn split_at(&self, addr: Self::Addr) -> Option<(Self, Self)> {
+        let (start, end) = self.raw_bounds();
+        let raw = addr.to_raw();
+        Some((
+            Self::from_raw_bounds(start, raw)?,
+            Self::from_raw_bounds(raw, end)?,
+        ))
+    }
+
+    /// Returns whether both the start and the (exclusive) end of the range are
+    /// aligned to `align`, which must be a power of two.
+    fn is_aligned_to(&self, align: usize) -> bool {
+        debug_assert!(align.is_power_of_two());
+        let (start, end) = self.raw_bounds();
+        start & (align - 1) == 0 && end & (align - 1) == 0
+    }
+
+    /// Returns whether an access of `width` bytes at `addr`, i.e. the addresses
+    /// `[addr, addr + width)`, lies entirely within the range.
+    fn contains_access(&self, addr: Self::Addr, width: AccessWidth) -> bool {
+        let (start, end) = self.raw_bounds();
+        let raw = addr.to_raw();
+        match raw.checked_add(width.size()) {
+            Some(access_end) => start <= raw && access_end <= end,
+            None => false,
+        }
+    }
+}
+
+impl DeviceAddrRangeExt for GuestPhysAddrRange {
+    fn raw_bounds(&self) -> (usize, usize) {
+        (self.start.as_usize(), self.end.as_usize())
+    }
+
+    fn from_raw_bounds(start: usize, end: usize) -> Option<Self> {
+        (start < end).then(|| GuestPhysAddrRange::new(start.into(), end.into()))
+    }
+}
+
+impl DeviceAddrRangeExt for PortRange {
+    fn raw_bounds(&self) -> (usize, usize) {
+        (self.start.to_raw(), self.end.to_raw() + 1)
+    }
+
+    fn from_raw_bounds(start: usize, end: usize) -> Option<Self> {
+        if start >= end {
+            return None;
+        }
+        Some(PortRange::new(
+            Port::from_raw(start)?,
+            Port::from_raw(end - 1)?,
+        ))
+    }
+}
+
+impl DeviceAddrRangeExt for SysRegAddrRange {
+    /// Note that a range ending at `usize::MAX` cannot be represented in the
+    /// half-open form, and its end is saturated to `usize::MAX`.
+    fn raw_bounds(&self) -> (usize, usize) {
+        (self.start.to_raw(), self.end.to_raw().saturating_add(1))
+    }
+
+    fn from_raw_bounds(start: usize, end: usize) -> Option<Self> {
+        (start < end)
+            .then(|| SysRegAddrRange::new(SysRegAddr::new(start), SysRegAddr::new(end - 1)))
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed register values keyed by [`AccessWidth`].
+
+use axaddrspace::device::AccessWidth;
+
+/// Returns a mask covering the bits of an access of `width`.
+pub const fn width_mask(width: AccessWidth) -> usize {
+    match width {
+        AccessWidth::Byte => 0xff,
+        AccessWidth::Word => 0xffff,
+        AccessWidth::Dword => 0xffff_ffff,
+        AccessWidth::Qword => usize::MAX,
+    }
+}
+
+/// A register value together with the width of the access that produced or
+/// consumes it.
+///
+/// The value is always stored truncated to its width, so devices can convert
+/// between the raw `usize` values passed to `handle_read`/`handle_write` and
+/// guest-visible bytes without repeating the masking logic. Multi-byte values
+/// use little-endian byte order.
+///
+/// # Example
+///
+/// ```rust
+/// use axaddrspace::device::AccessWidth;
+/// use axdevice_base::RegValue;
+///
+/// // A 16-bit guest write to the upper half of a 32-bit register.
+/// let reg = 0x1122_3344;
+/// let val = RegValue::new(0xaabb, AccessWidth::Word);
+/// assert_eq!(val.merge_into(reg, 2), 0xaabb_3344);
+///
+/// // A byte read of a negative value.
+/// let val = RegValue::new(0x80, AccessWidth::Byte);
+/// assert_eq!(val.sign_extend(), -128);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegValue {
+    bytes: [u8; 8],
+    width: AccessWidth,
+}
+
+impl RegValue {
+    /// Creates a register value of `width`, truncating `value` to it.
+    pub fn new(value: usize, width: AccessWidth) -> Self {
+        Self {
+            bytes: ((value & width_mask(width)) as u64).to_le_bytes(),
+            width,
+        }
+    }
+
+    /// Creates a register value from little-endian bytes. The width is the
+    /// length of `bytes`.
+    ///
+    /// Returns `None` if the length of `bytes` is not a valid access width.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let width = AccessWidth::try_from(bytes.len()).ok()?;
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Some(Self { bytes: buf, width })
+    }
+
+    /// Extracts the part of the wider register `reg` accessed with `width` at
+    /// byte `offset` within the register.
+    ///
+    /// Bytes beyond the width of `usize` read as zero.
+    pub fn extract(reg: usize, offset: usize, width: AccessWidth) -> Self {
+        let value = reg.checked_shr((offset * 8) as u32).unwrap_or(0);
+        Self::new(value, width)
+    }
+
+    /// Returns the width of the value.
+    pub fn width(&self) -> AccessWidth {
+        self.width
+    }
+
+    /// Returns the little-endian bytes of the value, `width` bytes long.
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.bytes[..self.width.size()]
+    }
+
+    /// Returns the value zero-extended to `usize`.
+    pub fn zero_extend(&self) -> usize {
+        u64::from_le_bytes(self.bytes) as usize
+    }
+
+    /// Returns the value sign-extended to `isize`.
+    pub fn sign_extend(&self) -> isize {
+        let shift = usize::BITS - (self.width.size() * 8) as u32;
+        ((self.zero_extend() << shift) as isize) >> shift
+    }
+
+    /// Writes this value into the wider register `reg` at byte `offset` within
+    /// the register, leaving all other bits of `reg` unchanged.
+    ///
+    /// Bytes beyond the width of `usize` are discarded.
+    pub fn merge_into(&self, reg: usize, offset: usize) -> usize {
+        let shift = (offset * 8) as u32;
+        let mask = width_mask(self.width).checked_shl(shift).unwrap_or(0);
+        let value = self.zero_extend().checked_shl(shift).unwrap_or(0);
+        (reg & !mask) | (value & mask)
+    }
+
+    /// Writes this value into `reg`, changing only the bits set in
+    /// `writable`, e.g. to preserve read-only fields of a register.
+    pub fn merge_masked(&self, reg: usize, writable: usize) -> usize {
+        (reg & !writable) | (self.zero_extend() & writable)
+    }
+
+    /// Returns the value with the order of its bytes reversed.
+    pub fn swap_bytes(&self) -> Self {
+        let mut swapped = *self;
+        swapped.bytes[..self.width.size()].reverse();
+        swapped
+    }
+}
+
+/// The byte order of the values of guest accesses.
+///
+/// Device models work on little-endian values, like [`RegValue`]. A
+/// [`DeviceManager`](crate::DeviceManager) set up for a big-endian guest with
+/// [`set_guest_byte_order`](crate::DeviceManager::set_guest_byte_order)
+/// converts the values of its accesses, so that device models need no
+/// knowledge of the guest byte order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ByteOrder {
+    /// Least significant byte first.
+    #[default]
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+impl ByteOrder {
+    /// Converts `val`, the value of an access of `width` in this byte order,
+    /// to little-endian, and a little-endian value back to this byte order.
+    pub fn convert(self, val: usize, width: AccessWidth) -> usize {
+        match self {
+            Self::Little => val,
+            Self::Big => RegValue::new(val, width).swap_bytes().zero_extend(),
+        }
+    }
+}
+
+impl From<RegValue> for usize {
+    fn from(value: RegValue) -> usize {
+        value.zero_extend()
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Propagation of guest-programmed region changes to the hypervisor.
+
+use alloc::sync::Arc;
+
+use axerrno::AxResult;
+
+/// Identifies a region of a device whose placement is programmed by the guest,
+/// such as a PCI BAR or a relocatable virtqueue window.
+///
+/// The numbering is chosen by the device. PCI devices number their BARs by
+/// index, [`BAR0`](Self::BAR0) to [`BAR5`](Self::BAR5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RegionId(u32);
+
+impl RegionId {
+    /// The region of PCI BAR 0.
+    pub const BAR0: Self = Self(0);
+    /// The region of PCI BAR 1.
+    pub const BAR1: Self = Self(1);
+    /// The region of PCI BAR 2.
+    pub const BAR2: Self = Self(2);
+    /// The region of PCI BAR 3.
+    pub const BAR3: Self = Self(3);
+    /// The region of PCI BAR 4.
+    pub const BAR4: Self = Self(4);
+    /// The region of PCI BAR 5.
+    pub const BAR5: Self = Self(5);
+
+    /// Creates a region identifier from its raw value.
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+
+    /// Returns the raw value of the identifier.
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+/// Receives changes of the guest-programmed regions of a device.
+///
+/// The sink is injected with [`BaseDeviceOps::set_region_sink`]. Each device
+/// gets its own sink, usually a [`DeviceRegionSink`] reporting the changes to
+/// the hypervisor's [`RegionUpdateHandler`] on behalf of the device. Each call
+/// is applied as a single update of the guest address space, so that the guest
+/// never observes a region half moved. Regions start disabled; a PCI device
+/// reports the BAR changes of its configuration space with
+/// [`PciConfigSpace::apply_bar_changes`]:
+///
+/// ```rust,ignore
+/// let changes = self.config.write(offset, width, val)?;
+/// if let Some(sink) = self.region_sink.lock().as_ref() {
+///     self.config.apply_bar_changes(&changes, sink.as_ref())?;
+/// }
+/// ```
+///
+/// [`BaseDeviceOps::set_region_sink`]: crate::BaseDeviceOps::set_region_sink
+/// [`PciConfigSpace::apply_bar_changes`]: crate::PciConfigSpace::apply_bar_changes
+pub trait RegionUpdateSink: Send + Sync {
+    /// Moves `region` to `[new_base, new_base + new_size)`, keeping its
+    /// enabled state.
+    ///
+    /// Returns `Err(AxError::AlreadyExists)` if the new placement overlaps
+    /// another region or device.
+    fn remap(&self, region: RegionId, new_base: usize, new_size: usize) -> AxResult;
+
+    /// Maps `region` into the guest address space at its last placement.
+    ///
+    /// Returns `Err(AxError::BadState)` if the region was never placed with
+    /// [`remap`](Self::remap).
+    fn enable(&self, region: RegionId) -> AxResult;
+
+    /// Unmaps `region` from the guest address space.
+    fn disable(&self, region: RegionId) -> AxResult;
+}
+
+/// Receives the region changes of all devices of a
+/// [`DeviceManager`](crate::DeviceManager).
+///
+/// The hypervisor implements this trait and sets it with
+/// [`DeviceManager::set_region_handler`](crate::DeviceManager::set_region_handler),
+/// which injects a [`DeviceRegionSink`] into every registered device. Region
+/// identifiers are chosen by each device, so changes are keyed by `device`,
+/// the raw start address of the device range (see
+/// [`DeviceAddrRangeExt::raw_bounds`](crate::DeviceAddrRangeExt::raw_bounds)),
+/// which is unique among the devices of a manager.
+///
+/// The methods behave as those of [`RegionUpdateSink`].
+pub trait RegionUpdateHandler: Send + Sync {
+    /// Moves `region` of `device` to `[new_base, new_base + new_size)`.
+    fn remap(&self, device: usize, region: RegionId, new_base: usize, new_size: usize) -> AxResult;
+
+    /// Maps `region` of `device` at its last placement.
+    fn enable(&self, device: usize, region: RegionId) -> AxResult;
+
+    /// Unmaps `region` of `device`.
+    fn disable(&self, device: usize, region: RegionId) -> AxResult;
+}
+
+/// The [`RegionUpdateSink`] of one device, reporting its region changes to a
+/// [`RegionUpdateHandler`] keyed by the device.
+pub struct DeviceRegionSink {
+    device: usize,
+    handler: Arc<dyn RegionUpdateHandler>,
+}
+
+impl DeviceRegionSink {
+    /// Creates the sink of the device whose range starts at the raw address
+    /// `device`.
+    pub fn new(device: usize, handler: Arc<dyn RegionUpdateHandler>) -> Self {
+        Self { device, handler }
+    }
+
+    /// Returns the key of the device.
+    pub fn device(&self) -> usize {
+        self.device
+    }
+}
+
+impl RegionUpdateSink for DeviceRegionSink {
+    fn remap(&self, region: RegionId, new_base: usize, new_size: usize) -> AxResult {
+        self.handler.remap(self.device, region, new_base, new_size)
+    }
+
+    fn enable(&self, region: RegionId) -> AxResult {
+        self.handler.enable(self.device, region)
+    }
+
+    fn disable(&self, region: RegionId) -> AxResult {
+        self.handler.disable(self.device, region)
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording access traces and replaying them against a device.
+
+use alloc::vec::Vec;
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxError, AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    AccessKind, BaseDeviceOps, DeviceAddrRangeExt, DeviceTracer, RawDeviceAddr, TraceRecord,
+};
+
+/// A [`DeviceTracer`] keeping the traced accesses in memory.
+///
+/// The recorded trace can be saved with [`encode`](Self::encode) and fed back
+/// into a device with [`replay`].
+pub struct TraceRecorder {
+    clock: fn() -> u64,
+    records: Mutex<Vec<TraceRecord>>,
+}
+
+impl TraceRecorder {
+    /// Creates an empty recorder, taking timestamps from `clock`.
+    pub const fn new(clock: fn() -> u64) -> Self {
+        Self {
+            clock,
+            records: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the recorded accesses, oldest first.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.lock().clone()
+    }
+
+    /// Returns the recorded accesses in the binary [`TraceRecord`] encoding.
+    pub fn encode(&self) -> Vec<u8> {
+        self.records
+            .lock()
+            .iter()
+            .flat_map(|record| record.encode())
+            .collect()
+    }
+
+    /// Discards all recorded accesses.
+    pub fn clear(&self) {
+        self.records.lock().clear();
+    }
+}
+
+impl<R: DeviceAddrRangeExt> DeviceTracer<R> for TraceRecorder {
+    fn on_access(
+        &self,
+        _device: &dyn BaseDeviceOps<R>,
+        addr: R::Addr,
+        width: AccessWidth,
+        value: usize,
+        kind: AccessKind,
+        result: AxResult,
+    ) {
+        let record = TraceRecord {
+            timestamp_ns: (self.clock)(),
+            addr: addr.to_raw() as u64,
+            value: value as u64,
+            width,
+            kind,
+            error: result.err(),
+        };
+        self.records.lock().push(record);
+    }
+}
+
+/// Decodes a trace made of consecutive encoded [`TraceRecord`]s.
+///
+/// Returns `Err(AxError::InvalidData)` if the trace is truncated or holds an
+/// invalid record.
+pub fn decode_trace(bytes: &[u8]) -> AxResult<Vec<TraceRecord>> {
+    if !bytes.len().is_multiple_of(TraceRecord::SIZE) {
+        return ax_err!(InvalidData, "truncated trace");
+    }
+    bytes
+        .chunks_exact(TraceRecord::SIZE)
+        .map(TraceRecord::decode)
+        .collect()
+}
+
+/// A replayed access whose outcome differs from the trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayMismatch {
+    /// The index of the access in the trace.
+    pub index: usize,
+    /// The recorded access.
+    pub expected: TraceRecord,
+    /// The value read by the replayed access, or 0 for writes and failed
+    /// reads.
+    pub value: u64,
+    /// The error returned by the replayed access, if any.
+    pub error: Option<AxError>,
+}
+
+/// Feeds the accesses of `trace` into `device`, in order, and returns the
+/// accesses whose outcome differs from the recorded one.
+///
+/// Writes are replayed with their recorded value and compared by their error
+/// only; reads are compared by value and error. This makes a recorded trace
+/// usable as a regression test of a device model: replaying it against a new
+/// version of the device must return no mismatch. Timestamps are ignored.
+///
+/// Returns `Err(AxError::InvalidData)` if a recorded address is not a valid
+/// address for the device.
+pub fn replay<R: DeviceAddrRangeExt + 'static>(
+    device: &dyn BaseDeviceOps<R>,
+    trace: &[TraceRecord],
+) -> AxResult<Vec<ReplayMismatch>> {
+    let mut mismatches = Vec::new();
+    for (index, expected) in trace.iter().enumerate() {
+        let Some(addr) = R::Addr::from_raw(expected.addr as usize) else {
+            return ax_err!(InvalidData, "trace address not representable");
+        };
+        let (value, error) = match expected.kind {
+            AccessKind::Read => match device.handle_read(addr, expected.width) {
+                Ok(val) => (val as u64, None),
+                Err(err) => (0, Some(err)),
+            },
+            AccessKind::Write => {
+                let result = device.handle_write(addr, expected.width, expected.value as usize);
+                (0, result.err())
+            }
+        };
+        let matches = error == expected.error
+            && (expected.kind == AccessKind::Write || value == expected.value);
+        if !matches {
+            mismatches.push(ReplayMismatch {
+                index,
+                expected: *expected,
+                value,
+                error,
+            });
+        }
+    }
+    Ok(mismatches)
+}
@@ -0,0 +1,123 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated random number generator device.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::AxResult;
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, EntropySource, RegValue};
+
+/// A simple MMIO true random number generator backed by an [`EntropySource`].
+///
+/// The device gives the guest early-boot entropy without requiring virtio
+/// drivers. To keep a guest from draining the host source, reads are limited
+/// by a byte budget that the hypervisor refills periodically with
+/// [`replenish`](Self::replenish). Registers:
+///
+/// | Offset | Name     | Access | Description                                  |
+/// |--------|----------|--------|----------------------------------------------|
+/// | 0x00   | `STATUS` | RO     | Bit 0: entropy is available.                 |
+/// | 0x08   | `DATA`   | RO     | Random bits, any access width up to 64 bits. |
+///
+/// Reading `DATA` while no entropy is available returns zero, so guest drivers
+/// must check `STATUS` first, as with most hardware TRNGs.
+pub struct TrngDevice {
+    range: GuestPhysAddrRange,
+    source: Arc<dyn EntropySource>,
+    budget: AtomicUsize,
+    burst: usize,
+}
+
+impl TrngDevice {
+    const REG_STATUS: usize = 0x00;
+    const REG_DATA: usize = 0x08;
+
+    /// `STATUS` bit: entropy is available.
+    pub const STATUS_AVAILABLE: usize = 1 << 0;
+
+    /// Creates a TRNG at `base` drawing from `source`.
+    ///
+    /// The guest may read at most `burst` bytes before the budget has to be
+    /// replenished. The budget starts full.
+    pub fn new(base: GuestPhysAddr, source: Arc<dyn EntropySource>, burst: usize) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x1000),
+            source,
+            budget: AtomicUsize::new(burst),
+            burst,
+        }
+    }
+
+    /// Adds `bytes` to the read budget of the guest, up to the burst size.
+    pub fn replenish(&self, bytes: usize) {
+        let _ = self
+            .budget
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |budget| {
+                Some(budget.saturating_add(bytes).min(self.burst))
+            });
+    }
+
+    /// Returns the number of bytes the guest may currently read.
+    pub fn budget(&self) -> usize {
+        self.budget.load(Ordering::Acquire)
+    }
+
+    fn read_data(&self, width: AccessWidth) -> AxResult<usize> {
+        let len = width.size();
+        let taken = self
+            .budget
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |budget| {
+                budget.checked_sub(len)
+            });
+        if taken.is_err() {
+            return Ok(0);
+        }
+        let mut buf = [0; 8];
+        let filled = self.source.fill(&mut buf[..len])?;
+        if filled < len {
+            // Unfilled bytes stay zero; don't charge the guest for them.
+            self.replenish(len - filled);
+        }
+        Ok(RegValue::from_bytes(&buf[..len]).unwrap().zero_extend())
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for TrngDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        match self.range.offset_of(addr) {
+            Some(Self::REG_STATUS) => {
+                let available = self.budget() > 0;
+                Ok(RegValue::new(available as usize * Self::STATUS_AVAILABLE, width).zero_extend())
+            }
+            Some(Self::REG_DATA) => self.read_data(width),
+            _ => Ok(0),
+        }
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+}
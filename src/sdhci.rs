@@ -0,0 +1,966 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SD host controller emulation over a block backend.
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxError, AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    AccessCompleter, AccessOutcome, BaseDeviceOps, BlockBackend, Capability, CapabilitySet,
+    CompletionToken, DeviceAddrRangeExt, EmuDeviceType, GuestMemoryAccessor,
+};
+
+/// The block size of the emulated card, a high-capacity card addressed by
+/// block.
+const SD_BLOCK_SIZE: usize = 512;
+
+/// The relative card address published by the card.
+const SD_RCA: u32 = 0x0001;
+
+/// The operation conditions register: powered up, high capacity, 2.7-3.6 V.
+const SD_OCR: u32 = 0xc0ff_8000;
+const SD_OCR_VOLTAGES: u32 = 0x00ff_8000;
+
+/// The SD configuration register: SD 1.0, 1-bit and 4-bit bus widths.
+const SD_SCR: [u8; 8] = [0x00, 0x05, 0, 0, 0, 0, 0, 0];
+
+/// Card status bits of R1 responses.
+const R1_OUT_OF_RANGE: u32 = 1 << 31;
+const R1_BLOCK_LEN_ERROR: u32 = 1 << 29;
+const R1_WP_VIOLATION: u32 = 1 << 26;
+const R1_READY_FOR_DATA: u32 = 1 << 8;
+const R1_APP_CMD: u32 = 1 << 5;
+
+/// The state of the emulated card (SD physical layer specification, 4.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CardState {
+    Idle = 0,
+    Ready = 1,
+    Ident = 2,
+    Stby = 3,
+    Tran = 4,
+    Data = 5,
+    Rcv = 6,
+    Prg = 7,
+}
+
+/// A data transfer in progress.
+struct Transfer {
+    id: u64,
+    read: bool,
+    dma: bool,
+    lba: u64,
+    block_len: usize,
+    len: usize,
+    /// For reads, the data once delivered; for writes, the data written by
+    /// the guest so far.
+    data: Vec<u8>,
+    /// The number of bytes read or written by the guest through the data
+    /// port.
+    pos: usize,
+    /// A read of the data port waiting for the data to be delivered.
+    waiting: Option<(CompletionToken, AccessWidth)>,
+}
+
+impl Transfer {
+    fn is_pio_read(&self) -> bool {
+        self.read && !self.dma
+    }
+}
+
+/// Backend work started by a register access, run once the state lock is
+/// released.
+enum Request {
+    /// Reads `len` bytes at `lba` from the backend.
+    Read { id: u64, lba: u64, len: usize },
+    /// Delivers data supplied by the card itself, such as its SCR.
+    Supply { id: u64, data: Vec<u8> },
+    /// Writes data written by the guest through the data port.
+    Write { id: u64, lba: u64, data: Vec<u8> },
+    /// Writes `len` bytes of guest memory at `addr`.
+    DmaWrite {
+        id: u64,
+        lba: u64,
+        addr: GuestPhysAddr,
+        len: usize,
+    },
+}
+
+#[derive(Default)]
+struct Deferred {
+    request: Option<Request>,
+    wake: Option<CompletionToken>,
+}
+
+struct SdhciState {
+    sdma_addr: u32,
+    block_size: u16,
+    block_count: u16,
+    argument: u32,
+    transfer_mode: u16,
+    command: u16,
+    response: [u32; 4],
+    host_control: u8,
+    power_control: u8,
+    clock_control: u16,
+    timeout_control: u8,
+    normal_int: u16,
+    error_int: u16,
+    normal_int_enable: u16,
+    error_int_enable: u16,
+    normal_signal_enable: u16,
+    error_signal_enable: u16,
+    card: CardState,
+    app_cmd: bool,
+    transfer: Option<Transfer>,
+}
+
+impl SdhciState {
+    fn new() -> Self {
+        Self {
+            sdma_addr: 0,
+            block_size: 0,
+            block_count: 0,
+            argument: 0,
+            transfer_mode: 0,
+            command: 0,
+            response: [0; 4],
+            host_control: 0,
+            power_control: 0,
+            clock_control: 0,
+            timeout_control: 0,
+            normal_int: 0,
+            error_int: 0,
+            normal_int_enable: 0,
+            error_int_enable: 0,
+            normal_signal_enable: 0,
+            error_signal_enable: 0,
+            card: CardState::Idle,
+            app_cmd: false,
+            transfer: None,
+        }
+    }
+
+    /// Sets normal interrupt status bits, as far as they are enabled.
+    fn raise(&mut self, bits: u16) {
+        self.normal_int |= bits & self.normal_int_enable;
+    }
+
+    /// Sets error interrupt status bits, as far as they are enabled.
+    fn raise_error(&mut self, bits: u16) {
+        self.error_int |= bits & self.error_int_enable;
+    }
+
+    fn card_status(&self) -> u32 {
+        let mut status = (self.card as u32) << 9;
+        if self.transfer.is_none() {
+            status |= R1_READY_FOR_DATA;
+        }
+        if self.app_cmd {
+            status |= R1_APP_CMD;
+        }
+        status
+    }
+
+    /// Drops the transfer in progress, returning the token of a data port read
+    /// waiting for it.
+    fn abort_transfer(&mut self) -> Option<CompletionToken> {
+        let transfer = self.transfer.take()?;
+        if matches!(self.card, CardState::Data | CardState::Rcv | CardState::Prg) {
+            self.card = CardState::Tran;
+        }
+        transfer.waiting.map(|(token, _)| token)
+    }
+
+    fn end_transfer(&mut self) {
+        self.abort_transfer();
+        self.raise(SdhciBase::INT_TRANSFER_COMPLETE);
+    }
+
+    fn fail_transfer(&mut self) -> Option<CompletionToken> {
+        let waiting = self.abort_transfer();
+        self.raise_error(SdhciBase::ERR_DATA_TIMEOUT);
+        waiting
+    }
+
+    fn read_data_port(&mut self, width: AccessWidth) -> usize {
+        let Some(transfer) = self
+            .transfer
+            .as_mut()
+            .filter(|t| t.is_pio_read() && t.data.len() == t.len)
+        else {
+            return 0;
+        };
+        let start = transfer.pos;
+        let end = (start + width.size()).min(transfer.len);
+        let val = transfer.data[start..end]
+            .iter()
+            .rev()
+            .fold(0, |val, byte| (val << 8) | *byte as usize);
+        transfer.pos = end;
+        if end == transfer.len {
+            self.end_transfer();
+        } else if start / transfer.block_len != end / transfer.block_len {
+            self.raise(SdhciBase::INT_BUFFER_READ_READY);
+        }
+        val
+    }
+
+    fn write_data_port(&mut self, width: AccessWidth, val: usize) -> Option<Request> {
+        let transfer = self
+            .transfer
+            .as_mut()
+            .filter(|t| !t.read && !t.dma && t.pos < t.len)?;
+        let start = transfer.pos;
+        let end = (start + width.size()).min(transfer.len);
+        transfer
+            .data
+            .extend_from_slice(&val.to_le_bytes()[..end - start]);
+        transfer.pos = end;
+        if end == transfer.len {
+            self.card = CardState::Prg;
+            return Some(Request::Write {
+                id: transfer.id,
+                lba: transfer.lba,
+                data: core::mem::take(&mut transfer.data),
+            });
+        }
+        if start / transfer.block_len != end / transfer.block_len {
+            self.raise(SdhciBase::INT_BUFFER_WRITE_READY);
+        }
+        None
+    }
+}
+
+/// The state shared with the completion callbacks of the backend.
+struct SdhciShared {
+    state: Mutex<SdhciState>,
+    /// The identifier of the next transfer. It survives resets, so that a
+    /// backend completion for a transfer aborted by a reset cannot match a
+    /// transfer started after it.
+    next_transfer: AtomicU64,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+    completer: Mutex<Option<Arc<dyn AccessCompleter>>>,
+}
+
+impl SdhciShared {
+    fn finish_read(&self, id: u64, result: AxResult<Vec<u8>>) {
+        let dma = self.dma.lock().clone();
+        let mut state = self.state.lock();
+        let addr = GuestPhysAddr::from(state.sdma_addr as usize);
+        let Some(transfer) = state.transfer.as_mut().filter(|t| t.id == id) else {
+            // The transfer was aborted.
+            return;
+        };
+        let len = transfer.len;
+        let result = result.and_then(|data| match (transfer.dma, &dma) {
+            (false, _) => Ok(Some(data)),
+            (true, Some(dma)) => dma.write(addr, &data).map(|()| None),
+            (true, None) => ax_err!(BadState, "no DMA accessor"),
+        });
+        let wake = match result {
+            Ok(Some(data)) => {
+                transfer.data = data;
+                let waiting = transfer.waiting.take();
+                state.raise(SdhciBase::INT_BUFFER_READ_READY);
+                waiting.map(|(token, width)| (token, state.read_data_port(width)))
+            }
+            Ok(None) => {
+                state.sdma_addr = state.sdma_addr.wrapping_add(len as u32);
+                state.end_transfer();
+                None
+            }
+            Err(err) => {
+                warn!("SDHCI read failed: {err:?}");
+                state.fail_transfer().map(|token| (token, 0))
+            }
+        };
+        drop(state);
+        if let Some((token, val)) = wake {
+            self.wake(token, val);
+        }
+    }
+
+    fn finish_write(&self, id: u64, result: AxResult) {
+        let mut state = self.state.lock();
+        let Some(transfer) = state.transfer.as_ref().filter(|t| t.id == id) else {
+            return;
+        };
+        let (dma, len) = (transfer.dma, transfer.len);
+        match result {
+            Ok(()) => {
+                if dma {
+                    state.sdma_addr = state.sdma_addr.wrapping_add(len as u32);
+                }
+                state.end_transfer();
+            }
+            Err(err) => {
+                warn!("SDHCI write failed: {err:?}");
+                state.fail_transfer();
+            }
+        }
+    }
+
+    /// Completes a data port read that was waiting for a transfer.
+    fn wake(&self, token: CompletionToken, val: usize) {
+        let completer = self.completer.lock().clone();
+        if let Some(completer) = completer {
+            completer.complete(token, Ok(val));
+        }
+    }
+}
+
+/// A single-slot SD host controller following the SD Host Controller
+/// Simplified Specification 3.00, with an emulated SDHC card backed by a
+/// [`BlockBackend`].
+///
+/// The controller gives guests without virtio drivers, such as minimal RTOS
+/// guests, a storage path through their stock SDHCI driver. The card answers
+/// the commands of the SD initialization sequence (`CMD0`, `CMD8`, `ACMD41`,
+/// `CMD2`, `CMD3`, `CMD9`, `CMD7`, `ACMD51`, `ACMD6`) and data transfers with
+/// `CMD17`/`CMD18` and `CMD24`/`CMD25` in 512-byte blocks, by PIO through the
+/// buffer data port or by SDMA. Other commands time out, as with a card that
+/// does not implement them.
+///
+/// Transfers are submitted to the backend with
+/// [`BlockBackend::read_blocks_async`] and
+/// [`BlockBackend::write_blocks_async`] and reported through the interrupt
+/// status registers when the backend completes them. A PIO read of the data
+/// port through [`BaseDeviceOps::handle_read_async`] before the data has
+/// arrived returns [`AccessOutcome::Pending`] and is completed through the
+/// [`AccessCompleter`] once it has.
+///
+/// Interrupt status bits are maintained, but the device has no interrupt line:
+/// the hypervisor injects the interrupt while
+/// [`irq_pending`](Self::irq_pending) is set, or guest drivers poll. Multi-block
+/// transfers must set the block count enable bit of the transfer mode, and the
+/// SDMA buffer boundary is ignored: each transfer is contiguous in guest
+/// memory. ADMA is not supported.
+pub struct SdhciBase {
+    range: GuestPhysAddrRange,
+    backend: Arc<dyn BlockBackend>,
+    cid: u128,
+    csd: u128,
+    shared: Arc<SdhciShared>,
+}
+
+impl SdhciBase {
+    const SDMA_ADDR: usize = 0x00;
+    const BLOCK_SIZE: usize = 0x04;
+    const BLOCK_COUNT: usize = 0x06;
+    const ARGUMENT: usize = 0x08;
+    const TRANSFER_MODE: usize = 0x0c;
+    const COMMAND: usize = 0x0e;
+    const RESPONSE: usize = 0x10;
+    const BUFFER_DATA_PORT: usize = 0x20;
+    const PRESENT_STATE: usize = 0x24;
+    const HOST_CONTROL: usize = 0x28;
+    const POWER_CONTROL: usize = 0x29;
+    const CLOCK_CONTROL: usize = 0x2c;
+    const TIMEOUT_CONTROL: usize = 0x2e;
+    const SOFTWARE_RESET: usize = 0x2f;
+    const NORMAL_INT_STATUS: usize = 0x30;
+    const ERROR_INT_STATUS: usize = 0x32;
+    const NORMAL_INT_ENABLE: usize = 0x34;
+    const ERROR_INT_ENABLE: usize = 0x36;
+    const NORMAL_SIGNAL_ENABLE: usize = 0x38;
+    const ERROR_SIGNAL_ENABLE: usize = 0x3a;
+    const CAPABILITIES: usize = 0x40;
+    const HOST_VERSION: usize = 0xfe;
+
+    /// The offsets and sizes of the registers other than the buffer data port.
+    const REGS: [(usize, usize); 25] = [
+        (Self::SDMA_ADDR, 4),
+        (Self::BLOCK_SIZE, 2),
+        (Self::BLOCK_COUNT, 2),
+        (Self::ARGUMENT, 4),
+        (Self::TRANSFER_MODE, 2),
+        (Self::COMMAND, 2),
+        (Self::RESPONSE, 4),
+        (Self::RESPONSE + 4, 4),
+        (Self::RESPONSE + 8, 4),
+        (Self::RESPONSE + 12, 4),
+        (Self::PRESENT_STATE, 4),
+        (Self::HOST_CONTROL, 1),
+        (Self::POWER_CONTROL, 1),
+        (Self::CLOCK_CONTROL, 2),
+        (Self::TIMEOUT_CONTROL, 1),
+        (Self::SOFTWARE_RESET, 1),
+        (Self::NORMAL_INT_STATUS, 2),
+        (Self::ERROR_INT_STATUS, 2),
+        (Self::NORMAL_INT_ENABLE, 2),
+        (Self::ERROR_INT_ENABLE, 2),
+        (Self::NORMAL_SIGNAL_ENABLE, 2),
+        (Self::ERROR_SIGNAL_ENABLE, 2),
+        (Self::CAPABILITIES, 4),
+        (Self::CAPABILITIES + 4, 4),
+        (Self::HOST_VERSION, 2),
+    ];
+
+    const TRANSFER_DMA: u16 = 1 << 0;
+    const TRANSFER_BLOCK_COUNT: u16 = 1 << 1;
+
+    const COMMAND_RESPONSE_MASK: u16 = 0b11;
+    const COMMAND_RESPONSE_BUSY: u16 = 0b11;
+    const COMMAND_DATA_PRESENT: u16 = 1 << 5;
+
+    const PRESENT_DAT_INHIBIT: u32 = 1 << 1;
+    const PRESENT_DAT_ACTIVE: u32 = 1 << 2;
+    const PRESENT_WRITE_ACTIVE: u32 = 1 << 8;
+    const PRESENT_READ_ACTIVE: u32 = 1 << 9;
+    const PRESENT_BUFFER_WRITE: u32 = 1 << 10;
+    const PRESENT_BUFFER_READ: u32 = 1 << 11;
+    const PRESENT_CARD: u32 = 0b111 << 16;
+    const PRESENT_WRITABLE: u32 = 1 << 19;
+    const PRESENT_LINES: u32 = 0x1f << 20;
+
+    const CLOCK_INTERNAL_ENABLE: u16 = 1 << 0;
+    const CLOCK_INTERNAL_STABLE: u16 = 1 << 1;
+
+    const RESET_ALL: u8 = 1 << 0;
+    const RESET_DATA: u8 = 1 << 2;
+
+    /// 50 MHz base and timeout clocks, high speed, SDMA and 3.3 V.
+    const CAPS: u32 = 50 | (1 << 7) | (50 << 8) | (1 << 21) | (1 << 22) | (1 << 24);
+    /// Specification version 3.00.
+    const SPEC_VERSION: u32 = 2;
+
+    /// Normal interrupt status bit: command complete.
+    pub const INT_COMMAND_COMPLETE: u16 = 1 << 0;
+    /// Normal interrupt status bit: transfer complete.
+    pub const INT_TRANSFER_COMPLETE: u16 = 1 << 1;
+    /// Normal interrupt status bit: the buffer data port accepts a block.
+    pub const INT_BUFFER_WRITE_READY: u16 = 1 << 4;
+    /// Normal interrupt status bit: a block can be read from the buffer data
+    /// port.
+    pub const INT_BUFFER_READ_READY: u16 = 1 << 5;
+    /// Normal interrupt status bit: an error interrupt status bit is set.
+    pub const INT_ERROR: u16 = 1 << 15;
+    /// Error interrupt status bit: the card did not respond to the command.
+    pub const ERR_COMMAND_TIMEOUT: u16 = 1 << 0;
+    /// Error interrupt status bit: the data transfer failed.
+    pub const ERR_DATA_TIMEOUT: u16 = 1 << 4;
+
+    /// Creates a controller at `base` with a card backed by `backend`.
+    ///
+    /// The card capacity reported to the guest is rounded down to a multiple
+    /// of 512 KiB. Returns `Err(AxError::InvalidInput)` if the block size of
+    /// the backend is not 512 bytes.
+    pub fn new(base: GuestPhysAddr, backend: Arc<dyn BlockBackend>) -> AxResult<Self> {
+        if backend.block_size() != SD_BLOCK_SIZE {
+            return ax_err!(InvalidInput, "SD cards need a 512-byte block backend");
+        }
+        // CSD version 2.0: 25 MHz, 512-byte blocks, capacity (C_SIZE + 1) *
+        // 512 KiB.
+        let c_size = (backend.num_blocks() / 1024)
+            .saturating_sub(1)
+            .min(0x3f_ffff) as u128;
+        let csd = (1 << 126)
+            | (0x0e << 112)
+            | (0x32 << 96)
+            | (0x5b5 << 84)
+            | (9 << 80)
+            | (c_size << 48)
+            | (1 << 46)
+            | (0x7f << 39)
+            | (2 << 26)
+            | (9 << 22)
+            | ((backend.is_read_only() as u128) << 12)
+            | 1;
+        // Manufacturer 0xaa, OEM "AX", product "AXSD0", revision 1.0, serial
+        // number 1, manufactured in January 2025.
+        let cid = (0xaa << 120)
+            | (0x4158 << 104)
+            | (0x41_5853_4430 << 64)
+            | (0x10 << 56)
+            | (1 << 24)
+            | (0x191 << 8)
+            | 1;
+        Ok(Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x100),
+            backend,
+            cid,
+            csd,
+            shared: Arc::new(SdhciShared {
+                state: Mutex::new(SdhciState::new()),
+                next_transfer: AtomicU64::new(0),
+                dma: Mutex::new(None),
+                completer: Mutex::new(None),
+            }),
+        })
+    }
+
+    /// Returns the block backend of the card.
+    pub fn backend(&self) -> &Arc<dyn BlockBackend> {
+        &self.backend
+    }
+
+    /// Returns whether the controller asserts its interrupt: an interrupt
+    /// status bit is set whose signal is enabled.
+    pub fn irq_pending(&self) -> bool {
+        let state = self.shared.state.lock();
+        state.normal_int & state.normal_signal_enable != 0
+            || state.error_int & state.error_signal_enable != 0
+    }
+
+    /// Returns the register at `offset` and its size, or a reserved byte.
+    fn reg_at(offset: usize) -> (usize, usize) {
+        Self::REGS
+            .into_iter()
+            .find(|&(reg, size)| (reg..reg + size).contains(&offset))
+            .unwrap_or((offset, 1))
+    }
+
+    /// Returns the R2 response registers for the 136-bit register `reg`,
+    /// whose CRC byte is not stored.
+    fn r2(reg: u128) -> [u32; 4] {
+        let reg = reg >> 8;
+        [0, 1, 2, 3].map(|i| (reg >> (32 * i)) as u32)
+    }
+
+    fn present_state(&self, state: &SdhciState) -> u32 {
+        let mut present = Self::PRESENT_CARD | Self::PRESENT_LINES;
+        if !self.backend.is_read_only() {
+            present |= Self::PRESENT_WRITABLE;
+        }
+        if let Some(transfer) = &state.transfer {
+            present |= Self::PRESENT_DAT_INHIBIT | Self::PRESENT_DAT_ACTIVE;
+            if !transfer.read {
+                present |= Self::PRESENT_WRITE_ACTIVE;
+                if !transfer.dma && transfer.pos < transfer.len {
+                    present |= Self::PRESENT_BUFFER_WRITE;
+                }
+            } else {
+                present |= Self::PRESENT_READ_ACTIVE;
+                if transfer.is_pio_read() && transfer.data.len() == transfer.len {
+                    present |= Self::PRESENT_BUFFER_READ;
+                }
+            }
+        }
+        present
+    }
+
+    fn read_reg(&self, state: &SdhciState, reg: usize) -> u32 {
+        match reg {
+            Self::SDMA_ADDR => state.sdma_addr,
+            Self::BLOCK_SIZE => state.block_size as u32,
+            Self::BLOCK_COUNT => state.block_count as u32,
+            Self::ARGUMENT => state.argument,
+            Self::TRANSFER_MODE => state.transfer_mode as u32,
+            Self::COMMAND => state.command as u32,
+            Self::RESPONSE..Self::BUFFER_DATA_PORT => state.response[(reg - Self::RESPONSE) / 4],
+            Self::PRESENT_STATE => self.present_state(state),
+            Self::HOST_CONTROL => state.host_control as u32,
+            Self::POWER_CONTROL => state.power_control as u32,
+            Self::CLOCK_CONTROL => state.clock_control as u32,
+            Self::TIMEOUT_CONTROL => state.timeout_control as u32,
+            Self::NORMAL_INT_STATUS => {
+                let error = if state.error_int != 0 {
+                    Self::INT_ERROR
+                } else {
+                    0
+                };
+                (state.normal_int | error) as u32
+            }
+            Self::ERROR_INT_STATUS => state.error_int as u32,
+            Self::NORMAL_INT_ENABLE => state.normal_int_enable as u32,
+            Self::ERROR_INT_ENABLE => state.error_int_enable as u32,
+            Self::NORMAL_SIGNAL_ENABLE => state.normal_signal_enable as u32,
+            Self::ERROR_SIGNAL_ENABLE => state.error_signal_enable as u32,
+            Self::CAPABILITIES => Self::CAPS,
+            Self::HOST_VERSION => Self::SPEC_VERSION,
+            _ => 0,
+        }
+    }
+
+    /// Writes the bits `mask` of the register at `reg` with `bits`.
+    fn write_reg(
+        &self,
+        state: &mut SdhciState,
+        reg: usize,
+        bits: u32,
+        mask: u32,
+        deferred: &mut Deferred,
+    ) {
+        let merge = |old: u32| (old & !mask) | bits;
+        match reg {
+            Self::SDMA_ADDR => state.sdma_addr = merge(state.sdma_addr),
+            Self::BLOCK_SIZE => state.block_size = merge(state.block_size as u32) as u16,
+            Self::BLOCK_COUNT => state.block_count = merge(state.block_count as u32) as u16,
+            Self::ARGUMENT => state.argument = merge(state.argument),
+            Self::TRANSFER_MODE => state.transfer_mode = merge(state.transfer_mode as u32) as u16,
+            Self::COMMAND => {
+                state.command = merge(state.command as u32) as u16;
+                // Writing the upper byte issues the command.
+                if mask & 0xff00 != 0 {
+                    self.issue(state, deferred);
+                }
+            }
+            Self::HOST_CONTROL => state.host_control = bits as u8,
+            Self::POWER_CONTROL => state.power_control = bits as u8,
+            Self::CLOCK_CONTROL => {
+                let val = merge(state.clock_control as u32) as u16 & !Self::CLOCK_INTERNAL_STABLE;
+                // The internal clock stabilizes as soon as it is enabled.
+                state.clock_control = val | ((val & Self::CLOCK_INTERNAL_ENABLE) << 1);
+            }
+            Self::TIMEOUT_CONTROL => state.timeout_control = bits as u8,
+            Self::SOFTWARE_RESET => {
+                if bits as u8 & Self::RESET_ALL != 0 {
+                    deferred.wake = state.abort_transfer();
+                    *state = SdhciState::new();
+                } else if bits as u8 & Self::RESET_DATA != 0 {
+                    deferred.wake = state.abort_transfer();
+                }
+            }
+            Self::NORMAL_INT_STATUS => state.normal_int &= !bits as u16,
+            Self::ERROR_INT_STATUS => state.error_int &= !bits as u16,
+            Self::NORMAL_INT_ENABLE => {
+                state.normal_int_enable = merge(state.normal_int_enable as u32) as u16;
+                state.normal_int &= state.normal_int_enable;
+            }
+            Self::ERROR_INT_ENABLE => {
+                state.error_int_enable = merge(state.error_int_enable as u32) as u16;
+                state.error_int &= state.error_int_enable;
+            }
+            Self::NORMAL_SIGNAL_ENABLE => {
+                state.normal_signal_enable = merge(state.normal_signal_enable as u32) as u16
+            }
+            Self::ERROR_SIGNAL_ENABLE => {
+                state.error_signal_enable = merge(state.error_signal_enable as u32) as u16
+            }
+            _ => {}
+        }
+    }
+
+    fn read_regs(&self, state: &SdhciState, offset: usize, len: usize) -> usize {
+        let mut val = 0u64;
+        let mut pos = offset;
+        while pos < offset + len {
+            let (reg, size) = Self::reg_at(pos);
+            let end = (reg + size).min(offset + len);
+            let part = (self.read_reg(state, reg) as u64 >> ((pos - reg) * 8))
+                & ((1 << ((end - pos) * 8)) - 1);
+            val |= part << ((pos - offset) * 8);
+            pos = end;
+        }
+        val as usize
+    }
+
+    fn write_regs(
+        &self,
+        state: &mut SdhciState,
+        offset: usize,
+        len: usize,
+        val: u64,
+        deferred: &mut Deferred,
+    ) {
+        let mut pos = offset;
+        while pos < offset + len {
+            let (reg, size) = Self::reg_at(pos);
+            let end = (reg + size).min(offset + len);
+            let shift = (pos - reg) * 8;
+            let mask = (((1u64 << ((end - pos) * 8)) - 1) << shift) as u32;
+            let bits = ((val >> ((pos - offset) * 8)) << shift) as u32 & mask;
+            self.write_reg(state, reg, bits, mask, deferred);
+            pos = end;
+        }
+    }
+
+    /// Executes the command in `COMMAND`, as the card would.
+    fn issue(&self, state: &mut SdhciState, deferred: &mut Deferred) {
+        let index = (state.command >> 8) & 0x3f;
+        let arg = state.argument;
+        let status = state.card_status();
+        let app_cmd = core::mem::take(&mut state.app_cmd);
+        let addressed = arg >> 16 == SD_RCA;
+        let response = match (app_cmd, index, state.card) {
+            (_, 0, _) => {
+                deferred.wake = state.abort_transfer();
+                state.card = CardState::Idle;
+                Some([0; 4])
+            }
+            (_, 2, CardState::Ready) => {
+                state.card = CardState::Ident;
+                Some(Self::r2(self.cid))
+            }
+            (_, 3, CardState::Ident | CardState::Stby) => {
+                state.card = CardState::Stby;
+                Some([(SD_RCA << 16) | (status & 0x1fff), 0, 0, 0])
+            }
+            (true, 6, CardState::Tran) => Some([status, 0, 0, 0]),
+            (_, 7, CardState::Stby | CardState::Tran) => {
+                state.card = if addressed {
+                    CardState::Tran
+                } else {
+                    CardState::Stby
+                };
+                Some([status, 0, 0, 0])
+            }
+            (_, 8, CardState::Idle) => Some([arg & 0xfff, 0, 0, 0]),
+            (_, 9, CardState::Stby) if addressed => Some(Self::r2(self.csd)),
+            (_, 12, CardState::Data | CardState::Rcv) => {
+                deferred.wake = state.abort_transfer();
+                Some([status, 0, 0, 0])
+            }
+            (_, 13, card) if addressed && card as u32 >= CardState::Stby as u32 => {
+                Some([status, 0, 0, 0])
+            }
+            (_, 16, CardState::Tran) => {
+                let error = if arg as usize == SD_BLOCK_SIZE {
+                    0
+                } else {
+                    R1_BLOCK_LEN_ERROR
+                };
+                Some([status | error, 0, 0, 0])
+            }
+            (_, 17 | 18 | 24 | 25, CardState::Tran) => {
+                Some([self.start_transfer(state, index, status, deferred), 0, 0, 0])
+            }
+            (true, 41, CardState::Idle | CardState::Ready) => {
+                if arg & SD_OCR_VOLTAGES != 0 {
+                    state.card = CardState::Ready;
+                }
+                Some([SD_OCR, 0, 0, 0])
+            }
+            (true, 51, CardState::Tran) => {
+                let id = self.new_transfer(state, true, 0, SD_SCR.len());
+                state.card = CardState::Data;
+                deferred.request = Some(Request::Supply {
+                    id,
+                    data: SD_SCR.to_vec(),
+                });
+                Some([status, 0, 0, 0])
+            }
+            (_, 55, card) if !matches!(card, CardState::Ready | CardState::Ident) => {
+                state.app_cmd = true;
+                Some([status | R1_APP_CMD, 0, 0, 0])
+            }
+            _ => None,
+        };
+        match response {
+            Some(response) => {
+                state.response = response;
+                state.raise(Self::INT_COMMAND_COMPLETE);
+                // Busy responses without data end with a transfer complete.
+                let busy =
+                    state.command & Self::COMMAND_RESPONSE_MASK == Self::COMMAND_RESPONSE_BUSY;
+                if busy && state.command & Self::COMMAND_DATA_PRESENT == 0 {
+                    state.raise(Self::INT_TRANSFER_COMPLETE);
+                }
+            }
+            None => state.raise_error(Self::ERR_COMMAND_TIMEOUT),
+        }
+    }
+
+    fn new_transfer(&self, state: &mut SdhciState, read: bool, lba: u64, len: usize) -> u64 {
+        let id = self.shared.next_transfer.fetch_add(1, Ordering::Relaxed);
+        state.transfer = Some(Transfer {
+            id,
+            read,
+            dma: state.transfer_mode & Self::TRANSFER_DMA != 0,
+            lba,
+            block_len: len.min(SD_BLOCK_SIZE),
+            len,
+            data: Vec::new(),
+            pos: 0,
+            waiting: None,
+        });
+        id
+    }
+
+    /// Starts the data transfer of a block read or write command, returning
+    /// its R1 response.
+    fn start_transfer(
+        &self,
+        state: &mut SdhciState,
+        index: u16,
+        status: u32,
+        deferred: &mut Deferred,
+    ) -> u32 {
+        let read = matches!(index, 17 | 18);
+        let blocks = match index {
+            17 | 24 => 1,
+            _ if state.transfer_mode & Self::TRANSFER_BLOCK_COUNT != 0 => state.block_count as u64,
+            _ => 0,
+        };
+        let lba = state.argument as u64;
+        let error = if blocks == 0 || (state.block_size & 0xfff) as usize != SD_BLOCK_SIZE {
+            Some(0)
+        } else if lba
+            .checked_add(blocks)
+            .is_none_or(|end| end > self.backend.num_blocks())
+        {
+            Some(R1_OUT_OF_RANGE)
+        } else if !read && self.backend.is_read_only() {
+            Some(R1_WP_VIOLATION)
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            state.raise_error(Self::ERR_DATA_TIMEOUT);
+            return status | error;
+        }
+
+        let len = blocks as usize * SD_BLOCK_SIZE;
+        let id = self.new_transfer(state, read, lba, len);
+        let addr = GuestPhysAddr::from(state.sdma_addr as usize);
+        let dma = state.transfer_mode & Self::TRANSFER_DMA != 0;
+        if read {
+            state.card = CardState::Data;
+            deferred.request = Some(Request::Read { id, lba, len });
+        } else {
+            state.card = CardState::Rcv;
+            if dma {
+                deferred.request = Some(Request::DmaWrite { id, lba, addr, len });
+            } else {
+                state.raise(Self::INT_BUFFER_WRITE_READY);
+            }
+        }
+        status
+    }
+
+    /// Starts the backend work and wakes the waiting reads deferred by a
+    /// register access.
+    fn run(&self, deferred: Deferred) {
+        if let Some(token) = deferred.wake {
+            self.shared.wake(token, 0);
+        }
+        let Some(request) = deferred.request else {
+            return;
+        };
+        let shared = self.shared.clone();
+        match request {
+            Request::Read { id, lba, len } => self.backend.read_blocks_async(
+                lba,
+                len,
+                Box::new(move |result| shared.finish_read(id, result)),
+            ),
+            Request::Supply { id, data } => shared.finish_read(id, Ok(data)),
+            Request::Write { id, lba, data } => self.backend.write_blocks_async(
+                lba,
+                data,
+                Box::new(move |result| shared.finish_write(id, result)),
+            ),
+            Request::DmaWrite { id, lba, addr, len } => {
+                let dma = self.shared.dma.lock().clone();
+                let mut data = vec![0; len];
+                let read = match dma {
+                    Some(dma) => dma.read(addr, &mut data),
+                    None => Err(AxError::BadState),
+                };
+                match read {
+                    Ok(()) => self.backend.write_blocks_async(
+                        lba,
+                        data,
+                        Box::new(move |result| shared.finish_write(id, result)),
+                    ),
+                    Err(err) => shared.finish_write(id, Err(err)),
+                }
+            }
+        }
+    }
+
+    fn is_data_port(offset: usize) -> bool {
+        (Self::BUFFER_DATA_PORT..Self::PRESENT_STATE).contains(&offset)
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for SdhciBase {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let Some(offset) = self.range.offset_of(addr) else {
+            return Ok(0);
+        };
+        let mut state = self.shared.state.lock();
+        if Self::is_data_port(offset) {
+            return Ok(state.read_data_port(width));
+        }
+        Ok(self.read_regs(&state, offset, width.size()))
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        let Some(offset) = self.range.offset_of(addr) else {
+            return Ok(());
+        };
+        let mut deferred = Deferred::default();
+        let mut state = self.shared.state.lock();
+        if Self::is_data_port(offset) {
+            deferred.request = state.write_data_port(width, val);
+        } else {
+            self.write_regs(&mut state, offset, width.size(), val as u64, &mut deferred);
+        }
+        drop(state);
+        self.run(deferred);
+        Ok(())
+    }
+
+    /// Reads of the buffer data port made while a PIO read waits for the
+    /// backend complete once the data arrives.
+    fn handle_read_async(
+        &self,
+        addr: GuestPhysAddr,
+        width: AccessWidth,
+    ) -> AxResult<AccessOutcome> {
+        let async_port = self.range.offset_of(addr).is_some_and(Self::is_data_port)
+            && self.shared.completer.lock().is_some();
+        if async_port {
+            let mut state = self.shared.state.lock();
+            if let Some(transfer) = state
+                .transfer
+                .as_mut()
+                .filter(|t| t.is_pio_read() && t.data.len() < t.len && t.waiting.is_none())
+            {
+                let token = CompletionToken::next();
+                transfer.waiting = Some((token, width));
+                return Ok(AccessOutcome::Pending(token));
+            }
+        }
+        self.handle_read(addr, width).map(AccessOutcome::Completed)
+    }
+
+    fn reset(&self) -> AxResult {
+        let mut state = self.shared.state.lock();
+        let waiting = state.abort_transfer();
+        *state = SdhciState::new();
+        drop(state);
+        if let Some(token) = waiting {
+            self.shared.wake(token, 0);
+        }
+        Ok(())
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.shared.dma.lock() = Some(accessor);
+    }
+
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        *self.shared.completer.lock() = Some(completer);
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        Capability::AsyncIo | Capability::Reset
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SPI controller emulation with chip-select routed slave devices.
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::AxResult;
+use spin::Mutex;
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, RegValue, SlaveRegistry};
+
+/// A device attached to an emulated SPI bus.
+pub trait SpiSlave: Send + Sync {
+    /// Called when the chip select line of this slave is asserted.
+    fn select(&self) {}
+
+    /// Exchanges one byte full-duplex: receives `tx` from the controller and
+    /// returns the byte shifted out at the same time.
+    fn transfer(&self, tx: u8) -> u8;
+
+    /// Called when the chip select line of this slave is deasserted, ending
+    /// the current command.
+    fn deselect(&self) {}
+}
+
+/// An SPI bus, mapping chip-select indices to slave devices.
+pub type SpiBus = SlaveRegistry<u32, dyn SpiSlave>;
+
+struct SpiState {
+    enable: bool,
+    cs: u32,
+    selected: Option<Arc<dyn SpiSlave>>,
+    rx_fifo: VecDeque<u8>,
+}
+
+/// A simple SPI master controller routing transfers to the slave selected by
+/// its chip-select register. All registers are 32 bits wide:
+///
+/// | Offset | Name     | Access | Description                                   |
+/// |--------|----------|--------|-----------------------------------------------|
+/// | 0x00   | `CTRL`   | RW     | Bit 0: controller enabled.                    |
+/// | 0x04   | `CS`     | RW     | Index of the asserted chip select, or [`SpiControllerBase::CS_NONE`]. |
+/// | 0x08   | `DATA`   | RW     | Write: transmit a byte. Read: pop a received byte. |
+/// | 0x0c   | `STATUS` | RO     | Bit 0: RX FIFO not empty, bit 1: TX FIFO not full. |
+/// | 0x10   | `RXLVL`  | RO     | Number of bytes in the RX FIFO.               |
+///
+/// Each byte written to `DATA` is exchanged with the selected slave right
+/// away, and the byte shifted back is queued in the RX FIFO. With no slave
+/// selected (or none attached at the selected index) the controller receives
+/// `0xff`, the idle level of a pulled-up MISO line.
+pub struct SpiControllerBase {
+    range: GuestPhysAddrRange,
+    bus: Arc<SpiBus>,
+    state: Mutex<SpiState>,
+}
+
+impl SpiControllerBase {
+    const REG_CTRL: usize = 0x00;
+    const REG_CS: usize = 0x04;
+    const REG_DATA: usize = 0x08;
+    const REG_STATUS: usize = 0x0c;
+    const REG_RXLVL: usize = 0x10;
+
+    const FIFO_DEPTH: usize = 64;
+
+    /// The `CS` value deasserting all chip selects.
+    pub const CS_NONE: u32 = u32::MAX;
+
+    /// Creates a controller at `base` driving the slaves attached to `bus`.
+    pub fn new(base: GuestPhysAddr, bus: Arc<SpiBus>) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x100),
+            bus,
+            state: Mutex::new(SpiState {
+                enable: false,
+                cs: Self::CS_NONE,
+                selected: None,
+                rx_fifo: VecDeque::with_capacity(Self::FIFO_DEPTH),
+            }),
+        }
+    }
+
+    /// Returns the bus of the controller.
+    pub fn bus(&self) -> &Arc<SpiBus> {
+        &self.bus
+    }
+
+    fn set_cs(&self, state: &mut SpiState, cs: u32) {
+        if cs == state.cs {
+            return;
+        }
+        if let Some(slave) = state.selected.take() {
+            slave.deselect();
+        }
+        state.cs = cs;
+        if cs != Self::CS_NONE {
+            state.selected = self.bus.get(cs);
+            if let Some(slave) = &state.selected {
+                slave.select();
+            }
+        }
+    }
+
+    fn transmit(state: &mut SpiState, tx: u8) {
+        if !state.enable {
+            return;
+        }
+        let rx = match &state.selected {
+            Some(slave) => slave.transfer(tx),
+            None => 0xff,
+        };
+        // Like most controllers, drop received data when the FIFO is full.
+        if state.rx_fifo.len() < Self::FIFO_DEPTH {
+            state.rx_fifo.push_back(rx);
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for SpiControllerBase {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let mut state = self.state.lock();
+        let val = match self.range.offset_of(addr) {
+            Some(Self::REG_CTRL) => state.enable as usize,
+            Some(Self::REG_CS) => state.cs as usize,
+            Some(Self::REG_DATA) => state.rx_fifo.pop_front().unwrap_or(0) as usize,
+            Some(Self::REG_STATUS) => 0b10 | !state.rx_fifo.is_empty() as usize,
+            Some(Self::REG_RXLVL) => state.rx_fifo.len(),
+            _ => 0,
+        };
+        Ok(RegValue::new(val, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        let mut state = self.state.lock();
+        match self.range.offset_of(addr) {
+            Some(Self::REG_CTRL) => {
+                state.enable = val & 1 != 0;
+                if !state.enable {
+                    self.set_cs(&mut state, Self::CS_NONE);
+                    state.rx_fifo.clear();
+                }
+            }
+            Some(Self::REG_CS) => self.set_cs(&mut state, val as u32),
+            Some(Self::REG_DATA) => Self::transmit(&mut state, val as u8),
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Versioned framing of saved device state.
+
+use alloc::vec::Vec;
+
+use axerrno::{AxResult, ax_err};
+
+/// The header prefixed to the state saved by
+/// [`BaseDeviceOps::save_state`](crate::BaseDeviceOps::save_state).
+///
+/// The header carries a magic number, the version of the device's state
+/// layout and the length of the payload, so that `load_state` can reject
+/// truncated data and migrate or refuse state saved by another version of the
+/// device model. All fields are little-endian.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::DeviceStateHeader;
+///
+/// let state = DeviceStateHeader::encode(2, &[0xaa, 0xbb]);
+/// let (header, payload) = DeviceStateHeader::decode(&state).unwrap();
+/// assert_eq!(header.version, 2);
+/// assert_eq!(payload, [0xaa, 0xbb]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceStateHeader {
+    /// The version of the state layout, defined by the device model.
+    pub version: u32,
+    /// The length of the payload following the header, in bytes.
+    pub len: u32,
+}
+
+impl DeviceStateHeader {
+    /// The magic number starting every saved state.
+    pub const MAGIC: [u8; 4] = *b"AXDS";
+
+    /// The encoded size of the header, in bytes.
+    pub const SIZE: usize = 12;
+
+    /// Returns `payload` prefixed with a header for state layout `version`.
+    pub fn encode(version: u32, payload: &[u8]) -> Vec<u8> {
+        let mut state = Vec::with_capacity(Self::SIZE + payload.len());
+        state.extend_from_slice(&Self::MAGIC);
+        state.extend_from_slice(&version.to_le_bytes());
+        state.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        state.extend_from_slice(payload);
+        state
+    }
+
+    /// Splits saved state into its header and payload.
+    ///
+    /// Returns `Err(AxError::InvalidData)` if the magic number is wrong or the
+    /// payload length does not match the header.
+    pub fn decode(state: &[u8]) -> AxResult<(Self, &[u8])> {
+        let Some((header, payload)) = state.split_at_checked(Self::SIZE) else {
+            return ax_err!(InvalidData, "device state too short");
+        };
+        if header[..4] != Self::MAGIC {
+            return ax_err!(InvalidData, "bad device state magic");
+        }
+        let header = Self {
+            version: u32::from_le_bytes(header[4..8].try_into().unwrap()),
+            len: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+        };
+        if payload.len() != header.len as usize {
+            return ax_err!(InvalidData, "device state length mismatch");
+        }
+        Ok((header, payload))
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-device access statistics.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axaddrspace::device::AccessWidth;
+use axerrno::AxResult;
+use spin::Mutex;
+
+use crate::{
+    AccessContext, AccessKind, AccessOutcome, BaseDeviceOps, ClockSource, DeviceAddrRangeExt,
+    forward::forward_base_device_ops,
+};
+
+/// Access counters of a device or of one of its regions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AccessStats {
+    /// The number of reads.
+    pub reads: u64,
+    /// The number of writes.
+    pub writes: u64,
+    /// The number of bytes read.
+    pub bytes_read: u64,
+    /// The number of bytes written.
+    pub bytes_written: u64,
+    /// The number of accesses the device failed.
+    pub errors: u64,
+    /// The monotonic time of the last access in nanoseconds, or `None` if
+    /// there was no access since a clock was injected into the
+    /// [`StatsDevice`].
+    pub last_access_ns: Option<u64>,
+}
+
+impl AccessStats {
+    fn record(
+        &mut self,
+        width: AccessWidth,
+        kind: AccessKind,
+        count: u64,
+        ok: bool,
+        now_ns: Option<u64>,
+    ) {
+        let bytes = width.size() as u64 * count;
+        match kind {
+            AccessKind::Read => {
+                self.reads += count;
+                self.bytes_read += bytes;
+            }
+            AccessKind::Write => {
+                self.writes += count;
+                self.bytes_written += bytes;
+            }
+        }
+        self.errors += !ok as u64;
+        if now_ns.is_some() {
+            self.last_access_ns = now_ns;
+        }
+    }
+}
+
+/// Access statistics of a device, returned by [`BaseDeviceOps::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceStats<R> {
+    /// The counters of all accesses to the device.
+    pub total: AccessStats,
+    /// The counters of the accesses to each region of interest of the device,
+    /// e.g. a notification register or a data window.
+    pub regions: Vec<(R, AccessStats)>,
+}
+
+/// A wrapper maintaining the [`DeviceStats`] of a device.
+///
+/// Like [`JournaledDevice`](crate::JournaledDevice), statistics are opt-in:
+/// wrap the device before registering it, and read the counters through
+/// [`BaseDeviceOps::stats`]. Regions added with
+/// [`with_region`](Self::with_region) get their own counters; an access is
+/// counted in the first region containing it, and a bulk access of `count`
+/// elements as `count` accesses of the region containing its first element.
+///
+/// Timestamps are the monotonic time of the [`ClockSource`] injected with
+/// [`BaseDeviceOps::set_clock_source`], which is also passed on to the wrapped
+/// device. Accesses before a clock is injected are counted without a
+/// timestamp.
+pub struct StatsDevice<R, D> {
+    inner: D,
+    clock: Mutex<Option<Arc<dyn ClockSource>>>,
+    stats: Mutex<DeviceStats<R>>,
+}
+
+impl<R: DeviceAddrRangeExt + Clone, D: BaseDeviceOps<R>> StatsDevice<R, D> {
+    /// Wraps `inner`.
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            clock: Mutex::new(None),
+            stats: Mutex::new(DeviceStats {
+                total: AccessStats::default(),
+                regions: Vec::new(),
+            }),
+        }
+    }
+
+    /// Counts the accesses to `region` separately.
+    pub fn with_region(self, region: R) -> Self {
+        self.stats
+            .lock()
+            .regions
+            .push((region, AccessStats::default()));
+        self
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Resets all counters.
+    pub fn clear(&self) {
+        let mut stats = self.stats.lock();
+        stats.total = AccessStats::default();
+        for (_, region) in &mut stats.regions {
+            *region = AccessStats::default();
+        }
+    }
+
+    fn record(&self, addr: R::Addr, width: AccessWidth, kind: AccessKind, ok: bool) {
+        self.record_n(addr, width, kind, 1, ok);
+    }
+
+    fn record_n(&self, addr: R::Addr, width: AccessWidth, kind: AccessKind, count: u64, ok: bool) {
+        let now_ns = self
+            .clock
+            .lock()
+            .as_ref()
+            .map(|clock| clock.monotonic().as_nanos() as u64);
+        let mut stats = self.stats.lock();
+        stats.total.record(width, kind, count, ok, now_ns);
+        if let Some((_, region)) = stats
+            .regions
+            .iter_mut()
+            .find(|(range, _)| range.contains(addr))
+        {
+            region.record(width, kind, count, ok, now_ns);
+        }
+    }
+}
+
+impl<R: DeviceAddrRangeExt + Clone + 'static, D: BaseDeviceOps<R>> BaseDeviceOps<R>
+    for StatsDevice<R, D>
+{
+    forward_base_device_ops!(
+        R,
+        inner => identity, handle_call, peek, poke, lifecycle, set_dma_accessor, set_completer,
+        set_region_sink, set_timer_service, on_timer, abi_version, coalesced_writes,
+        dump_registers, as_irq_chip, capabilities, manifest
+    );
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        *self.clock.lock() = Some(clock.clone());
+        self.inner.set_clock_source(clock);
+    }
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        let result = self.inner.handle_read(addr, width);
+        self.record(addr, width, AccessKind::Read, result.is_ok());
+        result
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        let result = self.inner.handle_write(addr, width, val);
+        self.record(addr, width, AccessKind::Write, result.is_ok());
+        result
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        let result = self.inner.handle_read_ctx(addr, width, ctx);
+        self.record(addr, width, AccessKind::Read, result.is_ok());
+        result
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        let result = self.inner.handle_write_ctx(addr, width, val, ctx);
+        self.record(addr, width, AccessKind::Write, result.is_ok());
+        result
+    }
+
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        let result = self.inner.handle_read_bulk(addr, width, count, stride, buf);
+        self.record_n(addr, width, AccessKind::Read, count as u64, result.is_ok());
+        result
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult {
+        let result = self
+            .inner
+            .handle_write_bulk(addr, width, count, stride, buf);
+        self.record_n(addr, width, AccessKind::Write, count as u64, result.is_ok());
+        result
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        let result = self.inner.handle_read_async(addr, width);
+        self.record(addr, width, AccessKind::Read, result.is_ok());
+        result
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        let result = self.inner.handle_write_async(addr, width, val);
+        self.record(addr, width, AccessKind::Write, result.is_ok());
+        result
+    }
+
+    fn stats(&self) -> Option<DeviceStats<R>> {
+        Some(self.stats.lock().clone())
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Paravirtual steal time published to the guest in shared memory.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{Ordering, fence};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    AccessContext, BaseDeviceOps, Capability, CapabilitySet, DeviceAddrRangeExt, DeviceManifest,
+    EmuDeviceType, GuestMemoryAccessor, RegValue,
+};
+
+/// Scheduling information about the vCPUs of a VM, implemented by the
+/// hypervisor scheduler.
+///
+/// Paravirtual devices such as [`StealTimeDevice`] report it to the guest,
+/// so that the guest scheduler accounts for the time its vCPUs spent waiting
+/// for a physical CPU instead of charging it to the tasks that were running.
+pub trait SchedulerInfoOps: Send + Sync {
+    /// Returns the nanoseconds vCPU `vcpu` was runnable but not running,
+    /// since the VM started.
+    fn steal_time_ns(&self, vcpu: usize) -> u64;
+
+    /// Returns whether vCPU `vcpu` is currently preempted by the host.
+    fn is_preempted(&self, vcpu: usize) -> bool;
+}
+
+/// The steal time of a vCPU, laid out as the `kvm_steal_time` structure:
+///
+/// | Offset | Field       | Description                                   |
+/// |--------|-------------|-----------------------------------------------|
+/// | 0x00   | `steal`     | Nanoseconds the vCPU was runnable but not running. |
+/// | 0x08   | `version`   | Seqlock counter, odd while an update is made. |
+/// | 0x0c   | `flags`     | Reserved, 0.                                  |
+/// | 0x10   | `preempted` | Bit 0 set while the vCPU is preempted.        |
+///
+/// Updates follow the seqlock protocol of
+/// [`PvClockInfo`](crate::PvClockInfo), with [`publish`](Self::publish) on
+/// the writer side and [`read`](Self::read) on the reader side.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StealTimeInfo {
+    /// Nanoseconds the vCPU was runnable but not running.
+    pub steal: u64,
+    /// The seqlock counter.
+    pub version: u32,
+    /// Reserved flags.
+    pub flags: u32,
+    /// [`PREEMPTED`](Self::PREEMPTED) while the vCPU is preempted.
+    pub preempted: u8,
+}
+
+impl StealTimeInfo {
+    /// The size of the structure in guest memory.
+    pub const SIZE: usize = 64;
+
+    /// `preempted` bit: the vCPU is preempted, so a guest spinning on a lock
+    /// held by it should yield instead.
+    pub const PREEMPTED: u8 = 1 << 0;
+
+    /// Writes the structure to guest memory at `addr` as a seqlock writer.
+    ///
+    /// `self.version` is advanced by a full seqlock cycle; the version
+    /// already in guest memory is ignored.
+    pub fn publish(&mut self, mem: &dyn GuestMemoryAccessor, addr: GuestPhysAddr) -> AxResult {
+        self.version |= 1;
+        mem.write_u32(addr + 8, self.version)?;
+        fence(Ordering::Release);
+        mem.write_u64(addr, self.steal)?;
+        mem.write_u32(addr + 12, self.flags)?;
+        mem.write(addr + 16, &[self.preempted])?;
+        fence(Ordering::Release);
+        self.version = self.version.wrapping_add(1);
+        mem.write_u32(addr + 8, self.version)
+    }
+
+    /// Reads the structure from guest memory at `addr` as a seqlock reader.
+    ///
+    /// Returns `Err(AxError::WouldBlock)` if an update was in progress or
+    /// completed during the read, in which case the caller should retry.
+    pub fn read(mem: &dyn GuestMemoryAccessor, addr: GuestPhysAddr) -> AxResult<Self> {
+        let version = mem.read_u32(addr + 8)?;
+        fence(Ordering::Acquire);
+        let steal = mem.read_u64(addr)?;
+        let flags = mem.read_u32(addr + 12)?;
+        let mut preempted = [0];
+        mem.read(addr + 16, &mut preempted)?;
+        fence(Ordering::Acquire);
+        if version & 1 != 0 || mem.read_u32(addr + 8)? != version {
+            return ax_err!(WouldBlock, "steal time update in progress");
+        }
+        Ok(Self {
+            steal,
+            version,
+            flags,
+            preempted: preempted[0],
+        })
+    }
+}
+
+#[derive(Default)]
+struct StealArea {
+    /// The value written to `AREA_LO`/`AREA_HI`.
+    area_reg: u64,
+    /// The structure being updated, once enabled by the guest.
+    area: Option<GuestPhysAddr>,
+    info: StealTimeInfo,
+}
+
+/// A paravirtual steal time device, reporting to each vCPU of the guest the
+/// time it spent waiting for a physical CPU, in the style of KVM steal time.
+///
+/// Each vCPU registers the address of a [`StealTimeInfo`] structure in its
+/// RAM through its own bank of registers, selected by the vCPU of the access
+/// (see [`AccessContext`]); accesses without a context use the bank of vCPU
+/// 0. The device fills the structures in through the [`GuestMemoryAccessor`]
+/// injected with [`set_dma_accessor`](BaseDeviceOps::set_dma_accessor), from
+/// the [`SchedulerInfoOps`] it is created with. The hypervisor calls
+/// [`update`](Self::update) before resuming a vCPU, as KVM does on each
+/// entry, so the guest sees its steal time advance and whether the other
+/// vCPUs are preempted. Registers:
+///
+/// | Offset | Name       | Access | Description                                  |
+/// |--------|------------|--------|----------------------------------------------|
+/// | 0x00   | `AREA_LO`  | RW     | Bits 31:6 of the structure address; bit 0 enables steal time. |
+/// | 0x04   | `AREA_HI`  | RW     | Bits 63:32 of the structure address.         |
+/// | 0x08   | `FEATURES` | RO     | Bit 0: steal time, bit 1: preempted hint.     |
+///
+/// The structure must be aligned to 64 bytes; the guest writes `AREA_HI`
+/// first, and the write to `AREA_LO` takes effect. Guests discover the
+/// device through the `axvisor,pv-steal-time` compatible string of its
+/// [`manifest`](BaseDeviceOps::manifest), emitted in the generated device
+/// tree.
+pub struct StealTimeDevice {
+    range: GuestPhysAddrRange,
+    scheduler: Arc<dyn SchedulerInfoOps>,
+    areas: Mutex<Vec<StealArea>>,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+}
+
+impl StealTimeDevice {
+    const REG_AREA_LO: usize = 0x00;
+    const REG_AREA_HI: usize = 0x04;
+    const REG_FEATURES: usize = 0x08;
+
+    /// `AREA_LO` bit: steal time is enabled.
+    pub const AREA_ENABLE: u64 = 1 << 0;
+
+    /// `FEATURES` bits: steal time and the preempted hint are supported.
+    pub const FEATURES: u32 = 0b11;
+
+    /// Creates a steal time device at `base` for a VM with `vcpus` vCPUs,
+    /// reporting the information of `scheduler`.
+    pub fn new(base: GuestPhysAddr, vcpus: usize, scheduler: Arc<dyn SchedulerInfoOps>) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x1000),
+            scheduler,
+            areas: Mutex::new((0..vcpus).map(|_| StealArea::default()).collect()),
+            dma: Mutex::new(None),
+        }
+    }
+
+    /// Returns the address of the structure enabled by vCPU `vcpu`, if any.
+    pub fn area(&self, vcpu: usize) -> Option<GuestPhysAddr> {
+        self.areas.lock().get(vcpu).and_then(|area| area.area)
+    }
+
+    /// Publishes the current steal time of vCPU `vcpu`, if it has enabled
+    /// steal time.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the vCPU does not exist, and
+    /// `Err(AxError::BadState)` if no DMA accessor has been injected.
+    pub fn update(&self, vcpu: usize) -> AxResult {
+        let mut areas = self.areas.lock();
+        match areas.get_mut(vcpu) {
+            Some(area) => self.publish(vcpu, area),
+            None => ax_err!(InvalidInput, "no such vCPU"),
+        }
+    }
+
+    fn publish(&self, vcpu: usize, area: &mut StealArea) -> AxResult {
+        let Some(addr) = area.area else {
+            return Ok(());
+        };
+        let Some(mem) = self.dma.lock().clone() else {
+            return ax_err!(BadState, "steal time device has no DMA accessor");
+        };
+        area.info.steal = self.scheduler.steal_time_ns(vcpu);
+        area.info.preempted = if self.scheduler.is_preempted(vcpu) {
+            StealTimeInfo::PREEMPTED
+        } else {
+            0
+        };
+        area.info.publish(&*mem, addr)
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for StealTimeDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        self.handle_read_ctx(addr, width, AccessContext::new(0))
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        self.handle_write_ctx(addr, width, val, AccessContext::new(0))
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: GuestPhysAddr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        let Some(area_reg) = self.areas.lock().get(ctx.vcpu_id).map(|area| area.area_reg) else {
+            return ax_err!(InvalidInput, "no such vCPU");
+        };
+        let val = match self.range.offset_of(addr) {
+            Some(Self::REG_AREA_LO) => area_reg as u32,
+            Some(Self::REG_AREA_HI) => (area_reg >> 32) as u32,
+            Some(Self::REG_FEATURES) => Self::FEATURES,
+            _ => 0,
+        };
+        Ok(RegValue::new(val as usize, width).zero_extend())
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: GuestPhysAddr,
+        _width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        let mut areas = self.areas.lock();
+        let Some(area) = areas.get_mut(ctx.vcpu_id) else {
+            return ax_err!(InvalidInput, "no such vCPU");
+        };
+        let val = val as u32 as u64;
+        match self.range.offset_of(addr) {
+            Some(Self::REG_AREA_HI) => {
+                area.area_reg = (area.area_reg & 0xffff_ffff) | val << 32;
+                Ok(())
+            }
+            Some(Self::REG_AREA_LO) => {
+                area.area_reg = (area.area_reg & !0xffff_ffff) | val;
+                area.area = (area.area_reg & Self::AREA_ENABLE != 0).then(|| {
+                    GuestPhysAddr::from(area.area_reg as usize & !(StealTimeInfo::SIZE - 1))
+                });
+                self.publish(ctx.vcpu_id, area)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn reset(&self) -> AxResult {
+        for area in self.areas.lock().iter_mut() {
+            area.area_reg = 0;
+            area.area = None;
+        }
+        Ok(())
+    }
+
+    fn on_vcpu_added(&self, index: usize) -> AxResult {
+        let mut areas = self.areas.lock();
+        if areas.len() <= index {
+            areas.resize_with(index + 1, StealArea::default);
+        }
+        Ok(())
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        Capability::Reset.into()
+    }
+
+    fn manifest(&self) -> DeviceManifest {
+        DeviceManifest::new("Paravirtual steal time").with_compatible("axvisor,pv-steal-time")
+    }
+}
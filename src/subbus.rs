@@ -0,0 +1,74 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Second-level buses behind emulated controller devices.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt::Debug;
+
+use axerrno::{AxResult, ax_err};
+use spin::RwLock;
+
+/// A registry of slave devices attached to a second-level bus (I2C, SPI, ...)
+/// behind an emulated controller.
+///
+/// The controller device owns the registry and routes transfers to the slave
+/// attached at the target address, while the hypervisor attaches slave models
+/// (sensors, EEPROMs, PMICs, flash chips) to it when building the platform.
+pub struct SlaveRegistry<A, S: ?Sized> {
+    slaves: RwLock<BTreeMap<A, Arc<S>>>,
+}
+
+impl<A: Ord + Copy + Debug, S: ?Sized> SlaveRegistry<A, S> {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            slaves: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Attaches `slave` at `addr`.
+    ///
+    /// Returns `Err(AxError::AlreadyExists)` if another slave is attached at
+    /// the same address.
+    pub fn attach(&self, addr: A, slave: Arc<S>) -> AxResult {
+        let mut slaves = self.slaves.write();
+        if slaves.contains_key(&addr) {
+            return ax_err!(AlreadyExists, "sub-bus address already in use");
+        }
+        slaves.insert(addr, slave);
+        Ok(())
+    }
+
+    /// Detaches and returns the slave at `addr`, if any.
+    pub fn detach(&self, addr: A) -> Option<Arc<S>> {
+        self.slaves.write().remove(&addr)
+    }
+
+    /// Returns the slave attached at `addr`, if any.
+    pub fn get(&self, addr: A) -> Option<Arc<S>> {
+        self.slaves.read().get(&addr).cloned()
+    }
+
+    /// Returns the addresses of all attached slaves, in ascending order.
+    pub fn addresses(&self) -> Vec<A> {
+        self.slaves.read().keys().copied().collect()
+    }
+}
+
+impl<A: Ord + Copy + Debug, S: ?Sized> Default for SlaveRegistry<A, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
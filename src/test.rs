@@ -13,11 +13,39 @@
 // limitations under the License.
 
 use alloc::vec;
-use alloc::{sync::Arc, vec::Vec};
-use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
-use axerrno::AxResult;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use axaddrspace::{
+    GuestPhysAddr, GuestPhysAddrRange,
+    device::{AccessWidth, Port, PortRange, SysRegAddrRange},
+};
+use axerrno::{AxError, AxResult};
 
-use crate::{BaseDeviceOps, EmuDeviceType, map_device_of_type};
+use crate::{
+    AccessCompleter, AccessContext, AccessFilter, AccessKind, AccessOutcome, AccessStats,
+    Backpressure, BalloonDevice, BaseDeviceOps, BaseMultiSpaceDeviceOps, BlockBackend,
+    BlockReadCallback, BusBridgeDevice, ByteOrder, Capability, CapabilitySet, CapacityCallback,
+    CatchUpPolicy, ClockResetControllerBase, ClockSource, CoalescedWrite, CoalescedWriteRing,
+    CompletionToken, ConfigError, ConfigValue, CoveragePoint, CoveredDevice, DeviceAddrRangeExt,
+    DeviceDeps, DeviceFactory, DeviceManager, DeviceManifest, DeviceRegionSink, DeviceRegistry,
+    DeviceStateHeader, DeviceTracer, DirtyRect, DispatchStrategy, DisplayConsumer, DmaTranslator,
+    Domain, DomainEvent, EcamWindow, EmuDeviceType, EmulatedDeviceConfig, EntropySource,
+    ErrorInjector, FilterAction, FlashDevice, FramebufferDevice, FsAttr, FsBackend, FsDirEntry,
+    FsHandle, GuestBufferList, GuestClock, GuestMemoryAccessor, GuestProfile, HostDeviceManager,
+    HypercallId, HypercallRange, I2cBus, I2cControllerBase, I2cSlave, InputBackend, InputCallback,
+    InputEvent, IrqRoute, IrqRoutingTable, IrqTarget, JournaledDevice, LatencyClass,
+    LatencyObserver, LowPowerAccess, MailboxDevice, MailboxHandler, MemoryControlOps, MmioDevice,
+    MsiMessage, MsixTable, NaturalWidthAdapter, NetBackend, NetModeration, PciBar, PciBarChange,
+    PciBdf, PciConfigAddr, PciConfigRange, PciConfigSpace, PermissionCheckedDevice,
+    PersistentStore, PowerState, PvClockDevice, PvClockInfo, QueueSet, RegValue, RegionAccess,
+    RegionConfig, RegionId, RegionSpace, RegionUpdateHandler, RegionUpdateSink, RxCallback,
+    SchedulerInfoOps, SdhciBase, SpiBus, SpiControllerBase, SpiSlave, SplitQueue, StatsDevice,
+    StealTimeDevice, StealTimeInfo, ThrottleResponse, ThrottledDevice, TimerService, TimerToken,
+    TpmBackend, TpmTisDevice, TraceRecord, TraceRecorder, TransactionalRegion, TrngDevice,
+    UnhandledAccessPolicy, UnifiedAddr, UnifiedAddrRange, ValidateConfig, VirtioFsDevice,
+    VirtioInputDevice, VirtioIommuDevice, VirtioMmioDevice, VirtioMmioRegs, VirtioNetDevice,
+    VirtioRngDevice, VirtualIrqChip, VmId, decode_trace, map_device_of_type, replay, space_views,
+    width_mask,
+};
 
 const DEVICE_A_TEST_METHOD_ANSWER: usize = 42;
 
@@ -87,3 +115,5610 @@ fn test_device_type_test() {
     }
     assert!(device_a_found, "DeviceA was not found");
 }
+
+#[test]
+fn test_range_arithmetic() {
+    let range = GuestPhysAddrRange::from_start_size(0x1000.into(), 0x1000);
+    let head = GuestPhysAddrRange::from_start_size(0x800.into(), 0x1000);
+    let inner = GuestPhysAddrRange::from_start_size(0x1400.into(), 0x400);
+    let far = GuestPhysAddrRange::from_start_size(0x4000.into(), 0x1000);
+
+    assert_eq!(
+        range.intersect(&head).unwrap().raw_bounds(),
+        (0x1000, 0x1800)
+    );
+    assert!(range.intersect(&far).is_none());
+    assert!(range.covers(&inner) && !range.covers(&head));
+
+    let (below, above) = range.subtract(&inner);
+    assert_eq!(below.unwrap().raw_bounds(), (0x1000, 0x1400));
+    assert_eq!(above.unwrap().raw_bounds(), (0x1800, 0x2000));
+    assert!(matches!(range.subtract(&range), (None, None)));
+
+    let (lo, hi) = range.split_at(0x1100.into()).unwrap();
+    assert_eq!((lo.size(), hi.size()), (0x100, 0xf00));
+    assert!(range.split_at(0x1000.into()).is_none());
+
+    assert!(range.is_aligned_to(0x1000) && !head.is_aligned_to(0x1000));
+    assert!(range.contains_access(0x1ff8.into(), AccessWidth::Qword));
+    assert!(!range.contains_access(0x1ffc.into(), AccessWidth::Qword));
+    assert_eq!(range.offset_of(0x1234.into()), Some(0x234));
+
+    // Inclusive ranges are normalized to half-open bounds.
+    let ports = PortRange::new(Port::new(0x3f8), Port::new(0x3ff));
+    assert_eq!(ports.size(), 8);
+    assert!(ports.contains_access(Port::new(0x3fe), AccessWidth::Word));
+    assert!(!ports.contains_access(Port::new(0x3ff), AccessWidth::Word));
+    let (lo, hi) = ports.split_at(Port::new(0x3fc)).unwrap();
+    assert_eq!((lo.end, hi.start), (Port::new(0x3fb), Port::new(0x3fc)));
+    let whole = PortRange::new(Port::new(0), Port::new(0xffff));
+    assert_eq!(whole.size(), 0x10000);
+    assert!(PortRange::from_raw_bounds(0xfff0, 0x10001).is_none());
+}
+
+#[test]
+fn test_journaled_device() {
+    let device = JournaledDevice::new(DeviceA, 2);
+
+    device
+        .handle_write(0x1000.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    device
+        .handle_write(0x1004.into(), AccessWidth::Dword, 2)
+        .unwrap();
+    assert_eq!(
+        device.handle_read(0x1008.into(), AccessWidth::Byte),
+        Ok(0x1008)
+    );
+
+    let records = device.records();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].addr, 0x1004.into());
+    assert_eq!(records[0].kind, AccessKind::Write);
+    assert_eq!(records[1].value, 0x1008);
+    assert_eq!(records[1].error, None);
+
+    device.clear();
+    assert!(device.records().is_empty());
+}
+
+/// A periodic timer at 0xa000 ticking every millisecond. Its register reads
+/// the number of delivered ticks; missed ticks being slewed in are kept
+/// separately.
+#[derive(Default)]
+struct PeriodicTimer {
+    /// The delivered ticks and the missed ticks still to be delivered.
+    ticks: spin::Mutex<(u64, u64)>,
+}
+
+impl PeriodicTimer {
+    const PERIOD_NS: u64 = 1_000_000;
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for PeriodicTimer {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xa000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(self.ticks.lock().0 as usize)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn on_time_jump(&self, delta_ns: u64, policy: CatchUpPolicy) {
+        let missed = delta_ns / Self::PERIOD_NS;
+        let mut ticks = self.ticks.lock();
+        match policy {
+            CatchUpPolicy::Discard => {}
+            CatchUpPolicy::Slew => ticks.1 += missed,
+            CatchUpPolicy::InjectAll => ticks.0 += missed,
+        }
+    }
+}
+
+#[test]
+fn test_time_jump_catch_up() {
+    assert_eq!(CatchUpPolicy::default(), CatchUpPolicy::Discard);
+    // Devices without timers ignore time jumps.
+    DeviceA.on_time_jump(u64::MAX, CatchUpPolicy::InjectAll);
+
+    // The hook reaches the timer through wrappers.
+    let timer = JournaledDevice::new(PeriodicTimer::default(), 4);
+    timer.on_time_jump(5_500_000, CatchUpPolicy::Discard);
+    assert_eq!(*timer.inner().ticks.lock(), (0, 0));
+    timer.on_time_jump(5_500_000, CatchUpPolicy::Slew);
+    assert_eq!(*timer.inner().ticks.lock(), (0, 5));
+    timer.on_time_jump(3_000_000, CatchUpPolicy::InjectAll);
+    assert_eq!(timer.handle_read(0xa000.into(), AccessWidth::Dword), Ok(3));
+}
+
+#[test]
+fn test_reg_value() {
+    let val = RegValue::from_bytes(&[0x34, 0x12]).unwrap();
+    assert_eq!(val.width(), AccessWidth::Word);
+    assert_eq!(val.zero_extend(), 0x1234);
+    assert_eq!(val.to_bytes(), &[0x34, 0x12]);
+    assert!(RegValue::from_bytes(&[0; 3]).is_none());
+
+    assert_eq!(
+        RegValue::new(0x1_ffff, AccessWidth::Word).zero_extend(),
+        0xffff
+    );
+    assert_eq!(RegValue::new(0xfffe, AccessWidth::Word).sign_extend(), -2);
+    assert_eq!(
+        RegValue::new(0x7fff, AccessWidth::Word).sign_extend(),
+        0x7fff
+    );
+    assert_eq!(
+        RegValue::new(usize::MAX, AccessWidth::Qword).sign_extend(),
+        -1
+    );
+
+    let reg = 0x1122_3344_5566_7788;
+    assert_eq!(
+        RegValue::extract(reg, 4, AccessWidth::Word).zero_extend(),
+        0x3344
+    );
+    let byte = RegValue::new(0xaa, AccessWidth::Byte);
+    assert_eq!(byte.merge_into(reg, 7), 0xaa22_3344_5566_7788);
+    assert_eq!(byte.merge_into(reg, 8), reg);
+    assert_eq!(byte.merge_masked(0xff00, 0x0f), 0xff0a);
+}
+
+#[derive(Default)]
+struct RecordingMemory {
+    removed: spin::Mutex<Vec<GuestPhysAddrRange>>,
+}
+
+impl MemoryControlOps for RecordingMemory {
+    fn request_add(&self, range: GuestPhysAddrRange) -> AxResult {
+        self.removed.lock().retain(|r| *r != range);
+        Ok(())
+    }
+
+    fn request_remove(&self, range: GuestPhysAddrRange) -> AxResult {
+        self.removed.lock().push(range);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_balloon_device() {
+    let memory = Arc::new(RecordingMemory::default());
+    let balloon = BalloonDevice::new(0x9000_0000.into(), memory.clone());
+    balloon.set_target(2);
+    assert_eq!(
+        balloon.handle_read(0x9000_0000.into(), AccessWidth::Dword),
+        Ok(2)
+    );
+
+    for pfn in [0x100, 0x101] {
+        balloon
+            .handle_write(0x9000_0008.into(), AccessWidth::Dword, pfn)
+            .unwrap();
+    }
+    assert_eq!(
+        balloon.handle_read(0x9000_0004.into(), AccessWidth::Dword),
+        Ok(2)
+    );
+    assert_eq!(memory.removed.lock()[1].start, 0x101000.into());
+
+    balloon
+        .handle_write(0x9000_000c.into(), AccessWidth::Dword, 0x100)
+        .unwrap();
+    assert_eq!(balloon.actual(), 1);
+    assert_eq!(memory.removed.lock().len(), 1);
+}
+
+struct MemoryStore(spin::Mutex<Vec<u8>>);
+
+impl PersistentStore for MemoryStore {
+    fn load(&self, buf: &mut [u8]) -> AxResult {
+        buf.fill(0xff);
+        Ok(())
+    }
+
+    fn store(&self, offset: usize, data: &[u8]) -> AxResult {
+        self.0.lock()[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flash_device() {
+    let store = Arc::new(MemoryStore(spin::Mutex::new(vec![0xff; 0x200])));
+    let flash = FlashDevice::new(0x1000.into(), 0x100, 2, store.clone()).unwrap();
+    let ctrl = 0x1200;
+
+    flash
+        .handle_write(0x1000.into(), AccessWidth::Word, 0x12f0)
+        .unwrap();
+    flash
+        .handle_write(0x1000.into(), AccessWidth::Byte, 0x0f)
+        .unwrap();
+    assert_eq!(
+        flash.handle_read(0x1000.into(), AccessWidth::Word),
+        Ok(0x1200)
+    );
+    assert_eq!(store.0.lock()[..2], [0x00, 0x12]);
+
+    flash.set_write_protect(1..2, true);
+    flash
+        .handle_write(0x1100.into(), AccessWidth::Byte, 0)
+        .unwrap();
+    assert_eq!(
+        flash.handle_read(0x1100.into(), AccessWidth::Byte),
+        Ok(0xff)
+    );
+    let status = flash.handle_read(ctrl.into(), AccessWidth::Dword).unwrap() as u32;
+    assert_eq!(
+        status,
+        FlashDevice::STATUS_READY | FlashDevice::STATUS_PROTECT_ERR
+    );
+    flash
+        .handle_write(ctrl.into(), AccessWidth::Dword, status as usize)
+        .unwrap();
+
+    // A write straddling into the protected sector changes neither sector.
+    flash
+        .handle_write(0x10fc.into(), AccessWidth::Qword, 0)
+        .unwrap();
+    assert_eq!(
+        flash.handle_read(0x10fc.into(), AccessWidth::Qword),
+        Ok(usize::MAX)
+    );
+    let status = flash.handle_read(ctrl.into(), AccessWidth::Dword).unwrap() as u32;
+    assert_eq!(
+        status,
+        FlashDevice::STATUS_READY | FlashDevice::STATUS_PROTECT_ERR
+    );
+    flash
+        .handle_write(ctrl.into(), AccessWidth::Dword, status as usize)
+        .unwrap();
+
+    flash
+        .handle_write((ctrl + 4).into(), AccessWidth::Dword, 0)
+        .unwrap();
+    assert_eq!(
+        flash.handle_read(0x1000.into(), AccessWidth::Word),
+        Ok(0xffff)
+    );
+    assert_eq!(
+        flash.handle_read(ctrl.into(), AccessWidth::Dword),
+        Ok(FlashDevice::STATUS_READY as usize)
+    );
+}
+
+/// A RAM disk whose reads complete when `complete` is called, while
+/// `deferred` is set.
+struct RamDisk {
+    data: spin::Mutex<Vec<u8>>,
+    deferred: core::sync::atomic::AtomicBool,
+    pending: spin::Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+impl RamDisk {
+    fn new(blocks: usize) -> Self {
+        Self {
+            data: spin::Mutex::new(vec![0; blocks * 512]),
+            deferred: core::sync::atomic::AtomicBool::new(false),
+            pending: spin::Mutex::new(Vec::new()),
+        }
+    }
+
+    fn complete(&self) {
+        let pending = core::mem::take(&mut *self.pending.lock());
+        pending.into_iter().for_each(|done| done());
+    }
+}
+
+impl BlockBackend for RamDisk {
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn num_blocks(&self) -> u64 {
+        (self.data.lock().len() / 512) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> AxResult {
+        let start = lba as usize * 512;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> AxResult {
+        let start = lba as usize * 512;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&self) -> AxResult {
+        Ok(())
+    }
+
+    fn read_blocks_async(&self, lba: u64, len: usize, done: BlockReadCallback) {
+        let mut buf = vec![0; len];
+        let result = self.read_blocks(lba, &mut buf).map(|()| buf);
+        if self.deferred.load(core::sync::atomic::Ordering::Relaxed) {
+            self.pending.lock().push(Box::new(move || done(result)));
+        } else {
+            done(result);
+        }
+    }
+}
+
+#[test]
+fn test_sdhci_controller() {
+    const R136: usize = 0b01;
+    const R48: usize = 0b10;
+    const R48_BUSY: usize = 0b11;
+    const DATA: usize = 1 << 5;
+
+    let disk = Arc::new(RamDisk::new(1024));
+    disk.data.lock()[512..1024].fill(0x5a);
+    let sd = SdhciBase::new(0x9000.into(), disk.clone()).unwrap();
+    let read = |offset: usize, width| sd.handle_read((0x9000 + offset).into(), width).unwrap();
+    let write = |offset: usize, width, val| {
+        sd.handle_write((0x9000 + offset).into(), width, val)
+            .unwrap()
+    };
+    let cmd = |index: usize, arg: usize, flags: usize| {
+        write(0x08, AccessWidth::Dword, arg);
+        write(0x0e, AccessWidth::Word, (index << 8) | flags);
+    };
+    let status = || {
+        let status = read(0x30, AccessWidth::Dword);
+        write(0x30, AccessWidth::Dword, status);
+        status
+    };
+    assert_eq!(read(0xfe, AccessWidth::Word), 2);
+    assert_eq!(read(0x24, AccessWidth::Dword) & 0xf_0000, 0xf_0000);
+
+    // Enable all status bits, and the interrupt for command completion.
+    write(0x34, AccessWidth::Dword, 0xffff_ffff);
+    write(
+        0x38,
+        AccessWidth::Word,
+        SdhciBase::INT_COMMAND_COMPLETE as usize,
+    );
+    write(0x2c, AccessWidth::Word, 1);
+    assert_eq!(read(0x2c, AccessWidth::Word), 0b11);
+
+    // Card initialization.
+    cmd(0, 0, 0);
+    assert!(sd.irq_pending());
+    cmd(8, 0x1aa, R48);
+    assert_eq!(read(0x10, AccessWidth::Dword), 0x1aa);
+    cmd(55, 0, R48);
+    cmd(41, 0x00ff_8000, R48);
+    assert_eq!(read(0x10, AccessWidth::Dword), 0xc0ff_8000);
+    cmd(2, 0, R136);
+    assert_eq!(read(0x1f, AccessWidth::Byte), 0x00);
+    assert_eq!(read(0x1e, AccessWidth::Byte), 0xaa);
+    cmd(3, 0, R48);
+    let rca = read(0x12, AccessWidth::Word);
+    cmd(9, rca << 16, R136);
+    // CSD version 2.0, 512 KiB.
+    assert_eq!(read(0x1c, AccessWidth::Dword) >> 22, 1);
+    assert_eq!(read(0x14, AccessWidth::Dword) >> 8 & 0x3f_ffff, 0);
+    cmd(7, rca << 16, R48_BUSY);
+    assert_eq!(status(), 0b11);
+    assert!(!sd.irq_pending());
+
+    // Commands the card does not implement time out.
+    cmd(5, 0, R48);
+    assert_eq!(
+        status(),
+        (SdhciBase::ERR_COMMAND_TIMEOUT as usize) << 16 | SdhciBase::INT_ERROR as usize
+    );
+
+    // PIO read of block 1.
+    write(0x04, AccessWidth::Word, 512);
+    write(0x0c, AccessWidth::Word, 1 << 4);
+    cmd(17, 1, R48 | DATA);
+    assert_eq!(status(), 0b10_0001);
+    assert_ne!(read(0x24, AccessWidth::Dword) & 1 << 11, 0);
+    for _ in 0..128 {
+        assert_eq!(read(0x20, AccessWidth::Dword), 0x5a5a_5a5a);
+    }
+    assert_eq!(status(), SdhciBase::INT_TRANSFER_COMPLETE as usize);
+    assert_eq!(read(0x24, AccessWidth::Dword) & 0b110, 0);
+
+    // PIO write of blocks 4 and 5, with the transfer mode and command written
+    // at once.
+    write(0x04, AccessWidth::Dword, (2 << 16) | 512);
+    write(0x08, AccessWidth::Dword, 4);
+    write(
+        0x0c,
+        AccessWidth::Dword,
+        (((25 << 8) | R48 | DATA) << 16) | (1 << 5) | (1 << 1),
+    );
+    assert_eq!(status(), 0b1_0001);
+    for i in 0..256 {
+        assert_ne!(read(0x24, AccessWidth::Dword) & 1 << 10, 0);
+        write(0x20, AccessWidth::Dword, i);
+    }
+    assert_eq!(status(), 0b1_0010);
+    assert_eq!(disk.data.lock()[2048..2052], [0, 0, 0, 0]);
+    assert_eq!(disk.data.lock()[3068..3072], [255, 0, 0, 0]);
+
+    // SDMA read of block 1 into guest memory.
+    let mem = Arc::new(TestMemory::new(0x1000));
+    sd.set_dma_accessor(mem.clone());
+    write(0x00, AccessWidth::Dword, TestMemory::BASE);
+    write(0x04, AccessWidth::Word, 512);
+    write(0x0c, AccessWidth::Word, (1 << 4) | 1);
+    cmd(17, 1, R48 | DATA);
+    assert_eq!(status(), 0b11);
+    assert!(mem.0.lock()[..512].iter().all(|byte| *byte == 0x5a));
+    assert_eq!(read(0x00, AccessWidth::Dword), TestMemory::BASE + 512);
+
+    // Out-of-range transfers fail without data.
+    write(0x0c, AccessWidth::Word, (1 << 5) | (1 << 4) | (1 << 1));
+    write(0x06, AccessWidth::Word, 2);
+    cmd(18, 1023, R48 | DATA);
+    assert_eq!(read(0x10, AccessWidth::Dword) >> 31, 1);
+    assert_eq!(
+        status(),
+        (SdhciBase::ERR_DATA_TIMEOUT as usize) << 16 | SdhciBase::INT_ERROR as usize | 1
+    );
+
+    // A PIO read of the data port before the data arrives completes when the
+    // backend delivers it.
+    let log = Arc::new(CompletionLog::default());
+    sd.set_completer(log.clone());
+    disk.deferred
+        .store(true, core::sync::atomic::Ordering::Relaxed);
+    write(0x0c, AccessWidth::Word, 1 << 4);
+    cmd(17, 1, R48 | DATA);
+    let Ok(AccessOutcome::Pending(token)) = sd.handle_read_async(0x9020.into(), AccessWidth::Dword)
+    else {
+        panic!("data port read should be pending");
+    };
+    assert!(log.0.lock().is_empty());
+    disk.complete();
+    assert_eq!(*log.0.lock(), [(token, Ok(0x5a5a_5a5a))]);
+    assert_eq!(
+        sd.handle_read_async(0x9020.into(), AccessWidth::Dword),
+        Ok(AccessOutcome::Completed(0x5a5a_5a5a))
+    );
+
+    // A reset abandons the transfer, and the card has to be initialized again.
+    sd.reset().unwrap();
+    assert_eq!(read(0x24, AccessWidth::Dword) & 0b110, 0);
+    write(0x34, AccessWidth::Dword, 0xffff_ffff);
+    cmd(17, 1, R48 | DATA);
+    assert_eq!(
+        status(),
+        (SdhciBase::ERR_COMMAND_TIMEOUT as usize) << 16 | SdhciBase::INT_ERROR as usize
+    );
+    assert!(sd.supports(Capability::AsyncIo));
+}
+
+#[test]
+fn test_sdhci_reset_with_pending_transfer() {
+    const R48: usize = 0b10;
+    const DATA: usize = 1 << 5;
+
+    let disk = Arc::new(RamDisk::new(16));
+    disk.data.lock()[512..1024].fill(0x5a);
+    disk.deferred
+        .store(true, core::sync::atomic::Ordering::Relaxed);
+    let sd = SdhciBase::new(0x9000.into(), disk.clone()).unwrap();
+    let read = |offset: usize, width| sd.handle_read((0x9000 + offset).into(), width).unwrap();
+    let write = |offset: usize, width, val| {
+        sd.handle_write((0x9000 + offset).into(), width, val)
+            .unwrap()
+    };
+    let cmd = |index: usize, arg: usize, flags: usize| {
+        write(0x08, AccessWidth::Dword, arg);
+        write(0x0e, AccessWidth::Word, (index << 8) | flags);
+    };
+    let read_block = |lba: usize| {
+        write(0x34, AccessWidth::Dword, 0xffff_ffff);
+        for (index, arg) in [
+            (0, 0),
+            (8, 0x1aa),
+            (55, 0),
+            (41, 0x00ff_8000),
+            (2, 0),
+            (3, 0),
+        ] {
+            cmd(index, arg, R48);
+        }
+        cmd(7, read(0x12, AccessWidth::Word) << 16, 0b11);
+        write(0x04, AccessWidth::Word, 512);
+        write(0x0c, AccessWidth::Word, 1 << 4);
+        cmd(17, lba, R48 | DATA);
+    };
+
+    // The read of block 1 is still in the backend when the controller is
+    // reset, and completes after the read of block 2 was issued: it must not
+    // be taken for the new transfer.
+    let ready = || read(0x30, AccessWidth::Word) & SdhciBase::INT_BUFFER_READ_READY as usize != 0;
+    for software in [false, true] {
+        read_block(1);
+        if software {
+            write(0x2f, AccessWidth::Byte, 1);
+        } else {
+            sd.reset().unwrap();
+        }
+        read_block(2);
+        let stale = disk.pending.lock().remove(0);
+        stale();
+        assert!(!ready());
+        disk.complete();
+        assert!(ready());
+        assert_eq!(read(0x20, AccessWidth::Dword), 0);
+    }
+}
+
+struct CountingSource;
+
+impl EntropySource for CountingSource {
+    fn fill(&self, buf: &mut [u8]) -> AxResult<usize> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        Ok(buf.len())
+    }
+}
+
+#[test]
+fn test_trng_rate_limit() {
+    let trng = TrngDevice::new(0x3000.into(), Arc::new(CountingSource), 6);
+    let data = 0x3008.into();
+
+    assert_eq!(trng.handle_read(data, AccessWidth::Dword), Ok(0x0403_0201));
+    assert_eq!(trng.handle_read(data, AccessWidth::Dword), Ok(0));
+    assert_eq!(trng.handle_read(data, AccessWidth::Word), Ok(0x0201));
+    assert_eq!(trng.handle_read(0x3000.into(), AccessWidth::Dword), Ok(0));
+
+    trng.replenish(100);
+    assert_eq!(trng.budget(), 6);
+    assert_eq!(trng.handle_read(0x3000.into(), AccessWidth::Dword), Ok(1));
+}
+
+#[test]
+fn test_virtio_rng() {
+    let base = GuestPhysAddr::from(0x1_0000);
+    let dev = VirtioRngDevice::new(base, Arc::new(CountingSource), 1000, 16);
+    let memory = Arc::new(TestMemory::new(0x1000));
+    let clock = Arc::new(FakeClock::default());
+    let timers = Arc::new(TimerLog::default());
+    dev.set_dma_accessor(memory.clone());
+    dev.set_clock_source(clock.clone());
+    dev.set_timer_service(timers.clone());
+    let write = |offset, val| dev.handle_write(base + offset, AccessWidth::Dword, val);
+    assert_eq!(dev.handle_read(base + 0x008, AccessWidth::Dword), Ok(4));
+
+    let ram = GuestPhysAddr::from(TestMemory::BASE);
+    write(0x038, 8).unwrap();
+    write(0x080, TestMemory::BASE).unwrap();
+    write(0x090, TestMemory::BASE + 0x100).unwrap();
+    write(0x0a0, TestMemory::BASE + 0x200).unwrap();
+    write(0x044, 1).unwrap();
+    write(0x070, 0xf).unwrap();
+
+    // Requests `len` bytes and returns the lengths of all used entries.
+    let request = |len: u32| {
+        memory
+            .write_u64(ram, (TestMemory::BASE + 0x400) as u64)
+            .unwrap();
+        memory.write_u32(ram + 8, len).unwrap();
+        memory
+            .write_u16(ram + 12, SplitQueue::DESC_F_WRITE)
+            .unwrap();
+        let idx = memory.read_u16(ram + 0x102).unwrap();
+        memory
+            .write_u16(ram + 0x104 + 2 * (idx % 8) as usize, 0)
+            .unwrap();
+        memory.write_u16(ram + 0x102, idx + 1).unwrap();
+        write(0x050, 0).unwrap();
+    };
+    let used = || {
+        let idx = memory.read_u16(ram + 0x202).unwrap() as usize;
+        (0..idx)
+            .map(|i| memory.read_u32(ram + 0x208 + 8 * i).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    request(8);
+    assert_eq!(used(), [8]);
+    let mut buf = [0; 8];
+    memory.read(ram + 0x400, &mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_ne!(dev.regs().interrupt_status(), 0);
+
+    // Requests get what is left of the burst, then wait for the bucket.
+    request(32);
+    assert_eq!(used(), [8, 8]);
+    request(4);
+    assert_eq!(used(), [8, 8]);
+    assert_eq!(dev.throttled(), 1);
+    let (delay, token) = timers.0.lock().pop().unwrap();
+    assert_eq!(delay, core::time::Duration::from_millis(4));
+
+    *clock.0.lock() += 4;
+    assert!(!dev.on_timer(TimerToken::next()));
+    assert!(dev.on_timer(token));
+    assert_eq!(used(), [8, 8, 4]);
+}
+
+/// A 256-byte EEPROM with an auto-incrementing address pointer.
+struct Eeprom {
+    data: spin::Mutex<([u8; 256], u8, bool)>,
+}
+
+impl I2cSlave for Eeprom {
+    fn start(&self, read: bool) -> AxResult {
+        // The first byte of a write sets the address pointer.
+        self.data.lock().2 = !read;
+        Ok(())
+    }
+
+    fn write(&self, byte: u8) -> AxResult {
+        let mut state = self.data.lock();
+        let (mem, ptr, addressing) = &mut *state;
+        if core::mem::take(addressing) {
+            *ptr = byte;
+        } else {
+            mem[*ptr as usize] = byte;
+            *ptr = ptr.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> u8 {
+        let mut state = self.data.lock();
+        let (mem, ptr, _) = &mut *state;
+        let byte = mem[*ptr as usize];
+        *ptr = ptr.wrapping_add(1);
+        byte
+    }
+}
+
+#[test]
+fn test_i2c_controller() {
+    let bus = Arc::new(I2cBus::new());
+    let eeprom = Arc::new(Eeprom {
+        data: spin::Mutex::new(([0; 256], 0, false)),
+    });
+    bus.attach(0x50, eeprom.clone()).unwrap();
+    assert!(bus.attach(0x50, eeprom).is_err());
+
+    let ctrl = I2cControllerBase::new(0x4000.into(), bus);
+    let write = |reg: usize, val: usize| {
+        ctrl.handle_write((0x4000 + reg).into(), AccessWidth::Dword, val)
+            .unwrap()
+    };
+    let read = |reg: usize| {
+        ctrl.handle_read((0x4000 + reg).into(), AccessWidth::Dword)
+            .unwrap()
+    };
+
+    write(0x04, 0x50); // IC_TAR
+    write(0x6c, 1); // IC_ENABLE
+    // Write 0xab, 0xcd at offset 0x10, then read them back.
+    for cmd in [
+        0x10,
+        0xab,
+        0xcd | 1 << 9,
+        0x10,
+        1 << 8 | 1 << 10,
+        1 << 8 | 1 << 9,
+    ] {
+        write(0x10, cmd);
+    }
+    assert_eq!(read(0x78), 2); // IC_RXFLR
+    assert_eq!((read(0x10), read(0x10)), (0xab, 0xcd));
+    assert_ne!(read(0x34) & 1 << 9, 0); // STOP_DET
+
+    // A transfer to a missing slave aborts with ADDR_NOACK.
+    write(0x04, 0x51);
+    write(0x10, 1 << 8 | 1 << 9);
+    assert_ne!(read(0x34) & 1 << 6, 0); // TX_ABRT
+    assert_eq!(read(0x80), 1); // IC_TX_ABRT_SOURCE
+    read(0x54); // IC_CLR_TX_ABRT
+    assert_eq!(read(0x34) & 1 << 6, 0);
+}
+
+/// Echoes the previously received byte, like a shift register.
+struct ShiftRegister(spin::Mutex<u8>);
+
+impl SpiSlave for ShiftRegister {
+    fn transfer(&self, tx: u8) -> u8 {
+        core::mem::replace(&mut self.0.lock(), tx)
+    }
+}
+
+#[test]
+fn test_spi_controller() {
+    let bus = Arc::new(SpiBus::new());
+    bus.attach(1, Arc::new(ShiftRegister(spin::Mutex::new(0x5a))))
+        .unwrap();
+    let ctrl = SpiControllerBase::new(0x5000.into(), bus);
+    let write = |reg: usize, val: usize| {
+        ctrl.handle_write((0x5000 + reg).into(), AccessWidth::Dword, val)
+            .unwrap()
+    };
+    let read = |reg: usize| {
+        ctrl.handle_read((0x5000 + reg).into(), AccessWidth::Dword)
+            .unwrap()
+    };
+
+    write(0x00, 1);
+    write(0x04, 1);
+    write(0x08, 0x11);
+    write(0x08, 0x22);
+    assert_eq!(read(0x10), 2);
+    assert_eq!((read(0x08), read(0x08)), (0x5a, 0x11));
+
+    write(0x04, 0);
+    write(0x08, 0x33);
+    assert_eq!(read(0x08), 0xff);
+}
+
+/// Records the domain events it receives.
+struct EventRecorder(spin::Mutex<Vec<DomainEvent>>);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for EventRecorder {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x7000.into(), 0x100)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(0)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn on_domain_event(&self, event: DomainEvent) -> AxResult {
+        self.0.lock().push(event);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_clock_reset_controller() {
+    let uart = Arc::new(EventRecorder(spin::Mutex::new(Vec::new())));
+    let mut uart_clk = Domain::new("uart_clk");
+    uart_clk.add(uart.clone());
+    let mut uart_rst = Domain::new("uart_rst");
+    uart_rst.add(uart.clone());
+
+    let ctrl = ClockResetControllerBase::new(
+        0x6000.into(),
+        vec![Domain::new("cpu_clk"), uart_clk],
+        vec![uart_rst],
+    )
+    .unwrap();
+    let write = |reg: usize, val: usize| {
+        ctrl.handle_write((0x6000 + reg).into(), AccessWidth::Dword, val)
+            .unwrap()
+    };
+    let read = |reg: usize| {
+        ctrl.handle_read((0x6000 + reg).into(), AccessWidth::Dword)
+            .unwrap()
+    };
+
+    assert_eq!((read(0x0), read(0x8), read(0xc)), (0b11, 2, 1));
+    write(0x0, 0b01);
+    write(0x0, 0b11);
+    write(0x4, 1);
+    write(0x4, 0);
+    assert_eq!(
+        *uart.0.lock(),
+        [
+            DomainEvent::ClockGate,
+            DomainEvent::ClockUngate,
+            DomainEvent::Reset
+        ]
+    );
+}
+
+/// Sums the payload bytes, rejecting command 0.
+struct SumService;
+
+impl MailboxHandler for SumService {
+    fn handle(&self, command: u32, payload: &mut [u8]) -> AxResult<u32> {
+        if command == 0 {
+            return axerrno::ax_err!(Unsupported);
+        }
+        let sum = payload.iter().map(|&b| b as u32).sum();
+        payload.fill(0);
+        Ok(sum)
+    }
+}
+
+#[test]
+fn test_mailbox_device() {
+    let mailbox = MailboxDevice::new(0x8000.into(), 16, Arc::new(SumService));
+    let write = |reg: usize, width: AccessWidth, val: usize| {
+        mailbox
+            .handle_write((0x8000 + reg).into(), width, val)
+            .unwrap()
+    };
+    let read = |reg: usize| {
+        mailbox
+            .handle_read((0x8000 + reg).into(), AccessWidth::Dword)
+            .unwrap()
+    };
+
+    assert_eq!(read(0x0c), 16);
+    write(0x100, AccessWidth::Dword, 0x0403_0201);
+    write(0x10f, AccessWidth::Byte, 0x10);
+    write(0x00, AccessWidth::Dword, 1);
+    assert_eq!(read(0x04), MailboxDevice::STATUS_DONE as usize);
+    assert_eq!((read(0x08), read(0x100)), (0x1a, 0));
+
+    write(0x04, AccessWidth::Dword, 0xff);
+    write(0x00, AccessWidth::Dword, 0);
+    assert_eq!(read(0x04), MailboxDevice::STATUS_ERROR as usize);
+    assert!(
+        mailbox
+            .handle_read(0x810e.into(), AccessWidth::Dword)
+            .is_err()
+    );
+}
+
+/// Answers every command with a fixed response.
+struct EchoTpm;
+
+impl TpmBackend for EchoTpm {
+    fn execute(&self, locality: u8, command: &[u8]) -> AxResult<Vec<u8>> {
+        assert_eq!((locality, command.len()), (0, 10));
+        Ok(vec![0x80, 0x01, 0, 0, 0, 10, 0, 0, 0, 0])
+    }
+}
+
+#[test]
+fn test_tpm_tis() {
+    let tpm = TpmTisDevice::new(0xfed4_0000.into(), Arc::new(EchoTpm));
+    let write = |reg: usize, width: AccessWidth, val: usize| {
+        tpm.handle_write((0xfed4_0000 + reg).into(), width, val)
+            .unwrap()
+    };
+    let read = |reg: usize, width: AccessWidth| {
+        tpm.handle_read((0xfed4_0000 + reg).into(), width).unwrap()
+    };
+
+    // Locality 0 takes the TPM, locality 1 has to wait.
+    write(0x0000, AccessWidth::Byte, 0x02);
+    write(0x1000, AccessWidth::Byte, 0x02);
+    assert_eq!(tpm.active_locality(), Some(0));
+    assert_eq!(read(0x0000, AccessWidth::Byte) & 0x24, 0x24);
+    assert_eq!(read(0x1018, AccessWidth::Dword), 0xffff_ffff);
+
+    write(0x18, AccessWidth::Byte, 0x40);
+    assert_eq!(read(0x18, AccessWidth::Byte) & 0x40, 0x40);
+    write(0x24, AccessWidth::Dword, 0x0000_0180);
+    assert_eq!(read(0x18, AccessWidth::Byte) & 0x08, 0x08);
+    for byte in [0x00, 0x0a, 0x00, 0x00, 0x01, 0x44] {
+        write(0x24, AccessWidth::Byte, byte);
+    }
+    assert_eq!(read(0x18, AccessWidth::Byte) & 0x08, 0);
+    write(0x18, AccessWidth::Byte, 0x20);
+
+    assert_eq!(read(0x19, AccessWidth::Word), 10);
+    assert_eq!(read(0x24, AccessWidth::Dword), 0x0000_0180);
+    write(0x18, AccessWidth::Byte, 0x02);
+    let response: Vec<_> = (0..10).map(|_| read(0x24, AccessWidth::Byte)).collect();
+    assert_eq!(response, [0x80, 0x01, 0, 0, 0, 10, 0, 0, 0, 0]);
+    assert_eq!(read(0x18, AccessWidth::Byte) & 0x10, 0);
+
+    // Relinquishing hands the TPM to the pending locality.
+    write(0x0000, AccessWidth::Byte, 0x20);
+    assert_eq!(tpm.active_locality(), Some(1));
+}
+
+#[test]
+fn test_transactional_region() {
+    let regs = TransactionalRegion::new(&[0x0, 0x4], 0x8, 0x1);
+    let validate = |snapshot: &alloc::collections::BTreeMap<usize, usize>| {
+        if snapshot[&0x4] > 0x1000 {
+            return axerrno::ax_err!(InvalidInput);
+        }
+        Ok(())
+    };
+
+    assert!(regs.write(0xc, 0, validate).is_none());
+    regs.write(0x0, 0x8000, validate).unwrap().unwrap();
+    regs.write(0x4, 0x100, validate).unwrap().unwrap();
+    regs.write(0x8, 0x2, validate).unwrap().unwrap();
+    assert_eq!(
+        (regs.read(0x4), regs.committed(0x4)),
+        (Some(0x100), Some(0))
+    );
+    regs.write(0x8, 0x1, validate).unwrap().unwrap();
+    assert_eq!(regs.committed(0x0), Some(0x8000));
+
+    // A rejected commit restores the last committed values.
+    regs.write(0x0, 0x9000, validate).unwrap().unwrap();
+    regs.write(0x4, 0x2000, validate).unwrap().unwrap();
+    assert!(regs.write(0x8, 0x1, validate).unwrap().is_err());
+    assert_eq!(
+        (regs.read(0x0), regs.read(0x4)),
+        (Some(0x8000), Some(0x100))
+    );
+}
+
+#[test]
+fn test_covered_device() {
+    let device = CoveredDevice::new(DeviceA);
+    let point = |offset, width, kind| CoveragePoint {
+        offset,
+        width,
+        kind,
+    };
+
+    device
+        .handle_read(0x1004.into(), AccessWidth::Dword)
+        .unwrap();
+    device
+        .handle_read(0x1004.into(), AccessWidth::Dword)
+        .unwrap();
+    device
+        .handle_write(0x1000.into(), AccessWidth::Byte, 1)
+        .unwrap();
+
+    let report = device.report();
+    assert_eq!(report.len(), 2);
+    assert_eq!(
+        report[0].0,
+        point(0x0, AccessWidth::Byte, AccessKind::Write)
+    );
+    assert_eq!(report[1].1.hits, 2);
+    assert_eq!(
+        device.missing([
+            point(0x4, AccessWidth::Dword, AccessKind::Read),
+            point(0x4, AccessWidth::Dword, AccessKind::Write),
+        ]),
+        [point(0x4, AccessWidth::Dword, AccessKind::Write)]
+    );
+}
+
+/// Two 32-bit registers that reject any other access; register 1 is W1C.
+struct DwordRegs(spin::Mutex<[u32; 2]>);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for DwordRegs {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x9000.into(), 8)
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        assert_eq!((width, addr.as_usize() % 4), (AccessWidth::Dword, 0));
+        Ok(self.0.lock()[(addr.as_usize() - 0x9000) / 4] as usize)
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        assert_eq!((width, addr.as_usize() % 4), (AccessWidth::Dword, 0));
+        let mut regs = self.0.lock();
+        match addr.as_usize() - 0x9000 {
+            0 => regs[0] = val as u32,
+            _ => regs[1] &= !(val as u32),
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_natural_width_adapter() {
+    let device = NaturalWidthAdapter::new(
+        DwordRegs(spin::Mutex::new([0x4433_2211, 0xffff_ffff])),
+        AccessWidth::Dword,
+    )
+    .with_w1c(0x4, 0xffff_ffff);
+
+    assert_eq!(
+        device.handle_read(0x9001.into(), AccessWidth::Word),
+        Ok(0x3322)
+    );
+    assert_eq!(
+        device.handle_read(0x9000.into(), AccessWidth::Qword),
+        Ok(0xffff_ffff_4433_2211)
+    );
+    device
+        .handle_write(0x9002.into(), AccessWidth::Byte, 0xaa)
+        .unwrap();
+    assert_eq!(device.inner().0.lock()[0], 0x44aa_2211);
+
+    // Clearing one W1C byte leaves the other status bits set.
+    device
+        .handle_write(0x9005.into(), AccessWidth::Byte, 0x0f)
+        .unwrap();
+    assert_eq!(device.inner().0.lock()[1], 0xffff_f0ff);
+    assert!(
+        device
+            .handle_read(0x9006.into(), AccessWidth::Dword)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_device_manager() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceB)).unwrap();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    assert!(manager.register(Arc::new(DeviceA)).is_err());
+    assert_eq!(manager.len(), 2);
+
+    assert_eq!(
+        manager.handle_read(0x1ffc.into(), AccessWidth::Dword),
+        Ok(0x1ffc)
+    );
+    assert_eq!(
+        manager.handle_read(0x2000.into(), AccessWidth::Dword),
+        Ok(0x2000)
+    );
+    assert!(
+        manager
+            .handle_read(0x1ffe.into(), AccessWidth::Dword)
+            .is_err()
+    );
+    assert!(
+        manager
+            .handle_write(0x3000.into(), AccessWidth::Dword, 0)
+            .is_err()
+    );
+
+    let manifest = manager.manifest();
+    assert_eq!(manifest.len(), 2);
+    assert_eq!(manifest[0].0, DeviceA.address_range());
+    assert_eq!(manifest[1].1.model, "Dummy");
+
+    let device = manager.unregister(0x1800.into()).unwrap();
+    assert!(map_device_of_type(&device, |_: &DeviceA| ()).is_some());
+    assert!(manager.find(0x1000.into()).is_none());
+    assert!(manager.find(0x2fff.into()).is_some());
+}
+
+/// Logs lifecycle calls with the base address of the device.
+struct LifecycleLog {
+    base: usize,
+    log: Arc<spin::Mutex<Vec<(usize, &'static str)>>>,
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for LifecycleLog {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(self.base.into(), 0x1000)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(0)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn reset(&self) -> AxResult {
+        self.log.lock().push((self.base, "reset"));
+        Ok(())
+    }
+
+    fn pause(&self) -> AxResult {
+        self.log.lock().push((self.base, "pause"));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_device_lifecycle() {
+    let log = Arc::new(spin::Mutex::new(Vec::new()));
+    let manager = DeviceManager::new();
+    for base in [0x2000, 0x1000] {
+        let log = log.clone();
+        manager
+            .register(Arc::new(LifecycleLog { base, log }))
+            .unwrap();
+    }
+
+    manager.reset_all().unwrap();
+    manager.pause_all().unwrap();
+    manager.resume_all().unwrap();
+    assert_eq!(
+        *log.lock(),
+        [
+            (0x2000, "reset"),
+            (0x1000, "reset"),
+            (0x1000, "pause"),
+            (0x2000, "pause")
+        ]
+    );
+
+    // Domain events are routed to the lifecycle methods by default.
+    log.lock().clear();
+    let mut domain = Domain::new("peripherals");
+    domain.add(manager.find(0x1000.into()).unwrap());
+    domain.apply(DomainEvent::Reset).unwrap();
+    assert_eq!(*log.lock(), [(0x1000, "reset")]);
+}
+
+#[test]
+// `BaseDeviceOps` objects are not `Send`, so neither are device managers.
+#[allow(clippy::arc_with_non_send_sync)]
+fn test_host_device_manager() {
+    let log = Arc::new(spin::Mutex::new(Vec::new()));
+    let host = HostDeviceManager::new();
+    for (id, base) in [(2, 0x2000), (1, 0x1000)] {
+        let manager = Arc::new(DeviceManager::new());
+        let uart = FifoUart {
+            base: base + 0x8000,
+            fifo_depth: id,
+        };
+        manager.register(Arc::new(StatsDevice::new(uart))).unwrap();
+        let log = log.clone();
+        manager
+            .register(Arc::new(LifecycleLog { base, log }))
+            .unwrap();
+        host.add_vm(VmId(id), manager).unwrap();
+    }
+    assert_eq!(
+        host.add_vm(VmId(1), Arc::new(DeviceManager::new())),
+        Err(AxError::AlreadyExists)
+    );
+    assert_eq!(host.vm_ids(), [VmId(1), VmId(2)]);
+
+    // UARTs are found across VMs, and accesses through the per-VM managers
+    // show up in the global statistics.
+    let uarts = host.devices_of_type(EmuDeviceType::Console);
+    assert_eq!(uarts.len(), 2);
+    assert_eq!(uarts[1].0, VmId(2));
+    assert_eq!(uarts[1].1.address_range().start, 0xa000.into());
+    let vm2 = host.vm(VmId(2)).unwrap();
+    assert_eq!(vm2.handle_read(0xa000.into(), AccessWidth::Dword), Ok(2));
+    vm2.handle_write(0xa000.into(), AccessWidth::Byte, 0)
+        .unwrap();
+    assert_eq!(host.stats()[0].1.reads, 0);
+    assert_eq!(host.stats()[1].1.reads, 1);
+    let total = host.total_stats();
+    assert_eq!((total.reads, total.writes, total.bytes_read), (1, 1, 4));
+
+    // Quiescing pauses every VM once, including VMs added while quiesced.
+    host.quiesce_all().unwrap();
+    host.quiesce_all().unwrap();
+    assert!(host.is_quiesced());
+    let manager = Arc::new(DeviceManager::new());
+    let log3 = log.clone();
+    manager
+        .register(Arc::new(LifecycleLog {
+            base: 0x3000,
+            log: log3,
+        }))
+        .unwrap();
+    host.add_vm(VmId(3), manager).unwrap();
+    assert_eq!(
+        *log.lock(),
+        [(0x1000, "pause"), (0x2000, "pause"), (0x3000, "pause")]
+    );
+    host.resume_all().unwrap();
+    assert!(!host.is_quiesced());
+    assert!(host.remove_vm(VmId(3)).is_some());
+    assert_eq!(host.len(), 2);
+}
+
+/// A counter at 0xc000 incremented by each write while its clock runs.
+#[derive(Default)]
+struct GatedCounter {
+    count: core::sync::atomic::AtomicUsize,
+    gated: core::sync::atomic::AtomicBool,
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for GatedCounter {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xc000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(self.count.load(core::sync::atomic::Ordering::Relaxed))
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        if !self.gated.load(core::sync::atomic::Ordering::Relaxed) {
+            self.count
+                .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn on_domain_event(&self, event: DomainEvent) -> AxResult {
+        let gated = self.gated.load(core::sync::atomic::Ordering::Relaxed);
+        match event {
+            DomainEvent::Reset => self.count.store(0, core::sync::atomic::Ordering::Relaxed),
+            DomainEvent::ClockGate | DomainEvent::ClockUngate => self.gated.store(
+                event == DomainEvent::ClockGate,
+                core::sync::atomic::Ordering::Relaxed,
+            ),
+            DomainEvent::Suspend if gated => return Err(AxError::BadState),
+            DomainEvent::Suspend | DomainEvent::Resume => {}
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_domain_events() {
+    let log = Arc::new(spin::Mutex::new(Vec::new()));
+    let counter = Arc::new(GatedCounter::default());
+    let mut domain = Domain::new("soc");
+    for base in [0x1000, 0x2000] {
+        let log = log.clone();
+        domain.add(Arc::new(LifecycleLog { base, log }));
+    }
+    domain.add(counter.clone());
+    assert_eq!(domain.name(), "soc");
+    assert_eq!(domain.members().len(), 3);
+
+    // Events taking the domain down are delivered in reverse order.
+    domain.apply(DomainEvent::Reset).unwrap();
+    domain.apply(DomainEvent::Suspend).unwrap();
+    domain.apply(DomainEvent::Resume).unwrap();
+    assert_eq!(
+        *log.lock(),
+        [
+            (0x1000, "reset"),
+            (0x2000, "reset"),
+            (0x2000, "pause"),
+            (0x1000, "pause")
+        ]
+    );
+
+    // A gated clock stops the counter until the domain is ungated.
+    let write = || counter.handle_write(0xc000.into(), AccessWidth::Dword, 0);
+    write().unwrap();
+    domain.apply(DomainEvent::ClockGate).unwrap();
+    write().unwrap();
+    assert_eq!(
+        counter.handle_read(0xc000.into(), AccessWidth::Dword),
+        Ok(1)
+    );
+
+    // Delivery stops at the first failing member, which comes last in
+    // membership order.
+    log.lock().clear();
+    assert_eq!(domain.apply(DomainEvent::Suspend), Err(AxError::BadState));
+    assert!(log.lock().is_empty());
+
+    domain.apply(DomainEvent::ClockUngate).unwrap();
+    write().unwrap();
+    assert_eq!(
+        counter.handle_read(0xc000.into(), AccessWidth::Dword),
+        Ok(2)
+    );
+    domain.apply(DomainEvent::Reset).unwrap();
+    assert_eq!(
+        counter.handle_read(0xc000.into(), AccessWidth::Dword),
+        Ok(0)
+    );
+}
+
+/// A single 32-bit register at 0xa000 that can be saved and restored.
+struct SavedReg(core::sync::atomic::AtomicU32);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for SavedReg {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xa000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(self.0.load(core::sync::atomic::Ordering::Relaxed) as usize)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        self.0
+            .store(val as u32, core::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        let val = self.handle_read(0xa000.into(), AccessWidth::Dword)? as u32;
+        Ok(DeviceStateHeader::encode(1, &val.to_le_bytes()))
+    }
+
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        let (_, payload) = DeviceStateHeader::decode(state)?;
+        let Ok(bytes) = payload.try_into() else {
+            return axerrno::ax_err!(InvalidData);
+        };
+        self.handle_write(
+            0xa000.into(),
+            AccessWidth::Dword,
+            u32::from_le_bytes(bytes) as usize,
+        )
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        Capability::Snapshot.into()
+    }
+}
+
+#[test]
+fn test_device_snapshot() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(SavedReg(0.into()))).unwrap();
+    manager
+        .handle_write(0xa000.into(), AccessWidth::Dword, 0x1234)
+        .unwrap();
+
+    let states = manager.save_all().unwrap();
+    manager
+        .handle_write(0xa000.into(), AccessWidth::Dword, 0)
+        .unwrap();
+    manager.load_all(&states).unwrap();
+    assert_eq!(
+        manager.handle_read(0xa000.into(), AccessWidth::Dword),
+        Ok(0x1234)
+    );
+
+    assert!(manager.load_all(&[]).is_err());
+    assert!(DeviceStateHeader::decode(&states[0].1[..10]).is_err());
+
+    // A device without snapshot support makes the whole snapshot fail.
+    manager.register(Arc::new(DeviceA)).unwrap();
+    assert!(manager.save_all().is_err());
+}
+
+#[test]
+// `BaseDeviceOps` objects are not `Send`, so neither are bridges to a bus of
+// them.
+#[allow(clippy::arc_with_non_send_sync)]
+fn test_bus_bridge() {
+    let window = GuestPhysAddrRange::from_start_size(0x4_0000.into(), 0x2000);
+    assert!(BusBridgeDevice::new(window, usize::MAX).is_err());
+    let bridge = Arc::new(BusBridgeDevice::new(window, 0x9000).unwrap());
+    bridge
+        .child()
+        .register(Arc::new(SavedReg(0.into())))
+        .unwrap();
+    let manager = DeviceManager::new();
+    manager.register(bridge.clone()).unwrap();
+
+    // Accesses are translated into the child bus.
+    manager
+        .handle_write(0x4_1000.into(), AccessWidth::Dword, 0x1234)
+        .unwrap();
+    assert_eq!(
+        bridge
+            .child()
+            .handle_read(0xa000.into(), AccessWidth::Dword),
+        Ok(0x1234)
+    );
+
+    // The child bus is saved and restored through the bridge.
+    let states = manager.save_all().unwrap();
+    manager
+        .handle_write(0x4_1000.into(), AccessWidth::Dword, 0)
+        .unwrap();
+    manager.load_all(&states).unwrap();
+    assert_eq!(
+        manager.handle_read(0x4_1000.into(), AccessWidth::Dword),
+        Ok(0x1234)
+    );
+    assert!(
+        bridge
+            .load_state(&DeviceStateHeader::encode(1, &[0; 19]))
+            .is_err()
+    );
+
+    // Accesses missing the child devices get the policy of the parent bus.
+    bridge
+        .child()
+        .register(Arc::new(DwordRegs(spin::Mutex::new([5, 0]))))
+        .unwrap();
+    assert_eq!(
+        manager.handle_read(0x4_0000.into(), AccessWidth::Dword),
+        Ok(5)
+    );
+    assert_eq!(
+        manager.handle_read(0x4_0010.into(), AccessWidth::Dword),
+        Err(AxError::NotFound)
+    );
+    manager.set_unhandled_policy(UnhandledAccessPolicy::ReadAsZeroWriteIgnore);
+    assert_eq!(
+        manager.handle_read(0x4_0010.into(), AccessWidth::Dword),
+        Ok(0)
+    );
+}
+
+/// Guest RAM of `len` bytes starting at guest physical address 0x8000_0000.
+struct TestMemory(spin::Mutex<Vec<u8>>);
+
+impl TestMemory {
+    const BASE: usize = 0x8000_0000;
+
+    fn new(len: usize) -> Self {
+        Self(spin::Mutex::new(vec![0; len]))
+    }
+
+    fn range(&self, addr: GuestPhysAddr, len: usize) -> AxResult<core::ops::Range<usize>> {
+        let start = addr.as_usize().wrapping_sub(Self::BASE);
+        match start.checked_add(len) {
+            Some(end) if end <= self.0.lock().len() => Ok(start..end),
+            _ => axerrno::ax_err!(BadAddress),
+        }
+    }
+}
+
+impl GuestMemoryAccessor for TestMemory {
+    fn read(&self, addr: GuestPhysAddr, buf: &mut [u8]) -> AxResult {
+        let range = self.range(addr, buf.len())?;
+        buf.copy_from_slice(&self.0.lock()[range]);
+        Ok(())
+    }
+
+    fn write(&self, addr: GuestPhysAddr, data: &[u8]) -> AxResult {
+        let range = self.range(addr, data.len())?;
+        self.0.lock()[range].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Writes every register write to guest RAM through its DMA accessor.
+struct DmaWriter(spin::Mutex<Option<Arc<dyn GuestMemoryAccessor>>>);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for DmaWriter {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xb000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(0)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        let dma = self.0.lock().clone().unwrap();
+        dma.write_u32(TestMemory::BASE.into(), val as u32)
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.0.lock() = Some(accessor);
+    }
+}
+
+#[test]
+fn test_dma_accessor() {
+    let memory = Arc::new(TestMemory::new(0x1000));
+    let manager = DeviceManager::new();
+    manager.set_dma_accessor(memory.clone());
+    manager
+        .register(Arc::new(DmaWriter(spin::Mutex::new(None))))
+        .unwrap();
+
+    manager
+        .handle_write(0xb000.into(), AccessWidth::Dword, 0x0403_0201)
+        .unwrap();
+    assert_eq!(memory.read_u32(TestMemory::BASE.into()), Ok(0x0403_0201));
+
+    // Overlapping copies behave like memmove.
+    let base = GuestPhysAddr::from(TestMemory::BASE);
+    memory.copy(base + 1, base, 4).unwrap();
+    assert_eq!(memory.read_u64(base), Ok(0x04_0302_0101));
+    assert!(memory.read_u32(base + 0xffe).is_err());
+}
+
+#[test]
+fn test_guest_buffer_list() {
+    let memory = TestMemory::new(0x1000);
+    let base = GuestPhysAddr::from(TestMemory::BASE);
+    let mut sg = GuestBufferList::new();
+    sg.push(base + 0x100, 3).unwrap();
+    sg.push(base + 0x200, 0).unwrap();
+    sg.push(base + 0x300, 5).unwrap();
+    assert_eq!((sg.len(), sg.buffers().len()), (8, 2));
+    assert!(sg.check_len(9).is_err());
+
+    sg.write(&memory, 1, &[1, 2, 3, 4]).unwrap();
+    assert_eq!(memory.read_u16(base + 0x101), Ok(0x0201));
+    assert_eq!(memory.read_u16(base + 0x300), Ok(0x0403));
+
+    let (head, tail) = sg.split_at(2).unwrap();
+    assert_eq!((head.len(), tail.len()), (2, 6));
+    let mut pieces = Vec::new();
+    tail.for_each_slice(&memory, 0, 3, |piece| {
+        pieces.push(piece.to_vec());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(pieces, [vec![2], vec![3, 4]]);
+
+    let mut buf = [0; 4];
+    assert!(sg.read(&memory, 5, &mut buf).is_err());
+    sg.push(base + 0x1000, 1).unwrap();
+    assert!(sg.read(&memory, 5, &mut buf).is_err());
+}
+
+#[test]
+fn test_split_virtqueue() {
+    let memory = TestMemory::new(0x1000);
+    let base = GuestPhysAddr::from(TestMemory::BASE);
+    let (desc, avail, used) = (base, base + 0x100, base + 0x200);
+    let write_desc = |table: GuestPhysAddr, index: usize, addr: usize, len, flags, next| {
+        let entry = table + 16 * index;
+        memory
+            .write_u64(entry, (TestMemory::BASE + addr) as u64)
+            .unwrap();
+        memory.write_u32(entry + 8, len).unwrap();
+        memory.write_u16(entry + 12, flags).unwrap();
+        memory.write_u16(entry + 14, next).unwrap();
+    };
+
+    // Chain 0 -> 1 (read, then write), and chain 2 pointing at an indirect
+    // table with a single writable buffer.
+    write_desc(desc, 0, 0x400, 16, SplitQueue::DESC_F_NEXT, 1);
+    write_desc(desc, 1, 0x500, 64, SplitQueue::DESC_F_WRITE, 0);
+    write_desc(desc, 2, 0x600, 16, SplitQueue::DESC_F_INDIRECT, 0);
+    write_desc(base + 0x600, 0, 0x700, 8, SplitQueue::DESC_F_WRITE, 0);
+    memory.write_u16(avail + 4, 0).unwrap();
+    memory.write_u16(avail + 6, 2).unwrap();
+    memory.write_u16(avail + 2, 2).unwrap();
+
+    let mut queue = SplitQueue::new(4, desc, avail, used).unwrap();
+    let chain = queue.pop(&memory).unwrap().unwrap();
+    assert_eq!(chain.head, 0);
+    assert_eq!((chain.readable.len(), chain.writable.len()), (16, 64));
+    let chain = queue.pop(&memory).unwrap().unwrap();
+    assert_eq!((chain.head, chain.writable.len()), (2, 8));
+    assert!(queue.pop(&memory).unwrap().is_none());
+
+    queue.push_used(&memory, 0, 64).unwrap();
+    assert_eq!(memory.read_u16(used + 2), Ok(1));
+    assert_eq!(memory.read_u32(used + 8), Ok(64));
+    assert!(queue.needs_notification(&memory).unwrap());
+    assert!(!queue.needs_notification(&memory).unwrap());
+
+    // With event idx, the driver asks to be interrupted only after used
+    // entry 2, i.e. once the used index moves past 2.
+    queue.set_event_idx(true);
+    memory.write_u16(avail + 4 + 2 * 4, 2).unwrap();
+    queue.push_used(&memory, 2, 8).unwrap();
+    assert!(!queue.needs_notification(&memory).unwrap());
+
+    // A descriptor chain looping back on itself is rejected.
+    write_desc(desc, 1, 0x500, 64, SplitQueue::DESC_F_NEXT, 0);
+    memory.write_u16(avail + 8, 0).unwrap();
+    memory.write_u16(avail + 2, 3).unwrap();
+    assert!(queue.pop(&memory).is_err());
+    assert!(SplitQueue::new(3, desc, avail, used).is_err());
+
+    // So is a readable buffer after an empty writable one.
+    let (avail, used) = (base + 0x300, base + 0x380);
+    write_desc(
+        desc,
+        3,
+        0x800,
+        0,
+        SplitQueue::DESC_F_WRITE | SplitQueue::DESC_F_NEXT,
+        2,
+    );
+    write_desc(desc, 2, 0x400, 16, 0, 0);
+    memory.write_u16(avail + 4, 3).unwrap();
+    memory.write_u16(avail + 2, 1).unwrap();
+    let mut queue = SplitQueue::new(4, desc, avail, used).unwrap();
+    assert_eq!(queue.pop(&memory), Err(AxError::InvalidData));
+}
+
+struct VirtioConsole {
+    regs: VirtioMmioRegs,
+    notified: spin::Mutex<Vec<u16>>,
+}
+
+impl VirtioMmioDevice for VirtioConsole {
+    fn read_config(&self, offset: usize, _width: AccessWidth) -> AxResult<usize> {
+        Ok(if offset == 0 { 80 } else { 0 })
+    }
+
+    fn write_config(&self, _offset: usize, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn queue_notify(&self, queue: u16) -> AxResult {
+        self.notified.lock().push(queue);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_queue_set() {
+    #[derive(Default)]
+    struct TxQueue {
+        index: usize,
+        sent: u32,
+    }
+
+    let queues = QueueSet::new(3, |index| TxQueue { index, sent: 0 });
+    assert_eq!(queues.len(), 3);
+    assert!(!queues.is_empty());
+    assert!(queues.get(3).is_none());
+    assert!(
+        queues
+            .iter()
+            .all(|(index, queue)| queue.lock().index == index)
+    );
+    assert_eq!(queues.enabled().count(), 0);
+
+    let (rx, tx) = (queues.get(0).unwrap(), queues.get(2).unwrap());
+    rx.set_enabled(true);
+    tx.set_enabled(true);
+    tx.set_affinity(Some(1));
+    assert_eq!(
+        queues
+            .enabled()
+            .map(|(index, queue)| (index, queue.affinity()))
+            .collect::<Vec<_>>(),
+        [(0, None), (2, Some(1))]
+    );
+
+    // Each queue has its own lock, so queues are serviced independently.
+    let mut rx_state = rx.lock();
+    tx.lock().sent += 1;
+    rx_state.sent += 2;
+    drop(rx_state);
+
+    // A queue reset leaves the other queues alone.
+    rx.reset();
+    assert!(!rx.is_enabled());
+    assert_eq!(rx.lock().sent, 0);
+    assert_eq!(tx.lock().sent, 1);
+
+    queues.reset();
+    assert_eq!(queues.enabled().count(), 0);
+    assert!(
+        queues
+            .iter()
+            .all(|(_, queue)| { queue.affinity().is_none() && queue.lock().sent == 0 })
+    );
+}
+
+#[test]
+fn test_virtio_mmio_regs() {
+    let dev = VirtioConsole {
+        regs: VirtioMmioRegs::new(3, 0x554d_4551, 1 << 32 | 1, 2, 64),
+        notified: spin::Mutex::new(Vec::new()),
+    };
+    let read = |offset| {
+        dev.regs
+            .handle_read(offset, AccessWidth::Dword, &dev)
+            .unwrap()
+    };
+    let write = |offset, val| dev.regs.handle_write(offset, AccessWidth::Dword, val, &dev);
+
+    assert_eq!(read(0x000), 0x7472_6976);
+    assert_eq!(read(0x004), 2);
+    assert_eq!(read(0x008), 3);
+    write(0x014, 1).unwrap();
+    assert_eq!(read(0x010), 1);
+    assert_eq!(read(0x100), 80);
+
+    // Features the device did not offer are refused.
+    write(0x020, 2).unwrap();
+    write(0x070, 0xb).unwrap();
+    assert_eq!(read(0x070), 0x3);
+    write(0x020, 1).unwrap();
+    write(0x070, 0xb).unwrap();
+    assert_eq!(read(0x070), 0xb);
+
+    write(0x030, 1).unwrap();
+    assert_eq!(read(0x034), 64);
+    assert!(write(0x038, 128).is_err());
+    write(0x038, 16).unwrap();
+    write(0x080, 0x1000).unwrap();
+    write(0x084, 0x1).unwrap();
+    write(0x044, 1).unwrap();
+    let queue = dev.regs.queue(1).unwrap();
+    assert!(queue.ready);
+    assert_eq!((queue.num, queue.desc), (16, 0x1_0000_1000));
+
+    write(0x050, 1).unwrap();
+    assert_eq!(*dev.notified.lock(), [1]);
+
+    dev.regs.raise_interrupt(VirtioMmioRegs::INT_CONFIG_CHANGE);
+    assert_eq!(read(0x060), 2);
+    assert_eq!(read(0x0fc), 1);
+    write(0x064, 2).unwrap();
+    assert_eq!(read(0x060), 0);
+
+    // With an out of range queue selected, only queue registers fail.
+    write(0x030, 5).unwrap();
+    assert_eq!(read(0x034), 0);
+    assert!(write(0x038, 16).is_err());
+    write(0x000, 0).unwrap();
+    assert!(write(0x0a4, 0).is_err());
+    assert!(write(0x0a0, 0x3000).is_err());
+    write(0x030, 0).unwrap();
+    write(0x0a0, 0x3000).unwrap();
+    write(0x0a4, 0x2).unwrap();
+    assert_eq!(dev.regs.queue(0).unwrap().device, 0x2_0000_3000);
+
+    write(0x070, 0).unwrap();
+    assert_eq!(read(0x070), 0);
+    assert!(!dev.regs.queue(1).unwrap().ready);
+}
+
+/// An in-memory share holding `hello.txt` and an empty directory `sub`.
+struct MemoryShare {
+    hello: spin::Mutex<Vec<u8>>,
+    open: spin::Mutex<Vec<bool>>,
+    read_only: core::sync::atomic::AtomicBool,
+}
+
+impl FsBackend for MemoryShare {
+    fn open(&self, path: &str, _write: bool) -> AxResult<FsHandle> {
+        if path != "hello.txt" {
+            return Err(AxError::NotFound);
+        }
+        let mut open = self.open.lock();
+        open.push(true);
+        Ok(open.len() as FsHandle - 1)
+    }
+
+    fn close(&self, handle: FsHandle) -> AxResult {
+        self.open.lock()[handle as usize] = false;
+        Ok(())
+    }
+
+    fn read(&self, _handle: FsHandle, offset: u64, buf: &mut [u8]) -> AxResult<usize> {
+        let data = self.hello.lock();
+        let data = data.get(offset as usize..).unwrap_or_default();
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn write(&self, _handle: FsHandle, offset: u64, buf: &[u8]) -> AxResult<usize> {
+        let mut data = self.hello.lock();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn readdir(&self, path: &str) -> AxResult<Vec<FsDirEntry>> {
+        match path {
+            "" => Ok(vec![
+                FsDirEntry {
+                    name: "hello.txt".into(),
+                    is_dir: false,
+                },
+                FsDirEntry {
+                    name: "sub".into(),
+                    is_dir: true,
+                },
+            ]),
+            "sub" => Ok(Vec::new()),
+            _ => Err(AxError::NotFound),
+        }
+    }
+
+    fn attr(&self, path: &str) -> AxResult<FsAttr> {
+        match path {
+            "" | "sub" => Ok(FsAttr {
+                mode: 0o755,
+                is_dir: true,
+                ..Default::default()
+            }),
+            "hello.txt" => Ok(FsAttr {
+                size: self.hello.lock().len() as u64,
+                mode: 0o644,
+                ..Default::default()
+            }),
+            _ => Err(AxError::NotFound),
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only.load(core::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn test_virtio_fs() {
+    const LOOKUP: u32 = 1;
+    const FORGET: u32 = 2;
+    const GETATTR: u32 = 3;
+    const OPEN: u32 = 14;
+    const READ: u32 = 15;
+    const WRITE: u32 = 16;
+    const RELEASE: u32 = 18;
+    const INIT: u32 = 26;
+    const OPENDIR: u32 = 27;
+    const READDIR: u32 = 28;
+
+    let share = Arc::new(MemoryShare {
+        hello: spin::Mutex::new(b"hello world".to_vec()),
+        open: spin::Mutex::new(Vec::new()),
+        read_only: core::sync::atomic::AtomicBool::new(false),
+    });
+    let base = GuestPhysAddr::from(0x1_0000);
+    assert!(VirtioFsDevice::new(base, share.clone(), "").is_err());
+    let dev = VirtioFsDevice::new(base, share.clone(), "share").unwrap();
+    let memory = Arc::new(TestMemory::new(0x4000));
+    dev.set_dma_accessor(memory.clone());
+    let read = |offset| dev.handle_read(base + offset, AccessWidth::Dword).unwrap();
+    let write = |offset, val| dev.handle_write(base + offset, AccessWidth::Dword, val);
+
+    assert_eq!(read(0x008), 26);
+    assert_eq!(read(0x100), u32::from_le_bytes(*b"shar") as usize);
+    assert_eq!(read(0x124), 1);
+
+    // Set up the request queue, with its rings at the start of guest RAM.
+    let ram = GuestPhysAddr::from(TestMemory::BASE);
+    write(0x030, 1).unwrap();
+    write(0x038, 8).unwrap();
+    write(0x080, TestMemory::BASE).unwrap();
+    write(0x090, TestMemory::BASE + 0x100).unwrap();
+    write(0x0a0, TestMemory::BASE + 0x200).unwrap();
+    write(0x044, 1).unwrap();
+
+    // The high-priority queue has its rings 0x3000 above.
+    write(0x030, 0).unwrap();
+    write(0x038, 8).unwrap();
+    write(0x080, TestMemory::BASE + 0x3000).unwrap();
+    write(0x090, TestMemory::BASE + 0x3100).unwrap();
+    write(0x0a0, TestMemory::BASE + 0x3200).unwrap();
+    write(0x044, 1).unwrap();
+
+    // Sends a request on `queue` through descriptors 0 (request) and 1
+    // (reply), and returns the error and payload of the reply.
+    let avail_idx = [core::cell::Cell::new(0u16), core::cell::Cell::new(0u16)];
+    let send = |queue: usize, opcode: u32, node: u64, body: &[u8]| -> (i32, Vec<u8>) {
+        let (rings, avail_idx) = match queue {
+            0 => (ram + 0x3000, &avail_idx[0]),
+            _ => (ram, &avail_idx[1]),
+        };
+        let mut req = Vec::new();
+        req.extend_from_slice(&(40 + body.len() as u32).to_le_bytes());
+        req.extend_from_slice(&opcode.to_le_bytes());
+        req.extend_from_slice(&7u64.to_le_bytes());
+        req.extend_from_slice(&node.to_le_bytes());
+        req.resize(40, 0);
+        req.extend_from_slice(body);
+        memory.write(ram + 0x1000, &req).unwrap();
+        for (index, addr, len, flags) in [
+            (0, 0x1000, req.len() as u32, SplitQueue::DESC_F_NEXT),
+            (1, 0x2000, 0x1000, SplitQueue::DESC_F_WRITE),
+        ] {
+            let desc = rings + 16 * index;
+            memory
+                .write_u64(desc, (TestMemory::BASE + addr) as u64)
+                .unwrap();
+            memory.write_u32(desc + 8, len).unwrap();
+            memory.write_u16(desc + 12, flags).unwrap();
+            memory.write_u16(desc + 14, 1).unwrap();
+        }
+        let idx = avail_idx.get();
+        memory
+            .write_u16(rings + 0x104 + 2 * (idx % 8) as usize, 0)
+            .unwrap();
+        avail_idx.set(idx.wrapping_add(1));
+        memory.write_u16(rings + 0x102, avail_idx.get()).unwrap();
+        write(0x050, queue).unwrap();
+
+        assert_eq!(memory.read_u16(rings + 0x202), Ok(avail_idx.get()));
+        let used_len = memory.read_u32(rings + 0x204 + 8 * (idx % 8) as usize + 4);
+        let mut reply = vec![0; used_len.unwrap() as usize];
+        memory.read(ram + 0x2000, &mut reply).unwrap();
+        if reply.is_empty() {
+            return (0, reply);
+        }
+        assert_eq!(
+            u32::from_le_bytes(reply[..4].try_into().unwrap()),
+            reply.len() as u32
+        );
+        assert_eq!(reply[8..16], 7u64.to_le_bytes());
+        let error = i32::from_le_bytes(reply[4..8].try_into().unwrap());
+        (error, reply.split_off(16))
+    };
+    let request = |opcode, node, body: &[u8]| send(1, opcode, node, body);
+    let u64_at =
+        |buf: &[u8], offset: usize| u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap());
+    let fh_body = |fh: u64, offset: u64, size: u32| {
+        let mut body = Vec::new();
+        body.extend_from_slice(&fh.to_le_bytes());
+        body.extend_from_slice(&offset.to_le_bytes());
+        body.extend_from_slice(&size.to_le_bytes());
+        body.resize(40, 0);
+        body
+    };
+
+    let (error, init) = request(INIT, 0, &[7, 0, 0, 0, 31, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0]);
+    assert_eq!((error, init.len(), init[0]), (0, 64, 7));
+    assert_ne!(read(0x060) & VirtioMmioRegs::INT_USED_BUFFER as usize, 0);
+
+    // Lookups resolve names below known nodes, and never leave the share.
+    let (error, entry) = request(LOOKUP, 1, b"hello.txt\0");
+    assert_eq!(error, 0);
+    let node = u64_at(&entry, 0);
+    assert_eq!(u64_at(&entry, 40 + 8), 11);
+    assert_eq!(request(LOOKUP, 1, b"missing\0").0, -2);
+    assert_eq!(request(LOOKUP, 1, b"../etc\0").0, -22);
+    assert_eq!(request(LOOKUP, 1, b"..\0").0, -13);
+    assert_eq!(request(GETATTR, 77, &[0; 16]).0, -2);
+
+    // Files opened read-only can't be written.
+    let (error, open) = request(OPEN, node, &[0; 8]);
+    assert_eq!(error, 0);
+    let fh = u64_at(&open, 0);
+    let (error, data) = request(READ, 0, &fh_body(fh, 0, 100));
+    assert_eq!((error, data.as_slice()), (0, &b"hello world"[..]));
+    let mut body = fh_body(fh, 6, 5);
+    body.extend_from_slice(b"there");
+    assert_eq!(request(WRITE, 0, &body).0, -9);
+
+    let (_, open) = request(OPEN, node, &[2, 0, 0, 0, 0, 0, 0, 0]);
+    let rw = u64_at(&open, 0);
+    let mut body = fh_body(rw, 6, 5);
+    body.extend_from_slice(b"there");
+    assert_eq!(request(WRITE, 0, &body), (0, vec![5, 0, 0, 0, 0, 0, 0, 0]));
+    assert_eq!(request(READ, 0, &fh_body(fh, 0, 100)).1, b"hello there");
+    assert_eq!(request(RELEASE, 0, &fh_body(rw, 0, 0)).0, 0);
+    assert_eq!(request(RELEASE, 0, &fh_body(rw, 0, 0)).0, -9);
+    assert_eq!(*share.open.lock(), [true, false]);
+
+    // Directory listings resume at the offset of the last entry returned.
+    assert_eq!(request(OPENDIR, node, &[0; 8]).0, -20);
+    let dir = u64_at(&request(OPENDIR, 1, &[0; 8]).1, 0);
+    let names = |listing: &[u8]| {
+        let mut names = Vec::new();
+        let mut pos = 0;
+        while pos < listing.len() {
+            let len = u32::from_le_bytes(listing[pos + 16..pos + 20].try_into().unwrap()) as usize;
+            names.push(
+                alloc::string::String::from_utf8(listing[pos + 24..pos + 24 + len].to_vec())
+                    .unwrap(),
+            );
+            pos += (24 + len).next_multiple_of(8);
+        }
+        names
+    };
+    assert_eq!(
+        names(&request(READDIR, 0, &fh_body(dir, 0, 4096)).1),
+        ["hello.txt", "sub"]
+    );
+    let (_, first) = request(READDIR, 0, &fh_body(dir, 0, 40));
+    assert_eq!(names(&first), ["hello.txt"]);
+    assert_eq!(u64_at(&first, 8), 1);
+    assert_eq!(
+        names(&request(READDIR, 0, &fh_body(dir, 1, 4096)).1),
+        ["sub"]
+    );
+    assert_eq!(request(READDIR, 0, &fh_body(fh, 0, 4096)).0, -20);
+
+    assert_eq!(request(99, 0, &[]).0, -38);
+    // The high-priority queue only acts on forgets.
+    assert_eq!(send(0, GETATTR, node, &[0; 16]), (0, Vec::new()));
+    assert_eq!(send(0, FORGET, node, &1u64.to_le_bytes()), (0, Vec::new()));
+    assert_eq!(request(GETATTR, node, &[0; 16]).0, -2);
+
+    share
+        .read_only
+        .store(true, core::sync::atomic::Ordering::Relaxed);
+    let (_, entry) = request(LOOKUP, 1, b"hello.txt\0");
+    assert_eq!(
+        request(OPEN, u64_at(&entry, 0), &[1, 0, 0, 0, 0, 0, 0, 0]).0,
+        -30
+    );
+
+    // A device reset closes the files left open.
+    write(0x070, 0).unwrap();
+    assert_eq!(*share.open.lock(), [false, false]);
+}
+
+/// A NIC backend recording sent frames, which stops taking frames while
+/// `full` is set, until [`drain`](Self::drain)ed.
+#[derive(Default)]
+struct FakeNic {
+    sent: spin::Mutex<Vec<Vec<u8>>>,
+    rx: spin::Mutex<Option<RxCallback>>,
+    full: core::sync::atomic::AtomicBool,
+    waiter: spin::Mutex<Option<CapacityCallback>>,
+}
+
+impl FakeNic {
+    fn drain(&self) {
+        self.full
+            .store(false, core::sync::atomic::Ordering::Relaxed);
+        if let Some(callback) = self.waiter.lock().take() {
+            callback();
+        }
+    }
+}
+
+impl Backpressure for FakeNic {
+    fn can_accept(&self, _len: usize) -> bool {
+        !self.full.load(core::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn on_capacity_available(&self, callback: CapacityCallback) {
+        if self.can_accept(1) {
+            callback();
+        } else {
+            *self.waiter.lock() = Some(callback);
+        }
+    }
+}
+
+impl NetBackend for FakeNic {
+    fn send(&self, frame: &[u8]) -> AxResult {
+        if self.full.load(core::sync::atomic::Ordering::Relaxed) {
+            return Err(AxError::WouldBlock);
+        }
+        self.sent.lock().push(frame.to_vec());
+        Ok(())
+    }
+
+    fn set_rx_callback(&self, callback: RxCallback) {
+        *self.rx.lock() = Some(callback);
+    }
+
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    fn mac(&self) -> [u8; 6] {
+        [0x52, 0x54, 0, 0x12, 0x34, 0x56]
+    }
+}
+
+#[test]
+fn test_virtio_net() {
+    let nic = Arc::new(FakeNic::default());
+    let base = GuestPhysAddr::from(0x1_0000);
+    assert!(VirtioNetDevice::new(base, nic.clone(), 0).is_err());
+    let dev = Arc::new(VirtioNetDevice::new(base, nic.clone(), 2).unwrap());
+    let memory = Arc::new(TestMemory::new(0x8000));
+    dev.set_dma_accessor(memory.clone());
+    dev.connect();
+    let read = |offset| dev.handle_read(base + offset, AccessWidth::Dword).unwrap();
+    let write = |offset, val| dev.handle_write(base + offset, AccessWidth::Dword, val);
+
+    assert_eq!(read(0x008), 1);
+    assert_eq!(read(0x100), 0x1200_5452);
+    assert_eq!(read(0x108), 1500 << 16 | 2);
+    assert_ne!(read(0x010) & 1 << 22, 0);
+
+    // Queue `q` has its rings at `q * 0x1000` and its buffers 0x400 above.
+    let ram = GuestPhysAddr::from(TestMemory::BASE);
+    for queue in [0, 1, 4] {
+        let rings = TestMemory::BASE + queue * 0x1000;
+        write(0x030, queue).unwrap();
+        write(0x038, 8).unwrap();
+        write(0x080, rings).unwrap();
+        write(0x090, rings + 0x100).unwrap();
+        write(0x0a0, rings + 0x200).unwrap();
+        write(0x044, 1).unwrap();
+    }
+    write(0x070, 0xf).unwrap();
+
+    // Makes a chain of `(offset, len, writable)` buffers available on `queue`.
+    let post = |queue: usize, bufs: &[(usize, u32, bool)]| {
+        let rings = ram + queue * 0x1000;
+        for (index, &(offset, len, writable)) in bufs.iter().enumerate() {
+            let desc = rings + 16 * index;
+            let last = index + 1 == bufs.len();
+            let flags = if writable {
+                SplitQueue::DESC_F_WRITE
+            } else {
+                0
+            } | if last { 0 } else { SplitQueue::DESC_F_NEXT };
+            let addr = TestMemory::BASE + queue * 0x1000 + offset;
+            memory.write_u64(desc, addr as u64).unwrap();
+            memory.write_u32(desc + 8, len).unwrap();
+            memory.write_u16(desc + 12, flags).unwrap();
+            memory.write_u16(desc + 14, index as u16 + 1).unwrap();
+        }
+        let idx = memory.read_u16(rings + 0x102).unwrap();
+        memory
+            .write_u16(rings + 0x104 + 2 * (idx % 8) as usize, 0)
+            .unwrap();
+        memory.write_u16(rings + 0x102, idx + 1).unwrap();
+    };
+    let used_idx = |queue: usize| memory.read_u16(ram + queue * 0x1000 + 0x202).unwrap();
+    let mut frame = vec![0; 12];
+    frame.extend_from_slice(b"frame");
+    memory.write(ram + 0x1400, &frame).unwrap();
+
+    post(1, &[(0x400, 17, false)]);
+    write(0x050, 1).unwrap();
+    assert_eq!(*nic.sent.lock(), [b"frame"]);
+    assert_eq!(used_idx(1), 1);
+    assert_eq!(dev.take_notifications(), [(1, None)]);
+    assert_ne!(dev.regs().interrupt_status(), 0);
+
+    // A full backend stalls the queue until its capacity callback resumes it.
+    nic.full.store(true, core::sync::atomic::Ordering::Relaxed);
+    dev.set_queue_affinity(1, Some(3)).unwrap();
+    assert!(dev.set_queue_affinity(5, None).is_err());
+    post(1, &[(0x400, 17, false)]);
+    write(0x050, 1).unwrap();
+    assert_eq!((nic.sent.lock().len(), used_idx(1)), (1, 1));
+    assert!(dev.take_notifications().is_empty());
+    let stalled = Arc::downgrade(&dev);
+    nic.on_capacity_available(Box::new(move || {
+        stalled.upgrade().unwrap().resume_tx().unwrap();
+    }));
+    assert_eq!(nic.sent.lock().len(), 1);
+    nic.drain();
+    assert_eq!((nic.sent.lock().len(), used_idx(1)), (2, 2));
+    assert_eq!(dev.take_notifications(), [(1, Some(3))]);
+
+    // With moderation, a lone completion is signalled when the timer
+    // expires, and a full batch right away.
+    let timers = Arc::new(TimerLog::default());
+    dev.set_timer_service(timers.clone());
+    dev.set_moderation(NetModeration {
+        max_frames: 2,
+        max_delay: core::time::Duration::from_millis(1),
+    });
+    post(1, &[(0x400, 17, false)]);
+    write(0x050, 1).unwrap();
+    assert!(dev.take_notifications().is_empty());
+    let (delay, token) = timers.0.lock().pop().unwrap();
+    assert_eq!(delay, core::time::Duration::from_millis(1));
+    assert!(!dev.on_timer(TimerToken::next()));
+    assert!(dev.on_timer(token));
+    assert_eq!(dev.take_notifications(), [(1, Some(3))]);
+    post(1, &[(0x400, 17, false)]);
+    post(1, &[(0x400, 17, false)]);
+    write(0x050, 1).unwrap();
+    assert_eq!(dev.take_notifications(), [(1, Some(3))]);
+    assert!(timers.0.lock().is_empty());
+    dev.set_moderation(NetModeration::default());
+
+    // Received frames land in the receive buffers of the first pair.
+    let rx_frame = [0xffu8; 20];
+    post(0, &[(0x400, 64, true)]);
+    (nic.rx.lock().as_ref().unwrap())(&rx_frame);
+    let mut buf = [0; 32];
+    memory.read(ram + 0x400, &mut buf).unwrap();
+    assert_eq!((buf[10], &buf[12..]), (1, &rx_frame[..]));
+    assert_eq!(memory.read_u32(ram + 0x208), Ok(32));
+    assert_eq!(dev.take_notifications(), [(0, None)]);
+    assert_eq!(dev.receive(&rx_frame), Ok(false));
+
+    // The control queue switches the number of active pairs.
+    let ctrl = |pairs: u8| {
+        memory.write(ram + 0x4400, &[4, 0, pairs, 0]).unwrap();
+        post(4, &[(0x400, 4, false), (0x500, 1, true)]);
+        write(0x050, 4).unwrap();
+        let mut ack = [0xff];
+        memory.read(ram + 0x4500, &mut ack).unwrap();
+        ack[0]
+    };
+    assert_eq!(ctrl(2), 0);
+    assert_eq!(dev.active_pairs(), 2);
+    assert_eq!(ctrl(3), 1);
+    assert_eq!(dev.active_pairs(), 2);
+
+    write(0x070, 0).unwrap();
+    assert_eq!(dev.active_pairs(), 1);
+    assert_eq!(dev.receive(&rx_frame), Ok(false));
+}
+
+#[test]
+fn test_pci_config_space() {
+    let config = PciConfigSpace::new(0x1af4, 0x1042, 0x01_80_00, 0);
+    config
+        .set_bar(
+            2,
+            PciBar::Memory64 {
+                size: 0x1_0000_0000,
+                prefetchable: true,
+            },
+        )
+        .unwrap();
+    assert!(config.set_bar(3, PciBar::Io { size: 0x10 }).is_err());
+    let cap = config.add_capability(0x09, &[4, 0xaa]).unwrap();
+    let next = config.add_capability(0x05, &[0; 12]).unwrap();
+    let read = |offset, width| config.read(offset, width).unwrap();
+
+    assert_eq!(read(0x00, AccessWidth::Dword), 0x1042_1af4);
+    assert_eq!(read(0x0b, AccessWidth::Byte), 0x01);
+    assert_eq!(read(0x34, AccessWidth::Byte), cap);
+    assert_eq!(read(cap + 1, AccessWidth::Byte), next);
+    assert_eq!(read(0x06, AccessWidth::Word) & 0x10, 0x10);
+
+    // Identification fields are read-only.
+    config.write(0x00, AccessWidth::Dword, 0).unwrap();
+    assert_eq!(read(0x00, AccessWidth::Dword), 0x1042_1af4);
+
+    // 64-bit BAR sizing, with memory decoding disabled: nothing is reported.
+    config.write(0x18, AccessWidth::Dword, 0xffff_ffff).unwrap();
+    config.write(0x1c, AccessWidth::Dword, 0xffff_ffff).unwrap();
+    assert_eq!(read(0x18, AccessWidth::Dword), 0x0c);
+    assert_eq!(read(0x1c, AccessWidth::Dword), 0xffff_ffff);
+    assert!(
+        config
+            .write(0x1c, AccessWidth::Dword, 0x40)
+            .unwrap()
+            .is_empty()
+    );
+    config.write(0x18, AccessWidth::Dword, 0).unwrap();
+    assert_eq!(config.bar_address(2), Some(0x40_0000_0000));
+
+    // Enabling memory decoding maps the BAR.
+    let mapped = PciBarChange {
+        index: 2,
+        address: Some(0x40_0000_0000),
+    };
+    assert_eq!(
+        config.write(0x04, AccessWidth::Word, 0x2).unwrap(),
+        [mapped]
+    );
+    assert!(config.write(0x3c, AccessWidth::Byte, 5).unwrap().is_empty());
+
+    // Sizing probes while decoding leave the BAR in place, and restoring its
+    // address is not a change.
+    for (offset, val) in [(0x18, 0xffff_ffff), (0x1c, 0xffff_ffff), (0x1c, 0x40)] {
+        assert!(
+            config
+                .write(offset, AccessWidth::Dword, val)
+                .unwrap()
+                .is_empty()
+        );
+    }
+    assert!(
+        config
+            .write(0x18, AccessWidth::Dword, 0)
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        config.write(0x1c, AccessWidth::Dword, 0x80).unwrap(),
+        [PciBarChange {
+            index: 2,
+            address: Some(0x80_0000_0000),
+        }]
+    );
+    assert_eq!(
+        config.write(0x04, AccessWidth::Word, 0).unwrap(),
+        [PciBarChange {
+            index: 2,
+            address: None,
+        }]
+    );
+
+    // The changes drive the region sink of the device.
+    let log = Arc::new(RegionLog::default());
+    let sink = DeviceRegionSink::new(0x3000, log.clone());
+    for command in [0x2, 0] {
+        let changes = config.write(0x04, AccessWidth::Word, command).unwrap();
+        config.apply_bar_changes(&changes, &sink).unwrap();
+    }
+    assert_eq!(
+        *log.0.lock(),
+        [
+            (
+                0x3000,
+                RegionId::BAR2,
+                Some((0x80_0000_0000, 0x1_0000_0000))
+            ),
+            (0x3000, RegionId::BAR2, None),
+        ]
+    );
+    let undeclared = PciBarChange {
+        index: 5,
+        address: Some(0),
+    };
+    assert!(config.apply_bar_changes(&[undeclared], &sink).is_err());
+
+    // Status error bits are write-1-to-clear.
+    config.write_raw(0x07, &[0x80]).unwrap();
+    config.write(0x06, AccessWidth::Word, 0x8000).unwrap();
+    assert_eq!(read(0x06, AccessWidth::Word), 0x10);
+
+    config.write(0x04, AccessWidth::Word, 0xffff).unwrap();
+    assert_eq!(config.command(), 0x0547);
+    assert!(config.read(0xfe, AccessWidth::Dword).is_err());
+    assert!(config.read(usize::MAX, AccessWidth::Byte).is_err());
+}
+
+struct PciFunction {
+    bdf: PciBdf,
+    config: PciConfigSpace,
+}
+
+impl BaseDeviceOps<PciConfigRange> for PciFunction {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> PciConfigRange {
+        PciConfigRange::function(self.bdf)
+    }
+
+    fn handle_read(&self, addr: PciConfigAddr, width: AccessWidth) -> AxResult<usize> {
+        self.config.read(addr.offset as usize, width)
+    }
+
+    fn handle_write(&self, addr: PciConfigAddr, width: AccessWidth, val: usize) -> AxResult {
+        self.config
+            .write(addr.offset as usize, width, val)
+            .map(|_| ())
+    }
+
+    /// Only the interrupt line is saved.
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        let line = self.config.read(0x3c, AccessWidth::Byte)? as u8;
+        Ok(DeviceStateHeader::encode(1, &[line]))
+    }
+
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        let (_, payload) = DeviceStateHeader::decode(state)?;
+        self.config.write_raw(0x3c, payload)
+    }
+}
+
+#[test]
+fn test_ecam_window() {
+    let addr = PciConfigAddr::from_ecam_offset(0x0012_a310).unwrap();
+    assert_eq!(addr.bdf, PciBdf::new(1, 5, 2).unwrap());
+    assert_eq!(addr.offset, 0x310);
+    assert_eq!(addr.ecam_offset(), 0x0012_a310);
+
+    let ecam = EcamWindow::new(0x3000_0000.into(), 0, 1).unwrap();
+    let bdf = PciBdf::new(0, 3, 0).unwrap();
+    ecam.register(Arc::new(PciFunction {
+        bdf,
+        config: PciConfigSpace::new(0x1af4, 0x1041, 0x02_00_00, 1),
+    }))
+    .unwrap();
+    assert!(
+        ecam.register(Arc::new(PciFunction {
+            bdf: PciBdf::new(2, 0, 0).unwrap(),
+            config: PciConfigSpace::new(0x1af4, 0x1041, 0, 0),
+        }))
+        .is_err()
+    );
+
+    let base = 0x3000_0000 + (3 << 15);
+    assert_eq!(
+        ecam.handle_read(base.into(), AccessWidth::Dword).unwrap(),
+        0x1041_1af4
+    );
+    ecam.handle_write((base + 0x3c).into(), AccessWidth::Byte, 9)
+        .unwrap();
+    assert_eq!(
+        ecam.handle_read((base + 0x3c).into(), AccessWidth::Byte)
+            .unwrap(),
+        9
+    );
+
+    // Absent functions read as all ones.
+    assert_eq!(
+        ecam.handle_read((0x3000_0000 + (4 << 15)).into(), AccessWidth::Word)
+            .unwrap(),
+        0xffff
+    );
+    assert!(ecam.unregister(bdf).is_some());
+    assert!(ecam.functions().is_empty());
+}
+
+#[test]
+fn test_ecam_window_snapshot() {
+    let ecam = EcamWindow::new(0x3000_0000.into(), 0, 1).unwrap();
+    let line = |bdf: PciBdf| {
+        let addr = 0x3000_0000 + PciConfigAddr { bdf, offset: 0x3c }.ecam_offset();
+        GuestPhysAddr::from(addr)
+    };
+    let bdfs = [PciBdf::new(0, 3, 0).unwrap(), PciBdf::new(1, 0, 2).unwrap()];
+    for (bdf, irq) in bdfs.into_iter().zip([5, 11]) {
+        ecam.register(Arc::new(PciFunction {
+            bdf,
+            config: PciConfigSpace::new(0x1af4, 0x1041, 0, 0),
+        }))
+        .unwrap();
+        ecam.handle_write(line(bdf), AccessWidth::Byte, irq)
+            .unwrap();
+    }
+
+    let state = ecam.save_state().unwrap();
+    for bdf in bdfs {
+        ecam.handle_write(line(bdf), AccessWidth::Byte, 0).unwrap();
+    }
+    ecam.load_state(&state).unwrap();
+    assert_eq!(
+        ecam.handle_read(line(bdfs[0]), AccessWidth::Byte).unwrap(),
+        5
+    );
+    assert_eq!(
+        ecam.handle_read(line(bdfs[1]), AccessWidth::Byte).unwrap(),
+        11
+    );
+
+    // Truncated state, or state saved with other functions, is rejected.
+    assert!(ecam.load_state(&state[..state.len() - 1]).is_err());
+    ecam.unregister(bdfs[1]);
+    assert!(ecam.load_state(&state).is_err());
+}
+
+#[test]
+fn test_msix_table() {
+    let msix = MsixTable::new(70).unwrap();
+    assert_eq!((msix.table_size(), msix.pba_size()), (70 * 16, 16));
+    assert_eq!(msix.read_table(0x0c, AccessWidth::Dword).unwrap(), 1);
+
+    msix.write_table(0x40, AccessWidth::Qword, 0x1_fee0_0000)
+        .unwrap();
+    msix.write_table(0x48, AccessWidth::Dword, 0x41).unwrap();
+    let message = MsiMessage {
+        address: 0x1_fee0_0000,
+        data: 0x41,
+    };
+    assert_eq!(msix.message(4), Some(message));
+
+    // Disabled: nothing is delivered or latched.
+    assert_eq!(msix.trigger(4).unwrap(), None);
+    assert!(!msix.is_pending(4));
+
+    // Masked: the pending bit is latched and released on unmask.
+    msix.set_control(true, false);
+    assert_eq!(msix.trigger(4).unwrap(), None);
+    assert_eq!(msix.read_pba(0, AccessWidth::Qword).unwrap(), 1 << 4);
+    assert_eq!(
+        msix.write_table(0x4c, AccessWidth::Dword, 0).unwrap(),
+        Some(message)
+    );
+    assert!(!msix.is_pending(4));
+    assert_eq!(msix.trigger(4).unwrap(), Some(message));
+
+    // Function mask.
+    msix.set_control(true, true);
+    assert_eq!(msix.trigger(4).unwrap(), None);
+    assert_eq!(msix.set_control(true, false), [message]);
+
+    assert!(msix.read_table(0x42, AccessWidth::Word).is_err());
+    assert!(msix.read_table(usize::MAX - 7, AccessWidth::Qword).is_err());
+    assert!(msix.read_pba(usize::MAX - 7, AccessWidth::Qword).is_err());
+    assert!(msix.trigger(70).is_err());
+    msix.reset();
+    assert_eq!(msix.message(4).unwrap().address, 0);
+}
+
+/// A register banked per vCPU.
+struct BankedReg(spin::Mutex<[u32; 4]>);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for BankedReg {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xc000.into(), 4)
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        self.handle_read_ctx(addr, width, AccessContext::default())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        self.handle_write_ctx(addr, width, val, AccessContext::default())
+    }
+
+    fn handle_read_ctx(
+        &self,
+        _addr: GuestPhysAddr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        let val = self.0.lock()[ctx.vcpu_id] as usize;
+        Ok(RegValue::new(val, width).zero_extend())
+    }
+
+    fn handle_write_ctx(
+        &self,
+        _addr: GuestPhysAddr,
+        _width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        self.0.lock()[ctx.vcpu_id] = val as u32;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_access_context() {
+    let manager = DeviceManager::new();
+    manager
+        .register(Arc::new(NaturalWidthAdapter::new(
+            JournaledDevice::new(BankedReg(spin::Mutex::new([0; 4])), 8),
+            AccessWidth::Dword,
+        )))
+        .unwrap();
+    let cpu1 = AccessContext::new(1).with_pc(0x8000_1000);
+    let cpu2 = AccessContext::new(2);
+
+    manager
+        .handle_write_ctx(0xc000.into(), AccessWidth::Dword, 0x1111, cpu1)
+        .unwrap();
+    manager
+        .handle_write_ctx(0xc001.into(), AccessWidth::Byte, 0x22, cpu2)
+        .unwrap();
+    assert_eq!(
+        manager
+            .handle_read_ctx(0xc000.into(), AccessWidth::Dword, cpu1)
+            .unwrap(),
+        0x1111
+    );
+    assert_eq!(
+        manager
+            .handle_read_ctx(0xc000.into(), AccessWidth::Dword, cpu2)
+            .unwrap(),
+        0x2200
+    );
+    // Without a context, the device falls back to vCPU 0.
+    assert_eq!(
+        manager
+            .handle_read(0xc000.into(), AccessWidth::Dword)
+            .unwrap(),
+        0
+    );
+}
+
+/// A register banked per vCPU, with banks added as vCPUs are hot-added, up to
+/// `max_vcpus`.
+struct HotplugBankedReg {
+    banks: spin::Mutex<Vec<u32>>,
+    max_vcpus: usize,
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for HotplugBankedReg {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::InterruptController
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xd000.into(), 4)
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        self.handle_read_ctx(addr, width, AccessContext::default())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        self.handle_write_ctx(addr, width, val, AccessContext::default())
+    }
+
+    fn handle_read_ctx(
+        &self,
+        _addr: GuestPhysAddr,
+        _width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        match self.banks.lock().get(ctx.vcpu_id) {
+            Some(bank) => Ok(*bank as usize),
+            None => Err(AxError::BadState),
+        }
+    }
+
+    fn handle_write_ctx(
+        &self,
+        _addr: GuestPhysAddr,
+        _width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        match self.banks.lock().get_mut(ctx.vcpu_id) {
+            Some(bank) => *bank = val as u32,
+            None => return Err(AxError::BadState),
+        }
+        Ok(())
+    }
+
+    fn on_vcpu_added(&self, index: usize) -> AxResult {
+        let mut banks = self.banks.lock();
+        if index >= self.max_vcpus {
+            return Err(AxError::NoMemory);
+        }
+        if index >= banks.len() {
+            banks.resize(index + 1, 0);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_vcpu_hot_add() {
+    let reg = JournaledDevice::new(
+        HotplugBankedReg {
+            banks: spin::Mutex::new(vec![0; 2]),
+            max_vcpus: 4,
+        },
+        8,
+    );
+    let cpu = AccessContext::new;
+    assert_eq!(
+        reg.handle_write_ctx(0xd000.into(), AccessWidth::Dword, 3, cpu(3)),
+        Err(AxError::BadState)
+    );
+
+    // The hook reaches the device through wrappers, and the new bank starts
+    // in its reset state.
+    reg.on_vcpu_added(3).unwrap();
+    assert_eq!(
+        reg.handle_read_ctx(0xd000.into(), AccessWidth::Dword, cpu(3)),
+        Ok(0)
+    );
+    reg.handle_write_ctx(0xd000.into(), AccessWidth::Dword, 3, cpu(3))
+        .unwrap();
+    reg.handle_write_ctx(0xd000.into(), AccessWidth::Dword, 1, cpu(1))
+        .unwrap();
+    assert_eq!(
+        reg.handle_read_ctx(0xd000.into(), AccessWidth::Dword, cpu(3)),
+        Ok(3)
+    );
+
+    // Adding an existing vCPU again keeps its bank.
+    reg.on_vcpu_added(1).unwrap();
+    assert_eq!(
+        reg.handle_read_ctx(0xd000.into(), AccessWidth::Dword, cpu(1)),
+        Ok(1)
+    );
+
+    // A device that cannot serve more vCPUs vetoes the hot-add.
+    assert_eq!(reg.on_vcpu_added(4), Err(AxError::NoMemory));
+    assert_eq!(reg.inner().banks.lock().len(), 4);
+
+    // Devices without per-vCPU state accept any vCPU.
+    assert_eq!(DeviceA.on_vcpu_added(64), Ok(()));
+}
+
+#[test]
+fn test_bulk_access() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    manager.register(Arc::new(DeviceB)).unwrap();
+    let regs = Arc::new(DwordRegs(spin::Mutex::new([0; 2])));
+    manager.register(regs.clone()).unwrap();
+
+    let mut buf = [0; 8];
+    manager
+        .handle_read_bulk(0x1ff8.into(), AccessWidth::Word, 4, -2, &mut buf)
+        .unwrap();
+    assert_eq!(buf, [0xf8, 0x1f, 0xf6, 0x1f, 0xf4, 0x1f, 0xf2, 0x1f]);
+    assert_eq!(
+        manager.handle_read_bulk(0x1ff8.into(), AccessWidth::Word, 8, 2, &mut [0; 16]),
+        Err(AxError::BadAddress)
+    );
+    assert_eq!(
+        manager.handle_read_bulk(0x1000.into(), AccessWidth::Word, 3, 2, &mut buf),
+        Err(AxError::InvalidInput)
+    );
+
+    // A zero stride repeatedly writes the same register.
+    let data = [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0];
+    manager
+        .handle_write_bulk(0x9000.into(), AccessWidth::Dword, 3, 0, &data)
+        .unwrap();
+    assert_eq!(regs.0.lock()[0], 3);
+}
+
+/// A device at 0x1000 counting the bulk accesses it serves in one call.
+#[derive(Default)]
+struct BulkDevice(core::sync::atomic::AtomicUsize);
+
+impl BulkDevice {
+    fn bulk_calls(&self) -> usize {
+        self.0.load(core::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for BulkDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x1000.into(), 0x1000)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(0)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn handle_read_bulk(
+        &self,
+        _addr: GuestPhysAddr,
+        _width: AccessWidth,
+        _count: usize,
+        _stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        self.0.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        buf.fill(0x5a);
+        Ok(())
+    }
+
+    fn handle_write_bulk(
+        &self,
+        _addr: GuestPhysAddr,
+        _width: AccessWidth,
+        _count: usize,
+        _stride: isize,
+        _buf: &[u8],
+    ) -> AxResult {
+        self.0.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Reads and writes two dwords at 0x1000 in bulk through `device`.
+fn bulk_read_write(device: &impl BaseDeviceOps<GuestPhysAddrRange>) -> AxResult<[u8; 8]> {
+    let mut buf = [0; 8];
+    device.handle_read_bulk(0x1000.into(), AccessWidth::Dword, 2, 4, &mut buf)?;
+    device.handle_write_bulk(0x1000.into(), AccessWidth::Dword, 2, 4, &buf)?;
+    Ok(buf)
+}
+
+#[test]
+fn test_wrappers_forward_bulk_access() {
+    let device = JournaledDevice::new(BulkDevice::default(), 8);
+    assert_eq!(bulk_read_write(&device), Ok([0x5a; 8]));
+    assert_eq!(device.inner().bulk_calls(), 2);
+    assert_eq!(device.records().len(), 4);
+    assert_eq!(device.records()[1].addr, 0x1004.into());
+    assert_eq!(device.records()[1].value, 0x5a5a_5a5a);
+
+    let device = StatsDevice::new(BulkDevice::default());
+    bulk_read_write(&device).unwrap();
+    assert_eq!(device.inner().bulk_calls(), 2);
+    let total = device.stats().unwrap().total;
+    assert_eq!((total.reads, total.bytes_written), (2, 8));
+
+    let device = CoveredDevice::new(BulkDevice::default());
+    bulk_read_write(&device).unwrap();
+    assert_eq!(device.inner().bulk_calls(), 2);
+    assert_eq!(device.report().len(), 4);
+
+    let device = NaturalWidthAdapter::new(BulkDevice::default(), AccessWidth::Dword);
+    bulk_read_write(&device).unwrap();
+    assert_eq!(device.inner().bulk_calls(), 2);
+    // Accesses of another width are split by the adapter.
+    device
+        .handle_read_bulk(0x1000.into(), AccessWidth::Byte, 2, 1, &mut [0; 2])
+        .unwrap();
+    assert_eq!(device.inner().bulk_calls(), 2);
+
+    let device = ErrorInjector::new(BulkDevice::default());
+    device.corrupt_reads(0xff);
+    assert_eq!(
+        bulk_read_write(&device),
+        Ok([0xa5, 0x5a, 0x5a, 0x5a, 0xa5, 0x5a, 0x5a, 0x5a])
+    );
+    assert_eq!(device.inner().bulk_calls(), 2);
+    assert_eq!(device.accesses(), 4);
+    // A bulk access containing the failing access fails as a whole.
+    device.fail_nth(2, AxError::Io);
+    assert_eq!(bulk_read_write(&device), Err(AxError::Io));
+    assert_eq!(device.inner().bulk_calls(), 2);
+
+    let device = ThrottledDevice::new(BulkDevice::default(), 1, 3);
+    assert_eq!(bulk_read_write(&device), Err(AxError::WouldBlock));
+    assert_eq!(device.inner().bulk_calls(), 1);
+    assert_eq!(device.throttled(), 2);
+
+    let device = PermissionCheckedDevice::new(BulkDevice::default()).with_region(
+        GuestPhysAddrRange::from_start_size(0x1004.into(), 4),
+        RegionAccess::ReadOnly,
+    );
+    assert_eq!(bulk_read_write(&device), Err(AxError::PermissionDenied));
+    assert_eq!(device.inner().bulk_calls(), 1);
+}
+
+#[derive(Default)]
+struct CompletionLog(spin::Mutex<Vec<(CompletionToken, AxResult<usize>)>>);
+
+impl AccessCompleter for CompletionLog {
+    fn complete(&self, token: CompletionToken, result: AxResult<usize>) {
+        self.0.lock().push((token, result));
+    }
+}
+
+/// A device whose reads complete when `finish` is called.
+#[derive(Default)]
+struct SlowDevice {
+    completer: spin::Mutex<Option<Arc<dyn AccessCompleter>>>,
+    inflight: spin::Mutex<Option<CompletionToken>>,
+}
+
+impl SlowDevice {
+    fn finish(&self, val: usize) {
+        let token = self.inflight.lock().take().unwrap();
+        let completer = self.completer.lock().clone().unwrap();
+        completer.complete(token, Ok(val));
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for SlowDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xd000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Err(AxError::WouldBlock)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn handle_read_async(
+        &self,
+        _addr: GuestPhysAddr,
+        _width: AccessWidth,
+    ) -> AxResult<AccessOutcome> {
+        let token = CompletionToken::next();
+        *self.inflight.lock() = Some(token);
+        Ok(AccessOutcome::Pending(token))
+    }
+
+    fn set_completer(&self, completer: Arc<dyn AccessCompleter>) {
+        *self.completer.lock() = Some(completer);
+    }
+}
+
+#[test]
+fn test_async_completion() {
+    let manager = DeviceManager::new();
+    let slow = Arc::new(SlowDevice::default());
+    manager.register(slow.clone()).unwrap();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    let log = Arc::new(CompletionLog::default());
+    manager.set_completer(log.clone());
+
+    // Devices without asynchronous support complete synchronously.
+    assert_eq!(
+        manager.handle_read_async(0x1004.into(), AccessWidth::Dword),
+        Ok(AccessOutcome::Completed(0x1004))
+    );
+
+    let Ok(AccessOutcome::Pending(token)) =
+        manager.handle_read_async(0xd000.into(), AccessWidth::Dword)
+    else {
+        panic!("read should be pending");
+    };
+    assert!(log.0.lock().is_empty());
+    slow.finish(7);
+    assert_eq!(*log.0.lock(), [(token, Ok(7))]);
+    assert_ne!(CompletionToken::next(), token);
+}
+
+/// A device with a doorbell register at offset 0 whose writes are coalesced.
+struct Doorbell {
+    ring: CoalescedWriteRing<GuestPhysAddrRange>,
+    rung: spin::Mutex<Vec<usize>>,
+}
+
+impl Doorbell {
+    /// Creates a doorbell at 0xe000 whose ring holds two writes.
+    fn new() -> Self {
+        Self {
+            ring: CoalescedWriteRing::new(
+                vec![GuestPhysAddrRange::from_start_size(0xe000.into(), 4)],
+                2,
+            ),
+            rung: spin::Mutex::new(Vec::new()),
+        }
+    }
+
+    fn process(&self) {
+        self.ring
+            .drain(|write| {
+                self.rung.lock().push(write.val);
+                Ok(())
+            })
+            .unwrap();
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for Doorbell {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0xe000.into(), 8)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(0)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        self.process();
+        self.rung.lock().push(val);
+        Ok(())
+    }
+
+    fn coalesced_writes(&self) -> Option<&CoalescedWriteRing<GuestPhysAddrRange>> {
+        Some(&self.ring)
+    }
+}
+
+#[test]
+fn test_coalesced_writes() {
+    let doorbell = Arc::new(Doorbell::new());
+    let manager = DeviceManager::new();
+    manager.register(doorbell.clone()).unwrap();
+
+    manager
+        .handle_write(0xe000.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    manager
+        .handle_write(0xe000.into(), AccessWidth::Dword, 2)
+        .unwrap();
+    assert!(doorbell.rung.lock().is_empty());
+    assert_eq!(doorbell.ring.len(), 2);
+
+    // A full ring falls back to a synchronous write, which drains it first.
+    manager
+        .handle_write(0xe000.into(), AccessWidth::Dword, 3)
+        .unwrap();
+    assert_eq!(*doorbell.rung.lock(), [1, 2, 3]);
+
+    // Other registers are not coalesced.
+    manager
+        .handle_write(0xe004.into(), AccessWidth::Dword, 4)
+        .unwrap();
+    assert_eq!(*doorbell.rung.lock(), [1, 2, 3, 4]);
+    assert!(doorbell.ring.is_empty());
+
+    manager
+        .handle_write(0xe000.into(), AccessWidth::Word, 5)
+        .unwrap();
+    let mut drained = Vec::new();
+    doorbell
+        .ring
+        .drain(|write| {
+            drained.push(write);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(
+        drained,
+        [CoalescedWrite {
+            addr: GuestPhysAddr::from(0xe000),
+            width: AccessWidth::Word,
+            val: 5,
+        }]
+    );
+}
+
+static FAKE_NOW: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+fn fake_now() -> u64 {
+    FAKE_NOW.load(core::sync::atomic::Ordering::Relaxed)
+}
+
+#[test]
+fn test_device_stats() {
+    let device = StatsDevice::new(DwordRegs(spin::Mutex::new([0; 2])))
+        .with_region(GuestPhysAddrRange::from_start_size(0x9004.into(), 4));
+    let journaled = JournaledDevice::new(device, 4);
+    assert!(DeviceA.stats().is_none());
+
+    let clock = Arc::new(FakeClock::default());
+    journaled.set_clock_source(clock.clone());
+    *clock.0.lock() = 100;
+    journaled
+        .handle_write(0x9000.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    *clock.0.lock() = 200;
+    journaled
+        .handle_read(0x9004.into(), AccessWidth::Dword)
+        .unwrap();
+
+    let stats = journaled.stats().unwrap();
+    assert_eq!(
+        (
+            stats.total.reads,
+            stats.total.writes,
+            stats.total.bytes_written
+        ),
+        (1, 1, 4)
+    );
+    assert_eq!(stats.total.last_access_ns, Some(200_000_000));
+    let (_, region) = stats.regions[0];
+    assert_eq!((region.reads, region.writes), (1, 0));
+
+    journaled.inner().clear();
+    assert_eq!(journaled.stats().unwrap().total, AccessStats::default());
+}
+
+#[derive(Default)]
+struct RecordingTracer(spin::Mutex<Vec<u8>>);
+
+impl DeviceTracer<GuestPhysAddrRange> for RecordingTracer {
+    fn on_access(
+        &self,
+        _device: &dyn BaseDeviceOps<GuestPhysAddrRange>,
+        addr: GuestPhysAddr,
+        width: AccessWidth,
+        value: usize,
+        kind: AccessKind,
+        result: AxResult,
+    ) {
+        let record = TraceRecord {
+            timestamp_ns: fake_now(),
+            addr: addr.as_usize() as u64,
+            value: value as u64,
+            width,
+            kind,
+            error: result.err(),
+        };
+        self.0.lock().extend_from_slice(&record.encode());
+    }
+}
+
+#[test]
+fn test_device_tracer() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    manager
+        .register(Arc::new(DwordRegs(spin::Mutex::new([0; 2]))))
+        .unwrap();
+    let tracer = Arc::new(RecordingTracer::default());
+    manager
+        .handle_read(0x1000.into(), AccessWidth::Dword)
+        .unwrap();
+    manager.set_tracer(Some(tracer.clone()));
+
+    manager
+        .handle_read(0x1008.into(), AccessWidth::Word)
+        .unwrap();
+    manager
+        .handle_write(0x9000.into(), AccessWidth::Dword, 0x55)
+        .unwrap();
+    assert!(
+        manager
+            .handle_read(0x1ffe.into(), AccessWidth::Dword)
+            .is_err()
+    );
+    manager
+        .handle_read_bulk(0x1010.into(), AccessWidth::Byte, 2, 1, &mut [0; 2])
+        .unwrap();
+    manager.set_tracer(None);
+    manager
+        .handle_read(0x1000.into(), AccessWidth::Dword)
+        .unwrap();
+
+    let trace = tracer.0.lock();
+    let records: Vec<_> = trace
+        .chunks(TraceRecord::SIZE)
+        .map(|bytes| TraceRecord::decode(bytes).unwrap())
+        .collect();
+    let summary: Vec<_> = records
+        .iter()
+        .map(|record| (record.kind, record.addr, record.value))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (AccessKind::Read, 0x1008, 0x1008),
+            (AccessKind::Write, 0x9000, 0x55),
+            (AccessKind::Read, 0x1010, 0x10),
+            (AccessKind::Read, 0x1011, 0x11),
+        ]
+    );
+    assert_eq!(records[1].width, AccessWidth::Dword);
+    assert!(records.iter().all(|record| record.error.is_none()));
+    assert!(TraceRecord::decode(&trace[..TraceRecord::SIZE - 1]).is_err());
+    drop(trace);
+
+    // Shadow subscribers only see the accesses within their ranges.
+    let shadow = Arc::new(RecordingTracer::default());
+    let subscriber: Arc<dyn DeviceTracer<GuestPhysAddrRange>> = shadow.clone();
+    let regs = GuestPhysAddrRange::from_start_size(0x9000.into(), 8);
+    manager.add_shadow_subscriber(regs, subscriber.clone());
+    manager
+        .handle_write(0x9004.into(), AccessWidth::Dword, 0x66)
+        .unwrap();
+    manager
+        .handle_read(0x1000.into(), AccessWidth::Dword)
+        .unwrap();
+    manager
+        .handle_read_bulk(0x9000.into(), AccessWidth::Dword, 2, 4, &mut [0; 8])
+        .unwrap();
+    assert!(manager.remove_shadow_subscriber(&subscriber));
+    assert!(!manager.remove_shadow_subscriber(&subscriber));
+    manager
+        .handle_write(0x9000.into(), AccessWidth::Dword, 0)
+        .unwrap();
+    assert_eq!(tracer.0.lock().len(), records.len() * TraceRecord::SIZE);
+
+    let shadowed: Vec<_> = shadow
+        .0
+        .lock()
+        .chunks(TraceRecord::SIZE)
+        .map(|bytes| {
+            let record = TraceRecord::decode(bytes).unwrap();
+            (record.kind, record.addr, record.value)
+        })
+        .collect();
+    assert_eq!(
+        shadowed,
+        [
+            (AccessKind::Write, 0x9004, 0x66),
+            (AccessKind::Read, 0x9000, 0x55),
+            (AccessKind::Read, 0x9004, 0),
+        ]
+    );
+}
+
+#[test]
+fn test_trace_replay() {
+    let manager = DeviceManager::new();
+    manager
+        .register(Arc::new(DwordRegs(spin::Mutex::new([0; 2]))))
+        .unwrap();
+    let recorder = Arc::new(TraceRecorder::new(fake_now));
+    manager.set_tracer(Some(recorder.clone()));
+    manager
+        .handle_write(0x9000.into(), AccessWidth::Dword, 0x1234)
+        .unwrap();
+    manager
+        .handle_read(0x9000.into(), AccessWidth::Dword)
+        .unwrap();
+    manager
+        .handle_read(0x9004.into(), AccessWidth::Dword)
+        .unwrap();
+    let trace = decode_trace(&recorder.encode()).unwrap();
+    assert_eq!(trace, recorder.records());
+    assert_eq!(trace.len(), 3);
+
+    // The same device model reproduces the trace.
+    let fresh = DwordRegs(spin::Mutex::new([0; 2]));
+    assert!(replay(&fresh, &trace).unwrap().is_empty());
+
+    // A behavior change is reported at the first diverging read.
+    let changed = DwordRegs(spin::Mutex::new([0, 7]));
+    let mismatches = replay(&changed, &trace).unwrap();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!((mismatches[0].index, mismatches[0].value), (2, 7));
+
+    assert!(decode_trace(&recorder.encode()[1..]).is_err());
+}
+
+#[test]
+fn test_error_injector() {
+    let device = ErrorInjector::new(DeviceA);
+    device.fail_nth(2, AxError::Io);
+    assert_eq!(
+        device.handle_read(0x1000.into(), AccessWidth::Dword),
+        Ok(0x1000)
+    );
+    assert_eq!(
+        device.handle_write(0x1000.into(), AccessWidth::Dword, 0),
+        Err(AxError::Io)
+    );
+    assert!(
+        device
+            .handle_write(0x1000.into(), AccessWidth::Dword, 0)
+            .is_ok()
+    );
+
+    device.corrupt_reads(0xff);
+    assert_eq!(
+        device.handle_read(0x1000.into(), AccessWidth::Dword),
+        Ok(0x10ff)
+    );
+    device.clear();
+    assert_eq!(
+        device.handle_read(0x1000.into(), AccessWidth::Dword),
+        Ok(0x1000)
+    );
+    assert_eq!(device.accesses(), 5);
+
+    let slow = ErrorInjector::new(SlowDevice::default());
+    let log = Arc::new(CompletionLog::default());
+    slow.set_completer(log.clone());
+    slow.drop_completions(true);
+    slow.handle_read_async(0xd000.into(), AccessWidth::Dword)
+        .unwrap();
+    slow.inner().finish(1);
+    assert!(log.0.lock().is_empty());
+    slow.drop_completions(false);
+    slow.handle_read_async(0xd000.into(), AccessWidth::Dword)
+        .unwrap();
+    slow.inner().finish(2);
+    assert_eq!(log.0.lock().len(), 1);
+}
+
+#[test]
+fn test_mock_device() {
+    use crate::testing::{MockCompleter, MockDevice, assert_read, assert_write};
+
+    let device = MockDevice::new(GuestPhysAddrRange::from_start_size(0xf000.into(), 0x10))
+        .with_register(0x0, 0xaa)
+        .with_read_only(0x8, 0x55);
+    device.script_read(0x0, Err(AxError::Io));
+    device.script_write(0x0, Err(AxError::Io));
+
+    assert_eq!(
+        device.handle_read(0xf000.into(), AccessWidth::Dword),
+        Err(AxError::Io)
+    );
+    assert_read(&device, 0xf000.into(), AccessWidth::Byte, 0xaa);
+    assert!(
+        device
+            .handle_write(0xf000.into(), AccessWidth::Dword, 1)
+            .is_err()
+    );
+    assert_write(&device, 0xf000.into(), AccessWidth::Dword, 2);
+    assert_write(&device, 0xf008.into(), AccessWidth::Dword, 2);
+    assert_eq!(
+        (device.register(0x0), device.register(0x8)),
+        (Some(2), Some(0x55))
+    );
+    assert_eq!(
+        device.handle_read(0xf004.into(), AccessWidth::Dword),
+        Err(AxError::BadAddress)
+    );
+
+    let accesses = device.accesses();
+    assert_eq!(accesses.len(), 6);
+    assert_eq!(
+        (accesses[3].kind, accesses[3].value),
+        (AccessKind::Write, 2)
+    );
+    device.clear_accesses();
+    assert!(device.accesses().is_empty());
+
+    let completer = MockCompleter::new();
+    let token = CompletionToken::next();
+    completer.complete(token, Ok(3));
+    assert_eq!(completer.result_of(token), Some(Ok(3)));
+    assert_eq!(completer.completions().len(), 1);
+}
+
+#[test]
+fn test_fuzz_helpers() {
+    use crate::testing::{FUZZ_ACCESS_SIZE, MockDevice, fuzz_accesses, fuzz_random};
+
+    let device = MockDevice::new(GuestPhysAddrRange::from_start_size(0xf000.into(), 0x10))
+        .with_register(0x0, usize::MAX)
+        .with_register(0x8, 0);
+    let report = fuzz_random(&device, 42, 500);
+    assert_eq!(report.reads + report.writes, 500);
+    assert!(report.errors > 0 && report.errors < 500);
+
+    // An aligned qword write of 0x1122 to offset 8.
+    let mut data = [0; FUZZ_ACCESS_SIZE];
+    data[0] = 0b1111;
+    data[1] = 8;
+    data[5..7].copy_from_slice(&[0x22, 0x11]);
+    let report = fuzz_accesses(&device, &data);
+    assert_eq!((report.writes, report.errors), (1, 0));
+    assert_eq!(device.register(0x8), Some(0x1122));
+}
+
+/// A region change of a device: the new base and size, or `None` when
+/// disabled.
+type RegionChange = (usize, RegionId, Option<(usize, usize)>);
+
+/// Records the region changes reported by devices.
+#[derive(Default)]
+struct RegionLog(spin::Mutex<Vec<RegionChange>>);
+
+impl RegionUpdateHandler for RegionLog {
+    fn remap(&self, device: usize, region: RegionId, new_base: usize, new_size: usize) -> AxResult {
+        self.0
+            .lock()
+            .push((device, region, Some((new_base, new_size))));
+        Ok(())
+    }
+
+    fn enable(&self, _device: usize, _region: RegionId) -> AxResult {
+        Ok(())
+    }
+
+    fn disable(&self, device: usize, region: RegionId) -> AxResult {
+        self.0.lock().push((device, region, None));
+        Ok(())
+    }
+}
+
+/// A device with a relocatable 4K window whose base is written at offset 0.
+struct WindowDevice {
+    base: usize,
+    sink: spin::Mutex<Option<Arc<dyn RegionUpdateSink>>>,
+}
+
+impl WindowDevice {
+    fn new(base: usize) -> Self {
+        Self {
+            base,
+            sink: spin::Mutex::new(None),
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for WindowDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(self.base.into(), 8)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(0)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        let sink = self.sink.lock().clone().ok_or(AxError::BadState)?;
+        let window = RegionId::new(0);
+        if val == 0 {
+            return sink.disable(window);
+        }
+        sink.remap(window, val, 0x1000)?;
+        sink.enable(window)
+    }
+
+    fn set_region_sink(&self, sink: Arc<dyn RegionUpdateSink>) {
+        *self.sink.lock() = Some(sink);
+    }
+}
+
+#[test]
+fn test_region_sink() {
+    let manager = DeviceManager::new();
+    manager
+        .register(Arc::new(WindowDevice::new(0xa000)))
+        .unwrap();
+    let log = Arc::new(RegionLog::default());
+    manager.set_region_handler(log.clone());
+    // Injected at registration, through the wrapper.
+    manager
+        .register(Arc::new(JournaledDevice::new(WindowDevice::new(0xb000), 4)))
+        .unwrap();
+
+    // Both devices report their region 0, told apart by the handler.
+    manager
+        .handle_write(0xb000.into(), AccessWidth::Qword, 0x8000_0000)
+        .unwrap();
+    manager
+        .handle_write(0xa000.into(), AccessWidth::Qword, 0x9000_0000)
+        .unwrap();
+    manager
+        .handle_write(0xb000.into(), AccessWidth::Qword, 0)
+        .unwrap();
+    assert_eq!(
+        *log.0.lock(),
+        [
+            (0xb000, RegionId::new(0), Some((0x8000_0000, 0x1000))),
+            (0xa000, RegionId::new(0), Some((0x9000_0000, 0x1000))),
+            (0xb000, RegionId::new(0), None)
+        ]
+    );
+
+    // Functions behind an ECAM window are told apart by their BDF.
+    let bdf = PciBdf::new(1, 2, 3).unwrap();
+    let id = bdf.region_id(RegionId::new(5));
+    assert_eq!(id, RegionId::new(0x0113_0005));
+    assert_eq!(PciBdf::split_region_id(id), (bdf, RegionId::new(5)));
+}
+
+/// A latch written through port 0x80 and read back through MMIO at 0xf000,
+/// counting its resets.
+#[derive(Default)]
+struct PostCode(spin::Mutex<usize>, core::sync::atomic::AtomicUsize);
+
+impl BaseMultiSpaceDeviceOps for PostCode {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_ranges(&self) -> Vec<UnifiedAddrRange> {
+        vec![
+            PortRange::new(Port::new(0x80), Port::new(0x80)).into(),
+            GuestPhysAddrRange::from_start_size(0xf000.into(), 8).into(),
+        ]
+    }
+
+    fn handle_read(&self, addr: UnifiedAddr, _width: AccessWidth) -> AxResult<usize> {
+        match addr {
+            UnifiedAddr::Mmio(_) => Ok(*self.0.lock()),
+            _ => Err(AxError::Unsupported),
+        }
+    }
+
+    fn handle_write(&self, addr: UnifiedAddr, _width: AccessWidth, val: usize) -> AxResult {
+        match addr {
+            UnifiedAddr::Port(_) => *self.0.lock() = val,
+            _ => return Err(AxError::Unsupported),
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> AxResult {
+        *self.0.lock() = 0;
+        self.1.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn save_state(&self) -> AxResult<Vec<u8>> {
+        Ok(vec![*self.0.lock() as u8])
+    }
+
+    fn load_state(&self, state: &[u8]) -> AxResult {
+        *self.0.lock() = *state.first().ok_or(AxError::InvalidData)? as usize;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_multi_space_device() {
+    let post_code = Arc::new(PostCode::default());
+    let device: Arc<dyn BaseMultiSpaceDeviceOps> = post_code.clone();
+    let mmio = DeviceManager::<GuestPhysAddrRange>::new();
+    let pio = DeviceManager::<PortRange>::new();
+    for view in space_views(&device) {
+        mmio.register(Arc::new(view)).unwrap();
+    }
+    for view in space_views(&device) {
+        pio.register(Arc::new(view)).unwrap();
+    }
+    assert!(space_views::<SysRegAddrRange>(&device).is_empty());
+
+    pio.handle_write(Port::new(0x80), AccessWidth::Byte, 0x2a)
+        .unwrap();
+    assert_eq!(
+        mmio.handle_read(0xf004.into(), AccessWidth::Dword),
+        Ok(0x2a)
+    );
+    assert!(
+        UnifiedAddrRange::from(GuestPhysAddrRange::from_start_size(0xf000.into(), 8))
+            .contains(UnifiedAddr::Mmio(0xf004.into()))
+    );
+    assert!(!device.address_ranges()[0].contains(UnifiedAddr::Mmio(0x80.into())));
+
+    // Lifecycle and snapshot hooks reach the device once, through the view of
+    // its first range.
+    let pio_state = pio.save_all().unwrap();
+    let mmio_state = mmio.save_all().unwrap();
+    assert_eq!(pio_state[0].1, [0x2a]);
+    assert!(mmio_state[0].1.is_empty());
+    mmio.reset_all().unwrap();
+    pio.reset_all().unwrap();
+    assert_eq!(post_code.1.load(core::sync::atomic::Ordering::Relaxed), 1);
+    assert!(space_views::<PortRange>(&device)[0].is_primary());
+    assert_eq!(mmio.handle_read(0xf000.into(), AccessWidth::Byte), Ok(0));
+    mmio.load_all(&mmio_state).unwrap();
+    pio.load_all(&pio_state).unwrap();
+    assert_eq!(mmio.handle_read(0xf000.into(), AccessWidth::Byte), Ok(0x2a));
+    assert_eq!(
+        mmio.load_all(&[(mmio_state[0].0, vec![1])]),
+        Err(AxError::InvalidData)
+    );
+}
+
+/// A minimal PSCI implementation answering `PSCI_VERSION` and
+/// `CPU_SUSPEND`.
+struct Psci;
+
+impl BaseDeviceOps<HypercallRange> for Psci {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> HypercallRange {
+        HypercallRange::new(HypercallId(0x8400_0000), HypercallId(0x8400_001f))
+    }
+
+    fn handle_read(&self, _addr: HypercallId, _width: AccessWidth) -> AxResult<usize> {
+        Err(AxError::Unsupported)
+    }
+
+    fn handle_write(&self, _addr: HypercallId, _width: AccessWidth, _val: usize) -> AxResult {
+        Err(AxError::Unsupported)
+    }
+
+    fn handle_call(&self, addr: HypercallId, args: &[usize]) -> AxResult<[usize; 4]> {
+        match addr.0 {
+            0x8400_0000 => Ok([0x1_0001, 0, 0, 0]),
+            0x8400_0001 => Ok([args[0], 0, 0, 0]),
+            _ => Err(AxError::Unsupported),
+        }
+    }
+}
+
+#[test]
+fn test_hypercall_device() {
+    let firmware = DeviceManager::new();
+    firmware.register(Arc::new(Psci)).unwrap();
+    assert_eq!(
+        firmware.handle_call(HypercallId(0x8400_0000), &[]),
+        Ok([0x1_0001, 0, 0, 0])
+    );
+    assert_eq!(
+        firmware.handle_call(HypercallId(0x8400_0001), &[7]),
+        Ok([7, 0, 0, 0])
+    );
+    assert_eq!(
+        firmware.handle_call(HypercallId(0xc400_0001), &[]),
+        Err(AxError::NotFound)
+    );
+
+    // Devices of other address spaces do not handle calls.
+    assert_eq!(
+        DeviceA.handle_call(0x1000.into(), &[]),
+        Err(AxError::Unsupported)
+    );
+}
+
+/// A 32-line interrupt controller delivering everything to vCPU 0, with
+/// level-triggered lines.
+#[derive(Default)]
+struct TinyIrqChip {
+    lines: spin::Mutex<u32>,
+    active: spin::Mutex<u32>,
+}
+
+impl VirtualIrqChip for TinyIrqChip {
+    fn inject(&self, irq: usize, level: bool) -> AxResult {
+        if irq >= 32 {
+            return Err(AxError::InvalidInput);
+        }
+        let mut lines = self.lines.lock();
+        *lines = (*lines & !(1 << irq)) | ((level as u32) << irq);
+        Ok(())
+    }
+
+    fn eoi(&self, irq: usize) -> AxResult {
+        *self.active.lock() &= !(1 << irq);
+        Ok(())
+    }
+
+    fn set_affinity(&self, irq: usize, vcpu: usize) -> AxResult {
+        if irq >= 32 || vcpu != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        Ok(())
+    }
+
+    fn pending(&self, vcpu: usize) -> Option<usize> {
+        let pending = *self.lines.lock() & !*self.active.lock();
+        (vcpu == 0 && pending != 0).then(|| pending.trailing_zeros() as usize)
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for TinyIrqChip {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x9000.into(), 8)
+    }
+
+    /// Reading the acknowledge register activates the pending interrupt.
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        let irq = self.pending(0).ok_or(AxError::NotFound)?;
+        *self.active.lock() |= 1 << irq;
+        Ok(irq)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        self.eoi(val)
+    }
+
+    fn as_irq_chip(&self) -> Option<&dyn VirtualIrqChip> {
+        Some(self)
+    }
+}
+
+#[test]
+fn test_irq_chip() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    assert!(manager.irq_chip().is_none());
+    manager
+        .register(Arc::new(StatsDevice::new(TinyIrqChip::default())))
+        .unwrap();
+
+    let device = manager.irq_chip().unwrap();
+    let chip = device.as_irq_chip().unwrap();
+    assert_eq!(chip.inject(40, true), Err(AxError::InvalidInput));
+    chip.inject(5, true).unwrap();
+    chip.inject(3, true).unwrap();
+    assert_eq!(chip.pending(0), Some(3));
+    assert_eq!(chip.pending(1), None);
+
+    // The guest acknowledges and completes interrupt 3.
+    assert_eq!(
+        manager.handle_read(0x9000.into(), AccessWidth::Dword),
+        Ok(3)
+    );
+    assert_eq!(chip.pending(0), Some(5));
+    chip.inject(3, false).unwrap();
+    manager
+        .handle_write(0x9004.into(), AccessWidth::Dword, 3)
+        .unwrap();
+    assert_eq!(chip.pending(0), Some(5));
+}
+
+#[test]
+fn test_irq_routing_table() {
+    let configs = [
+        EmulatedDeviceConfig {
+            name: "uart0".into(),
+            irq_id: 33,
+            ..Default::default()
+        },
+        EmulatedDeviceConfig {
+            name: "rtc".into(),
+            ..Default::default()
+        },
+    ];
+    let mut table = IrqRoutingTable::from_configs(&configs);
+    assert_eq!(table.routes().len(), 1);
+    assert_eq!(table.route("uart0", 0), Some(IrqTarget::Gsi(33)));
+    assert_eq!(table.route("rtc", 0), None);
+
+    // A second device sharing the UART interrupt.
+    table
+        .add(IrqRoute {
+            device: "uart1".into(),
+            line: 0,
+            target: IrqTarget::Gsi(33),
+            masked: false,
+        })
+        .unwrap();
+    assert_eq!(
+        table.sources(IrqTarget::Gsi(33)).collect::<Vec<_>>(),
+        [("uart0", 0), ("uart1", 0)]
+    );
+    let route = table.routes()[1].clone();
+    assert_eq!(table.add(route), Err(AxError::AlreadyExists));
+
+    // Remapping to MSI keeps the mask.
+    let msi = IrqTarget::Msi(MsiMessage {
+        address: 0xfee0_0000,
+        data: 0x41,
+    });
+    table.set_masked("uart1", 0, true).unwrap();
+    assert_eq!(table.remap("uart1", 0, msi), Ok(IrqTarget::Gsi(33)));
+    assert_eq!(table.route("uart1", 0), None);
+    table.set_masked("uart1", 0, false).unwrap();
+    assert_eq!(table.route("uart1", 0), Some(msi));
+    assert_eq!(table.remap("rtc", 0, msi), Err(AxError::NotFound));
+    assert!(table.remove("uart0", 0).is_some());
+    assert_eq!(table.route("uart0", 0), None);
+}
+
+#[test]
+fn test_typed_config_params() {
+    let mut config = EmulatedDeviceConfig {
+        name: "virtio-net0".into(),
+        cfg_list: vec![128, 2],
+        ..Default::default()
+    };
+    config
+        .params
+        .insert("queue_size".into(), ConfigValue::Int(256));
+    config
+        .params
+        .insert("backend".into(), ConfigValue::Str("tap0".into()));
+    config.params.insert("offset".into(), ConfigValue::Int(-4));
+    config.params.insert(
+        "macs".into(),
+        ConfigValue::Array(vec![ConfigValue::Int(0x52), ConfigValue::Int(0x54)]),
+    );
+
+    assert_eq!(config.get_u64("queue_size"), Some(256));
+    assert_eq!(config.get_str("backend"), Some("tap0"));
+    assert_eq!(config.get_u64("offset"), None);
+    assert_eq!(config.get_i64("offset"), Some(-4));
+    assert_eq!(config.get_array("macs").map(<[_]>::len), Some(2));
+    assert_eq!(config.get_bool("backend"), None);
+    assert_eq!(config.get_u64("missing"), None);
+
+    // Explicit parameters take precedence over the legacy list.
+    config.import_cfg_list(&["queue_size", "num_queues", "mtu"]);
+    assert_eq!(config.get_u64("queue_size"), Some(256));
+    assert_eq!(config.get_u64("num_queues"), Some(2));
+    assert_eq!(config.get_u64("mtu"), None);
+}
+
+/// A UART whose only register, at its base address, reads the depth of its
+/// receive FIFO.
+struct FifoUart {
+    base: usize,
+    fifo_depth: usize,
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for FifoUart {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Console
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(self.base.into(), 0x1000)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(self.fifo_depth)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn manifest(&self) -> DeviceManifest {
+        DeviceManifest::new("16550 UART")
+            .with_guest_profile(GuestProfile::Linux)
+            .with_guest_profile(GuestProfile::Zephyr)
+    }
+}
+
+#[test]
+fn test_guest_profile() {
+    // Minimal RTOS drivers expect a character-at-a-time UART, unless the
+    // configuration asks otherwise.
+    let registry = DeviceRegistry::new();
+    registry
+        .register(
+            EmuDeviceType::Console,
+            Arc::new(
+                |cfg: &EmulatedDeviceConfig, _: &DeviceDeps| -> AxResult<MmioDevice> {
+                    let default_depth = match cfg.guest_profile {
+                        GuestProfile::Linux | GuestProfile::Windows => 16,
+                        GuestProfile::Zephyr | GuestProfile::RtosGeneric => 1,
+                    };
+                    Ok(Arc::new(FifoUart {
+                        base: cfg.base_ipa,
+                        fifo_depth: cfg
+                            .get_u64("fifo-depth")
+                            .map_or(default_depth, |d| d as usize),
+                    }))
+                },
+            ),
+        )
+        .unwrap();
+
+    let mut cfg = EmulatedDeviceConfig {
+        name: "uart0".into(),
+        base_ipa: 0x9000,
+        emu_type: EmuDeviceType::Console as usize,
+        ..Default::default()
+    };
+    assert_eq!(cfg.guest_profile, GuestProfile::Linux);
+    let depth = |cfg: &EmulatedDeviceConfig| {
+        registry
+            .create(cfg, &DeviceDeps::new())
+            .unwrap()
+            .handle_read(0x9000.into(), AccessWidth::Byte)
+            .unwrap()
+    };
+    assert_eq!(depth(&cfg), 16);
+    cfg.guest_profile = GuestProfile::Zephyr;
+    assert_eq!(depth(&cfg), 1);
+    cfg.params.insert("fifo-depth".into(), ConfigValue::Int(64));
+    assert_eq!(depth(&cfg), 64);
+
+    let manifest = registry
+        .create(&cfg, &DeviceDeps::new())
+        .unwrap()
+        .manifest();
+    assert_eq!(
+        manifest.guest_profiles,
+        [GuestProfile::Linux, GuestProfile::Zephyr]
+    );
+}
+
+#[test]
+fn test_config_regions() {
+    let mut config = EmulatedDeviceConfig {
+        name: "host-bridge".into(),
+        base_ipa: 0x3000_0000,
+        length: 0x1000_0000,
+        regions: vec![RegionConfig {
+            base: 0xcf8,
+            size: 8,
+            space: RegionSpace::Port,
+            access: RegionAccess::ReadWrite,
+        }],
+        ..Default::default()
+    };
+    assert_eq!(
+        config.address_ranges(),
+        Ok(vec![
+            GuestPhysAddrRange::from_start_size(0x3000_0000.into(), 0x1000_0000).into(),
+            PortRange::new(Port::new(0xcf8), Port::new(0xcff)).into(),
+        ])
+    );
+
+    config.length = 0;
+    config.regions[0].base = 0xfffc;
+    assert_eq!(config.address_ranges(), Err(AxError::InvalidInput));
+    config.regions[0].size = 0;
+    assert_eq!(config.address_ranges(), Err(AxError::InvalidInput));
+}
+
+/// A virtio-mmio device requiring a 512-byte, page aligned window and a
+/// backend.
+struct VirtioBlkConfig;
+
+impl ValidateConfig for VirtioBlkConfig {
+    fn validate_config(cfg: &EmulatedDeviceConfig) -> Result<(), ConfigError> {
+        cfg.check_length(0x200)?;
+        cfg.check_base_aligned(0x1000)?;
+        cfg.check_irq(32..=1019)?;
+        cfg.check_regions()?;
+        cfg.require_param("backend")
+    }
+}
+
+#[test]
+fn test_validate_config() {
+    let mut cfg = EmulatedDeviceConfig {
+        name: "virtio-blk0".into(),
+        base_ipa: 0x0a00_0200,
+        irq_id: 48,
+        ..Default::default()
+    };
+    assert_eq!(
+        VirtioBlkConfig::validate_config(&cfg),
+        Err(ConfigError::ZeroLength)
+    );
+    cfg.length = 0x200;
+    assert_eq!(
+        VirtioBlkConfig::validate_config(&cfg),
+        Err(ConfigError::MisalignedBase {
+            base: 0x0a00_0200,
+            align: 0x1000
+        })
+    );
+    cfg.base_ipa = 0x0a00_0000;
+    cfg.regions.push(RegionConfig {
+        base: 0,
+        size: 0,
+        space: RegionSpace::Mmio,
+        access: RegionAccess::ReadOnly,
+    });
+    assert_eq!(
+        VirtioBlkConfig::validate_config(&cfg),
+        Err(ConfigError::BadRegion { index: 0 })
+    );
+    cfg.regions.clear();
+    let err = VirtioBlkConfig::validate_config(&cfg).unwrap_err();
+    assert_eq!(alloc::format!("{err}"), "missing parameter `backend`");
+    assert_eq!(AxError::from(err), AxError::InvalidInput);
+
+    cfg.params
+        .insert("backend".into(), ConfigValue::Str("disk0".into()));
+    assert_eq!(VirtioBlkConfig::validate_config(&cfg), Ok(()));
+}
+
+/// Creates [`TrngDevice`]s fed by the entropy source named by the `backend`
+/// parameter.
+struct TrngFactory;
+
+impl DeviceFactory for TrngFactory {
+    fn create(&self, cfg: &EmulatedDeviceConfig, deps: &DeviceDeps) -> AxResult<MmioDevice> {
+        let source = deps.backend::<dyn EntropySource>(cfg.get_str("backend").unwrap())?;
+        let burst = cfg.get_u64("burst").unwrap_or(4) as usize;
+        Ok(Arc::new(TrngDevice::new(
+            cfg.base_ipa.into(),
+            source,
+            burst,
+        )))
+    }
+
+    fn validate(&self, cfg: &EmulatedDeviceConfig) -> Result<(), ConfigError> {
+        cfg.require_param("backend")
+    }
+}
+
+#[test]
+fn test_device_registry() {
+    let registry = DeviceRegistry::new();
+    registry
+        .register(EmuDeviceType::Console, Arc::new(TrngFactory))
+        .unwrap();
+    registry
+        .register(
+            EmuDeviceType::Dummy,
+            Arc::new(
+                |_: &EmulatedDeviceConfig, _: &DeviceDeps| -> AxResult<MmioDevice> {
+                    Ok(Arc::new(DeviceA))
+                },
+            ),
+        )
+        .unwrap();
+    assert_eq!(
+        registry
+            .register(EmuDeviceType::Dummy, Arc::new(TrngFactory))
+            .unwrap_err(),
+        AxError::AlreadyExists
+    );
+
+    let deps = DeviceDeps::new()
+        .with_backend::<dyn EntropySource>("hwrng", Arc::new(CountingSource))
+        .with_backend::<dyn PersistentStore>(
+            "nvram",
+            Arc::new(MemoryStore(spin::Mutex::new(Vec::new()))),
+        );
+    let mut trng = EmulatedDeviceConfig {
+        name: "trng".into(),
+        base_ipa: 0x3000,
+        emu_type: EmuDeviceType::Console as usize,
+        ..Default::default()
+    };
+    assert_eq!(
+        registry.create(&trng, &deps).err(),
+        Some(AxError::InvalidInput)
+    );
+    trng.params
+        .insert("backend".into(), ConfigValue::Str("nvram".into()));
+    assert_eq!(
+        registry.create(&trng, &deps).err(),
+        Some(AxError::InvalidInput)
+    );
+    trng.params
+        .insert("backend".into(), ConfigValue::Str("hwrng".into()));
+
+    let dummy = EmulatedDeviceConfig::default();
+    let devices = registry.create_all(&[trng, dummy], &deps).unwrap();
+    assert_eq!(devices[0].address_range().start, 0x3000.into());
+    assert_eq!(
+        devices[1].handle_read(0x1008.into(), AccessWidth::Byte),
+        Ok(0x1008)
+    );
+    assert_eq!(deps.dma().err(), Some(AxError::NotFound));
+    assert_eq!(
+        DeviceRegistry::new()
+            .create(&EmulatedDeviceConfig::default(), &deps)
+            .err(),
+        Some(AxError::NotFound)
+    );
+}
+
+#[cfg(feature = "static-registry")]
+fn create_dummy(_cfg: &EmulatedDeviceConfig, _deps: &DeviceDeps) -> AxResult<MmioDevice> {
+    Ok(Arc::new(DeviceA))
+}
+
+#[cfg(feature = "static-registry")]
+fn validate_dummy(cfg: &EmulatedDeviceConfig) -> Result<(), ConfigError> {
+    cfg.check_length(0x1000)
+}
+
+#[cfg(feature = "static-registry")]
+crate::register_device_type!(EmuDeviceType::Dummy, create_dummy, validate_dummy);
+
+#[cfg(feature = "static-registry")]
+#[test]
+fn test_static_device_registration() {
+    let registry = DeviceRegistry::new();
+    registry.register_static().unwrap();
+    assert!(registry.factory(EmuDeviceType::Dummy).is_some());
+    assert_eq!(registry.register_static(), Err(AxError::AlreadyExists));
+
+    let mut cfg = EmulatedDeviceConfig::default();
+    assert_eq!(
+        registry.create(&cfg, &DeviceDeps::new()).err(),
+        Some(AxError::InvalidInput)
+    );
+    cfg.length = 0x1000;
+    let device = registry.create(&cfg, &DeviceDeps::new()).unwrap();
+    assert_eq!(device.emu_type(), EmuDeviceType::Dummy);
+}
+
+#[test]
+fn test_register_dump() {
+    use crate::testing::MockDevice;
+
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    let mock = MockDevice::new(GuestPhysAddrRange::from_start_size(0x2000.into(), 0x10))
+        .with_register(0x0, 0x41)
+        .with_read_only(0x8, 0xdead);
+    manager
+        .register(Arc::new(JournaledDevice::new(mock, 4)))
+        .unwrap();
+
+    let mut out = alloc::string::String::new();
+    manager.dump(&mut out).unwrap();
+    assert_eq!(
+        out.lines().collect::<Vec<_>>(),
+        [
+            "Dummy [0x1000, 0x2000):",
+            "Dummy [0x2000, 0x2010):",
+            "  mock             +0x000 = 0x0000000000000041",
+            "  mock             +0x008 = 0x000000000000dead",
+        ]
+    );
+}
+
+#[test]
+fn test_peek_poke() {
+    use crate::testing::MockDevice;
+
+    let mock = MockDevice::new(GuestPhysAddrRange::from_start_size(0x2000.into(), 0x10))
+        .with_read_only(0x0, 0x1234_5678);
+    mock.script_read(0x0, Ok(0));
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    manager
+        .register(Arc::new(NaturalWidthAdapter::new(mock, AccessWidth::Dword)))
+        .unwrap();
+
+    // Debugger accesses neither consume the scripted read nor get recorded.
+    assert_eq!(manager.peek(0x2002.into(), AccessWidth::Word), Ok(0x1234));
+    manager
+        .poke(0x2000.into(), AccessWidth::Byte, 0xaa)
+        .unwrap();
+    assert_eq!(
+        manager.peek(0x2000.into(), AccessWidth::Dword),
+        Ok(0x1234_56aa)
+    );
+    assert_eq!(
+        manager.handle_read(0x2000.into(), AccessWidth::Dword),
+        Ok(0)
+    );
+    let device = manager.find(0x2000.into()).unwrap();
+    assert!(device.supports(Capability::Peek));
+    let accesses = map_device_of_type(
+        &device,
+        |adapter: &NaturalWidthAdapter<_, MockDevice<_>>| adapter.inner().accesses().len(),
+    );
+    assert_eq!(accesses, Some(1));
+
+    assert_eq!(
+        manager.peek(0x1000.into(), AccessWidth::Dword),
+        Err(AxError::Unsupported)
+    );
+}
+
+/// A device with a single register, supporting D0 and D3hot.
+#[derive(Default)]
+struct SleepyDevice {
+    state: spin::Mutex<PowerState>,
+    reg: spin::Mutex<usize>,
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for SleepyDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x4000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(*self.reg.lock())
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        *self.reg.lock() = val;
+        Ok(())
+    }
+
+    fn power_state(&self) -> PowerState {
+        *self.state.lock()
+    }
+
+    fn set_power_state(&self, state: PowerState) -> AxResult {
+        match state {
+            PowerState::D0 | PowerState::D3Hot => {
+                *self.state.lock() = state;
+                Ok(())
+            }
+            _ => Err(AxError::Unsupported),
+        }
+    }
+}
+
+#[test]
+fn test_capabilities() {
+    let mut caps = CapabilitySet::empty();
+    assert!(caps.is_empty());
+    caps.insert(Capability::HotUnplug);
+    let caps = caps | Capability::Snapshot | (Capability::Reset | Capability::Peek);
+    assert!(caps.contains(Capability::Reset));
+    assert!(!caps.contains(Capability::AsyncIo));
+    // Capabilities are listed in declaration order, whatever the insertion
+    // order.
+    assert_eq!(
+        caps.iter().collect::<Vec<_>>(),
+        [
+            Capability::Snapshot,
+            Capability::Reset,
+            Capability::HotUnplug,
+            Capability::Peek
+        ]
+    );
+    assert_eq!(caps.iter().collect::<CapabilitySet>(), caps);
+
+    // Capabilities are discovered through wrappers, so that the hypervisor
+    // can pick the devices taking part in a subsystem after registration.
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    manager
+        .register(Arc::new(StatsDevice::new(JournaledDevice::new(
+            SavedReg(0.into()),
+            8,
+        ))))
+        .unwrap();
+    let snapshotting: Vec<_> = manager
+        .devices()
+        .into_iter()
+        .filter(|device| device.supports(Capability::Snapshot))
+        .map(|device| device.address_range())
+        .collect();
+    assert_eq!(snapshotting, [SavedReg(0.into()).address_range()]);
+    assert!(DeviceA.capabilities().is_empty());
+}
+
+#[test]
+fn test_power_states() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    manager
+        .register(Arc::new(StatsDevice::new(SleepyDevice::default())))
+        .unwrap();
+    assert_eq!(
+        DeviceA.set_power_state(PowerState::D3Hot),
+        Err(AxError::Unsupported)
+    );
+
+    let device = manager.find(0x4000.into()).unwrap();
+    manager
+        .handle_write(0x4000.into(), AccessWidth::Dword, 7)
+        .unwrap();
+    device.set_power_state(PowerState::D3Hot).unwrap();
+    assert_eq!(device.power_state(), PowerState::D3Hot);
+
+    // Accesses are dispatched by default.
+    assert_eq!(
+        manager.handle_read(0x4000.into(), AccessWidth::Dword),
+        Ok(7)
+    );
+
+    manager.set_low_power_access(LowPowerAccess::Refuse);
+    assert_eq!(
+        manager.handle_read(0x4000.into(), AccessWidth::Dword),
+        Err(AxError::BadState)
+    );
+    assert_eq!(
+        manager.handle_read(0x1000.into(), AccessWidth::Dword),
+        Ok(0x1000)
+    );
+
+    manager.set_low_power_access(LowPowerAccess::AllOnes);
+    manager
+        .handle_write(0x4000.into(), AccessWidth::Dword, 9)
+        .unwrap();
+    assert_eq!(
+        manager.handle_read(0x4000.into(), AccessWidth::Word),
+        Ok(0xffff)
+    );
+    let mut buf = [0; 4];
+    manager
+        .handle_read_bulk(0x4000.into(), AccessWidth::Byte, 4, 1, &mut buf)
+        .unwrap();
+    assert_eq!(buf, [0xff; 4]);
+
+    device.set_power_state(PowerState::D0).unwrap();
+    assert_eq!(
+        manager.handle_read(0x4000.into(), AccessWidth::Dword),
+        Ok(7)
+    );
+}
+
+#[derive(Default)]
+struct TimerLog(spin::Mutex<Vec<(core::time::Duration, TimerToken)>>);
+
+impl TimerService for TimerLog {
+    fn schedule_after(&self, delay: core::time::Duration, token: TimerToken) -> AxResult {
+        let mut pending = self.0.lock();
+        pending.retain(|(_, pending)| *pending != token);
+        pending.push((delay, token));
+        Ok(())
+    }
+
+    fn cancel(&self, token: TimerToken) -> bool {
+        let mut pending = self.0.lock();
+        let len = pending.len();
+        pending.retain(|(_, pending)| *pending != token);
+        pending.len() != len
+    }
+}
+
+/// A watchdog at 0x5000 that expires 10ms after its last kick.
+struct Watchdog {
+    token: TimerToken,
+    timers: spin::Mutex<Option<Arc<dyn TimerService>>>,
+    expired: spin::Mutex<bool>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            token: TimerToken::next(),
+            timers: spin::Mutex::new(None),
+            expired: spin::Mutex::new(false),
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for Watchdog {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x5000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        Ok(*self.expired.lock() as usize)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        match self.timers.lock().as_ref() {
+            Some(timers) => {
+                timers.schedule_after(core::time::Duration::from_millis(10), self.token)
+            }
+            None => Err(AxError::BadState),
+        }
+    }
+
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        *self.timers.lock() = Some(service);
+    }
+
+    fn on_timer(&self, token: TimerToken) -> bool {
+        if token != self.token {
+            return false;
+        }
+        *self.expired.lock() = true;
+        true
+    }
+}
+
+#[test]
+fn test_timer_service() {
+    let timers = Arc::new(TimerLog::default());
+    let manager = DeviceManager::new();
+    manager.set_timer_service(timers.clone());
+    manager.register(Arc::new(DeviceA)).unwrap();
+    manager
+        .register(Arc::new(JournaledDevice::new(Watchdog::default(), 4)))
+        .unwrap();
+
+    // Kicking the watchdog twice leaves a single pending timer.
+    manager
+        .handle_write(0x5000.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    manager
+        .handle_write(0x5000.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    let pending = timers.0.lock().clone();
+    assert_eq!(pending.len(), 1);
+    let (delay, token) = pending[0];
+    assert_eq!(delay, core::time::Duration::from_millis(10));
+
+    assert!(!manager.fire_timer(TimerToken::next()));
+    assert_eq!(
+        manager.handle_read(0x5000.into(), AccessWidth::Dword),
+        Ok(0)
+    );
+    assert!(manager.fire_timer(token));
+    assert_eq!(
+        manager.handle_read(0x5000.into(), AccessWidth::Dword),
+        Ok(1)
+    );
+    assert!(timers.cancel(token));
+    assert!(!timers.cancel(token));
+}
+
+/// A host clock ticking at 1 kHz, started at 1_000_000 seconds after the epoch.
+#[derive(Default)]
+struct FakeClock(spin::Mutex<u64>);
+
+impl ClockSource for FakeClock {
+    fn ticks(&self) -> u64 {
+        *self.0.lock()
+    }
+
+    fn frequency(&self) -> u64 {
+        1000
+    }
+
+    fn wall_time(&self) -> core::time::Duration {
+        core::time::Duration::from_secs(1_000_000) + core::time::Duration::from_millis(self.ticks())
+    }
+}
+
+/// An RTC at 0x6000 whose only register holds the wall-clock time in seconds.
+#[derive(Default)]
+struct Rtc(spin::Mutex<Option<Arc<dyn ClockSource>>>);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for Rtc {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x6000.into(), 4)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        let clock = self.0.lock().clone().ok_or(AxError::BadState)?;
+        Ok(clock.wall_time().as_secs() as usize)
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        *self.0.lock() = Some(clock);
+    }
+}
+
+#[test]
+fn test_clock_source() {
+    let host = Arc::new(FakeClock::default());
+    let guest = Arc::new(GuestClock::new(host.clone()));
+    let manager = DeviceManager::new();
+    manager
+        .register(Arc::new(StatsDevice::new(Rtc::default())))
+        .unwrap();
+    assert_eq!(
+        manager.handle_read(0x6000.into(), AccessWidth::Dword),
+        Err(AxError::BadState)
+    );
+    manager.set_clock_source(guest.clone());
+
+    *host.0.lock() = 2500;
+    assert_eq!(guest.monotonic(), core::time::Duration::from_millis(2500));
+    assert_eq!(
+        manager.handle_read(0x6000.into(), AccessWidth::Dword),
+        Ok(1_000_002)
+    );
+
+    // Setting the guest time moves the wall clock but not the ticks.
+    guest.set_wall_time(core::time::Duration::from_secs(500));
+    assert_eq!(guest.offset_ns(), -999_502_500_000_000);
+    assert_eq!(
+        manager.handle_read(0x6000.into(), AccessWidth::Dword),
+        Ok(500)
+    );
+    *host.0.lock() = 4500;
+    assert_eq!(
+        manager.handle_read(0x6000.into(), AccessWidth::Dword),
+        Ok(502)
+    );
+    assert_eq!(guest.ticks(), 4500);
+
+    // A device refused at registration gets no services.
+    let refused = Arc::new(Rtc::default());
+    assert_eq!(
+        manager.register(refused.clone()),
+        Err(AxError::AlreadyExists)
+    );
+    assert!(refused.0.lock().is_none());
+}
+
+#[test]
+fn test_pvclock() {
+    let clock = Arc::new(FakeClock::default());
+    let mem = Arc::new(TestMemory::new(0x1000));
+    let device = PvClockDevice::new(0x7000.into());
+    let page = GuestPhysAddr::from(TestMemory::BASE + 0x40);
+    // Nothing is published before the guest enables the clock.
+    assert_eq!(device.update(), Ok(()));
+    device
+        .handle_write(0x7004.into(), AccessWidth::Dword, 0)
+        .unwrap();
+    assert_eq!(
+        device.handle_write(0x7000.into(), AccessWidth::Dword, page.as_usize() | 1),
+        Err(AxError::BadState)
+    );
+    device.set_clock_source(clock.clone());
+    device.set_dma_accessor(mem.clone());
+
+    *clock.0.lock() = 100;
+    device
+        .handle_write(0x7000.into(), AccessWidth::Dword, page.as_usize() | 1)
+        .unwrap();
+    assert_eq!(device.page(), Some(page));
+    assert_eq!(
+        device.handle_read(0x7000.into(), AccessWidth::Dword),
+        Ok(page.as_usize() | 1)
+    );
+    let info = PvClockInfo::read(&*mem, page).unwrap();
+    assert_eq!(info.version, 2);
+    assert_eq!(info.tsc_timestamp, 100);
+    assert_eq!(info.system_time, 100_000_000);
+    assert_eq!(info.flags, PvClockInfo::FLAG_STABLE);
+    // The guest computes the time from its own counter reads.
+    assert_eq!(info.ticks_to_ns(150), 150_000_000);
+    assert_eq!(info.ticks_to_ns(1_100), 1_100_000_000);
+    for frequency in [24_000_000, 3_000_000_000] {
+        let ns = PvClockInfo::new(0, frequency, 0).ticks_to_ns(frequency * 10);
+        assert!(ns.abs_diff(10_000_000_000) < 10, "{frequency} Hz: {ns}");
+    }
+
+    // Re-anchoring bumps the version by a full seqlock cycle.
+    *clock.0.lock() = 5_000;
+    device.update().unwrap();
+    let info = PvClockInfo::read(&*mem, page).unwrap();
+    assert_eq!(info.version, 4);
+    assert_eq!(info.ticks_to_ns(5_250), 5_250_000_000);
+
+    // A reader racing with an update is told to retry.
+    mem.write_u32(page, 5).unwrap();
+    assert_eq!(PvClockInfo::read(&*mem, page), Err(AxError::WouldBlock));
+
+    // Disabling the clock stops updates.
+    device.reset().unwrap();
+    assert_eq!(device.page(), None);
+    device.update().unwrap();
+    assert_eq!(mem.read_u32(page), Ok(5));
+}
+
+#[test]
+fn test_throttled_device() {
+    // Two accesses per second, in bursts of up to 3.
+    let device = ThrottledDevice::new(DeviceA, 2, 3);
+    let clock = Arc::new(FakeClock::default());
+    device.set_clock_source(clock.clone());
+    let addr = GuestPhysAddr::from(0x1000);
+    for _ in 0..3 {
+        assert_eq!(device.handle_read(addr, AccessWidth::Dword), Ok(0x1000));
+    }
+    assert_eq!(
+        device.handle_read(addr, AccessWidth::Dword),
+        Err(AxError::WouldBlock)
+    );
+    assert_eq!(
+        device.handle_write(addr, AccessWidth::Dword, 0),
+        Err(AxError::WouldBlock)
+    );
+    assert_eq!(
+        device.peek(addr, AccessWidth::Dword),
+        Err(AxError::Unsupported)
+    );
+    assert_eq!(device.throttled(), 2);
+
+    // Half a second later, one token has been refilled.
+    *clock.0.lock() = 500;
+    assert_eq!(device.handle_read(addr, AccessWidth::Dword), Ok(0x1000));
+    assert_eq!(
+        device.handle_read(addr, AccessWidth::Dword),
+        Err(AxError::WouldBlock)
+    );
+
+    let device = ThrottledDevice::new(DeviceA, 1, 1)
+        .with_response(ThrottleResponse::Drop)
+        .writes_only();
+    device.handle_write(addr, AccessWidth::Dword, 0).unwrap();
+    device.handle_write(addr, AccessWidth::Dword, 0).unwrap();
+    assert_eq!(device.throttled(), 1);
+    for _ in 0..4 {
+        assert_eq!(device.handle_read(addr, AccessWidth::Dword), Ok(0x1000));
+    }
+    assert_eq!(device.handle_call(addr, &[]), Ok([0; 4]));
+
+    // Coalesced doorbell writes take tokens too.
+    let manager = DeviceManager::new();
+    manager
+        .register(Arc::new(ThrottledDevice::new(Doorbell::new(), 1, 1)))
+        .unwrap();
+    manager
+        .handle_write(0xe000.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    assert_eq!(
+        manager.handle_write(0xe000.into(), AccessWidth::Dword, 2),
+        Err(AxError::WouldBlock)
+    );
+}
+
+#[test]
+fn test_permission_checked_device() {
+    let config = EmulatedDeviceConfig {
+        regions: vec![
+            RegionConfig {
+                base: 0x1000,
+                size: 0x100,
+                space: RegionSpace::Mmio,
+                access: RegionAccess::ReadOnly,
+            },
+            RegionConfig {
+                base: 0x60,
+                size: 1,
+                space: RegionSpace::Port,
+                access: RegionAccess::ReadOnly,
+            },
+        ],
+        ..Default::default()
+    };
+    let device = PermissionCheckedDevice::new(DeviceA)
+        .with_config(&config)
+        .unwrap()
+        .with_region(
+            GuestPhysAddrRange::from_start_size(0x1100.into(), 0x100),
+            RegionAccess::WriteOnly,
+        );
+    assert_eq!(
+        device.access_of(0x1000.into()),
+        Some(RegionAccess::ReadOnly)
+    );
+    assert_eq!(device.access_of(0x1200.into()), None);
+
+    assert_eq!(
+        device.handle_read(0x1010.into(), AccessWidth::Dword),
+        Ok(0x1010)
+    );
+    assert_eq!(
+        device.handle_write(0x1010.into(), AccessWidth::Dword, 1),
+        Err(AxError::PermissionDenied)
+    );
+    assert_eq!(
+        device.handle_read(0x1110.into(), AccessWidth::Dword),
+        Err(AxError::PermissionDenied)
+    );
+    device
+        .handle_write(0x1110.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    assert_eq!(
+        device.handle_read(0x1200.into(), AccessWidth::Dword),
+        Ok(0x1200)
+    );
+
+    let device = PermissionCheckedDevice::new(DeviceA)
+        .with_region(
+            GuestPhysAddrRange::from_start_size(0x1000.into(), 0x1000),
+            RegionAccess::ReadOnly,
+        )
+        .with_error(AxError::BadAddress);
+    assert_eq!(
+        device.handle_write(0x1000.into(), AccessWidth::Dword, 1),
+        Err(AxError::BadAddress)
+    );
+
+    // Writes the doorbell would coalesce are checked too.
+    let doorbell = Arc::new(PermissionCheckedDevice::new(Doorbell::new()).with_region(
+        GuestPhysAddrRange::from_start_size(0xe000.into(), 4),
+        RegionAccess::ReadOnly,
+    ));
+    let manager = DeviceManager::new();
+    manager.register(doorbell.clone()).unwrap();
+    assert_eq!(
+        manager.handle_write(0xe000.into(), AccessWidth::Dword, 1),
+        Err(AxError::PermissionDenied)
+    );
+    manager
+        .handle_write(0xe004.into(), AccessWidth::Dword, 2)
+        .unwrap();
+    assert!(doorbell.inner().ring.is_empty());
+    assert_eq!(*doorbell.inner().rung.lock(), [2]);
+}
+
+#[test]
+fn test_permission_checked_straddling_access() {
+    let device = PermissionCheckedDevice::new(DeviceA)
+        .with_region(
+            GuestPhysAddrRange::from_start_size(0x1100.into(), 0x100),
+            RegionAccess::ReadOnly,
+        )
+        .with_region(
+            GuestPhysAddrRange::from_start_size(0x1200.into(), 0x100),
+            RegionAccess::WriteOnly,
+        );
+
+    // Starting below a read-only region and running into it.
+    assert_eq!(
+        device.handle_write(0x10fc.into(), AccessWidth::Qword, 1),
+        Err(AxError::PermissionDenied)
+    );
+    device
+        .handle_write(0x10f8.into(), AccessWidth::Qword, 1)
+        .unwrap();
+    // Starting in a read-only region and running into a write-only one.
+    assert_eq!(
+        device.handle_read(0x11fc.into(), AccessWidth::Qword),
+        Err(AxError::PermissionDenied)
+    );
+    assert_eq!(
+        device.handle_read(0x11f8.into(), AccessWidth::Qword),
+        Ok(0x11f8)
+    );
+    // Starting in a write-only region and running past its end.
+    assert_eq!(
+        device.handle_read(0x12fe.into(), AccessWidth::Dword),
+        Err(AxError::PermissionDenied)
+    );
+}
+
+/// A device at 0x7000 implementing only its first register.
+struct SparseDevice;
+
+impl BaseDeviceOps<GuestPhysAddrRange> for SparseDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x7000.into(), 0x10)
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        match addr.as_usize() {
+            0x7000 => Ok(0x5a),
+            _ => Err(AxError::Unsupported),
+        }
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        match addr.as_usize() {
+            0x7000 => Ok(()),
+            _ => Err(AxError::Unsupported),
+        }
+    }
+}
+
+#[test]
+fn test_unhandled_access_policy() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(SparseDevice)).unwrap();
+    assert_eq!(
+        manager.handle_read(0x8000.into(), AccessWidth::Dword),
+        Err(AxError::NotFound)
+    );
+    assert_eq!(
+        manager.handle_read(0x7004.into(), AccessWidth::Dword),
+        Err(AxError::Unsupported)
+    );
+
+    manager.set_unhandled_policy(UnhandledAccessPolicy::ReadAsZeroWriteIgnore);
+    assert_eq!(
+        manager.handle_read(0x8000.into(), AccessWidth::Dword),
+        Ok(0)
+    );
+    manager
+        .handle_write(0x8000.into(), AccessWidth::Dword, 1)
+        .unwrap();
+    assert_eq!(
+        manager.handle_read(0x7004.into(), AccessWidth::Dword),
+        Ok(0)
+    );
+    // Asynchronous accesses are completed by the policy as well.
+    assert_eq!(
+        manager.handle_read_async(0x8000.into(), AccessWidth::Dword),
+        Ok(AccessOutcome::Completed(0))
+    );
+    assert_eq!(
+        manager.handle_write_async(0x7004.into(), AccessWidth::Dword, 1),
+        Ok(AccessOutcome::Completed(0))
+    );
+    // Other errors are not affected.
+    assert_eq!(
+        manager.handle_read(0x700e.into(), AccessWidth::Dword),
+        Err(AxError::BadAddress)
+    );
+
+    // Region policies override the default, the latest one first.
+    let device_range = SparseDevice.address_range();
+    manager.set_region_unhandled_policy(device_range, UnhandledAccessPolicy::Fault);
+    manager.set_region_unhandled_policy(
+        GuestPhysAddrRange::from_start_size(0x7008.into(), 8),
+        UnhandledAccessPolicy::LogAndIgnore,
+    );
+    assert_eq!(
+        manager.unhandled_policy(0x7004.into()),
+        UnhandledAccessPolicy::Fault
+    );
+    assert_eq!(
+        manager.handle_write(0x7004.into(), AccessWidth::Dword, 1),
+        Err(AxError::Unsupported)
+    );
+    assert_eq!(
+        manager.handle_read_async(0x7004.into(), AccessWidth::Dword),
+        Err(AxError::Unsupported)
+    );
+    assert_eq!(
+        manager.handle_read_ctx(0x7008.into(), AccessWidth::Dword, AccessContext::default()),
+        Ok(0)
+    );
+    assert_eq!(
+        manager.handle_read(0x7000.into(), AccessWidth::Dword),
+        Ok(0x5a)
+    );
+
+    // Setting a range again replaces its policy, and policies can be removed.
+    let window = GuestPhysAddrRange::from_start_size(0x7008.into(), 8);
+    manager.set_region_unhandled_policy(window, UnhandledAccessPolicy::Fault);
+    assert_eq!(
+        manager.unhandled_policy(0x7008.into()),
+        UnhandledAccessPolicy::Fault
+    );
+    assert_eq!(
+        manager.remove_region_unhandled_policy(window),
+        Some(UnhandledAccessPolicy::Fault)
+    );
+    assert_eq!(manager.remove_region_unhandled_policy(window), None);
+    manager.remove_region_unhandled_policy(device_range);
+    assert_eq!(
+        manager.unhandled_policy(0x7008.into()),
+        UnhandledAccessPolicy::ReadAsZeroWriteIgnore
+    );
+}
+
+/// A device at 0x6000 whose registers read as their address, except in the
+/// given hole.
+struct HoledDevice(GuestPhysAddrRange);
+
+impl BaseDeviceOps<GuestPhysAddrRange> for HoledDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(0x6000.into(), 0x10)
+    }
+
+    fn holes(&self) -> Vec<GuestPhysAddrRange> {
+        vec![self.0]
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, _width: AccessWidth) -> AxResult<usize> {
+        assert!(!self.0.contains(addr), "access to a hole dispatched");
+        Ok(addr.as_usize())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, _width: AccessWidth, _val: usize) -> AxResult {
+        assert!(!self.0.contains(addr), "access to a hole dispatched");
+        Ok(())
+    }
+}
+
+#[test]
+fn test_device_holes() {
+    let manager = DeviceManager::new();
+    let outside = GuestPhysAddrRange::from_start_size(0x600c.into(), 8);
+    assert_eq!(
+        manager.register(Arc::new(HoledDevice(outside))),
+        Err(AxError::InvalidInput)
+    );
+    let hole = GuestPhysAddrRange::from_start_size(0x6004.into(), 4);
+    // Holes are declared through wrappers as well.
+    manager
+        .register(Arc::new(JournaledDevice::new(HoledDevice(hole), 4)))
+        .unwrap();
+
+    assert_eq!(
+        manager.handle_read(0x6000.into(), AccessWidth::Dword),
+        Ok(0x6000)
+    );
+    assert_eq!(
+        manager.handle_read(0x6004.into(), AccessWidth::Byte),
+        Err(AxError::NotFound)
+    );
+    // Accesses partly overlapping a hole are not dispatched either.
+    assert_eq!(
+        manager.handle_write(0x6002.into(), AccessWidth::Dword, 0),
+        Err(AxError::NotFound)
+    );
+    assert_eq!(
+        manager.handle_read_bulk(0x6000.into(), AccessWidth::Dword, 3, 4, &mut [0; 12]),
+        Err(AxError::NotFound)
+    );
+    manager
+        .handle_read_bulk(0x6000.into(), AccessWidth::Dword, 2, 8, &mut [0; 8])
+        .unwrap();
+
+    // Hole hits get the unhandled access policy.
+    manager.set_unhandled_policy(UnhandledAccessPolicy::ReadAsZeroWriteIgnore);
+    assert_eq!(
+        manager.handle_read(0x6004.into(), AccessWidth::Dword),
+        Ok(0)
+    );
+    manager
+        .handle_write(0x6006.into(), AccessWidth::Word, 1)
+        .unwrap();
+}
+
+/// A 64-bit register at `base`, declaring [`Capability::GuestByteOrder`] if
+/// `native` is set.
+struct ByteOrderReg {
+    base: usize,
+    native: bool,
+    reg: core::sync::atomic::AtomicUsize,
+}
+
+impl ByteOrderReg {
+    fn new(base: usize, native: bool) -> Self {
+        Self {
+            base,
+            native,
+            reg: 0.into(),
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for ByteOrderReg {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        GuestPhysAddrRange::from_start_size(self.base.into(), 8)
+    }
+
+    fn handle_read(&self, _addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        Ok(self.reg.load(core::sync::atomic::Ordering::Relaxed) & width_mask(width))
+    }
+
+    fn handle_write(&self, _addr: GuestPhysAddr, _width: AccessWidth, val: usize) -> AxResult {
+        self.reg.store(val, core::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        if self.native {
+            Capability::GuestByteOrder.into()
+        } else {
+            CapabilitySet::empty()
+        }
+    }
+}
+
+#[test]
+fn test_guest_byte_order() {
+    let manager = DeviceManager::new();
+    let converted = Arc::new(ByteOrderReg::new(0x1000, false));
+    let native = Arc::new(ByteOrderReg::new(0x2000, true));
+    manager.register(converted.clone()).unwrap();
+    manager.register(native.clone()).unwrap();
+    manager.set_guest_byte_order(ByteOrder::Big);
+    assert_eq!(manager.guest_byte_order(), ByteOrder::Big);
+
+    for base in [0x1000, 0x2000] {
+        manager
+            .handle_write(base.into(), AccessWidth::Dword, 0x1122_3344)
+            .unwrap();
+    }
+    assert_eq!(
+        converted.reg.load(core::sync::atomic::Ordering::Relaxed),
+        0x4433_2211
+    );
+    assert_eq!(
+        native.reg.load(core::sync::atomic::Ordering::Relaxed),
+        0x1122_3344
+    );
+    for base in [0x1000, 0x2000] {
+        assert_eq!(
+            manager.handle_read(base.into(), AccessWidth::Dword),
+            Ok(0x1122_3344)
+        );
+    }
+    assert_eq!(
+        manager.handle_read_async(0x1000.into(), AccessWidth::Word),
+        Ok(AccessOutcome::Completed(0x1122))
+    );
+
+    // Bulk accesses are converted element by element.
+    manager
+        .handle_write_bulk(0x1000.into(), AccessWidth::Word, 1, 0, &[0xaa, 0xbb])
+        .unwrap();
+    assert_eq!(
+        converted.reg.load(core::sync::atomic::Ordering::Relaxed),
+        0xaabb
+    );
+    let mut buf = [0; 2];
+    manager
+        .handle_read_bulk(0x1000.into(), AccessWidth::Word, 1, 0, &mut buf)
+        .unwrap();
+    assert_eq!(buf, [0xaa, 0xbb]);
+
+    assert_eq!(ByteOrder::Little.convert(0x1234, AccessWidth::Word), 0x1234);
+}
+
+#[derive(Default)]
+struct LatencyLog(spin::Mutex<Vec<(usize, LatencyClass)>>);
+
+impl LatencyObserver for LatencyLog {
+    fn on_access(&self, addr: usize, class: LatencyClass) {
+        self.0.lock().push((addr, class));
+    }
+}
+
+#[test]
+fn test_latency_classes() {
+    let manager = DeviceManager::new();
+    manager.register(Arc::new(DeviceA)).unwrap();
+    manager.set_region_latency(DeviceA.address_range(), LatencyClass::Bulk);
+    manager.set_region_latency(
+        GuestPhysAddrRange::from_start_size(0x1100.into(), 0x100),
+        LatencyClass::RealTime,
+    );
+    assert_eq!(manager.latency_class(0x1100.into()), LatencyClass::RealTime);
+    assert_eq!(manager.latency_class(0x5000.into()), LatencyClass::Normal);
+
+    let log = Arc::new(LatencyLog::default());
+    manager.set_latency_observer(Some(log.clone()));
+    manager
+        .handle_read(0x1000.into(), AccessWidth::Dword)
+        .unwrap();
+    manager
+        .handle_write_async(0x1104.into(), AccessWidth::Dword, 0)
+        .unwrap();
+    // Accesses are reported before dispatch, even if no device handles them.
+    assert!(
+        manager
+            .handle_write(0x5000.into(), AccessWidth::Dword, 0)
+            .is_err()
+    );
+    // Debugger accesses are not.
+    manager.peek(0x1100.into(), AccessWidth::Dword).ok();
+    manager.set_latency_observer(None);
+    manager
+        .handle_read(0x1000.into(), AccessWidth::Dword)
+        .unwrap();
+    assert_eq!(
+        *log.0.lock(),
+        [
+            (0x1000, LatencyClass::Bulk),
+            (0x1104, LatencyClass::RealTime),
+            (0x5000, LatencyClass::Normal),
+        ]
+    );
+}
+
+/// Keeps the guest from setting bit 2 (bus master) of the register at
+/// 0x1000, denies writes to 0x2000 and hides the value of 0x2000.
+struct BusMasterFilter;
+
+impl AccessFilter<GuestPhysAddrRange> for BusMasterFilter {
+    fn filter(
+        &self,
+        _device: &dyn BaseDeviceOps<GuestPhysAddrRange>,
+        addr: GuestPhysAddr,
+        _width: AccessWidth,
+        value: usize,
+        kind: AccessKind,
+    ) -> FilterAction {
+        match (addr.as_usize(), kind) {
+            (0x1000, AccessKind::Write) => FilterAction::Rewrite(value & !0b100),
+            (0x2000, AccessKind::Write) => FilterAction::Deny,
+            (0x2000, AccessKind::Read) => FilterAction::Rewrite(0x5a),
+            _ => FilterAction::Allow,
+        }
+    }
+}
+
+#[test]
+fn test_access_filter() {
+    let manager = DeviceManager::new();
+    let command = Arc::new(ByteOrderReg::new(0x1000, false));
+    let hidden = Arc::new(ByteOrderReg::new(0x2000, false));
+    manager.register(command.clone()).unwrap();
+    manager.register(hidden.clone()).unwrap();
+    let filter: Arc<dyn AccessFilter<GuestPhysAddrRange>> = Arc::new(BusMasterFilter);
+    manager.add_access_filter(filter.clone());
+    let tracer = Arc::new(RecordingTracer::default());
+    manager.set_tracer(Some(tracer.clone()));
+
+    // A rewritten write reaches the device, and is traced, with its new value.
+    manager
+        .handle_write(0x1000.into(), AccessWidth::Word, 0b111)
+        .unwrap();
+    assert_eq!(
+        command.reg.load(core::sync::atomic::Ordering::Relaxed),
+        0b011
+    );
+    assert_eq!(TraceRecord::decode(&tracer.0.lock()).unwrap().value, 0b011);
+    manager
+        .handle_write_bulk(0x1000.into(), AccessWidth::Byte, 1, 0, &[0xff])
+        .unwrap();
+    assert_eq!(
+        command.reg.load(core::sync::atomic::Ordering::Relaxed),
+        0xfb
+    );
+
+    // A denied write never reaches the device.
+    hidden.reg.store(1, core::sync::atomic::Ordering::Relaxed);
+    assert_eq!(
+        manager.handle_write(0x2000.into(), AccessWidth::Dword, 0),
+        Err(AxError::PermissionDenied)
+    );
+    assert_eq!(
+        manager.handle_write_async(0x2000.into(), AccessWidth::Dword, 0),
+        Err(AxError::PermissionDenied)
+    );
+    assert_eq!(
+        manager.handle_write_bulk(0x2000.into(), AccessWidth::Byte, 1, 0, &[0]),
+        Err(AxError::PermissionDenied)
+    );
+    assert_eq!(hidden.reg.load(core::sync::atomic::Ordering::Relaxed), 1);
+
+    // A rewritten read completes without the device.
+    assert_eq!(
+        manager.handle_read(0x2000.into(), AccessWidth::Dword),
+        Ok(0x5a)
+    );
+    assert_eq!(
+        manager.handle_read_async(0x2000.into(), AccessWidth::Dword),
+        Ok(AccessOutcome::Completed(0x5a))
+    );
+    let mut buf = [0; 2];
+    manager
+        .handle_read_bulk(0x2000.into(), AccessWidth::Byte, 2, 0, &mut buf)
+        .unwrap();
+    assert_eq!(buf, [0x5a, 0x5a]);
+    assert_eq!(
+        manager.handle_read(0x1000.into(), AccessWidth::Byte),
+        Ok(0xfb)
+    );
+
+    assert!(manager.remove_access_filter(&filter));
+    assert!(!manager.remove_access_filter(&filter));
+    assert_eq!(
+        manager.handle_read(0x2000.into(), AccessWidth::Dword),
+        Ok(1)
+    );
+}
+
+#[test]
+fn test_dispatch_strategy() {
+    use crate::testing::MockDevice;
+
+    assert_eq!(
+        DeviceManager::<GuestPhysAddrRange>::new().dispatch_strategy(),
+        DispatchStrategy::Sorted
+    );
+    let sorted = DeviceManager::new();
+    let paged = DeviceManager::with_dispatch_strategy(DispatchStrategy::PageIndex);
+    assert_eq!(paged.dispatch_strategy(), DispatchStrategy::PageIndex);
+    // Hundreds of virtio-mmio transports sharing pages, a device straddling
+    // two pages, and a device too large to be indexed.
+    let mut ranges: Vec<_> = (0..256)
+        .map(|i| GuestPhysAddrRange::from_start_size((0x1000_0000 + i * 0x200).into(), 0x200))
+        .collect();
+    ranges.push(GuestPhysAddrRange::from_start_size(
+        0x1002_0f00.into(),
+        0x200,
+    ));
+    ranges.push(GuestPhysAddrRange::from_start_size(
+        0x2000_0000.into(),
+        0x100_0000,
+    ));
+    for range in &ranges {
+        sorted.register(Arc::new(MockDevice::new(*range))).unwrap();
+        paged.register(Arc::new(MockDevice::new(*range))).unwrap();
+    }
+
+    let lookup = |manager: &DeviceManager<GuestPhysAddrRange>, addr: usize| {
+        manager
+            .find(addr.into())
+            .map(|device| device.address_range())
+    };
+    let check = || {
+        for addr in (0x0fff_f000..0x1002_2000).step_by(0x80).chain([
+            0x2000_0000,
+            0x2080_1234,
+            0x20ff_ffff,
+            0x2100_0000,
+        ]) {
+            assert_eq!(lookup(&paged, addr), lookup(&sorted, addr), "{addr:#x}");
+        }
+    };
+    check();
+    assert_eq!(
+        lookup(&paged, 0x1002_1000),
+        Some(GuestPhysAddrRange::from_start_size(
+            0x1002_0f00.into(),
+            0x200
+        ))
+    );
+    assert_eq!(lookup(&paged, 0x1002_2000), None);
+
+    // The index follows the devices being unregistered.
+    for addr in [0x1000_0000, 0x1000_0600, 0x1002_0f00] {
+        assert!(sorted.unregister(addr.into()).is_some());
+        assert!(paged.unregister(addr.into()).is_some());
+    }
+    check();
+    assert_eq!(lookup(&paged, 0x1000_0600), None);
+    assert_eq!(
+        paged.handle_read(0x1000_0600.into(), AccessWidth::Dword),
+        Err(AxError::NotFound)
+    );
+}
+
+/// A remote console keyboard with a left mouse button, logging the LED
+/// changes of the guest.
+#[derive(Default)]
+struct RemoteConsole {
+    callback: spin::Mutex<Option<InputCallback>>,
+    leds: spin::Mutex<Vec<InputEvent>>,
+}
+
+impl InputBackend for RemoteConsole {
+    fn set_event_callback(&self, callback: InputCallback) {
+        *self.callback.lock() = Some(callback);
+    }
+
+    fn name(&self) -> alloc::string::String {
+        "remote-kbd".into()
+    }
+
+    fn event_codes(&self, kind: u16) -> Vec<u16> {
+        match kind {
+            InputEvent::EV_KEY => vec![30, 0x110],
+            _ => Vec::new(),
+        }
+    }
+
+    fn on_status(&self, event: InputEvent) {
+        self.leds.lock().push(event);
+    }
+}
+
+#[test]
+fn test_virtio_input() {
+    let console = Arc::new(RemoteConsole::default());
+    let base = GuestPhysAddr::from(0x1_0000);
+    let dev = Arc::new(VirtioInputDevice::new(base, console.clone()));
+    let memory = Arc::new(TestMemory::new(0x2000));
+    dev.set_dma_accessor(memory.clone());
+    dev.connect();
+    let read = |offset, width| dev.handle_read(base + offset, width).unwrap();
+    let write = |offset, val| dev.handle_write(base + offset, AccessWidth::Dword, val);
+    assert_eq!(read(0x008, AccessWidth::Dword), 18);
+
+    // The configuration space reports the selected name and event bitmaps.
+    dev.handle_write(base + 0x100, AccessWidth::Word, 0x01)
+        .unwrap();
+    assert_eq!(read(0x102, AccessWidth::Byte), 10);
+    assert_eq!(read(0x108, AccessWidth::Dword), 0x6f6d_6572);
+    dev.handle_write(base + 0x100, AccessWidth::Word, 0x0111)
+        .unwrap();
+    assert_eq!(read(0x102, AccessWidth::Byte), 0x110 / 8 + 1);
+    assert_eq!(read(0x108 + 30 / 8, AccessWidth::Byte), 1 << (30 % 8));
+    assert_eq!(read(0x108 + 0x110 / 8, AccessWidth::Byte), 1);
+    dev.handle_write(base + 0x101, AccessWidth::Byte, InputEvent::EV_REL as usize)
+        .unwrap();
+    assert_eq!(read(0x102, AccessWidth::Byte), 0);
+
+    let key_a = InputEvent::new(InputEvent::EV_KEY, 30, 1);
+    let inject = |events: &[InputEvent]| (console.callback.lock().as_ref().unwrap())(events);
+    assert_eq!(dev.inject(&[key_a]), Ok(false));
+
+    // Queue `q` has its rings at `q * 0x1000` and its buffers 0x400 above.
+    let ram = GuestPhysAddr::from(TestMemory::BASE);
+    for queue in [0, 1] {
+        let rings = TestMemory::BASE + queue * 0x1000;
+        write(0x030, queue).unwrap();
+        write(0x038, 8).unwrap();
+        write(0x080, rings).unwrap();
+        write(0x090, rings + 0x100).unwrap();
+        write(0x0a0, rings + 0x200).unwrap();
+        write(0x044, 1).unwrap();
+    }
+    write(0x070, 0xf).unwrap();
+
+    // Makes an 8-byte buffer available on `queue` and notifies the device.
+    let post = |queue: usize, writable: bool| {
+        let rings = ram + queue * 0x1000;
+        let idx = memory.read_u16(rings + 0x102).unwrap();
+        let desc = rings + 16 * (idx % 8) as usize;
+        let buf = TestMemory::BASE + queue * 0x1000 + 0x400 + 8 * (idx % 8) as usize;
+        memory.write_u64(desc, buf as u64).unwrap();
+        memory.write_u32(desc + 8, 8).unwrap();
+        let flags = if writable {
+            SplitQueue::DESC_F_WRITE
+        } else {
+            0
+        };
+        memory.write_u16(desc + 12, flags).unwrap();
+        memory
+            .write_u16(rings + 0x104 + 2 * (idx % 8) as usize, idx % 8)
+            .unwrap();
+        memory.write_u16(rings + 0x102, idx + 1).unwrap();
+        write(0x050, queue).unwrap();
+    };
+    let event_at = |index: usize| {
+        let mut bytes = [0; 8];
+        memory.read(ram + 0x400 + 8 * index, &mut bytes).unwrap();
+        InputEvent::decode(&bytes)
+    };
+
+    // Events wait for the driver to make buffers available.
+    inject(&[key_a]);
+    assert_eq!(dev.pending(), 2);
+    post(0, true);
+    assert_eq!(memory.read_u16(ram + 0x202), Ok(1));
+    assert_eq!(event_at(0), key_a);
+    assert_ne!(dev.regs().interrupt_status(), 0);
+    post(0, true);
+    assert_eq!(event_at(1), InputEvent::new(InputEvent::EV_SYN, 0, 0));
+    assert_eq!(dev.pending(), 0);
+
+    // Groups that do not fit are dropped whole.
+    let burst = vec![key_a; VirtioInputDevice::MAX_PENDING];
+    assert_eq!(dev.inject(&burst), Ok(false));
+    assert_eq!(dev.pending(), 0);
+
+    // Status events go to the backend.
+    let caps_lock = InputEvent::new(InputEvent::EV_LED, 1, 1);
+    memory.write(ram + 0x1400, &caps_lock.encode()).unwrap();
+    post(1, false);
+    assert_eq!(*console.leds.lock(), [caps_lock]);
+    assert_eq!(memory.read_u16(ram + 0x1202), Ok(1));
+}
+
+/// Steal time growing by 1 ms per query, with vCPU 1 preempted.
+#[derive(Default)]
+struct FakeScheduler(spin::Mutex<u64>);
+
+impl SchedulerInfoOps for FakeScheduler {
+    fn steal_time_ns(&self, vcpu: usize) -> u64 {
+        let mut steal = self.0.lock();
+        *steal += 1_000_000;
+        *steal + vcpu as u64
+    }
+
+    fn is_preempted(&self, vcpu: usize) -> bool {
+        vcpu == 1
+    }
+}
+
+#[test]
+fn test_steal_time() {
+    let mem = Arc::new(TestMemory::new(0x1000));
+    let device = StealTimeDevice::new(0x7000.into(), 1, Arc::new(FakeScheduler::default()));
+    assert_eq!(device.manifest().compatible, ["axvisor,pv-steal-time"]);
+    let area = |vcpu: usize| GuestPhysAddr::from(TestMemory::BASE + 0x40 * (vcpu + 1));
+    let enable = |vcpu: usize| {
+        device.handle_write_ctx(
+            0x7000.into(),
+            AccessWidth::Dword,
+            area(vcpu).as_usize() | 1,
+            AccessContext::new(vcpu),
+        )
+    };
+    assert_eq!(enable(0), Err(AxError::BadState));
+    device.set_dma_accessor(mem.clone());
+    assert_eq!(
+        device.handle_read(0x7008.into(), AccessWidth::Dword),
+        Ok(StealTimeDevice::FEATURES as usize)
+    );
+
+    // Each vCPU enables its own structure, once it exists.
+    enable(0).unwrap();
+    assert_eq!(enable(1), Err(AxError::InvalidInput));
+    device.on_vcpu_added(1).unwrap();
+    enable(1).unwrap();
+    assert_eq!(device.area(0), Some(area(0)));
+    assert_eq!(
+        device.handle_read_ctx(0x7000.into(), AccessWidth::Dword, AccessContext::new(1)),
+        Ok(area(1).as_usize() | 1)
+    );
+    let info = StealTimeInfo::read(&*mem, area(0)).unwrap();
+    assert_eq!(
+        (info.steal, info.version, info.preempted),
+        (1_000_000, 2, 0)
+    );
+    let info = StealTimeInfo::read(&*mem, area(1)).unwrap();
+    assert_eq!(info.preempted, StealTimeInfo::PREEMPTED);
+
+    // Updates advance the steal time by a full seqlock cycle.
+    device.update(0).unwrap();
+    let info = StealTimeInfo::read(&*mem, area(0)).unwrap();
+    assert_eq!((info.steal, info.version), (3_000_000, 4));
+    assert_eq!(device.update(2), Err(AxError::InvalidInput));
+    mem.write_u32(area(0) + 8, 5).unwrap();
+    assert_eq!(
+        StealTimeInfo::read(&*mem, area(0)),
+        Err(AxError::WouldBlock)
+    );
+
+    // Resetting disables steal time on every vCPU.
+    device.reset().unwrap();
+    assert_eq!(device.area(1), None);
+    device.update(0).unwrap();
+    assert_eq!(mem.read_u32(area(0) + 8), Ok(5));
+}
+
+#[derive(Default)]
+struct DamageCount(core::sync::atomic::AtomicUsize);
+
+impl DisplayConsumer for DamageCount {
+    fn on_damage(&self) {
+        self.0.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_framebuffer() {
+    assert!(FramebufferDevice::new(0x7000.into(), 0, 70).is_err());
+    let device = FramebufferDevice::new(0x7000.into(), 100, 70).unwrap();
+    let mem = Arc::new(TestMemory::new(0x8000));
+    let consumer = Arc::new(DamageCount::default());
+    device.set_consumer(Some(consumer.clone()));
+    let read = |offset: usize| device.handle_read((0x7000 + offset).into(), AccessWidth::Dword);
+    let write =
+        |offset: usize, val| device.handle_write((0x7000 + offset).into(), AccessWidth::Dword, val);
+    assert_eq!(read(0x10), Ok(400));
+    assert_eq!(read(0x14), Ok(0x3432_5258));
+    let rect = |x, y, width, height| DirtyRect {
+        x,
+        y,
+        width,
+        height,
+    };
+    assert_eq!(
+        device.read_rect(rect(0, 0, 1, 1), &mut [0; 4]),
+        Err(AxError::BadState)
+    );
+
+    // Enabling the display dirties every tile, clipped to the framebuffer.
+    device.set_dma_accessor(mem.clone());
+    write(0x04, 0).unwrap();
+    write(0x00, TestMemory::BASE | 1).unwrap();
+    assert_eq!(device.framebuffer(), Some(TestMemory::BASE.into()));
+    assert_eq!(
+        device.take_dirty(),
+        [
+            rect(0, 0, 64, 64),
+            rect(64, 0, 36, 64),
+            rect(0, 64, 64, 6),
+            rect(64, 64, 36, 6),
+        ]
+    );
+    assert_eq!(device.take_dirty(), []);
+
+    // Reported damage dirties the tiles it touches.
+    write(0x18, 70 | 10 << 16).unwrap();
+    write(0x1c, 20 | 60 << 16).unwrap();
+    write(0x20, 1).unwrap();
+    assert_eq!(consumer.0.load(core::sync::atomic::Ordering::Relaxed), 2);
+    assert_eq!(read(0x1c), Ok(20 | 60 << 16));
+    assert_eq!(
+        device.take_dirty(),
+        [rect(64, 0, 36, 64), rect(64, 64, 36, 6)]
+    );
+
+    // The consumer copies the pixels of a rectangle out of guest RAM.
+    let pixel = |x: usize, y: usize| GuestPhysAddr::from(TestMemory::BASE + y * 400 + x * 4);
+    mem.write_u32(pixel(98, 68), 0x00ff_0000).unwrap();
+    mem.write_u32(pixel(99, 69), 0x0000_ff00).unwrap();
+    let mut buf = [0; 16];
+    device.read_rect(rect(98, 68, 2, 2), &mut buf).unwrap();
+    assert_eq!(buf[..4], 0x00ff_0000u32.to_le_bytes());
+    assert_eq!(buf[12..], 0x0000_ff00u32.to_le_bytes());
+    assert_eq!(
+        device.read_rect(rect(99, 68, 2, 2), &mut buf),
+        Err(AxError::InvalidInput)
+    );
+
+    device.reset().unwrap();
+    assert_eq!(device.framebuffer(), None);
+}
+
+/// A mapping of [`IommuTables`], as `(domain, iova, last, paddr, access)`.
+type IommuMapping = (u32, u64, u64, usize, RegionAccess);
+
+/// A translator keeping the attached endpoints and the mappings of each
+/// domain.
+#[derive(Default)]
+struct IommuTables {
+    endpoints: spin::Mutex<Vec<(u32, u32)>>,
+    mappings: spin::Mutex<Vec<IommuMapping>>,
+}
+
+impl DmaTranslator for IommuTables {
+    fn attach(&self, domain: u32, endpoint: u32) -> AxResult {
+        let mut endpoints = self.endpoints.lock();
+        endpoints.retain(|&(_, e)| e != endpoint);
+        endpoints.push((domain, endpoint));
+        Ok(())
+    }
+
+    fn detach(&self, domain: u32, endpoint: u32) -> AxResult {
+        let mut endpoints = self.endpoints.lock();
+        let len = endpoints.len();
+        endpoints.retain(|&attached| attached != (domain, endpoint));
+        if endpoints.len() == len {
+            return Err(AxError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn map(
+        &self,
+        domain: u32,
+        iova: u64,
+        last: u64,
+        paddr: GuestPhysAddr,
+        access: RegionAccess,
+    ) -> AxResult {
+        let mut mappings = self.mappings.lock();
+        if mappings
+            .iter()
+            .any(|&(d, start, end, ..)| d == domain && start <= last && iova <= end)
+        {
+            return Err(AxError::AlreadyExists);
+        }
+        mappings.push((domain, iova, last, paddr.as_usize(), access));
+        Ok(())
+    }
+
+    fn unmap(&self, domain: u32, iova: u64, last: u64) -> AxResult {
+        self.mappings
+            .lock()
+            .retain(|&(d, start, end, ..)| d != domain || start < iova || end > last);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_virtio_iommu() {
+    const ATTACH: u8 = 1;
+    const DETACH: u8 = 2;
+    const MAP: u8 = 3;
+    const UNMAP: u8 = 4;
+
+    let tables = Arc::new(IommuTables::default());
+    let base = GuestPhysAddr::from(0x1_0000);
+    assert!(VirtioIommuDevice::new(base, tables.clone(), 0, 1..=8).is_err());
+    let dev = VirtioIommuDevice::new(base, tables.clone(), 0xffff_f000, 1..=8).unwrap();
+    let memory = Arc::new(TestMemory::new(0x3000));
+    dev.set_dma_accessor(memory.clone());
+    let read = |offset| dev.handle_read(base + offset, AccessWidth::Dword).unwrap();
+    let write = |offset, val| dev.handle_write(base + offset, AccessWidth::Dword, val);
+
+    assert_eq!(read(0x008), 23);
+    assert_eq!(read(0x100), 0xffff_f000);
+    assert_eq!((read(0x110), read(0x114)), (0xffff_ffff, 0xffff_ffff));
+    assert_eq!((read(0x118), read(0x11c)), (1, 8));
+
+    let ram = GuestPhysAddr::from(TestMemory::BASE);
+    write(0x030, 0).unwrap();
+    write(0x038, 8).unwrap();
+    write(0x080, TestMemory::BASE).unwrap();
+    write(0x090, TestMemory::BASE + 0x100).unwrap();
+    write(0x0a0, TestMemory::BASE + 0x200).unwrap();
+    write(0x044, 1).unwrap();
+    write(0x070, 0xf).unwrap();
+
+    // Sends a request of type `op` with `body` through descriptors 0
+    // (request) and 1 (status), and returns the status.
+    let avail_idx = core::cell::Cell::new(0u16);
+    let request = |op: u8, body: &[u8]| -> u8 {
+        let mut req = vec![op, 0, 0, 0];
+        req.extend_from_slice(body);
+        memory.write(ram + 0x1000, &req).unwrap();
+        for (index, addr, len, flags) in [
+            (0, 0x1000, req.len() as u32, SplitQueue::DESC_F_NEXT),
+            (1, 0x2000, 4, SplitQueue::DESC_F_WRITE),
+        ] {
+            let desc = ram + 16 * index;
+            memory
+                .write_u64(desc, (TestMemory::BASE + addr) as u64)
+                .unwrap();
+            memory.write_u32(desc + 8, len).unwrap();
+            memory.write_u16(desc + 12, flags).unwrap();
+            memory.write_u16(desc + 14, 1).unwrap();
+        }
+        let idx = avail_idx.get();
+        memory
+            .write_u16(ram + 0x104 + 2 * (idx % 8) as usize, 0)
+            .unwrap();
+        avail_idx.set(idx + 1);
+        memory.write_u16(ram + 0x102, idx + 1).unwrap();
+        write(0x050, 0).unwrap();
+        assert_eq!(memory.read_u16(ram + 0x202), Ok(idx + 1));
+        let mut status = [0xff];
+        memory.read(ram + 0x2000, &mut status).unwrap();
+        status[0]
+    };
+    let attach = |domain: u32, endpoint: u32| {
+        let mut body = Vec::new();
+        body.extend_from_slice(&domain.to_le_bytes());
+        body.extend_from_slice(&endpoint.to_le_bytes());
+        body.resize(20, 0);
+        body
+    };
+    let map = |domain: u32, start: u64, end: u64, phys: u64, flags: u32| {
+        let mut body = domain.to_le_bytes().to_vec();
+        for field in [start, end, phys] {
+            body.extend_from_slice(&field.to_le_bytes());
+        }
+        body.extend_from_slice(&flags.to_le_bytes());
+        body
+    };
+
+    assert_eq!(request(ATTACH, &attach(1, 0x10)), 0);
+    assert_eq!(*tables.endpoints.lock(), [(1, 0x10)]);
+    // Domains outside the configured range are rejected.
+    assert_eq!(request(ATTACH, &attach(9, 0x10)), 5);
+
+    // A single request maps a region larger than 4 GiB.
+    assert_eq!(request(MAP, &map(1, 0, 0x1_ffff_ffff, 0x4000_0000, 3)), 0);
+    assert_eq!(
+        *tables.mappings.lock(),
+        [(1, 0, 0x1_ffff_ffff, 0x4000_0000, RegionAccess::ReadWrite)]
+    );
+    // Overlapping, misaligned and permissionless mappings are invalid.
+    assert_eq!(request(MAP, &map(1, 0x1000, 0x1fff, 0, 1)), 4);
+    assert_eq!(request(MAP, &map(1, 0x2_0000_0800, 0x2_0000_0fff, 0, 1)), 4);
+    assert_eq!(request(MAP, &map(1, 0x2_0000_0000, 0x2_0000_0fff, 0, 0)), 4);
+    assert_eq!(request(MAP, &map(1, 0x2_0000_0000, 0x2_0000_0fff, 0, 1)), 0);
+
+    let mut unmap = map(1, 0, 0x1_ffff_ffff, 0, 0);
+    unmap.truncate(20);
+    unmap.resize(24, 0);
+    assert_eq!(request(UNMAP, &unmap), 0);
+    assert_eq!(tables.mappings.lock().len(), 1);
+
+    // Translator errors are reported as request status codes.
+    assert_eq!(request(DETACH, &attach(1, 0x10)), 0);
+    assert_eq!(request(DETACH, &attach(1, 0x10)), 6);
+    assert_eq!(request(0x7f, &[]), 2);
+
+    // Restricting the input range notifies the driver.
+    let generation = read(0x0fc);
+    assert!(dev.set_input_range(0x1000, 0xffff_ffff).is_ok());
+    assert_eq!(read(0x0fc), generation + 1);
+    assert_ne!(
+        dev.regs().interrupt_status() & VirtioMmioRegs::INT_CONFIG_CHANGE,
+        0
+    );
+    assert_eq!(read(0x108), 0x1000);
+    assert_eq!(request(MAP, &map(1, 0, 0xfff, 0, 1)), 5);
+}
@@ -0,0 +1,430 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mocks and assertion helpers for testing device models and hypervisor code.
+//!
+//! This module is only available with the `testing` feature.
+//!
+//! # Example
+//!
+//! ```rust
+//! # #[cfg(feature = "testing")] {
+//! use axaddrspace::{GuestPhysAddrRange, device::AccessWidth};
+//! use axdevice_base::testing::{MockDevice, assert_read, assert_write};
+//!
+//! let range = GuestPhysAddrRange::from_start_size(0x1000.into(), 0x100);
+//! let device = MockDevice::new(range)
+//!     .with_register(0x0, 0)
+//!     .with_read_only(0x4, 0x1234);
+//!
+//! assert_write(&device, 0x1000.into(), AccessWidth::Dword, 5);
+//! assert_read(&device, 0x1000.into(), AccessWidth::Dword, 5);
+//! assert_write(&device, 0x1004.into(), AccessWidth::Dword, 0);
+//! assert_read(&device, 0x1004.into(), AccessWidth::Dword, 0x1234);
+//! assert_eq!(device.accesses().len(), 4);
+//! # }
+//! ```
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+
+use axaddrspace::device::{AccessWidth, DeviceAddrRange};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    AccessCompleter, AccessKind, AccessRecord, BaseDeviceOps, Capability, CapabilitySet,
+    CompletionToken, DeviceAddrRangeExt, EmuDeviceType, RawDeviceAddr, RegValue, RegisterDump,
+    width_mask,
+};
+
+struct MockRegister {
+    value: usize,
+    writable: bool,
+}
+
+/// A device with a scriptable register map, recording every access.
+///
+/// Registers are declared by offset with
+/// [`with_register`](Self::with_register) and
+/// [`with_read_only`](Self::with_read_only). Reads return the register value,
+/// unless results were queued for the register with
+/// [`script_read`](Self::script_read); writes update writable registers,
+/// unless results were queued with [`script_write`](Self::script_write).
+/// Accesses to offsets without a register fail with
+/// `Err(AxError::BadAddress)`.
+pub struct MockDevice<R: DeviceAddrRangeExt> {
+    range: R,
+    regs: Mutex<BTreeMap<usize, MockRegister>>,
+    reads: Mutex<BTreeMap<usize, VecDeque<AxResult<usize>>>>,
+    writes: Mutex<BTreeMap<usize, VecDeque<AxResult>>>,
+    log: Mutex<Vec<AccessRecord<R::Addr>>>,
+}
+
+impl<R: DeviceAddrRangeExt> MockDevice<R> {
+    /// Creates a mock device covering `range`, with no registers.
+    pub fn new(range: R) -> Self {
+        Self {
+            range,
+            regs: Mutex::new(BTreeMap::new()),
+            reads: Mutex::new(BTreeMap::new()),
+            writes: Mutex::new(BTreeMap::new()),
+            log: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Declares a writable register at `offset` with the initial `value`.
+    pub fn with_register(self, offset: usize, value: usize) -> Self {
+        self.add_register(offset, value, true)
+    }
+
+    /// Declares a read-only register at `offset`; writes to it are ignored.
+    pub fn with_read_only(self, offset: usize, value: usize) -> Self {
+        self.add_register(offset, value, false)
+    }
+
+    /// Queues `result` as the outcome of a future read of the register at
+    /// `offset`. Queued results are consumed in order, one per read.
+    pub fn script_read(&self, offset: usize, result: AxResult<usize>) {
+        self.reads
+            .lock()
+            .entry(offset)
+            .or_default()
+            .push_back(result);
+    }
+
+    /// Queues `result` as the outcome of a future write of the register at
+    /// `offset`, which then leaves the register unchanged.
+    pub fn script_write(&self, offset: usize, result: AxResult) {
+        self.writes
+            .lock()
+            .entry(offset)
+            .or_default()
+            .push_back(result);
+    }
+
+    /// Returns the current value of the register at `offset`.
+    pub fn register(&self, offset: usize) -> Option<usize> {
+        self.regs.lock().get(&offset).map(|reg| reg.value)
+    }
+
+    /// Returns the accesses handled by the device, oldest first.
+    pub fn accesses(&self) -> Vec<AccessRecord<R::Addr>> {
+        self.log.lock().clone()
+    }
+
+    /// Discards the recorded accesses.
+    pub fn clear_accesses(&self) {
+        self.log.lock().clear();
+    }
+
+    fn add_register(self, offset: usize, value: usize, writable: bool) -> Self {
+        self.regs
+            .lock()
+            .insert(offset, MockRegister { value, writable });
+        self
+    }
+
+    fn read(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        if let Some(result) = self
+            .reads
+            .lock()
+            .get_mut(&offset)
+            .and_then(VecDeque::pop_front)
+        {
+            return result;
+        }
+        match self.regs.lock().get(&offset) {
+            Some(reg) => Ok(RegValue::new(reg.value, width).zero_extend()),
+            None => ax_err!(BadAddress, "no mock register at offset"),
+        }
+    }
+
+    fn write(&self, offset: usize, val: usize) -> AxResult {
+        if let Some(result) = self
+            .writes
+            .lock()
+            .get_mut(&offset)
+            .and_then(VecDeque::pop_front)
+        {
+            return result;
+        }
+        match self.regs.lock().get_mut(&offset) {
+            Some(reg) if reg.writable => {
+                reg.value = val;
+                Ok(())
+            }
+            Some(_) => Ok(()),
+            None => ax_err!(BadAddress, "no mock register at offset"),
+        }
+    }
+}
+
+impl<R: DeviceAddrRangeExt + Clone + 'static> BaseDeviceOps<R> for MockDevice<R> {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> R {
+        self.range.clone()
+    }
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        let result = match self.range.offset_of(addr) {
+            Some(offset) => self.read(offset, width),
+            None => ax_err!(BadAddress, "access outside of the mock device"),
+        };
+        self.log.lock().push(AccessRecord {
+            addr,
+            width,
+            kind: AccessKind::Read,
+            value: *result.as_ref().unwrap_or(&0),
+            error: result.err(),
+        });
+        result
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        let result = match self.range.offset_of(addr) {
+            Some(offset) => self.write(offset, val),
+            None => ax_err!(BadAddress, "access outside of the mock device"),
+        };
+        self.log.lock().push(AccessRecord {
+            addr,
+            width,
+            kind: AccessKind::Write,
+            value: val,
+            error: result.err(),
+        });
+        result
+    }
+
+    /// Reads the register value, without consuming scripted results or
+    /// recording the access.
+    fn peek(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        let offset = self.range.offset_of(addr);
+        match offset.and_then(|offset| self.register(offset)) {
+            Some(value) => Ok(value & width_mask(width)),
+            None => ax_err!(BadAddress, "no mock register at offset"),
+        }
+    }
+
+    /// Sets the register value, even for read-only registers, without
+    /// recording the access.
+    fn poke(&self, addr: R::Addr, _width: AccessWidth, val: usize) -> AxResult {
+        let offset = self.range.offset_of(addr);
+        let mut regs = self.regs.lock();
+        match offset.and_then(|offset| regs.get_mut(&offset)) {
+            Some(reg) => {
+                reg.value = val;
+                Ok(())
+            }
+            None => ax_err!(BadAddress, "no mock register at offset"),
+        }
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        Capability::Peek.into()
+    }
+
+    /// Reports every register as `"mock"`, with the width of `usize`.
+    fn dump_registers(&self) -> Vec<RegisterDump> {
+        let width = AccessWidth::try_from(size_of::<usize>()).unwrap();
+        self.regs
+            .lock()
+            .iter()
+            .map(|(&offset, reg)| RegisterDump::new("mock", offset, width, reg.value))
+            .collect()
+    }
+}
+
+/// An [`AccessCompleter`] recording every completion.
+#[derive(Default)]
+pub struct MockCompleter {
+    completions: Mutex<Vec<(CompletionToken, AxResult<usize>)>>,
+}
+
+impl MockCompleter {
+    /// Creates a completer with no recorded completion.
+    pub const fn new() -> Self {
+        Self {
+            completions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the recorded completions, oldest first.
+    pub fn completions(&self) -> Vec<(CompletionToken, AxResult<usize>)> {
+        self.completions.lock().clone()
+    }
+
+    /// Returns the result of the completion of `token`, if it was completed.
+    pub fn result_of(&self, token: CompletionToken) -> Option<AxResult<usize>> {
+        self.completions
+            .lock()
+            .iter()
+            .find(|(completed, _)| *completed == token)
+            .map(|(_, result)| *result)
+    }
+}
+
+impl AccessCompleter for MockCompleter {
+    fn complete(&self, token: CompletionToken, result: AxResult<usize>) {
+        self.completions.lock().push((token, result));
+    }
+}
+
+/// Asserts that reading `addr` from `device` succeeds and returns `expected`.
+#[track_caller]
+pub fn assert_read<R: DeviceAddrRange + 'static>(
+    device: &dyn BaseDeviceOps<R>,
+    addr: R::Addr,
+    width: AccessWidth,
+    expected: usize,
+) {
+    match device.handle_read(addr, width) {
+        Ok(val) => assert_eq!(
+            val, expected,
+            "read of {addr:?} ({width:?}) returned {val:#x}, expected {expected:#x}"
+        ),
+        Err(err) => panic!("read of {addr:?} ({width:?}) failed: {err:?}"),
+    }
+}
+
+/// Asserts that writing `val` to `addr` of `device` succeeds.
+#[track_caller]
+pub fn assert_write<R: DeviceAddrRange + 'static>(
+    device: &dyn BaseDeviceOps<R>,
+    addr: R::Addr,
+    width: AccessWidth,
+    val: usize,
+) {
+    if let Err(err) = device.handle_write(addr, width, val) {
+        panic!("write of {val:#x} to {addr:?} ({width:?}) failed: {err:?}");
+    }
+}
+
+/// The accesses performed by [`fuzz_accesses`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FuzzReport {
+    /// The number of reads.
+    pub reads: usize,
+    /// The number of writes.
+    pub writes: usize,
+    /// The number of accesses the device failed.
+    pub errors: usize,
+}
+
+/// Decodes `data` into a sequence of accesses to `device` and performs them,
+/// checking basic invariants.
+///
+/// Every [`FUZZ_ACCESS_SIZE`] bytes of `data` describe one access: a control
+/// byte (bit 0: write, bits 1-2: width, bit 3: force natural alignment), a
+/// 32-bit offset reduced into the device range, and a 64-bit value for writes.
+/// All accesses lie within the declared address range of the device, so that
+/// the device must handle them without panicking; errors are allowed.
+///
+/// # Panics
+///
+/// Panics if the device changes its address range, or returns a read value
+/// wider than the access width.
+///
+/// # Example
+///
+/// A fuzz target is then a one-liner:
+///
+/// ```rust,ignore
+/// fuzz_target!(|data: &[u8]| {
+///     axdevice_base::testing::fuzz_accesses(&Uart16550::new(...), data);
+/// });
+/// ```
+pub fn fuzz_accesses<R: DeviceAddrRangeExt + PartialEq + core::fmt::Debug + 'static>(
+    device: &dyn BaseDeviceOps<R>,
+    data: &[u8],
+) -> FuzzReport {
+    let range = device.address_range();
+    let (start, end) = range.raw_bounds();
+    let mut report = FuzzReport::default();
+    for chunk in data.chunks_exact(FUZZ_ACCESS_SIZE) {
+        let ctrl = chunk[0];
+        let width = [
+            AccessWidth::Byte,
+            AccessWidth::Word,
+            AccessWidth::Dword,
+            AccessWidth::Qword,
+        ][(ctrl >> 1) as usize & 3];
+        let size = width.size();
+        let Some(span) = (end - start).checked_sub(size) else {
+            continue;
+        };
+        let mut offset = u32::from_le_bytes(chunk[1..5].try_into().unwrap()) as usize % (span + 1);
+        if ctrl & 0x8 != 0 {
+            offset &= !(size - 1);
+        }
+        let Some(addr) = R::Addr::from_raw(start + offset) else {
+            continue;
+        };
+        let val = u64::from_le_bytes(chunk[5..13].try_into().unwrap()) as usize;
+        let ok = if ctrl & 1 != 0 {
+            report.writes += 1;
+            device.handle_write(addr, width, val).is_ok()
+        } else {
+            report.reads += 1;
+            match device.handle_read(addr, width) {
+                Ok(read) => {
+                    assert!(
+                        read & !width_mask(width) == 0,
+                        "read of {addr:?} ({width:?}) returned {read:#x}, wider than the access"
+                    );
+                    true
+                }
+                Err(_) => false,
+            }
+        };
+        report.errors += !ok as usize;
+        assert_eq!(
+            device.address_range(),
+            range,
+            "device address range changed during an access"
+        );
+    }
+    report
+}
+
+/// The number of input bytes consumed by each access of [`fuzz_accesses`].
+pub const FUZZ_ACCESS_SIZE: usize = 13;
+
+/// Performs `count` pseudo-random accesses to `device` derived from `seed`,
+/// with the checks of [`fuzz_accesses`].
+///
+/// This is a deterministic alternative to a fuzzing engine, for plain unit
+/// tests.
+pub fn fuzz_random<R: DeviceAddrRangeExt + PartialEq + core::fmt::Debug + 'static>(
+    device: &dyn BaseDeviceOps<R>,
+    seed: u64,
+    count: usize,
+) -> FuzzReport {
+    // xorshift64*, which is good enough to pick accesses.
+    let mut state = seed | 1;
+    let data: Vec<u8> = (0..count * FUZZ_ACCESS_SIZE)
+        .map(|_| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+        })
+        .collect();
+    fuzz_accesses(device, &data)
+}
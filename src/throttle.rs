@@ -0,0 +1,331 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate limiting of the accesses to a device.
+
+use alloc::sync::Arc;
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axaddrspace::device::{AccessWidth, DeviceAddrRange};
+use axerrno::{AxError, AxResult};
+use spin::Mutex;
+
+use crate::{
+    AccessContext, AccessOutcome, BaseDeviceOps, ClockSource, RawDeviceAddr,
+    forward::forward_base_device_ops,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// What a [`ThrottledDevice`] does with the accesses exceeding its rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleResponse {
+    /// Fail the access with the given error.
+    Fail(AxError),
+    /// Complete the access without reaching the device: reads return 0 and
+    /// writes are dropped.
+    Drop,
+}
+
+impl Default for ThrottleResponse {
+    fn default() -> Self {
+        Self::Fail(AxError::WouldBlock)
+    }
+}
+
+/// A token bucket, counting tokens in billionths so that it refills smoothly.
+pub(crate) struct TokenBucket {
+    rate: u64,
+    capacity: u64,
+    tokens: u64,
+    last_ns: Option<u64>,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket of `burst` tokens, refilled with `rate` tokens
+    /// per second.
+    pub(crate) fn new(rate: u64, burst: u64) -> Self {
+        let capacity = burst.saturating_mul(NANOS_PER_SEC);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_ns: None,
+        }
+    }
+
+    /// Refills the bucket for the time elapsed since the last refill, given
+    /// the current monotonic time in nanoseconds.
+    pub(crate) fn refill(&mut self, now_ns: u64) {
+        let elapsed = self.last_ns.map_or(0, |last| now_ns.saturating_sub(last));
+        self.last_ns = Some(now_ns);
+        self.tokens = self
+            .tokens
+            .saturating_add(elapsed.saturating_mul(self.rate))
+            .min(self.capacity);
+    }
+
+    /// Takes `n` tokens, or none if fewer are available.
+    pub(crate) fn take(&mut self, n: u64) -> bool {
+        let cost = n.saturating_mul(NANOS_PER_SEC);
+        if self.tokens < cost {
+            return false;
+        }
+        self.tokens -= cost;
+        true
+    }
+
+    /// Takes up to `n` tokens, returning the number taken.
+    pub(crate) fn take_up_to(&mut self, n: u64) -> u64 {
+        let n = n.min(self.tokens / NANOS_PER_SEC);
+        self.tokens -= n * NANOS_PER_SEC;
+        n
+    }
+
+    /// Returns the time until `n` tokens are available, or `None` if the
+    /// bucket is never refilled.
+    pub(crate) fn time_until(&self, n: u64) -> Option<Duration> {
+        let missing = n
+            .saturating_mul(NANOS_PER_SEC)
+            .min(self.capacity)
+            .saturating_sub(self.tokens);
+        match self.rate {
+            0 if missing > 0 => None,
+            0 => Some(Duration::ZERO),
+            rate => Some(Duration::from_nanos(missing.div_ceil(rate))),
+        }
+    }
+}
+
+/// A wrapper limiting the rate of the accesses to a device, so that a guest
+/// hammering its registers cannot starve the other vCPUs.
+///
+/// Accesses are admitted by a token bucket holding up to `burst` accesses and
+/// refilled with `rate` accesses per second. Once the bucket is empty,
+/// accesses get the [`ThrottleResponse`] of the device instead of reaching it.
+/// Calls count as writes, a bulk access of `count` elements takes `count`
+/// tokens at once, and debugger accesses are never throttled. The coalesced
+/// write ring of the wrapped device is not exposed, so that doorbell writes
+/// take tokens like any other access instead of being logged by
+/// [`DeviceManager`](crate::DeviceManager).
+///
+/// The bucket is refilled following the monotonic time of the
+/// [`ClockSource`] injected with [`BaseDeviceOps::set_clock_source`], which is
+/// also passed on to the wrapped device. Until a clock is injected, the bucket
+/// is not refilled.
+pub struct ThrottledDevice<R, D> {
+    inner: D,
+    clock: Mutex<Option<Arc<dyn ClockSource>>>,
+    writes_only: bool,
+    response: ThrottleResponse,
+    bucket: Mutex<TokenBucket>,
+    throttled: AtomicU64,
+    _range: PhantomData<fn() -> R>,
+}
+
+impl<R: DeviceAddrRange, D: BaseDeviceOps<R>> ThrottledDevice<R, D> {
+    /// Wraps `inner`, admitting bursts of up to `burst` accesses and `rate`
+    /// accesses per second on average. The bucket starts full, and excess
+    /// accesses fail with [`AxError::WouldBlock`].
+    pub fn new(inner: D, rate: u64, burst: u64) -> Self {
+        Self {
+            inner,
+            clock: Mutex::new(None),
+            writes_only: false,
+            response: ThrottleResponse::default(),
+            bucket: Mutex::new(TokenBucket::new(rate, burst)),
+            throttled: AtomicU64::new(0),
+            _range: PhantomData,
+        }
+    }
+
+    /// Sets what happens to the accesses exceeding the rate.
+    pub fn with_response(mut self, response: ThrottleResponse) -> Self {
+        self.response = response;
+        self
+    }
+
+    /// Throttles only writes and calls, e.g. for a device whose writes are
+    /// notifications to its backend, leaving reads unlimited.
+    pub fn writes_only(mut self) -> Self {
+        self.writes_only = true;
+        self
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns the number of accesses that were throttled.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Takes a token for an access, returning whether the access may reach
+    /// the device.
+    fn admit(&self, write: bool) -> bool {
+        self.admit_n(write, 1)
+    }
+
+    /// Takes `n` tokens for a bulk access of `n` elements, returning whether
+    /// the whole access may reach the device.
+    fn admit_n(&self, write: bool, n: u64) -> bool {
+        if self.writes_only && !write {
+            return true;
+        }
+        let now_ns = self
+            .clock
+            .lock()
+            .as_ref()
+            .map(|clock| clock.monotonic().as_nanos() as u64);
+        let mut bucket = self.bucket.lock();
+        if let Some(now_ns) = now_ns {
+            bucket.refill(now_ns);
+        }
+        if bucket.take(n) {
+            true
+        } else {
+            self.throttled.fetch_add(n, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Returns the result of a throttled access: the error to fail it with,
+    /// or `Ok` if it completes without reaching the device.
+    fn refuse(&self) -> AxResult {
+        match self.response {
+            ThrottleResponse::Fail(error) => Err(error),
+            ThrottleResponse::Drop => Ok(()),
+        }
+    }
+}
+
+impl<R: DeviceAddrRange + 'static, D: BaseDeviceOps<R>> BaseDeviceOps<R> for ThrottledDevice<R, D> {
+    forward_base_device_ops!(
+        R,
+        inner => identity, peek, poke, lifecycle, set_dma_accessor, set_completer,
+        set_region_sink, set_timer_service, on_timer, abi_version, stats, dump_registers,
+        as_irq_chip, capabilities, manifest
+    );
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        *self.clock.lock() = Some(clock.clone());
+        self.inner.set_clock_source(clock);
+    }
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        if !self.admit(false) {
+            return self.refuse().map(|()| 0);
+        }
+        self.inner.handle_read(addr, width)
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        if !self.admit(true) {
+            return self.refuse();
+        }
+        self.inner.handle_write(addr, width, val)
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        if !self.admit(false) {
+            return self.refuse().map(|()| 0);
+        }
+        self.inner.handle_read_ctx(addr, width, ctx)
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        if !self.admit(true) {
+            return self.refuse();
+        }
+        self.inner.handle_write_ctx(addr, width, val, ctx)
+    }
+
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        if !self.admit_n(false, count as u64) {
+            return self.refuse().map(|()| buf.fill(0));
+        }
+        self.inner.handle_read_bulk(addr, width, count, stride, buf)
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult
+    where
+        R::Addr: RawDeviceAddr,
+    {
+        if !self.admit_n(true, count as u64) {
+            return self.refuse();
+        }
+        self.inner
+            .handle_write_bulk(addr, width, count, stride, buf)
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        if !self.admit(false) {
+            return self.refuse().map(|()| AccessOutcome::Completed(0));
+        }
+        self.inner.handle_read_async(addr, width)
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        if !self.admit(true) {
+            return self.refuse().map(|()| AccessOutcome::Completed(0));
+        }
+        self.inner.handle_write_async(addr, width, val)
+    }
+
+    fn handle_call(&self, addr: R::Addr, args: &[usize]) -> AxResult<[usize; 4]> {
+        if !self.admit(true) {
+            return self.refuse().map(|()| [0; 4]);
+        }
+        self.inner.handle_call(addr, args)
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Timekeeping support for timer-like devices.
+
+use alloc::sync::Arc;
+use core::{
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axerrno::AxResult;
+
+/// How a timer-like device catches up with guest time after a time jump.
+///
+/// A time jump happens when the VM did not run for a while, e.g. after the host
+/// paused it or after a snapshot was restored. A periodic timer that naively
+/// replays every missed period would then fire thousands of stale interrupts at
+/// once. The hypervisor picks one of these policies (usually per VM) and passes
+/// it to [`BaseDeviceOps::on_time_jump`](crate::BaseDeviceOps::on_time_jump).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CatchUpPolicy {
+    /// Drop all missed periods and re-arm timers relative to the current time.
+    ///
+    /// The guest observes a jump in wall-clock time but receives no stale
+    /// interrupts.
+    #[default]
+    Discard,
+    /// Deliver missed periods gradually at a higher rate until the guest has
+    /// caught up, keeping the number of delivered ticks exact.
+    Slew,
+    /// Deliver all missed periods immediately.
+    ///
+    /// This is only suitable for guests that count ticks to keep time and for
+    /// short jumps.
+    InjectAll,
+}
+
+/// Identifies a timer scheduled with a [`TimerService`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerToken(u64);
+
+impl TimerToken {
+    /// Allocates a new token, unique for the lifetime of the hypervisor.
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the raw value of the token.
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// Delivers timed callbacks to devices such as watchdogs and RTCs.
+///
+/// The hypervisor implements this trait and injects it with
+/// [`BaseDeviceOps::set_timer_service`]. When a timer expires, it calls
+/// [`DeviceManager::fire_timer`] with the token of the timer, which in turn
+/// calls [`BaseDeviceOps::on_timer`] on the device that scheduled it.
+///
+/// [`BaseDeviceOps::set_timer_service`]: crate::BaseDeviceOps::set_timer_service
+/// [`BaseDeviceOps::on_timer`]: crate::BaseDeviceOps::on_timer
+/// [`DeviceManager::fire_timer`]: crate::DeviceManager::fire_timer
+pub trait TimerService: Send + Sync {
+    /// Schedules the timer `token` to expire once `delay` has elapsed.
+    ///
+    /// Scheduling a token that is already pending re-arms it with the new
+    /// delay.
+    fn schedule_after(&self, delay: Duration, token: TimerToken) -> AxResult;
+
+    /// Cancels the timer `token`, returning whether it was pending.
+    fn cancel(&self, token: TimerToken) -> bool;
+}
+
+/// A host clock, providing monotonic ticks and wall-clock time to devices such
+/// as RTCs without binding them to architecture-specific counters.
+///
+/// The hypervisor implements this trait and injects it with
+/// [`BaseDeviceOps::set_clock_source`]; usually it injects a [`GuestClock`] so
+/// that the guest can keep its own wall-clock time.
+///
+/// [`BaseDeviceOps::set_clock_source`]: crate::BaseDeviceOps::set_clock_source
+pub trait ClockSource: Send + Sync {
+    /// Returns the current value of the monotonic tick counter.
+    fn ticks(&self) -> u64;
+
+    /// Returns the frequency of the tick counter in Hz.
+    fn frequency(&self) -> u64;
+
+    /// Returns the wall-clock time as the time elapsed since the Unix epoch.
+    fn wall_time(&self) -> Duration;
+
+    /// Returns the monotonic time elapsed since the tick counter was 0.
+    fn monotonic(&self) -> Duration {
+        let nanos = self.ticks() as u128 * 1_000_000_000 / self.frequency().max(1) as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// The clock of a guest, whose wall-clock time is offset from the host's.
+///
+/// The ticks are those of the host clock, so that the guest's monotonic time
+/// never jumps when its wall-clock time is set.
+pub struct GuestClock {
+    host: Arc<dyn ClockSource>,
+    offset_ns: AtomicI64,
+}
+
+impl GuestClock {
+    /// Creates a guest clock following `host`, with no offset.
+    pub fn new(host: Arc<dyn ClockSource>) -> Self {
+        Self {
+            host,
+            offset_ns: AtomicI64::new(0),
+        }
+    }
+
+    /// Returns the offset of the guest's wall-clock time from the host's, in
+    /// nanoseconds.
+    pub fn offset_ns(&self) -> i64 {
+        self.offset_ns.load(Ordering::Relaxed)
+    }
+
+    /// Sets the offset of the guest's wall-clock time from the host's, in
+    /// nanoseconds.
+    pub fn set_offset_ns(&self, offset_ns: i64) {
+        self.offset_ns.store(offset_ns, Ordering::Relaxed);
+    }
+
+    /// Sets the offset such that the guest's wall-clock time is now `time`,
+    /// e.g. when the guest programs an RTC.
+    pub fn set_wall_time(&self, time: Duration) {
+        let host = self.host.wall_time().as_nanos() as i128;
+        let offset = (time.as_nanos() as i128 - host).clamp(i64::MIN as i128, i64::MAX as i128);
+        self.set_offset_ns(offset as i64);
+    }
+}
+
+impl ClockSource for GuestClock {
+    fn ticks(&self) -> u64 {
+        self.host.ticks()
+    }
+
+    fn frequency(&self) -> u64 {
+        self.host.frequency()
+    }
+
+    fn wall_time(&self) -> Duration {
+        let nanos = self.host.wall_time().as_nanos() as i128 + self.offset_ns() as i128;
+        Duration::from_nanos(nanos.clamp(0, u64::MAX as i128) as u64)
+    }
+}
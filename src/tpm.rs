@@ -0,0 +1,296 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TPM TIS (TPM Interface Specification) MMIO transport.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, RegValue, TpmBackend, width_mask};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TisState {
+    Idle,
+    Ready,
+    Reception,
+    Completion,
+}
+
+struct TisInner {
+    active: Option<u8>,
+    pending: u8,
+    state: TisState,
+    buffer: Vec<u8>,
+    read_pos: usize,
+}
+
+impl TisInner {
+    /// Returns the size of the command being received, as given by its
+    /// header, or `None` if the header is incomplete.
+    fn expected_size(&self) -> Option<usize> {
+        let size = self.buffer.get(2..6)?;
+        Some(u32::from_be_bytes(size.try_into().unwrap()) as usize)
+    }
+
+    fn expects_data(&self) -> bool {
+        self.state == TisState::Reception
+            && self
+                .expected_size()
+                .is_none_or(|size| self.buffer.len() < size)
+    }
+}
+
+/// A TPM behind the TIS FIFO interface, delegating commands to a
+/// [`TpmBackend`].
+///
+/// The device covers the five 4 KiB locality pages (conventionally mapped at
+/// `0xfed4_0000`) and implements the locality and command protocol:
+///
+/// - A locality is requested by writing `requestUse` to its `TPM_ACCESS`, and
+///   relinquished by writing `activeLocality`. A relinquished TPM is handed to
+///   the highest pending locality.
+/// - Only the active locality may use `TPM_STS` and `TPM_DATA_FIFO`; reads
+///   from other localities return all ones and writes are ignored.
+/// - The command is written to the FIFO after setting `commandReady`, and is
+///   executed synchronously when `tpmGo` is written, after which the response
+///   can be read back from the FIFO.
+///
+/// Interrupts are not supported; `TPM_INTF_CAPABILITY` reports none, so guests
+/// poll `TPM_STS`.
+pub struct TpmTisDevice {
+    range: GuestPhysAddrRange,
+    backend: Arc<dyn TpmBackend>,
+    inner: Mutex<TisInner>,
+}
+
+impl TpmTisDevice {
+    const LOCALITY_COUNT: usize = 5;
+    const LOCALITY_SHIFT: usize = 12;
+
+    const REG_ACCESS: usize = 0x00;
+    const REG_INTF_CAPABILITY: usize = 0x14;
+    const REG_STS: usize = 0x18;
+    const REG_DATA_FIFO: usize = 0x24;
+    const REG_DID_VID: usize = 0xf00;
+    const REG_RID: usize = 0xf04;
+
+    const ACCESS_VALID: u8 = 1 << 7;
+    const ACCESS_ACTIVE_LOCALITY: u8 = 1 << 5;
+    const ACCESS_PENDING_REQUEST: u8 = 1 << 2;
+    const ACCESS_REQUEST_USE: u8 = 1 << 1;
+    const ACCESS_ESTABLISHMENT: u8 = 1 << 0;
+
+    const STS_VALID: u32 = 1 << 7;
+    const STS_COMMAND_READY: u32 = 1 << 6;
+    const STS_GO: u32 = 1 << 5;
+    const STS_DATA_AVAIL: u32 = 1 << 4;
+    const STS_EXPECT: u32 = 1 << 3;
+    const STS_RESPONSE_RETRY: u32 = 1 << 1;
+    const STS_BURST_SHIFT: u32 = 8;
+
+    const DID_VID: u32 = 0x0001_1014;
+    const RID: u32 = 0x01;
+
+    /// The size of the command and response buffer.
+    pub const BUFFER_SIZE: usize = 4096;
+
+    /// `TPM_RC_FAILURE` response, returned when the backend fails.
+    const FAILURE_RESPONSE: [u8; 10] = [0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x01];
+
+    /// Creates a TPM at `base` whose commands are executed by `backend`.
+    pub fn new(base: GuestPhysAddr, backend: Arc<dyn TpmBackend>) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(
+                base,
+                Self::LOCALITY_COUNT << Self::LOCALITY_SHIFT,
+            ),
+            backend,
+            inner: Mutex::new(TisInner {
+                active: None,
+                pending: 0,
+                state: TisState::Idle,
+                buffer: Vec::with_capacity(Self::BUFFER_SIZE),
+                read_pos: 0,
+            }),
+        }
+    }
+
+    /// Returns the currently active locality, if any.
+    pub fn active_locality(&self) -> Option<u8> {
+        self.inner.lock().active
+    }
+
+    fn read_access(inner: &TisInner, locality: u8) -> u8 {
+        let mut access = Self::ACCESS_VALID | Self::ACCESS_ESTABLISHMENT;
+        if inner.active == Some(locality) {
+            access |= Self::ACCESS_ACTIVE_LOCALITY;
+            if inner.pending & !(1 << locality) != 0 {
+                access |= Self::ACCESS_PENDING_REQUEST;
+            }
+        }
+        access
+    }
+
+    fn write_access(inner: &mut TisInner, locality: u8, val: u8) {
+        if val & Self::ACCESS_REQUEST_USE != 0 {
+            match inner.active {
+                None => inner.active = Some(locality),
+                Some(active) if active != locality => inner.pending |= 1 << locality,
+                Some(_) => {}
+            }
+        }
+        if val & Self::ACCESS_ACTIVE_LOCALITY != 0 {
+            if inner.active == Some(locality) {
+                inner.active = (0..Self::LOCALITY_COUNT as u8)
+                    .rev()
+                    .find(|l| inner.pending & (1 << l) != 0);
+                if let Some(next) = inner.active {
+                    inner.pending &= !(1 << next);
+                }
+                inner.state = TisState::Idle;
+                inner.buffer.clear();
+            } else {
+                inner.pending &= !(1 << locality);
+            }
+        }
+    }
+
+    fn read_sts(inner: &TisInner) -> u32 {
+        let mut sts = Self::STS_VALID;
+        let burst = match inner.state {
+            TisState::Idle => 0,
+            TisState::Ready | TisState::Reception => Self::BUFFER_SIZE - inner.buffer.len(),
+            TisState::Completion => inner.buffer.len() - inner.read_pos,
+        };
+        if inner.state == TisState::Ready {
+            sts |= Self::STS_COMMAND_READY;
+        }
+        if inner.expects_data() {
+            sts |= Self::STS_EXPECT;
+        }
+        if inner.state == TisState::Completion && burst > 0 {
+            sts |= Self::STS_DATA_AVAIL;
+        }
+        sts | ((burst.min(0xffff) as u32) << Self::STS_BURST_SHIFT)
+    }
+
+    fn write_sts(&self, inner: &mut TisInner, locality: u8, val: u32) {
+        if val & Self::STS_COMMAND_READY != 0 {
+            inner.state = TisState::Ready;
+            inner.buffer.clear();
+            inner.read_pos = 0;
+        } else if val & Self::STS_GO != 0 {
+            if inner.state == TisState::Reception && !inner.expects_data() {
+                self.execute(inner, locality);
+            }
+        } else if val & Self::STS_RESPONSE_RETRY != 0 && inner.state == TisState::Completion {
+            inner.read_pos = 0;
+        }
+    }
+
+    fn execute(&self, inner: &mut TisInner, locality: u8) {
+        let response = self
+            .backend
+            .execute(locality, &inner.buffer)
+            .unwrap_or_else(|err| {
+                warn!("TPM backend failed: {err:?}");
+                Self::FAILURE_RESPONSE.to_vec()
+            });
+        inner.buffer = response;
+        inner.buffer.truncate(Self::BUFFER_SIZE);
+        inner.read_pos = 0;
+        inner.state = TisState::Completion;
+    }
+
+    /// Splits an address into a locality and a register offset, rejecting
+    /// accesses crossing a locality page.
+    fn decode(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<(u8, usize)> {
+        if !self.range.contains_access(addr, width) {
+            return ax_err!(BadAddress);
+        }
+        let offset = addr.as_usize() - self.range.start.as_usize();
+        let reg = offset & ((1 << Self::LOCALITY_SHIFT) - 1);
+        if reg + width.size() > 1 << Self::LOCALITY_SHIFT {
+            return ax_err!(BadAddress);
+        }
+        Ok(((offset >> Self::LOCALITY_SHIFT) as u8, reg))
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for TpmTisDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        let (locality, reg) = self.decode(addr, width)?;
+        let mut inner = self.inner.lock();
+        let is_active = inner.active == Some(locality);
+        let (base, val) = match reg {
+            Self::REG_ACCESS => (reg, Self::read_access(&inner, locality) as u32),
+            Self::REG_INTF_CAPABILITY..Self::REG_STS => (Self::REG_INTF_CAPABILITY, 0),
+            Self::REG_STS..Self::REG_DATA_FIFO if !is_active => return Ok(width_mask(width)),
+            Self::REG_STS..Self::REG_DATA_FIFO => (Self::REG_STS, Self::read_sts(&inner)),
+            Self::REG_DATA_FIFO..0x28 => {
+                if !is_active || inner.state != TisState::Completion {
+                    return Ok(width_mask(width));
+                }
+                let mut bytes = [0xff; 8];
+                for byte in &mut bytes[..width.size()] {
+                    if let Some(&b) = inner.buffer.get(inner.read_pos) {
+                        *byte = b;
+                        inner.read_pos += 1;
+                    }
+                }
+                return Ok(RegValue::from_bytes(&bytes[..width.size()])
+                    .unwrap()
+                    .zero_extend());
+            }
+            Self::REG_DID_VID..Self::REG_RID => (Self::REG_DID_VID, Self::DID_VID),
+            Self::REG_RID => (reg, Self::RID),
+            _ => (reg, 0),
+        };
+        Ok(RegValue::extract(val as usize, reg - base, width).zero_extend())
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        let (locality, reg) = self.decode(addr, width)?;
+        let mut inner = self.inner.lock();
+        match reg {
+            Self::REG_ACCESS => Self::write_access(&mut inner, locality, val as u8),
+            _ if inner.active != Some(locality) => {}
+            Self::REG_STS => self.write_sts(&mut inner, locality, val as u32),
+            Self::REG_DATA_FIFO..0x28 => {
+                if matches!(inner.state, TisState::Ready | TisState::Reception) {
+                    inner.state = TisState::Reception;
+                    let room = Self::BUFFER_SIZE - inner.buffer.len();
+                    let bytes = RegValue::new(val, width);
+                    let bytes = bytes.to_bytes();
+                    inner
+                        .buffer
+                        .extend_from_slice(&bytes[..bytes.len().min(room)]);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracing of the accesses dispatched to devices.
+
+use axaddrspace::device::{AccessWidth, DeviceAddrRange};
+use axerrno::{AxError, AxResult, ax_err};
+
+use crate::{AccessKind, BaseDeviceOps};
+
+/// Observes the accesses dispatched by a [`DeviceManager`].
+///
+/// Install a tracer with [`DeviceManager::set_tracer`] to debug guest drivers
+/// without instrumenting every device. The tracer is called after each access
+/// routed to a device, from the accessing vCPU, so it should be cheap: the
+/// usual implementation encodes a [`TraceRecord`] into a ring buffer.
+///
+/// Monitoring tools interested in a few devices only subscribe to their
+/// ranges with [`DeviceManager::add_shadow_subscriber`] instead, and receive
+/// the same calls for the accesses within those ranges.
+///
+/// [`DeviceManager`]: crate::DeviceManager
+/// [`DeviceManager::set_tracer`]: crate::DeviceManager::set_tracer
+/// [`DeviceManager::add_shadow_subscriber`]: crate::DeviceManager::add_shadow_subscriber
+pub trait DeviceTracer<R: DeviceAddrRange>: Send + Sync {
+    /// Called after `device` handled an access.
+    ///
+    /// `value` is the value written, or the value read if the read succeeded,
+    /// and 0 otherwise.
+    fn on_access(
+        &self,
+        device: &dyn BaseDeviceOps<R>,
+        addr: R::Addr,
+        width: AccessWidth,
+        value: usize,
+        kind: AccessKind,
+        result: AxResult,
+    );
+}
+
+/// A traced access, with a compact binary encoding.
+///
+/// The encoding is [`SIZE`](Self::SIZE) bytes long, little-endian:
+///
+/// | Offset | Size | Field                                          |
+/// |--------|------|------------------------------------------------|
+/// | 0      | 8    | timestamp in nanoseconds                       |
+/// | 8      | 8    | raw address                                    |
+/// | 16     | 8    | value                                          |
+/// | 24     | 1    | access width in bytes; bit 7 set for writes    |
+/// | 25     | 1    | error code (see `AxError::code`), 0 on success |
+///
+/// # Example
+///
+/// ```rust
+/// use axaddrspace::device::AccessWidth;
+/// use axdevice_base::{AccessKind, TraceRecord};
+///
+/// let record = TraceRecord {
+///     timestamp_ns: 1000,
+///     addr: 0x0900_0000,
+///     value: 0x41,
+///     width: AccessWidth::Byte,
+///     kind: AccessKind::Write,
+///     error: None,
+/// };
+/// let bytes = record.encode();
+/// assert_eq!(TraceRecord::decode(&bytes).unwrap(), record);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// The time of the access, from the clock of the tracer.
+    pub timestamp_ns: u64,
+    /// The raw accessed address.
+    pub addr: u64,
+    /// The value read or written.
+    pub value: u64,
+    /// The access width.
+    pub width: AccessWidth,
+    /// Whether the access was a read or a write.
+    pub kind: AccessKind,
+    /// The error returned by the device, if any.
+    pub error: Option<AxError>,
+}
+
+impl TraceRecord {
+    /// The size of an encoded record.
+    pub const SIZE: usize = 26;
+
+    const WRITE: u8 = 1 << 7;
+
+    /// Encodes the record.
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.timestamp_ns.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.addr.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.value.to_le_bytes());
+        bytes[24] = self.width.size() as u8;
+        if self.kind == AccessKind::Write {
+            bytes[24] |= Self::WRITE;
+        }
+        bytes[25] = self.error.map_or(0, |err| err.code() as u8);
+        bytes
+    }
+
+    /// Decodes a record from the first [`SIZE`](Self::SIZE) bytes of `bytes`.
+    ///
+    /// Returns `Err(AxError::InvalidData)` if `bytes` is too short or does
+    /// not hold a valid record.
+    pub fn decode(bytes: &[u8]) -> AxResult<Self> {
+        if bytes.len() < Self::SIZE {
+            return ax_err!(InvalidData, "truncated trace record");
+        }
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let Ok(width) = AccessWidth::try_from((bytes[24] & !Self::WRITE) as usize) else {
+            return ax_err!(InvalidData, "invalid access width in trace record");
+        };
+        let error = match bytes[25] {
+            0 => None,
+            code => match AxError::try_from(code as i32) {
+                Ok(err) => Some(err),
+                Err(_) => return ax_err!(InvalidData, "invalid error code in trace record"),
+            },
+        };
+        Ok(Self {
+            timestamp_ns: u64_at(0),
+            addr: u64_at(8),
+            value: u64_at(16),
+            width,
+            kind: if bytes[24] & Self::WRITE != 0 {
+                AccessKind::Write
+            } else {
+                AccessKind::Read
+            },
+            error,
+        })
+    }
+}
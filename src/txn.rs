@@ -0,0 +1,138 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Two-phase register writes committed by a trigger register.
+
+use alloc::collections::BTreeMap;
+
+use axerrno::AxResult;
+use spin::Mutex;
+
+struct TransactionState {
+    staged: BTreeMap<usize, usize>,
+    committed: BTreeMap<usize, usize>,
+}
+
+/// A group of registers whose writes are buffered and applied atomically when
+/// a trigger ("GO") bit is written.
+///
+/// Writes to the staged offsets only update the staged copy of the registers.
+/// Writing the trigger register with any bit of the trigger mask set passes
+/// the complete staged set to the device's commit callback. If the callback
+/// accepts it, the staged set becomes the committed set; if it fails
+/// validation, the staged set is rolled back to the last committed values and
+/// the error is returned.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::TransactionalRegion;
+///
+/// // DMA source at 0x0, length at 0x4, GO is bit 0 of 0x8.
+/// let regs = TransactionalRegion::new(&[0x0, 0x4], 0x8, 0x1);
+/// regs.write(0x0, 0x8000, |_| Ok(())).unwrap().unwrap();
+/// regs.write(0x4, 0x100, |_| Ok(())).unwrap().unwrap();
+/// assert_eq!(regs.committed(0x4), Some(0));
+///
+/// regs.write(0x8, 0x1, |snapshot| {
+///     assert_eq!(snapshot[&0x4], 0x100);
+///     Ok(())
+/// })
+/// .unwrap()
+/// .unwrap();
+/// assert_eq!(regs.committed(0x4), Some(0x100));
+/// ```
+pub struct TransactionalRegion {
+    trigger: usize,
+    trigger_mask: usize,
+    state: Mutex<TransactionState>,
+}
+
+impl TransactionalRegion {
+    /// Creates a region staging writes to `offsets`, committed when `trigger`
+    /// is written with a value intersecting `trigger_mask`. All registers
+    /// start at zero.
+    pub fn new(offsets: &[usize], trigger: usize, trigger_mask: usize) -> Self {
+        let regs: BTreeMap<_, _> = offsets.iter().map(|&offset| (offset, 0)).collect();
+        Self {
+            trigger,
+            trigger_mask,
+            state: Mutex::new(TransactionState {
+                staged: regs.clone(),
+                committed: regs,
+            }),
+        }
+    }
+
+    /// Returns the staged value of the register at `offset`, or `None` if the
+    /// offset is not part of the region.
+    ///
+    /// Guests reading back a staged register see the value they wrote, even
+    /// before it is committed.
+    pub fn read(&self, offset: usize) -> Option<usize> {
+        self.state.lock().staged.get(&offset).copied()
+    }
+
+    /// Returns the committed value of the register at `offset`, or `None` if
+    /// the offset is not part of the region.
+    pub fn committed(&self, offset: usize) -> Option<usize> {
+        self.state.lock().committed.get(&offset).copied()
+    }
+
+    /// Handles a guest write of `val` at `offset`.
+    ///
+    /// Returns `None` if `offset` is neither a staged register nor the trigger
+    /// register, so that the device can handle the write itself. Otherwise
+    /// returns the result of the write, which is the result of `commit` if the
+    /// write triggered a commit.
+    ///
+    /// `commit` is called with the staged offsets and values while the region
+    /// is locked, so it must not access the region.
+    pub fn write(
+        &self,
+        offset: usize,
+        val: usize,
+        commit: impl FnOnce(&BTreeMap<usize, usize>) -> AxResult,
+    ) -> Option<AxResult> {
+        let mut state = self.state.lock();
+        if let Some(reg) = state.staged.get_mut(&offset) {
+            *reg = val;
+            return Some(Ok(()));
+        }
+        if offset != self.trigger {
+            return None;
+        }
+        if val & self.trigger_mask == 0 {
+            return Some(Ok(()));
+        }
+        let state = &mut *state;
+        match commit(&state.staged) {
+            Ok(()) => {
+                state.committed.clone_from(&state.staged);
+                Some(Ok(()))
+            }
+            Err(err) => {
+                state.staged.clone_from(&state.committed);
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// Discards all staged writes, e.g. on a device reset.
+    pub fn rollback(&self) {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        state.staged.clone_from(&state.committed);
+    }
+}
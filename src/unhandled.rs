@@ -0,0 +1,93 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Policies for guest accesses that no device handles.
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxError, AxResult};
+
+/// How a guest access that is not handled is completed.
+///
+/// An access is unhandled if it misses all devices (`AxError::NotFound`) or if
+/// the device does not implement the accessed register
+/// (`AxError::Unsupported`). Real guests probe undocumented offsets, and
+/// depending on the platform expect either a fault or reads as zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum UnhandledAccessPolicy {
+    /// The access fails with its error, which the hypervisor usually reflects
+    /// to the guest as a fault.
+    #[default]
+    Fault,
+    /// Reads return 0 and writes are ignored, silently.
+    ReadAsZeroWriteIgnore,
+    /// Reads return 0 and writes are ignored, logging a warning.
+    LogAndIgnore,
+}
+
+impl UnhandledAccessPolicy {
+    /// Returns whether `error` marks an unhandled access.
+    pub fn is_unhandled(error: AxError) -> bool {
+        matches!(error, AxError::NotFound | AxError::Unsupported)
+    }
+
+    /// Applies the policy to `result`, the result of a read of `addr`.
+    ///
+    /// Results other than unhandled errors are returned unchanged.
+    pub fn resolve_read(
+        self,
+        addr: usize,
+        width: AccessWidth,
+        result: AxResult<usize>,
+    ) -> AxResult<usize> {
+        match result {
+            Err(error) if Self::is_unhandled(error) => {
+                self.ignore(error, |error| {
+                    warn!("ignoring unhandled {width:?} read of {addr:#x}: {error:?}")
+                })?;
+                Ok(0)
+            }
+            result => result,
+        }
+    }
+
+    /// Applies the policy to `result`, the result of a write of `val` to
+    /// `addr`.
+    ///
+    /// Results other than unhandled errors are returned unchanged.
+    pub fn resolve_write(
+        self,
+        addr: usize,
+        width: AccessWidth,
+        val: usize,
+        result: AxResult,
+    ) -> AxResult {
+        match result {
+            Err(error) if Self::is_unhandled(error) => self.ignore(error, |error| {
+                warn!("ignoring unhandled {width:?} write of {val:#x} to {addr:#x}: {error:?}")
+            }),
+            result => result,
+        }
+    }
+
+    fn ignore(self, error: AxError, log: impl FnOnce(AxError)) -> AxResult {
+        match self {
+            Self::Fault => Err(error),
+            Self::ReadAsZeroWriteIgnore => Ok(()),
+            Self::LogAndIgnore => {
+                log(error);
+                Ok(())
+            }
+        }
+    }
+}
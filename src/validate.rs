@@ -0,0 +1,186 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validation of device configurations at VM build time.
+
+use alloc::string::String;
+use core::{fmt, ops::RangeInclusive};
+
+use axerrno::AxError;
+
+use crate::EmulatedDeviceConfig;
+
+/// Why a device rejected its [`EmulatedDeviceConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The device range has a length of 0.
+    ZeroLength,
+    /// The device range has the wrong length.
+    BadLength {
+        /// The configured length.
+        length: usize,
+        /// The length the device requires.
+        expected: usize,
+    },
+    /// The base address is not aligned as the device requires.
+    MisalignedBase {
+        /// The configured base address.
+        base: usize,
+        /// The required alignment.
+        align: usize,
+    },
+    /// The IRQ is not in the range supported by the device.
+    IrqOutOfRange {
+        /// The configured IRQ.
+        irq: usize,
+        /// The IRQs the device supports.
+        supported: RangeInclusive<usize>,
+    },
+    /// A region of [`EmulatedDeviceConfig::regions`] is empty or does not fit
+    /// in its address space.
+    BadRegion {
+        /// The index of the region.
+        index: usize,
+    },
+    /// A required parameter is missing.
+    MissingParam(String),
+    /// A parameter has the wrong type or an unsupported value.
+    BadParam {
+        /// The parameter name.
+        key: String,
+        /// What is wrong with the value.
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroLength => write!(f, "device length is 0"),
+            Self::BadLength { length, expected } => {
+                write!(f, "device length is {length:#x}, expected {expected:#x}")
+            }
+            Self::MisalignedBase { base, align } => {
+                write!(f, "base address {base:#x} is not aligned to {align:#x}")
+            }
+            Self::IrqOutOfRange { irq, supported } => write!(
+                f,
+                "IRQ {irq} is not in {}..={}",
+                supported.start(),
+                supported.end()
+            ),
+            Self::BadRegion { index } => write!(f, "region {index} is empty or out of bounds"),
+            Self::MissingParam(key) => write!(f, "missing parameter `{key}`"),
+            Self::BadParam { key, reason } => write!(f, "parameter `{key}`: {reason}"),
+        }
+    }
+}
+
+impl From<ConfigError> for AxError {
+    fn from(_: ConfigError) -> Self {
+        AxError::InvalidInput
+    }
+}
+
+/// Devices checking their configuration before they are created.
+///
+/// The VMM calls [`validate_config`](Self::validate_config) for every device
+/// while building a VM, so that bad configurations are reported with an
+/// actionable [`ConfigError`] instead of failing on the first guest access.
+///
+/// # Example
+///
+/// ```rust
+/// use axdevice_base::{ConfigError, EmulatedDeviceConfig, ValidateConfig};
+///
+/// struct Pl011;
+///
+/// impl ValidateConfig for Pl011 {
+///     fn validate_config(cfg: &EmulatedDeviceConfig) -> Result<(), ConfigError> {
+///         cfg.check_length(0x1000)?;
+///         cfg.check_base_aligned(0x1000)?;
+///         cfg.check_irq(32..=1019)
+///     }
+/// }
+///
+/// let cfg = EmulatedDeviceConfig {
+///     base_ipa: 0x0900_0000,
+///     length: 0x1000,
+///     irq_id: 8,
+///     ..Default::default()
+/// };
+/// assert!(matches!(
+///     Pl011::validate_config(&cfg),
+///     Err(ConfigError::IrqOutOfRange { irq: 8, .. })
+/// ));
+/// ```
+pub trait ValidateConfig {
+    /// Checks whether the device can be created from `cfg`.
+    fn validate_config(cfg: &EmulatedDeviceConfig) -> Result<(), ConfigError>;
+}
+
+impl EmulatedDeviceConfig {
+    /// Checks that the device range is exactly `expected` long.
+    pub fn check_length(&self, expected: usize) -> Result<(), ConfigError> {
+        match self.length {
+            0 => Err(ConfigError::ZeroLength),
+            length if length != expected => Err(ConfigError::BadLength { length, expected }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the base address is aligned to `align`, which must be a
+    /// power of two.
+    pub fn check_base_aligned(&self, align: usize) -> Result<(), ConfigError> {
+        debug_assert!(align.is_power_of_two());
+        if self.base_ipa & (align - 1) != 0 {
+            return Err(ConfigError::MisalignedBase {
+                base: self.base_ipa,
+                align,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the IRQ is in `supported`.
+    pub fn check_irq(&self, supported: RangeInclusive<usize>) -> Result<(), ConfigError> {
+        if !supported.contains(&self.irq_id) {
+            return Err(ConfigError::IrqOutOfRange {
+                irq: self.irq_id,
+                supported,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that all [`regions`](Self::regions) are valid address ranges.
+    pub fn check_regions(&self) -> Result<(), ConfigError> {
+        match self
+            .regions
+            .iter()
+            .position(|region| region.address_range().is_err())
+        {
+            Some(index) => Err(ConfigError::BadRegion { index }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that the parameter `key` is set.
+    pub fn require_param(&self, key: &str) -> Result<(), ConfigError> {
+        if !self.params.contains_key(key) {
+            return Err(ConfigError::MissingParam(key.into()));
+        }
+        Ok(())
+    }
+}
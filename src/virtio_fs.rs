@@ -0,0 +1,591 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The virtio-fs device over a host filesystem share.
+
+use alloc::{collections::BTreeMap, format, string::String, sync::Arc, vec, vec::Vec};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxError, AxResult, LinuxError, ax_err};
+use spin::Mutex;
+
+use crate::{
+    BaseDeviceOps, DescChain, DeviceAddrRangeExt, EmuDeviceType, FsAttr, FsBackend, FsHandle,
+    GuestMemoryAccessor, RegValue, SplitQueue, VirtioMmioDevice, VirtioMmioRegs, scope_path,
+};
+
+/// FUSE opcodes handled by the device.
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+/// The FUSE protocol version spoken by the device.
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+
+/// The node ID of the share root.
+const FUSE_ROOT_ID: u64 = 1;
+
+const FUSE_IN_HEADER_LEN: usize = 40;
+const FUSE_OUT_HEADER_LEN: usize = 16;
+
+/// The largest write the driver may send, advertised in `FUSE_INIT`.
+const FUSE_MAX_WRITE: u32 = 0x2_0000;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const DT_DIR: u32 = 4;
+const DT_REG: u32 = 8;
+const O_ACCMODE: u32 = 3;
+
+const VIRTIO_ID_FS: u32 = 26;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// The queue of forget and interrupt requests.
+const HIPRIO_QUEUE: usize = 0;
+
+/// The maximum length of the mount tag in the configuration space.
+const TAG_LEN: usize = 36;
+
+/// A file or directory opened by the driver.
+enum OpenFile {
+    File { handle: FsHandle, write: bool },
+    Dir { path: String },
+}
+
+/// A node looked up by the driver, until it is forgotten.
+struct Node {
+    path: String,
+    lookups: u64,
+}
+
+/// The FUSE session state: the nodes known to the driver and its open files.
+///
+/// Node IDs and file handles are allocated by the device, so the driver can
+/// only name paths it has looked up and files it has opened.
+struct FuseState {
+    nodes: BTreeMap<u64, Node>,
+    paths: BTreeMap<String, u64>,
+    files: BTreeMap<u64, OpenFile>,
+    next_node: u64,
+    next_fh: u64,
+}
+
+impl FuseState {
+    fn new() -> Self {
+        let mut state = Self {
+            nodes: BTreeMap::new(),
+            paths: BTreeMap::new(),
+            files: BTreeMap::new(),
+            next_node: FUSE_ROOT_ID + 1,
+            next_fh: 1,
+        };
+        state.insert_node(FUSE_ROOT_ID, String::new());
+        state
+    }
+
+    fn insert_node(&mut self, id: u64, path: String) {
+        self.paths.insert(path.clone(), id);
+        self.nodes.insert(id, Node { path, lookups: 1 });
+    }
+
+    fn path(&self, node: u64) -> AxResult<&str> {
+        match self.nodes.get(&node) {
+            Some(node) => Ok(&node.path),
+            None => ax_err!(NotFound, "unknown FUSE node"),
+        }
+    }
+
+    /// Returns the node ID of `path`, counting one more lookup.
+    fn lookup(&mut self, path: String) -> u64 {
+        if let Some(&id) = self.paths.get(&path) {
+            self.nodes.get_mut(&id).unwrap().lookups += 1;
+            return id;
+        }
+        let id = self.next_node;
+        self.next_node += 1;
+        self.insert_node(id, path);
+        id
+    }
+
+    fn forget(&mut self, node: u64, lookups: u64) {
+        if node == FUSE_ROOT_ID {
+            return;
+        }
+        let Some(entry) = self.nodes.get_mut(&node) else {
+            return;
+        };
+        entry.lookups = entry.lookups.saturating_sub(lookups);
+        if entry.lookups == 0 {
+            let entry = self.nodes.remove(&node).unwrap();
+            self.paths.remove(&entry.path);
+        }
+    }
+
+    fn open(&mut self, file: OpenFile) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.files.insert(fh, file);
+        fh
+    }
+
+    fn file(&self, fh: u64) -> AxResult<&OpenFile> {
+        match self.files.get(&fh) {
+            Some(file) => Ok(file),
+            None => ax_err!(BadFileDescriptor, "unknown FUSE file handle"),
+        }
+    }
+}
+
+/// A virtio-fs device (virtio 1.2, section 5.11) exporting a [`FsBackend`]
+/// share to the guest over the virtio-mmio transport.
+///
+/// The device speaks the FUSE protocol on its request queue (queue 1) and
+/// implements the subset needed to mount the share and read, write and list
+/// its files: `INIT`, `DESTROY`, `LOOKUP`, `FORGET`, `GETATTR`, `STATFS`,
+/// `OPEN`, `READ`, `WRITE`, `FLUSH`, `RELEASE`, `OPENDIR`, `READDIR` and
+/// `RELEASEDIR`. Other requests fail with `ENOSYS`. On the high-priority
+/// queue 0, where drivers send forgets and interrupts, only forgets are acted
+/// on: requests complete before the driver could interrupt them, so other
+/// requests are returned without a reply.
+///
+/// The guest never names host paths directly: every path is built from a
+/// node ID the device handed out and a single name component, and passes
+/// through [`scope_path`] before reaching the backend. File handles are
+/// likewise allocated by the device, and writes to a read-only share fail
+/// with `EROFS` before reaching the backend.
+///
+/// Requests are processed synchronously when the driver notifies the queue,
+/// through the accessor injected with
+/// [`set_dma_accessor`](BaseDeviceOps::set_dma_accessor). Used buffer
+/// notifications are recorded in [`regs`](Self::regs); injecting the
+/// interrupt is left to the hypervisor.
+pub struct VirtioFsDevice {
+    range: GuestPhysAddrRange,
+    regs: VirtioMmioRegs,
+    backend: Arc<dyn FsBackend>,
+    config: [u8; TAG_LEN + 4],
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+    queues: Mutex<[Option<SplitQueue>; 2]>,
+    fuse: Mutex<FuseState>,
+}
+
+impl VirtioFsDevice {
+    /// The vendor ID reported by the device.
+    pub const VENDOR_ID: u32 = 0x554d_4551;
+
+    /// The maximum size of each virtqueue.
+    pub const QUEUE_SIZE: u16 = 128;
+
+    /// Creates a virtio-fs device at `base` exporting `backend` under the
+    /// mount tag `tag`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `tag` is empty or longer than
+    /// 36 bytes.
+    pub fn new(base: GuestPhysAddr, backend: Arc<dyn FsBackend>, tag: &str) -> AxResult<Self> {
+        if tag.is_empty() || tag.len() > TAG_LEN {
+            return ax_err!(InvalidInput, "invalid virtio-fs tag");
+        }
+        let mut config = [0; TAG_LEN + 4];
+        config[..tag.len()].copy_from_slice(tag.as_bytes());
+        // One request queue.
+        config[TAG_LEN..].copy_from_slice(&1u32.to_le_bytes());
+        Ok(Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x200),
+            regs: VirtioMmioRegs::new(
+                VIRTIO_ID_FS,
+                Self::VENDOR_ID,
+                VIRTIO_F_VERSION_1,
+                2,
+                Self::QUEUE_SIZE,
+            ),
+            backend,
+            config,
+            dma: Mutex::new(None),
+            queues: Mutex::new([None, None]),
+            fuse: Mutex::new(FuseState::new()),
+        })
+    }
+
+    /// Returns the transport registers, e.g. to check for pending interrupts.
+    pub fn regs(&self) -> &VirtioMmioRegs {
+        &self.regs
+    }
+
+    /// Returns the exported share.
+    pub fn backend(&self) -> &Arc<dyn FsBackend> {
+        &self.backend
+    }
+
+    /// Processes one request chain, received on the high-priority queue if
+    /// `hiprio`, returning the number of bytes written to its writable
+    /// buffers.
+    fn process(
+        &self,
+        mem: &dyn GuestMemoryAccessor,
+        chain: &DescChain,
+        hiprio: bool,
+    ) -> AxResult<u32> {
+        let mut header = [0; FUSE_IN_HEADER_LEN];
+        chain.readable.read(mem, 0, &mut header)?;
+        let len = (read_u32(&header, 0)? as usize).min(chain.readable.len());
+        let opcode = read_u32(&header, 4)?;
+        let unique = read_u64(&header, 8)?;
+        let node = read_u64(&header, 16)?;
+        let mut body = vec![0; len.saturating_sub(FUSE_IN_HEADER_LEN)];
+        chain.readable.read(mem, FUSE_IN_HEADER_LEN, &mut body)?;
+
+        let max_out = chain.writable.len().saturating_sub(FUSE_OUT_HEADER_LEN);
+        let result = match opcode {
+            // Forgets have no reply.
+            FUSE_FORGET | FUSE_BATCH_FORGET => {
+                self.forget(opcode, node, &body);
+                return Ok(0);
+            }
+            _ if hiprio => return Ok(0),
+            _ => self.dispatch(opcode, node, &body, max_out),
+        };
+        let (error, payload) = match result {
+            Ok(payload) if payload.len() <= max_out => (0, payload),
+            Ok(_) => (-LinuxError::from(AxError::InvalidInput).code(), Vec::new()),
+            Err(err) => (-LinuxError::from(err).code(), Vec::new()),
+        };
+        let mut reply = Vec::with_capacity(FUSE_OUT_HEADER_LEN + payload.len());
+        put_u32(&mut reply, (FUSE_OUT_HEADER_LEN + payload.len()) as u32);
+        put_u32(&mut reply, error as u32);
+        put_u64(&mut reply, unique);
+        reply.extend_from_slice(&payload);
+        chain.writable.write(mem, 0, &reply)?;
+        Ok(reply.len() as u32)
+    }
+
+    fn dispatch(&self, opcode: u32, node: u64, body: &[u8], max_out: usize) -> AxResult<Vec<u8>> {
+        let mut fuse = self.fuse.lock();
+        let mut out = Vec::new();
+        match opcode {
+            FUSE_INIT => {
+                let max_readahead = read_u32(body, 8)?;
+                put_u32(&mut out, FUSE_KERNEL_VERSION);
+                put_u32(&mut out, FUSE_KERNEL_MINOR_VERSION);
+                put_u32(&mut out, max_readahead);
+                put_u32(&mut out, 0); // flags
+                put_u16(&mut out, 16); // max_background
+                put_u16(&mut out, 12); // congestion_threshold
+                put_u32(&mut out, FUSE_MAX_WRITE);
+                put_u32(&mut out, 1); // time_gran
+                put_u16(&mut out, (FUSE_MAX_WRITE / 4096) as u16); // max_pages
+                out.resize(64, 0);
+            }
+            FUSE_DESTROY => self.close_all(&mut fuse),
+            FUSE_LOOKUP => {
+                let path = child_path(fuse.path(node)?, read_name(body)?)?;
+                let attr = self.backend.attr(&path)?;
+                let ino = path_ino(&path);
+                let id = fuse.lookup(path);
+                put_u64(&mut out, id);
+                put_u64(&mut out, 0); // generation
+                put_u64(&mut out, 1); // entry_valid
+                put_u64(&mut out, 1); // attr_valid
+                put_u64(&mut out, 0); // entry_valid_nsec, attr_valid_nsec
+                put_attr(&mut out, ino, &attr);
+            }
+            FUSE_GETATTR => {
+                let path = fuse.path(node)?;
+                let attr = self.backend.attr(path)?;
+                put_u64(&mut out, 1); // attr_valid
+                put_u64(&mut out, 0); // attr_valid_nsec, dummy
+                put_attr(&mut out, path_ino(path), &attr);
+            }
+            FUSE_STATFS => {
+                out.resize(40, 0); // block and file counts
+                put_u32(&mut out, 4096); // bsize
+                put_u32(&mut out, 255); // namelen
+                put_u32(&mut out, 4096); // frsize
+                out.resize(80, 0);
+            }
+            FUSE_OPEN => {
+                let write = read_u32(body, 0)? & O_ACCMODE != 0;
+                if write && self.backend.is_read_only() {
+                    return ax_err!(ReadOnlyFilesystem, "virtio-fs share is read-only");
+                }
+                let path = fuse.path(node)?;
+                if self.backend.attr(path)?.is_dir {
+                    return ax_err!(IsADirectory);
+                }
+                let handle = self.backend.open(path, write)?;
+                let fh = fuse.open(OpenFile::File { handle, write });
+                put_u64(&mut out, fh);
+                put_u64(&mut out, 0); // open_flags, padding
+            }
+            FUSE_OPENDIR => {
+                let path = fuse.path(node)?;
+                if !self.backend.attr(path)?.is_dir {
+                    return ax_err!(NotADirectory);
+                }
+                let path = String::from(path);
+                let fh = fuse.open(OpenFile::Dir { path });
+                put_u64(&mut out, fh);
+                put_u64(&mut out, 0); // open_flags, padding
+            }
+            FUSE_READ => {
+                let (fh, offset) = (read_u64(body, 0)?, read_u64(body, 8)?);
+                let size = (read_u32(body, 16)? as usize).min(max_out);
+                let OpenFile::File { handle, .. } = fuse.file(fh)? else {
+                    return ax_err!(IsADirectory);
+                };
+                out.resize(size, 0);
+                let read = self.backend.read(*handle, offset, &mut out)?;
+                out.truncate(read);
+            }
+            FUSE_WRITE => {
+                let (fh, offset) = (read_u64(body, 0)?, read_u64(body, 8)?);
+                let size = read_u32(body, 16)? as usize;
+                let Some(data) = body.get(40..40 + size) else {
+                    return ax_err!(InvalidInput, "short FUSE write");
+                };
+                let OpenFile::File {
+                    handle,
+                    write: true,
+                } = fuse.file(fh)?
+                else {
+                    return ax_err!(BadFileDescriptor, "file not open for writing");
+                };
+                let written = self.backend.write(*handle, offset, data)?;
+                put_u32(&mut out, written as u32);
+                put_u32(&mut out, 0); // padding
+            }
+            FUSE_READDIR => {
+                let (fh, offset) = (read_u64(body, 0)?, read_u64(body, 8)?);
+                let size = (read_u32(body, 16)? as usize).min(max_out);
+                let OpenFile::Dir { path } = fuse.file(fh)? else {
+                    return ax_err!(NotADirectory);
+                };
+                let entries = self.backend.readdir(path)?;
+                for (index, entry) in entries.iter().enumerate().skip(offset as usize) {
+                    let len = (24 + entry.name.len()).next_multiple_of(8);
+                    if out.len() + len > size {
+                        break;
+                    }
+                    let start = out.len();
+                    put_u64(&mut out, path_ino(&child_path(path, &entry.name)?));
+                    put_u64(&mut out, index as u64 + 1); // offset of the next entry
+                    put_u32(&mut out, entry.name.len() as u32);
+                    put_u32(&mut out, if entry.is_dir { DT_DIR } else { DT_REG });
+                    out.extend_from_slice(entry.name.as_bytes());
+                    out.resize(start + len, 0);
+                }
+            }
+            FUSE_FLUSH => {
+                fuse.file(read_u64(body, 0)?)?;
+            }
+            FUSE_RELEASE | FUSE_RELEASEDIR => {
+                let fh = read_u64(body, 0)?;
+                fuse.file(fh)?;
+                if let Some(OpenFile::File { handle, .. }) = fuse.files.remove(&fh) {
+                    self.backend.close(handle)?;
+                }
+            }
+            _ => return ax_err!(Unsupported, "unsupported FUSE request"),
+        }
+        Ok(out)
+    }
+
+    fn forget(&self, opcode: u32, node: u64, body: &[u8]) {
+        let mut fuse = self.fuse.lock();
+        if opcode == FUSE_FORGET {
+            if let Ok(lookups) = read_u64(body, 0) {
+                fuse.forget(node, lookups);
+            }
+            return;
+        }
+        let count = read_u32(body, 0).unwrap_or(0) as usize;
+        for index in 0..count {
+            let entry = 8 + 16 * index;
+            let (Ok(node), Ok(lookups)) = (read_u64(body, entry), read_u64(body, entry + 8)) else {
+                break;
+            };
+            fuse.forget(node, lookups);
+        }
+    }
+
+    /// Closes all files left open by the driver and forgets all nodes.
+    fn close_all(&self, fuse: &mut FuseState) {
+        let old = core::mem::replace(fuse, FuseState::new());
+        for file in old.files.into_values() {
+            if let OpenFile::File { handle, .. } = file {
+                let _ = self.backend.close(handle);
+            }
+        }
+    }
+}
+
+impl VirtioMmioDevice for VirtioFsDevice {
+    fn read_config(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        Ok(match self.config.get(offset..offset + width.size()) {
+            Some(bytes) => RegValue::from_bytes(bytes).unwrap().zero_extend(),
+            None => 0,
+        })
+    }
+
+    fn write_config(&self, _offset: usize, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn queue_notify(&self, queue: u16) -> AxResult {
+        let index = queue as usize;
+        let Some(mem) = self.dma.lock().clone() else {
+            return ax_err!(BadState, "virtio-fs device has no DMA accessor");
+        };
+        let mut queues = self.queues.lock();
+        let Some(slot) = queues.get_mut(index) else {
+            return ax_err!(InvalidInput, "virtio queue out of range");
+        };
+        if slot.is_none() {
+            match self.regs.queue(index) {
+                Some(config) if config.ready => *slot = Some(config.to_split_queue()?),
+                _ => return Ok(()),
+            }
+        }
+        let vq = slot.as_mut().unwrap();
+        while let Some(chain) = vq.pop(&*mem)? {
+            let written = self
+                .process(&*mem, &chain, index == HIPRIO_QUEUE)
+                .unwrap_or_else(|err| {
+                    warn!("virtio-fs: dropping malformed request: {err:?}");
+                    0
+                });
+            vq.push_used(&*mem, chain.head, written)?;
+        }
+        if vq.needs_notification(&*mem)? {
+            self.regs.raise_interrupt(VirtioMmioRegs::INT_USED_BUFFER);
+        }
+        Ok(())
+    }
+
+    fn reset(&self) {
+        *self.queues.lock() = [None, None];
+        self.close_all(&mut self.fuse.lock());
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for VirtioFsDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_read(offset, width, self),
+            None => Ok(0),
+        }
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_write(offset, width, val, self),
+            None => Ok(()),
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+}
+
+/// Returns the share path of entry `name` of the directory at `parent`.
+fn child_path(parent: &str, name: &str) -> AxResult<String> {
+    if name.is_empty() || name.contains('/') {
+        return ax_err!(InvalidInput, "invalid file name");
+    }
+    scope_path(&format!("{parent}/{name}"))
+}
+
+/// Returns the inode number reported for `path`, a hash of the path within
+/// the share.
+fn path_ino(path: &str) -> u64 {
+    // FNV-1a.
+    path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Reads the NUL-terminated name at the start of `body`.
+fn read_name(body: &[u8]) -> AxResult<&str> {
+    let end = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+    core::str::from_utf8(&body[..end]).map_err(|_| AxError::InvalidInput)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> AxResult<u32> {
+    match buf.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes(bytes.try_into().unwrap())),
+        None => ax_err!(InvalidInput, "short FUSE request"),
+    }
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> AxResult<u64> {
+    match buf.get(offset..offset + 8) {
+        Some(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+        None => ax_err!(InvalidInput, "short FUSE request"),
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, val: u16) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, val: u64) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+/// Appends a `struct fuse_attr` for `attr`.
+fn put_attr(out: &mut Vec<u8>, ino: u64, attr: &FsAttr) {
+    let (secs, nsecs) = (attr.mtime_ns / 1_000_000_000, attr.mtime_ns % 1_000_000_000);
+    put_u64(out, ino);
+    put_u64(out, attr.size);
+    put_u64(out, attr.size.div_ceil(512)); // blocks
+    for _ in 0..3 {
+        put_u64(out, secs); // atime, mtime, ctime
+    }
+    for _ in 0..3 {
+        put_u32(out, nsecs as u32);
+    }
+    let kind = if attr.is_dir { S_IFDIR } else { S_IFREG };
+    put_u32(out, kind | (attr.mode & 0o7777));
+    put_u32(out, if attr.is_dir { 2 } else { 1 }); // nlink
+    put_u32(out, 0); // uid
+    put_u32(out, 0); // gid
+    put_u32(out, 0); // rdev
+    put_u32(out, 4096); // blksize
+    put_u32(out, 0); // flags
+}
@@ -0,0 +1,334 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The virtio input device over a host input event source.
+
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    BaseDeviceOps, DeviceAddrRangeExt, EmuDeviceType, GuestMemoryAccessor, InputBackend,
+    InputEvent, SplitQueue, VirtioMmioDevice, VirtioMmioRegs,
+};
+
+const VIRTIO_ID_INPUT: u32 = 18;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const EVENT_QUEUE: usize = 0;
+const STATUS_QUEUE: usize = 1;
+
+const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
+const VIRTIO_INPUT_CFG_ID_DEVIDS: u8 = 0x03;
+const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;
+const VIRTIO_INPUT_CFG_ABS_INFO: u8 = 0x12;
+
+/// The offset of the selected configuration data in the config space.
+const CFG_DATA: usize = 8;
+/// The size of the configuration data union.
+const CFG_DATA_SIZE: usize = 128;
+
+const BUS_VIRTUAL: u16 = 0x06;
+
+#[derive(Default)]
+struct InputState {
+    queues: [Option<SplitQueue>; 2],
+    /// Injected events waiting for a buffer on the event queue.
+    pending: VecDeque<InputEvent>,
+    select: u8,
+    subsel: u8,
+}
+
+/// A virtio input device (virtio 1.2, section 5.8) over an
+/// [`InputBackend`], on the virtio-mmio transport.
+///
+/// Events injected by the backend are delivered to the guest one per buffer
+/// of the event queue, each group followed by an `EV_SYN` event. Events the
+/// driver has no buffer for yet wait in the device, up to
+/// [`MAX_PENDING`](Self::MAX_PENDING) events; groups that do not fit are
+/// dropped whole, so the guest never sees half of an input change. Status
+/// events written by the guest on the status queue, such as keyboard LEDs,
+/// are handed to [`InputBackend::on_status`].
+///
+/// The configuration space reports the backend name and the event codes it
+/// may inject, selected by the driver as the specification describes. Used
+/// buffer notifications are recorded in [`regs`](Self::regs); injecting the
+/// interrupt is left to the hypervisor.
+pub struct VirtioInputDevice {
+    range: GuestPhysAddrRange,
+    regs: VirtioMmioRegs,
+    backend: Arc<dyn InputBackend>,
+    state: Mutex<InputState>,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+}
+
+impl VirtioInputDevice {
+    /// The vendor ID reported by the device.
+    pub const VENDOR_ID: u32 = 0x554d_4551;
+
+    /// The maximum size of each virtqueue.
+    pub const QUEUE_SIZE: u16 = 64;
+
+    /// The largest number of events waiting for event queue buffers.
+    pub const MAX_PENDING: usize = 256;
+
+    /// Creates a virtio input device at `base` over `backend`.
+    pub fn new(base: GuestPhysAddr, backend: Arc<dyn InputBackend>) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x200),
+            regs: VirtioMmioRegs::new(
+                VIRTIO_ID_INPUT,
+                Self::VENDOR_ID,
+                VIRTIO_F_VERSION_1,
+                2,
+                Self::QUEUE_SIZE,
+            ),
+            backend,
+            state: Mutex::new(InputState::default()),
+            dma: Mutex::new(None),
+        }
+    }
+
+    /// Returns the transport registers.
+    pub fn regs(&self) -> &VirtioMmioRegs {
+        &self.regs
+    }
+
+    /// Registers the device as the event callback of its backend.
+    ///
+    /// The callback holds a weak reference, so the backend does not keep the
+    /// device alive.
+    pub fn connect(self: &Arc<Self>) {
+        let dev = Arc::downgrade(self);
+        self.backend.set_event_callback(Box::new(move |events| {
+            if let Some(dev) = Weak::upgrade(&dev) {
+                let _ = dev.inject(events);
+            }
+        }));
+    }
+
+    /// Delivers a group of input events to the guest, followed by an
+    /// `EV_SYN` event.
+    ///
+    /// Returns `Ok(false)` if the group was dropped, because the driver is
+    /// not ready or too many events are already waiting for buffers.
+    pub fn inject(&self, events: &[InputEvent]) -> AxResult<bool> {
+        if self.regs.status() & VirtioMmioRegs::STATUS_DRIVER_OK == 0 {
+            return Ok(false);
+        }
+        let mut state = self.state.lock();
+        if state.pending.len() + events.len() + 1 > Self::MAX_PENDING {
+            return Ok(false);
+        }
+        state.pending.extend(events);
+        state
+            .pending
+            .push_back(InputEvent::new(InputEvent::EV_SYN, 0, 0));
+        self.flush_events(&mut state)?;
+        Ok(true)
+    }
+
+    /// Returns the number of injected events waiting for event queue
+    /// buffers.
+    pub fn pending(&self) -> usize {
+        self.state.lock().pending.len()
+    }
+
+    /// Returns the split queue of virtqueue `index`, creating it if the
+    /// driver has made it ready.
+    fn activate<'a>(
+        &self,
+        index: usize,
+        vq: &'a mut Option<SplitQueue>,
+    ) -> AxResult<Option<&'a mut SplitQueue>> {
+        if vq.is_none() {
+            match self.regs.queue(index) {
+                Some(config) if config.ready => *vq = Some(config.to_split_queue()?),
+                _ => return Ok(None),
+            }
+        }
+        Ok(vq.as_mut())
+    }
+
+    /// Moves pending events into the buffers of the event queue.
+    fn flush_events(&self, state: &mut InputState) -> AxResult {
+        let Some(mem) = self.dma.lock().clone() else {
+            return Ok(());
+        };
+        let Some(vq) = self.activate(EVENT_QUEUE, &mut state.queues[EVENT_QUEUE])? else {
+            return Ok(());
+        };
+        let mut completed = false;
+        while let Some(event) = state.pending.front() {
+            let Some(chain) = vq.pop(&*mem)? else {
+                break;
+            };
+            let written = if chain.writable.len() >= InputEvent::SIZE {
+                chain.writable.write(&*mem, 0, &event.encode())?;
+                state.pending.pop_front();
+                InputEvent::SIZE
+            } else {
+                0
+            };
+            vq.push_used(&*mem, chain.head, written as u32)?;
+            completed = true;
+        }
+        if completed && vq.needs_notification(&*mem)? {
+            self.regs.raise_interrupt(VirtioMmioRegs::INT_USED_BUFFER);
+        }
+        Ok(())
+    }
+
+    /// Hands the status events written by the guest to the backend.
+    fn process_status(&self, state: &mut InputState) -> AxResult {
+        let Some(mem) = self.dma.lock().clone() else {
+            return ax_err!(BadState, "virtio-input device has no DMA accessor");
+        };
+        let Some(vq) = self.activate(STATUS_QUEUE, &mut state.queues[STATUS_QUEUE])? else {
+            return Ok(());
+        };
+        let mut completed = false;
+        while let Some(chain) = vq.pop(&*mem)? {
+            let mut bytes = [0; InputEvent::SIZE];
+            if chain.readable.len() >= InputEvent::SIZE {
+                chain.readable.read(&*mem, 0, &mut bytes)?;
+                self.backend.on_status(InputEvent::decode(&bytes));
+            }
+            vq.push_used(&*mem, chain.head, 0)?;
+            completed = true;
+        }
+        if completed && vq.needs_notification(&*mem)? {
+            self.regs.raise_interrupt(VirtioMmioRegs::INT_USED_BUFFER);
+        }
+        Ok(())
+    }
+
+    /// Returns the configuration data selected by `select` and `subsel`.
+    fn config_data(&self, select: u8, subsel: u8) -> Vec<u8> {
+        let mut data = match select {
+            VIRTIO_INPUT_CFG_ID_NAME if subsel == 0 => self.backend.name().into_bytes(),
+            VIRTIO_INPUT_CFG_ID_DEVIDS if subsel == 0 => {
+                // bustype, vendor, product and version.
+                let mut ids = BUS_VIRTUAL.to_le_bytes().to_vec();
+                ids.resize(8, 0);
+                ids
+            }
+            VIRTIO_INPUT_CFG_EV_BITS => {
+                let mut bitmap = Vec::new();
+                for code in self.backend.event_codes(subsel as u16) {
+                    let byte = code as usize / 8;
+                    if byte < CFG_DATA_SIZE {
+                        if bitmap.len() <= byte {
+                            bitmap.resize(byte + 1, 0);
+                        }
+                        bitmap[byte] |= 1 << (code % 8);
+                    }
+                }
+                bitmap
+            }
+            VIRTIO_INPUT_CFG_ABS_INFO => match self.backend.abs_info(subsel as u16) {
+                Some(info) => [info.min, info.max, info.fuzz, info.flat, info.res]
+                    .iter()
+                    .flat_map(|field| field.to_le_bytes())
+                    .collect(),
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        data.truncate(CFG_DATA_SIZE);
+        data
+    }
+}
+
+impl VirtioMmioDevice for VirtioInputDevice {
+    fn read_config(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        let (select, subsel) = {
+            let state = self.state.lock();
+            (state.select, state.subsel)
+        };
+        let data = self.config_data(select, subsel);
+        let byte = |offset: usize| match offset {
+            0 => select,
+            1 => subsel,
+            2 => data.len() as u8,
+            CFG_DATA.. => data.get(offset - CFG_DATA).copied().unwrap_or(0),
+            _ => 0,
+        };
+        Ok((0..width.size())
+            .map(|i| (byte(offset + i) as usize) << (8 * i))
+            .sum())
+    }
+
+    fn write_config(&self, offset: usize, width: AccessWidth, val: usize) -> AxResult {
+        let mut state = self.state.lock();
+        for i in 0..width.size() {
+            let byte = (val >> (8 * i)) as u8;
+            match offset + i {
+                0 => state.select = byte,
+                1 => state.subsel = byte,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn queue_notify(&self, queue: u16) -> AxResult {
+        let mut state = self.state.lock();
+        match queue as usize {
+            // New event buffers take the events waiting for them.
+            EVENT_QUEUE => self.flush_events(&mut state),
+            STATUS_QUEUE => self.process_status(&mut state),
+            _ => ax_err!(InvalidInput, "virtio queue out of range"),
+        }
+    }
+
+    fn reset(&self) {
+        *self.state.lock() = InputState::default();
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for VirtioInputDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_read(offset, width, self),
+            None => Ok(0),
+        }
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_write(offset, width, val, self),
+            None => Ok(()),
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+}
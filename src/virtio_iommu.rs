@@ -0,0 +1,339 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The virtio IOMMU device over a DMA translator.
+
+use alloc::sync::Arc;
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxError, AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    BaseDeviceOps, DescChain, DeviceAddrRangeExt, DmaTranslator, EmuDeviceType,
+    GuestMemoryAccessor, RegionAccess, SplitQueue, VirtioMmioDevice, VirtioMmioRegs,
+};
+
+const VIRTIO_ID_IOMMU: u32 = 23;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+const VIRTIO_IOMMU_F_INPUT_RANGE: u64 = 1 << 0;
+const VIRTIO_IOMMU_F_DOMAIN_RANGE: u64 = 1 << 1;
+const VIRTIO_IOMMU_F_MAP_UNMAP: u64 = 1 << 2;
+
+const REQUEST_QUEUE: usize = 0;
+const EVENT_QUEUE: usize = 1;
+
+const VIRTIO_IOMMU_T_ATTACH: u8 = 1;
+const VIRTIO_IOMMU_T_DETACH: u8 = 2;
+const VIRTIO_IOMMU_T_MAP: u8 = 3;
+const VIRTIO_IOMMU_T_UNMAP: u8 = 4;
+
+const VIRTIO_IOMMU_S_OK: u8 = 0;
+const VIRTIO_IOMMU_S_UNSUPP: u8 = 2;
+const VIRTIO_IOMMU_S_DEVERR: u8 = 3;
+const VIRTIO_IOMMU_S_INVAL: u8 = 4;
+const VIRTIO_IOMMU_S_RANGE: u8 = 5;
+const VIRTIO_IOMMU_S_NOENT: u8 = 6;
+const VIRTIO_IOMMU_S_FAULT: u8 = 7;
+const VIRTIO_IOMMU_S_NOMEM: u8 = 8;
+
+const VIRTIO_IOMMU_MAP_F_READ: u32 = 1 << 0;
+const VIRTIO_IOMMU_MAP_F_WRITE: u32 = 1 << 1;
+
+/// The size of the request header, and of the status tail written back.
+const HEADER_SIZE: usize = 4;
+/// The size of the largest request, `MAP`.
+const MAX_REQUEST_SIZE: usize = 36;
+
+/// The I/O virtual addresses and domains the driver may use, reported in the
+/// configuration space.
+#[derive(Clone, Copy)]
+struct IommuConfig {
+    page_size_mask: u64,
+    input_start: u64,
+    input_end: u64,
+    domain_start: u32,
+    domain_end: u32,
+}
+
+/// A virtio IOMMU device (virtio 1.2, section 5.13) on the virtio-mmio
+/// transport, programming the mappings of a [`DmaTranslator`].
+///
+/// `ATTACH`, `DETACH`, `MAP` and `UNMAP` requests on the request queue are
+/// checked against the page granule, input range and domain range reported
+/// in the configuration space, then applied to the translator; its errors are
+/// returned to the guest as request status codes. Mapped ranges are
+/// inclusive 64-bit bounds, so a single request may map or unmap regions
+/// larger than 4 GiB, up to the whole input range.
+///
+/// The hypervisor may restrict the input range with
+/// [`set_input_range`](Self::set_input_range), e.g. after migrating to a host
+/// IOMMU with fewer address bits, which notifies the driver with a
+/// configuration change interrupt. Bypass, probe requests and fault events
+/// are not offered; the event queue is accepted but never used. Interrupts
+/// are recorded in [`regs`](Self::regs); injecting them is left to the
+/// hypervisor.
+pub struct VirtioIommuDevice {
+    range: GuestPhysAddrRange,
+    regs: VirtioMmioRegs,
+    translator: Arc<dyn DmaTranslator>,
+    config: Mutex<IommuConfig>,
+    queue: Mutex<Option<SplitQueue>>,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+}
+
+impl VirtioIommuDevice {
+    /// The vendor ID reported by the device.
+    pub const VENDOR_ID: u32 = 0x554d_4551;
+
+    /// The maximum size of each virtqueue.
+    pub const QUEUE_SIZE: u16 = 64;
+
+    /// Creates a virtio IOMMU device at `base` over `translator`, mapping
+    /// pages of the sizes in `page_size_mask` for the domains in
+    /// `domains`, over the whole 64-bit input range.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `page_size_mask` or `domains`
+    /// is empty.
+    pub fn new(
+        base: GuestPhysAddr,
+        translator: Arc<dyn DmaTranslator>,
+        page_size_mask: u64,
+        domains: core::ops::RangeInclusive<u32>,
+    ) -> AxResult<Self> {
+        if page_size_mask == 0 || domains.is_empty() {
+            return ax_err!(InvalidInput, "virtio-iommu needs a page size and a domain");
+        }
+        Ok(Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x200),
+            regs: VirtioMmioRegs::new(
+                VIRTIO_ID_IOMMU,
+                Self::VENDOR_ID,
+                VIRTIO_F_VERSION_1
+                    | VIRTIO_IOMMU_F_INPUT_RANGE
+                    | VIRTIO_IOMMU_F_DOMAIN_RANGE
+                    | VIRTIO_IOMMU_F_MAP_UNMAP,
+                2,
+                Self::QUEUE_SIZE,
+            ),
+            translator,
+            config: Mutex::new(IommuConfig {
+                page_size_mask,
+                input_start: 0,
+                input_end: u64::MAX,
+                domain_start: *domains.start(),
+                domain_end: *domains.end(),
+            }),
+            queue: Mutex::new(None),
+            dma: Mutex::new(None),
+        })
+    }
+
+    /// Returns the transport registers.
+    pub fn regs(&self) -> &VirtioMmioRegs {
+        &self.regs
+    }
+
+    /// Restricts the I/O virtual addresses the driver may map to
+    /// `start..=end`, and notifies the driver of the configuration change.
+    ///
+    /// Existing mappings outside the new range are left to the hypervisor.
+    /// Returns `Err(AxError::InvalidInput)` if `start` is above `end`.
+    pub fn set_input_range(&self, start: u64, end: u64) -> AxResult {
+        if start > end {
+            return ax_err!(InvalidInput, "empty virtio-iommu input range");
+        }
+        {
+            let mut config = self.config.lock();
+            config.input_start = start;
+            config.input_end = end;
+        }
+        self.regs.raise_interrupt(VirtioMmioRegs::INT_CONFIG_CHANGE);
+        Ok(())
+    }
+
+    /// Processes the requests on the request queue.
+    fn process_requests(&self) -> AxResult {
+        let Some(mem) = self.dma.lock().clone() else {
+            return ax_err!(BadState, "virtio-iommu device has no DMA accessor");
+        };
+        let mut queue = self.queue.lock();
+        if queue.is_none() {
+            match self.regs.queue(REQUEST_QUEUE) {
+                Some(config) if config.ready => *queue = Some(config.to_split_queue()?),
+                _ => return Ok(()),
+            }
+        }
+        let Some(vq) = queue.as_mut() else {
+            return Ok(());
+        };
+        let mut completed = false;
+        while let Some(chain) = vq.pop(&*mem)? {
+            let written = if chain.writable.len() >= HEADER_SIZE {
+                let status = self.handle_request(&*mem, &chain)?;
+                chain.writable.write(&*mem, 0, &[status, 0, 0, 0])?;
+                HEADER_SIZE
+            } else {
+                0
+            };
+            vq.push_used(&*mem, chain.head, written as u32)?;
+            completed = true;
+        }
+        if completed && vq.needs_notification(&*mem)? {
+            self.regs.raise_interrupt(VirtioMmioRegs::INT_USED_BUFFER);
+        }
+        Ok(())
+    }
+
+    /// Applies the request of `chain`, and returns its status.
+    fn handle_request(&self, mem: &dyn GuestMemoryAccessor, chain: &DescChain) -> AxResult<u8> {
+        let mut req = [0; MAX_REQUEST_SIZE];
+        let len = chain.readable.len().min(MAX_REQUEST_SIZE);
+        chain.readable.read(mem, 0, &mut req[..len])?;
+        let size = match req[0] {
+            VIRTIO_IOMMU_T_ATTACH | VIRTIO_IOMMU_T_DETACH => 24,
+            VIRTIO_IOMMU_T_MAP => MAX_REQUEST_SIZE,
+            VIRTIO_IOMMU_T_UNMAP => 28,
+            _ => return Ok(VIRTIO_IOMMU_S_UNSUPP),
+        };
+        if len < size {
+            return Ok(VIRTIO_IOMMU_S_INVAL);
+        }
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(req[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(req[offset..offset + 8].try_into().unwrap());
+
+        let config = *self.config.lock();
+        let domain = u32_at(4);
+        if !(config.domain_start..=config.domain_end).contains(&domain) {
+            return Ok(VIRTIO_IOMMU_S_RANGE);
+        }
+        let result = match req[0] {
+            VIRTIO_IOMMU_T_ATTACH | VIRTIO_IOMMU_T_DETACH => {
+                // No attach flags are offered.
+                if u32_at(12) != 0 {
+                    return Ok(VIRTIO_IOMMU_S_INVAL);
+                }
+                let endpoint = u32_at(8);
+                if req[0] == VIRTIO_IOMMU_T_ATTACH {
+                    self.translator.attach(domain, endpoint)
+                } else {
+                    self.translator.detach(domain, endpoint)
+                }
+            }
+            op => {
+                let (start, end) = (u64_at(8), u64_at(16));
+                if start > end {
+                    return Ok(VIRTIO_IOMMU_S_INVAL);
+                }
+                if start < config.input_start || end > config.input_end {
+                    return Ok(VIRTIO_IOMMU_S_RANGE);
+                }
+                // Mappings are made of whole pages of the smallest size.
+                let granule = 1 << config.page_size_mask.trailing_zeros();
+                if start % granule != 0 || end % granule != granule - 1 {
+                    return Ok(VIRTIO_IOMMU_S_INVAL);
+                }
+                if op == VIRTIO_IOMMU_T_UNMAP {
+                    self.translator.unmap(domain, start, end)
+                } else {
+                    let (phys, flags) = (u64_at(24), u32_at(32));
+                    if phys % granule != 0 {
+                        return Ok(VIRTIO_IOMMU_S_INVAL);
+                    }
+                    let access = match flags {
+                        VIRTIO_IOMMU_MAP_F_READ => RegionAccess::ReadOnly,
+                        VIRTIO_IOMMU_MAP_F_WRITE => RegionAccess::WriteOnly,
+                        f if f == VIRTIO_IOMMU_MAP_F_READ | VIRTIO_IOMMU_MAP_F_WRITE => {
+                            RegionAccess::ReadWrite
+                        }
+                        _ => return Ok(VIRTIO_IOMMU_S_INVAL),
+                    };
+                    let paddr = GuestPhysAddr::from(phys as usize);
+                    self.translator.map(domain, start, end, paddr, access)
+                }
+            }
+        };
+        Ok(match result {
+            Ok(()) => VIRTIO_IOMMU_S_OK,
+            Err(AxError::NotFound) => VIRTIO_IOMMU_S_NOENT,
+            Err(AxError::InvalidInput | AxError::AlreadyExists) => VIRTIO_IOMMU_S_INVAL,
+            Err(AxError::NoMemory) => VIRTIO_IOMMU_S_NOMEM,
+            Err(AxError::Unsupported) => VIRTIO_IOMMU_S_UNSUPP,
+            Err(AxError::BadAddress) => VIRTIO_IOMMU_S_FAULT,
+            Err(_) => VIRTIO_IOMMU_S_DEVERR,
+        })
+    }
+}
+
+impl VirtioMmioDevice for VirtioIommuDevice {
+    fn read_config(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        let config = *self.config.lock();
+        let mut space = [0; 40];
+        space[0..8].copy_from_slice(&config.page_size_mask.to_le_bytes());
+        space[8..16].copy_from_slice(&config.input_start.to_le_bytes());
+        space[16..24].copy_from_slice(&config.input_end.to_le_bytes());
+        space[24..28].copy_from_slice(&config.domain_start.to_le_bytes());
+        space[28..32].copy_from_slice(&config.domain_end.to_le_bytes());
+        Ok((0..width.size())
+            .map(|i| (space.get(offset + i).copied().unwrap_or(0) as usize) << (8 * i))
+            .sum())
+    }
+
+    fn write_config(&self, _offset: usize, _width: AccessWidth, _val: usize) -> AxResult {
+        // The bypass field is only writable with VIRTIO_IOMMU_F_BYPASS_CONFIG.
+        Ok(())
+    }
+
+    fn queue_notify(&self, queue: u16) -> AxResult {
+        match queue as usize {
+            REQUEST_QUEUE => self.process_requests(),
+            EVENT_QUEUE => Ok(()),
+            _ => ax_err!(InvalidInput, "virtio queue out of range"),
+        }
+    }
+
+    fn reset(&self) {
+        *self.queue.lock() = None;
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for VirtioIommuDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_read(offset, width, self),
+            None => Ok(0),
+        }
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_write(offset, width, val, self),
+            None => Ok(()),
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The virtio-mmio (version 2) transport register block.
+
+use alloc::{vec, vec::Vec};
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{RegValue, SplitQueue};
+
+/// The device-specific callbacks of a [`VirtioMmioRegs`] register block.
+pub trait VirtioMmioDevice {
+    /// Reads the device configuration space at `offset`.
+    fn read_config(&self, offset: usize, width: AccessWidth) -> AxResult<usize>;
+
+    /// Writes the device configuration space at `offset`.
+    fn write_config(&self, offset: usize, width: AccessWidth, val: usize) -> AxResult;
+
+    /// Handles a notification from the driver that queue `queue` has new
+    /// buffers.
+    fn queue_notify(&self, queue: u16) -> AxResult;
+
+    /// Resets the device-specific state, after the driver wrote 0 to
+    /// `Status`. The default implementation does nothing.
+    fn reset(&self) {}
+}
+
+/// The configuration of one virtqueue, as programmed by the driver.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VirtioQueueConfig {
+    /// The queue size selected by the driver.
+    pub num: u16,
+    /// Whether the driver has enabled the queue.
+    pub ready: bool,
+    /// The guest address of the descriptor table.
+    pub desc: u64,
+    /// The guest address of the driver (available) ring.
+    pub driver: u64,
+    /// The guest address of the device (used) ring.
+    pub device: u64,
+}
+
+impl VirtioQueueConfig {
+    /// Creates a [`SplitQueue`] for this configuration.
+    pub fn to_split_queue(&self) -> AxResult<SplitQueue> {
+        SplitQueue::new(
+            self.num,
+            (self.desc as usize).into(),
+            (self.driver as usize).into(),
+            (self.device as usize).into(),
+        )
+    }
+}
+
+struct VirtioMmioState {
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: Vec<VirtioQueueConfig>,
+    interrupt_status: u32,
+    status: u32,
+    config_generation: u32,
+}
+
+/// The virtio-mmio version 2 register layout (virtio 1.x, section 4.2.2),
+/// embedded by virtio device models.
+///
+/// The device forwards its `handle_read`/`handle_write` calls here with the
+/// offset from its base address, and implements [`VirtioMmioDevice`] for the
+/// device-specific configuration space (at offset 0x100) and queue
+/// notifications. Feature negotiation, queue setup, interrupt status and the
+/// status register are handled by this type.
+///
+/// Interrupts are recorded in `InterruptStatus` by
+/// [`raise_interrupt`](Self::raise_interrupt); injecting them into the guest
+/// is left to the device.
+pub struct VirtioMmioRegs {
+    device_id: u32,
+    vendor_id: u32,
+    device_features: u64,
+    queue_num_max: u16,
+    state: Mutex<VirtioMmioState>,
+}
+
+impl VirtioMmioRegs {
+    const MAGIC_VALUE: usize = 0x000;
+    const VERSION: usize = 0x004;
+    const DEVICE_ID: usize = 0x008;
+    const VENDOR_ID: usize = 0x00c;
+    const DEVICE_FEATURES: usize = 0x010;
+    const DEVICE_FEATURES_SEL: usize = 0x014;
+    const DRIVER_FEATURES: usize = 0x020;
+    const DRIVER_FEATURES_SEL: usize = 0x024;
+    const QUEUE_SEL: usize = 0x030;
+    const QUEUE_NUM_MAX: usize = 0x034;
+    const QUEUE_NUM: usize = 0x038;
+    const QUEUE_READY: usize = 0x044;
+    const QUEUE_NOTIFY: usize = 0x050;
+    const INTERRUPT_STATUS: usize = 0x060;
+    const INTERRUPT_ACK: usize = 0x064;
+    const STATUS: usize = 0x070;
+    const QUEUE_DESC_LOW: usize = 0x080;
+    const QUEUE_DESC_HIGH: usize = 0x084;
+    const QUEUE_DRIVER_LOW: usize = 0x090;
+    const QUEUE_DRIVER_HIGH: usize = 0x094;
+    const QUEUE_DEVICE_LOW: usize = 0x0a0;
+    const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+    const SHM_LEN_LOW: usize = 0x0b0;
+    const SHM_LEN_HIGH: usize = 0x0b4;
+    const SHM_BASE_LOW: usize = 0x0b8;
+    const SHM_BASE_HIGH: usize = 0x0bc;
+    const CONFIG_GENERATION: usize = 0x0fc;
+
+    /// The offset of the device configuration space.
+    pub const CONFIG_OFFSET: usize = 0x100;
+
+    /// `InterruptStatus` bit: a used buffer notification is pending.
+    pub const INT_USED_BUFFER: u32 = 1 << 0;
+    /// `InterruptStatus` bit: a configuration change notification is pending.
+    pub const INT_CONFIG_CHANGE: u32 = 1 << 1;
+
+    /// `Status` bit: the driver accepted the negotiated features.
+    pub const STATUS_FEATURES_OK: u32 = 8;
+    /// `Status` bit: the driver is ready to drive the device.
+    pub const STATUS_DRIVER_OK: u32 = 4;
+
+    /// Creates a register block for a device with virtio device ID
+    /// `device_id`, offering `device_features`, with `num_queues` queues of at
+    /// most `queue_num_max` entries.
+    pub fn new(
+        device_id: u32,
+        vendor_id: u32,
+        device_features: u64,
+        num_queues: usize,
+        queue_num_max: u16,
+    ) -> Self {
+        Self {
+            device_id,
+            vendor_id,
+            device_features,
+            queue_num_max,
+            state: Mutex::new(VirtioMmioState {
+                device_features_sel: 0,
+                driver_features: 0,
+                driver_features_sel: 0,
+                queue_sel: 0,
+                queues: vec![VirtioQueueConfig::default(); num_queues],
+                interrupt_status: 0,
+                status: 0,
+                config_generation: 0,
+            }),
+        }
+    }
+
+    /// Returns the features accepted by the driver.
+    pub fn driver_features(&self) -> u64 {
+        self.state.lock().driver_features
+    }
+
+    /// Returns the device status written by the driver.
+    pub fn status(&self) -> u32 {
+        self.state.lock().status
+    }
+
+    /// Returns the configuration of queue `index`.
+    pub fn queue(&self, index: usize) -> Option<VirtioQueueConfig> {
+        self.state.lock().queues.get(index).copied()
+    }
+
+    /// Returns the pending interrupt status bits.
+    pub fn interrupt_status(&self) -> u32 {
+        self.state.lock().interrupt_status
+    }
+
+    /// Sets `bits` (`INT_*`) in `InterruptStatus`. A configuration change
+    /// also bumps `ConfigGeneration`.
+    pub fn raise_interrupt(&self, bits: u32) {
+        let mut state = self.state.lock();
+        state.interrupt_status |= bits;
+        if bits & Self::INT_CONFIG_CHANGE != 0 {
+            state.config_generation = state.config_generation.wrapping_add(1);
+        }
+    }
+
+    /// Handles a read at `offset` from the base of the device.
+    pub fn handle_read(
+        &self,
+        offset: usize,
+        width: AccessWidth,
+        dev: &dyn VirtioMmioDevice,
+    ) -> AxResult<usize> {
+        if offset >= Self::CONFIG_OFFSET {
+            return dev.read_config(offset - Self::CONFIG_OFFSET, width);
+        }
+        let state = self.state.lock();
+        let queue = state.queues.get(state.queue_sel as usize);
+        let val = match offset {
+            Self::MAGIC_VALUE => 0x7472_6976,
+            Self::VERSION => 2,
+            Self::DEVICE_ID => self.device_id,
+            Self::VENDOR_ID => self.vendor_id,
+            Self::DEVICE_FEATURES => match state.device_features_sel {
+                0 => self.device_features as u32,
+                1 => (self.device_features >> 32) as u32,
+                _ => 0,
+            },
+            Self::QUEUE_NUM_MAX => queue.map_or(0, |_| self.queue_num_max as u32),
+            Self::QUEUE_READY => queue.is_some_and(|queue| queue.ready) as u32,
+            Self::INTERRUPT_STATUS => state.interrupt_status,
+            Self::STATUS => state.status,
+            // No shared memory regions: their length reads as all ones.
+            Self::SHM_LEN_LOW | Self::SHM_LEN_HIGH => u32::MAX,
+            Self::SHM_BASE_LOW | Self::SHM_BASE_HIGH => u32::MAX,
+            Self::CONFIG_GENERATION => state.config_generation,
+            _ => 0,
+        };
+        Ok(RegValue::new(val as usize, width).zero_extend())
+    }
+
+    /// Handles a write at `offset` from the base of the device.
+    pub fn handle_write(
+        &self,
+        offset: usize,
+        width: AccessWidth,
+        val: usize,
+        dev: &dyn VirtioMmioDevice,
+    ) -> AxResult {
+        if offset >= Self::CONFIG_OFFSET {
+            return dev.write_config(offset - Self::CONFIG_OFFSET, width, val);
+        }
+        let val = val as u32;
+        let mut state = self.state.lock();
+        match offset {
+            Self::DEVICE_FEATURES_SEL => state.device_features_sel = val,
+            Self::DRIVER_FEATURES => match state.driver_features_sel {
+                0 => set_low(&mut state.driver_features, val),
+                1 => set_high(&mut state.driver_features, val),
+                _ => {}
+            },
+            Self::DRIVER_FEATURES_SEL => state.driver_features_sel = val,
+            Self::QUEUE_SEL => state.queue_sel = val,
+            Self::QUEUE_NOTIFY => {
+                drop(state);
+                return dev.queue_notify(val as u16);
+            }
+            Self::INTERRUPT_ACK => state.interrupt_status &= !val,
+            Self::STATUS => {
+                if val == 0 {
+                    drop(state);
+                    self.reset();
+                    dev.reset();
+                    return Ok(());
+                }
+                let mut status = val;
+                // Refuse feature sets the device did not offer.
+                if state.driver_features & !self.device_features != 0 {
+                    status &= !Self::STATUS_FEATURES_OK;
+                }
+                state.status = status;
+            }
+            Self::QUEUE_NUM
+            | Self::QUEUE_READY
+            | Self::QUEUE_DESC_LOW
+            | Self::QUEUE_DESC_HIGH
+            | Self::QUEUE_DRIVER_LOW
+            | Self::QUEUE_DRIVER_HIGH
+            | Self::QUEUE_DEVICE_LOW
+            | Self::QUEUE_DEVICE_HIGH => {
+                let queue_num_max = self.queue_num_max;
+                let queue_sel = state.queue_sel as usize;
+                let Some(queue) = state.queues.get_mut(queue_sel) else {
+                    return ax_err!(InvalidInput, "virtio queue out of range");
+                };
+                match offset {
+                    Self::QUEUE_NUM if val <= queue_num_max as u32 => queue.num = val as u16,
+                    Self::QUEUE_NUM => return ax_err!(InvalidInput, "virtio queue too large"),
+                    Self::QUEUE_READY => queue.ready = val & 1 != 0,
+                    Self::QUEUE_DESC_LOW => set_low(&mut queue.desc, val),
+                    Self::QUEUE_DESC_HIGH => set_high(&mut queue.desc, val),
+                    Self::QUEUE_DRIVER_LOW => set_low(&mut queue.driver, val),
+                    Self::QUEUE_DRIVER_HIGH => set_high(&mut queue.driver, val),
+                    Self::QUEUE_DEVICE_LOW => set_low(&mut queue.device, val),
+                    _ => set_high(&mut queue.device, val),
+                }
+            }
+            // Writes to read-only and reserved registers are ignored.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn reset(&self) {
+        let mut state = self.state.lock();
+        state.device_features_sel = 0;
+        state.driver_features = 0;
+        state.driver_features_sel = 0;
+        state.queue_sel = 0;
+        state.queues.fill(VirtioQueueConfig::default());
+        state.interrupt_status = 0;
+        state.status = 0;
+    }
+}
+
+fn set_low(reg: &mut u64, val: u32) {
+    *reg = (*reg & !0xffff_ffff) | val as u64;
+}
+
+fn set_high(reg: &mut u64, val: u32) {
+    *reg = (*reg & 0xffff_ffff) | ((val as u64) << 32);
+}
@@ -0,0 +1,515 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The virtio-net device over a network packet backend.
+
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxError, AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    BaseDeviceOps, DescChain, DeviceAddrRangeExt, EmuDeviceType, GuestMemoryAccessor, NetBackend,
+    QueueSet, RegValue, SplitQueue, TimerService, TimerToken, VirtioMmioDevice, VirtioMmioRegs,
+};
+
+const VIRTIO_ID_NET: u32 = 1;
+
+const VIRTIO_NET_F_MTU: u64 = 1 << 3;
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+const VIRTIO_NET_F_CTRL_VQ: u64 = 1 << 17;
+const VIRTIO_NET_F_MQ: u64 = 1 << 22;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
+/// The size of `struct virtio_net_hdr` with `VIRTIO_F_VERSION_1`.
+const VIRTIO_NET_HDR_LEN: usize = 12;
+
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+const VIRTIO_NET_OK: u8 = 0;
+const VIRTIO_NET_ERR: u8 = 1;
+
+/// Interrupt moderation of a [`VirtioNetDevice`].
+///
+/// A queue's used buffer notification is raised once `max_frames` buffers
+/// have been completed since the last one, or `max_delay` after the first
+/// unsignalled completion, whichever comes first. The default of one frame
+/// signals every completion immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetModeration {
+    /// The number of completions that trigger a notification.
+    pub max_frames: u32,
+    /// The longest a completion waits for its notification.
+    pub max_delay: Duration,
+}
+
+impl Default for NetModeration {
+    fn default() -> Self {
+        Self {
+            max_frames: 1,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// The state of one virtqueue of a [`VirtioNetDevice`].
+#[derive(Default)]
+struct NetQueue {
+    vq: Option<SplitQueue>,
+    /// A transmit chain the backend could not take yet.
+    stalled: Option<DescChain>,
+    /// Completions not signalled to the driver yet.
+    pending: u32,
+}
+
+/// A virtio-net device (virtio 1.2, section 5.1) over a [`NetBackend`], on
+/// the virtio-mmio transport.
+///
+/// This is a skeleton covering the data path: frames are exchanged without
+/// offloads or mergeable receive buffers, so each receive buffer must hold a
+/// whole frame. With more than one queue pair, the device offers
+/// `VIRTIO_NET_F_MQ` and a control queue accepting
+/// `VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET`; received frames are spread over the
+/// active pairs by a hash of their Ethernet addresses and, for IPv4, their IP
+/// addresses and ports, so that each flow stays on one queue.
+///
+/// The queues live in a [`QueueSet`], each behind its own lock, so transmit
+/// notifications from vCPUs and frames received from the backend proceed in
+/// parallel on different queues. Used buffer notifications are moderated as
+/// configured with [`set_moderation`](Self::set_moderation), through the
+/// injected [`TimerService`]. Each notification is recorded in
+/// [`regs`](Self::regs) and queued with the affinity of its virtqueue (see
+/// [`set_queue_affinity`](Self::set_queue_affinity)); the hypervisor collects
+/// them with [`take_notifications`](Self::take_notifications) and injects the
+/// interrupts on the matching vCPUs.
+///
+/// When the backend returns `Err(AxError::WouldBlock)`, the transmit queue
+/// stalls on the frame until [`resume_tx`](Self::resume_tx) is called, e.g.
+/// from a [`Backpressure`](crate::Backpressure) capacity callback.
+pub struct VirtioNetDevice {
+    range: GuestPhysAddrRange,
+    regs: VirtioMmioRegs,
+    backend: Arc<dyn NetBackend>,
+    pairs: usize,
+    active_pairs: AtomicUsize,
+    queues: QueueSet<NetQueue>,
+    moderation: Mutex<NetModeration>,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+    timers: Mutex<Option<Arc<dyn TimerService>>>,
+    timer: TimerToken,
+    timer_armed: AtomicBool,
+    notifications: Mutex<Vec<(usize, Option<usize>)>>,
+}
+
+impl VirtioNetDevice {
+    /// The vendor ID reported by the device.
+    pub const VENDOR_ID: u32 = 0x554d_4551;
+
+    /// The maximum size of each virtqueue.
+    pub const QUEUE_SIZE: u16 = 256;
+
+    /// Creates a virtio-net device at `base` over `backend`, with `pairs`
+    /// receive/transmit queue pairs.
+    ///
+    /// Queue `2 * i` receives and queue `2 * i + 1` transmits for pair `i`;
+    /// with more than one pair, queue `2 * pairs` is the control queue.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `pairs` is 0 or above 0x8000.
+    pub fn new(base: GuestPhysAddr, backend: Arc<dyn NetBackend>, pairs: usize) -> AxResult<Self> {
+        if pairs == 0 || pairs > 0x8000 {
+            return ax_err!(InvalidInput, "invalid number of virtio-net queue pairs");
+        }
+        let mut features =
+            VIRTIO_F_VERSION_1 | VIRTIO_NET_F_MAC | VIRTIO_NET_F_MTU | VIRTIO_NET_F_STATUS;
+        let mut num_queues = 2 * pairs;
+        if pairs > 1 {
+            features |= VIRTIO_NET_F_CTRL_VQ | VIRTIO_NET_F_MQ;
+            num_queues += 1;
+        }
+        Ok(Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x200),
+            regs: VirtioMmioRegs::new(
+                VIRTIO_ID_NET,
+                Self::VENDOR_ID,
+                features,
+                num_queues,
+                Self::QUEUE_SIZE,
+            ),
+            backend,
+            pairs,
+            active_pairs: AtomicUsize::new(1),
+            queues: QueueSet::new(num_queues, |_| NetQueue::default()),
+            moderation: Mutex::new(NetModeration::default()),
+            dma: Mutex::new(None),
+            timers: Mutex::new(None),
+            timer: TimerToken::next(),
+            timer_armed: AtomicBool::new(false),
+            notifications: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Returns the transport registers.
+    pub fn regs(&self) -> &VirtioMmioRegs {
+        &self.regs
+    }
+
+    /// Returns the network backend.
+    pub fn backend(&self) -> &Arc<dyn NetBackend> {
+        &self.backend
+    }
+
+    /// Returns the number of queue pairs enabled by the driver.
+    pub fn active_pairs(&self) -> usize {
+        self.active_pairs.load(Ordering::Acquire)
+    }
+
+    /// Registers the device as the receive callback of its backend.
+    ///
+    /// The callback holds a weak reference, so the backend does not keep the
+    /// device alive.
+    pub fn connect(self: &Arc<Self>) {
+        let dev = Arc::downgrade(self);
+        self.backend.set_rx_callback(Box::new(move |frame| {
+            if let Some(dev) = Weak::upgrade(&dev) {
+                let _ = dev.receive(frame);
+            }
+        }));
+    }
+
+    /// Sets the interrupt moderation of all queues.
+    pub fn set_moderation(&self, moderation: NetModeration) {
+        *self.moderation.lock() = moderation;
+    }
+
+    /// Steers the notifications of virtqueue `queue` to `vcpu`, or to any
+    /// vCPU if `None`.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if the queue does not exist.
+    pub fn set_queue_affinity(&self, queue: usize, vcpu: Option<usize>) -> AxResult {
+        match self.queues.get(queue) {
+            Some(queue) => {
+                queue.set_affinity(vcpu);
+                Ok(())
+            }
+            None => ax_err!(InvalidInput, "virtio queue out of range"),
+        }
+    }
+
+    /// Returns and clears the used buffer notifications raised since the last
+    /// call, as virtqueue indices with the vCPU they are steered to.
+    pub fn take_notifications(&self) -> Vec<(usize, Option<usize>)> {
+        core::mem::take(&mut *self.notifications.lock())
+    }
+
+    /// Delivers a frame received from the host network to the guest.
+    ///
+    /// Returns `Ok(false)` if the frame was dropped, because the driver is
+    /// not ready or has no receive buffer available for it.
+    pub fn receive(&self, frame: &[u8]) -> AxResult<bool> {
+        if self.regs.status() & VirtioMmioRegs::STATUS_DRIVER_OK == 0 {
+            return Ok(false);
+        }
+        let Some(mem) = self.dma.lock().clone() else {
+            return Ok(false);
+        };
+        let index = 2 * (flow_hash(frame) % self.active_pairs() as u64) as usize;
+        let mut guard = self.queues.get(index).unwrap().lock();
+        let queue = &mut *guard;
+        let Some(vq) = self.activate(index, &mut queue.vq)? else {
+            return Ok(false);
+        };
+        let Some(chain) = vq.pop(&*mem)? else {
+            return Ok(false);
+        };
+        let len = VIRTIO_NET_HDR_LEN + frame.len();
+        let delivered = chain.writable.len() >= len;
+        if delivered {
+            let mut hdr = [0; VIRTIO_NET_HDR_LEN];
+            // num_buffers
+            hdr[10] = 1;
+            chain.writable.write(&*mem, 0, &hdr)?;
+            chain.writable.write(&*mem, VIRTIO_NET_HDR_LEN, frame)?;
+        }
+        vq.push_used(&*mem, chain.head, if delivered { len as u32 } else { 0 })?;
+        self.complete(&*mem, index, queue, 1)?;
+        Ok(delivered)
+    }
+
+    /// Retries the transmit queues, after the backend stalled them.
+    pub fn resume_tx(&self) -> AxResult {
+        let Some(mem) = self.dma.lock().clone() else {
+            return Ok(());
+        };
+        for pair in 0..self.pairs {
+            self.process_tx(&*mem, 2 * pair + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the split queue of virtqueue `index`, creating it if the
+    /// driver has made it ready.
+    fn activate<'a>(
+        &self,
+        index: usize,
+        vq: &'a mut Option<SplitQueue>,
+    ) -> AxResult<Option<&'a mut SplitQueue>> {
+        if vq.is_none() {
+            match self.regs.queue(index) {
+                Some(config) if config.ready => {
+                    *vq = Some(config.to_split_queue()?);
+                    self.queues.get(index).unwrap().set_enabled(true);
+                }
+                _ => return Ok(None),
+            }
+        }
+        Ok(vq.as_mut())
+    }
+
+    fn process_tx(&self, mem: &dyn GuestMemoryAccessor, index: usize) -> AxResult {
+        let mut guard = self.queues.get(index).unwrap().lock();
+        let queue = &mut *guard;
+        let Some(vq) = self.activate(index, &mut queue.vq)? else {
+            return Ok(());
+        };
+        let mut completed = 0;
+        loop {
+            let chain = match queue.stalled.take() {
+                Some(chain) => chain,
+                None => match vq.pop(mem)? {
+                    Some(chain) => chain,
+                    None => break,
+                },
+            };
+            let len = chain.readable.len().saturating_sub(VIRTIO_NET_HDR_LEN);
+            let mut frame = vec![0; len];
+            chain.readable.read(
+                mem,
+                VIRTIO_NET_HDR_LEN.min(chain.readable.len()),
+                &mut frame,
+            )?;
+            match self.backend.send(&frame) {
+                Err(AxError::WouldBlock) => {
+                    queue.stalled = Some(chain);
+                    break;
+                }
+                Err(err) => warn!("virtio-net: dropping frame: {err:?}"),
+                Ok(()) => {}
+            }
+            vq.push_used(mem, chain.head, 0)?;
+            completed += 1;
+        }
+        self.complete(mem, index, queue, completed)
+    }
+
+    fn process_ctrl(&self, mem: &dyn GuestMemoryAccessor, index: usize) -> AxResult {
+        let mut guard = self.queues.get(index).unwrap().lock();
+        let Some(vq) = self.activate(index, &mut guard.vq)? else {
+            return Ok(());
+        };
+        while let Some(chain) = vq.pop(mem)? {
+            let mut cmd = [0; 4];
+            let ack = match chain.readable.read(mem, 0, &mut cmd) {
+                Ok(()) if cmd[..2] == [VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET] => {
+                    let pairs = u16::from_le_bytes([cmd[2], cmd[3]]) as usize;
+                    if (1..=self.pairs).contains(&pairs) {
+                        self.active_pairs.store(pairs, Ordering::Release);
+                        VIRTIO_NET_OK
+                    } else {
+                        VIRTIO_NET_ERR
+                    }
+                }
+                _ => VIRTIO_NET_ERR,
+            };
+            let Some(offset) = chain.writable.len().checked_sub(1) else {
+                vq.push_used(mem, chain.head, 0)?;
+                continue;
+            };
+            chain.writable.write(mem, offset, &[ack])?;
+            vq.push_used(mem, chain.head, 1)?;
+        }
+        // Control commands are not moderated.
+        self.flush(mem, index, &mut guard)
+    }
+
+    /// Accounts `count` completions on queue `index`, signalling the driver
+    /// as the moderation allows.
+    fn complete(
+        &self,
+        mem: &dyn GuestMemoryAccessor,
+        index: usize,
+        queue: &mut NetQueue,
+        count: u32,
+    ) -> AxResult {
+        if count == 0 {
+            return Ok(());
+        }
+        queue.pending += count;
+        let moderation = *self.moderation.lock();
+        let timers = self.timers.lock().clone();
+        match timers {
+            Some(timers)
+                if queue.pending < moderation.max_frames && !moderation.max_delay.is_zero() =>
+            {
+                if !self.timer_armed.swap(true, Ordering::AcqRel) {
+                    timers.schedule_after(moderation.max_delay, self.timer)?;
+                }
+                Ok(())
+            }
+            _ => self.flush(mem, index, queue),
+        }
+    }
+
+    /// Signals the completions pending on queue `index`, unless the driver
+    /// suppressed notifications.
+    fn flush(&self, mem: &dyn GuestMemoryAccessor, index: usize, queue: &mut NetQueue) -> AxResult {
+        queue.pending = 0;
+        let Some(vq) = queue.vq.as_mut() else {
+            return Ok(());
+        };
+        if vq.needs_notification(mem)? {
+            self.regs.raise_interrupt(VirtioMmioRegs::INT_USED_BUFFER);
+            let affinity = self.queues.get(index).unwrap().affinity();
+            self.notifications.lock().push((index, affinity));
+        }
+        Ok(())
+    }
+}
+
+impl VirtioMmioDevice for VirtioNetDevice {
+    fn read_config(&self, offset: usize, width: AccessWidth) -> AxResult<usize> {
+        let status = if self.backend.link_up() {
+            VIRTIO_NET_S_LINK_UP
+        } else {
+            0
+        };
+        let mut config = [0; 12];
+        config[..6].copy_from_slice(&self.backend.mac());
+        config[6..8].copy_from_slice(&status.to_le_bytes());
+        config[8..10].copy_from_slice(&(self.pairs as u16).to_le_bytes());
+        config[10..].copy_from_slice(&(self.backend.mtu() as u16).to_le_bytes());
+        Ok(match config.get(offset..offset + width.size()) {
+            Some(bytes) => RegValue::from_bytes(bytes).unwrap().zero_extend(),
+            None => 0,
+        })
+    }
+
+    fn write_config(&self, _offset: usize, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn queue_notify(&self, queue: u16) -> AxResult {
+        let index = queue as usize;
+        let Some(mem) = self.dma.lock().clone() else {
+            return ax_err!(BadState, "virtio-net device has no DMA accessor");
+        };
+        if index >= self.queues.len() {
+            return ax_err!(InvalidInput, "virtio queue out of range");
+        }
+        match index {
+            _ if index == 2 * self.pairs => self.process_ctrl(&*mem, index),
+            _ if index % 2 == 1 => self.process_tx(&*mem, index),
+            // New receive buffers; frames are only delivered as they arrive.
+            _ => Ok(()),
+        }
+    }
+
+    fn reset(&self) {
+        self.queues.reset();
+        self.active_pairs.store(1, Ordering::Release);
+        self.notifications.lock().clear();
+        if self.timer_armed.swap(false, Ordering::AcqRel)
+            && let Some(timers) = self.timers.lock().clone()
+        {
+            timers.cancel(self.timer);
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for VirtioNetDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_read(offset, width, self),
+            None => Ok(0),
+        }
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_write(offset, width, val, self),
+            None => Ok(()),
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        *self.timers.lock() = Some(service);
+    }
+
+    /// Signals the completions held back by moderation.
+    fn on_timer(&self, token: TimerToken) -> bool {
+        if token != self.timer {
+            return false;
+        }
+        self.timer_armed.store(false, Ordering::Release);
+        if let Some(mem) = self.dma.lock().clone() {
+            for (index, queue) in self.queues.iter() {
+                let mut queue = queue.lock();
+                if queue.pending > 0 {
+                    let _ = self.flush(&*mem, index, &mut queue);
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Hashes the flow of `frame`: its Ethernet addresses and, for IPv4, its IP
+/// addresses and (assuming no IP options) ports.
+fn flow_hash(frame: &[u8]) -> u64 {
+    let is_ipv4 = frame.get(12..14) == Some(&[0x08, 0x00]);
+    let end = if is_ipv4 { 38 } else { 12 };
+    let header = frame.get(..end).unwrap_or(frame);
+    // FNV-1a over the addresses, skipping the IP header fields between.
+    header
+        .iter()
+        .enumerate()
+        .filter(|&(offset, _)| !(12..26).contains(&offset))
+        .fold(0xcbf2_9ce4_8422_2325, |hash, (_, &byte)| {
+            (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+        })
+}
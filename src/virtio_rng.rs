@@ -0,0 +1,247 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The virtio entropy device over a host entropy source.
+
+use alloc::{sync::Arc, vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axaddrspace::{GuestPhysAddr, GuestPhysAddrRange, device::AccessWidth};
+use axerrno::{AxResult, ax_err};
+use spin::Mutex;
+
+use crate::{
+    BaseDeviceOps, ClockSource, DescChain, DeviceAddrRangeExt, EmuDeviceType, EntropySource,
+    GuestMemoryAccessor, SplitQueue, TimerService, TimerToken, VirtioMmioDevice, VirtioMmioRegs,
+    throttle::TokenBucket,
+};
+
+const VIRTIO_ID_RNG: u32 = 4;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+#[derive(Default)]
+struct RngQueue {
+    vq: Option<SplitQueue>,
+    /// A request waiting for the rate limit to allow more bytes.
+    waiting: Option<DescChain>,
+}
+
+/// A virtio entropy device (virtio 1.2, section 5.4) over an
+/// [`EntropySource`], on the virtio-mmio transport.
+///
+/// The device fills each buffer the driver places on its request queue with
+/// random bytes. To keep a guest from draining the host source, the bytes
+/// handed out are limited by a token bucket, the same policy a
+/// [`ThrottledDevice`](crate::ThrottledDevice) applies to accesses: up to
+/// `burst` bytes at once and `rate` bytes per second on average, refilled
+/// following the [`ClockSource`] injected with
+/// [`set_clock_source`](BaseDeviceOps::set_clock_source). A request is
+/// answered with as many bytes as the bucket holds; when it is empty, the
+/// request waits until the bucket has refilled, using the injected
+/// [`TimerService`] to retry, or until the next queue notification if there
+/// is none.
+///
+/// Used buffer notifications are recorded in [`regs`](Self::regs); injecting
+/// the interrupt is left to the hypervisor.
+pub struct VirtioRngDevice {
+    range: GuestPhysAddrRange,
+    regs: VirtioMmioRegs,
+    source: Arc<dyn EntropySource>,
+    bucket: Mutex<TokenBucket>,
+    queue: Mutex<RngQueue>,
+    dma: Mutex<Option<Arc<dyn GuestMemoryAccessor>>>,
+    clock: Mutex<Option<Arc<dyn ClockSource>>>,
+    timers: Mutex<Option<Arc<dyn TimerService>>>,
+    timer: TimerToken,
+    throttled: AtomicU64,
+}
+
+impl VirtioRngDevice {
+    /// The vendor ID reported by the device.
+    pub const VENDOR_ID: u32 = 0x554d_4551;
+
+    /// The maximum size of the request queue.
+    pub const QUEUE_SIZE: u16 = 64;
+
+    /// Creates a virtio entropy device at `base` drawing from `source`,
+    /// handing out bursts of up to `burst` bytes and `rate` bytes per second
+    /// on average. The bucket starts full.
+    pub fn new(base: GuestPhysAddr, source: Arc<dyn EntropySource>, rate: u64, burst: u64) -> Self {
+        Self {
+            range: GuestPhysAddrRange::from_start_size(base, 0x200),
+            regs: VirtioMmioRegs::new(
+                VIRTIO_ID_RNG,
+                Self::VENDOR_ID,
+                VIRTIO_F_VERSION_1,
+                1,
+                Self::QUEUE_SIZE,
+            ),
+            source,
+            bucket: Mutex::new(TokenBucket::new(rate, burst)),
+            queue: Mutex::new(RngQueue::default()),
+            dma: Mutex::new(None),
+            clock: Mutex::new(None),
+            timers: Mutex::new(None),
+            timer: TimerToken::next(),
+            throttled: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the transport registers.
+    pub fn regs(&self) -> &VirtioMmioRegs {
+        &self.regs
+    }
+
+    /// Returns the number of times a request had to wait for the rate limit.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Takes up to `len` bytes from the bucket, returning the number granted.
+    /// If none are, returns when the bucket will hold `len` bytes instead.
+    fn admit(&self, len: usize) -> Result<usize, Option<core::time::Duration>> {
+        let now_ns = self
+            .clock
+            .lock()
+            .as_ref()
+            .map(|clock| clock.monotonic().as_nanos() as u64);
+        let mut bucket = self.bucket.lock();
+        if let Some(now_ns) = now_ns {
+            bucket.refill(now_ns);
+        }
+        match bucket.take_up_to(len as u64) {
+            0 if len > 0 => Err(bucket.time_until(len as u64)),
+            granted => Ok(granted as usize),
+        }
+    }
+
+    fn process(&self) -> AxResult {
+        let Some(mem) = self.dma.lock().clone() else {
+            return ax_err!(BadState, "virtio-rng device has no DMA accessor");
+        };
+        let mut guard = self.queue.lock();
+        let queue = &mut *guard;
+        if queue.vq.is_none() {
+            match self.regs.queue(0) {
+                Some(config) if config.ready => queue.vq = Some(config.to_split_queue()?),
+                _ => return Ok(()),
+            }
+        }
+        let vq = queue.vq.as_mut().unwrap();
+        let mut completed = false;
+        loop {
+            let chain = match queue.waiting.take() {
+                Some(chain) => chain,
+                None => match vq.pop(&*mem)? {
+                    Some(chain) => chain,
+                    None => break,
+                },
+            };
+            let granted = match self.admit(chain.writable.len()) {
+                Ok(granted) => granted,
+                Err(delay) => {
+                    self.throttled.fetch_add(1, Ordering::Relaxed);
+                    queue.waiting = Some(chain);
+                    if let (Some(delay), Some(timers)) = (delay, self.timers.lock().clone()) {
+                        timers.schedule_after(delay, self.timer)?;
+                    }
+                    break;
+                }
+            };
+            let mut buf = vec![0; granted];
+            let filled = self.source.fill(&mut buf).unwrap_or_else(|err| {
+                warn!("virtio-rng: entropy source failed: {err:?}");
+                0
+            });
+            chain.writable.write(&*mem, 0, &buf[..filled])?;
+            vq.push_used(&*mem, chain.head, filled as u32)?;
+            completed = true;
+        }
+        if completed && vq.needs_notification(&*mem)? {
+            self.regs.raise_interrupt(VirtioMmioRegs::INT_USED_BUFFER);
+        }
+        Ok(())
+    }
+}
+
+impl VirtioMmioDevice for VirtioRngDevice {
+    fn read_config(&self, _offset: usize, _width: AccessWidth) -> AxResult<usize> {
+        Ok(0)
+    }
+
+    fn write_config(&self, _offset: usize, _width: AccessWidth, _val: usize) -> AxResult {
+        Ok(())
+    }
+
+    fn queue_notify(&self, queue: u16) -> AxResult {
+        if queue != 0 {
+            return ax_err!(InvalidInput, "virtio queue out of range");
+        }
+        self.process()
+    }
+
+    fn reset(&self) {
+        *self.queue.lock() = RngQueue::default();
+        if let Some(timers) = self.timers.lock().clone() {
+            timers.cancel(self.timer);
+        }
+    }
+}
+
+impl BaseDeviceOps<GuestPhysAddrRange> for VirtioRngDevice {
+    fn emu_type(&self) -> EmuDeviceType {
+        EmuDeviceType::Dummy
+    }
+
+    fn address_range(&self) -> GuestPhysAddrRange {
+        self.range
+    }
+
+    fn handle_read(&self, addr: GuestPhysAddr, width: AccessWidth) -> AxResult<usize> {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_read(offset, width, self),
+            None => Ok(0),
+        }
+    }
+
+    fn handle_write(&self, addr: GuestPhysAddr, width: AccessWidth, val: usize) -> AxResult {
+        match self.range.offset_of(addr) {
+            Some(offset) => self.regs.handle_write(offset, width, val, self),
+            None => Ok(()),
+        }
+    }
+
+    fn set_dma_accessor(&self, accessor: Arc<dyn GuestMemoryAccessor>) {
+        *self.dma.lock() = Some(accessor);
+    }
+
+    fn set_clock_source(&self, clock: Arc<dyn ClockSource>) {
+        *self.clock.lock() = Some(clock);
+    }
+
+    fn set_timer_service(&self, service: Arc<dyn TimerService>) {
+        *self.timers.lock() = Some(service);
+    }
+
+    /// Retries the request waiting for the rate limit.
+    fn on_timer(&self, token: TimerToken) -> bool {
+        if token != self.timer {
+            return false;
+        }
+        if let Err(err) = self.process() {
+            warn!("virtio-rng: failed to process requests: {err:?}");
+        }
+        true
+    }
+}
@@ -0,0 +1,269 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Split virtqueue handling on top of [`GuestMemoryAccessor`].
+
+use core::sync::atomic::{Ordering, fence};
+
+use axaddrspace::GuestPhysAddr;
+use axerrno::{AxResult, ax_err};
+
+use crate::{GuestBufferList, GuestMemoryAccessor};
+
+/// A descriptor chain taken from the available ring of a [`SplitQueue`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescChain {
+    /// The index of the head descriptor, to be returned through
+    /// [`SplitQueue::push_used`].
+    pub head: u16,
+    /// The device-readable buffers of the chain, in order.
+    pub readable: GuestBufferList,
+    /// The device-writable buffers of the chain, in order.
+    pub writable: GuestBufferList,
+}
+
+/// The device side of a split virtqueue (virtio 1.x, section 2.7).
+///
+/// The queue walks descriptor chains (including indirect descriptor tables)
+/// from the available ring and returns them through the used ring. With
+/// `VIRTIO_F_EVENT_IDX` negotiated, notifications in both directions are
+/// suppressed through the `used_event` and `avail_event` fields.
+///
+/// All guest memory accesses go through the [`GuestMemoryAccessor`] passed to
+/// each call, so malformed rings result in errors rather than out-of-bounds
+/// accesses.
+#[derive(Debug, Clone)]
+pub struct SplitQueue {
+    size: u16,
+    desc: GuestPhysAddr,
+    avail: GuestPhysAddr,
+    used: GuestPhysAddr,
+    event_idx: bool,
+    last_avail_idx: u16,
+    used_idx: u16,
+    signalled_used: u16,
+}
+
+impl SplitQueue {
+    /// Descriptor flag: the chain continues at `next`.
+    pub const DESC_F_NEXT: u16 = 1;
+    /// Descriptor flag: the buffer is device-writable.
+    pub const DESC_F_WRITE: u16 = 2;
+    /// Descriptor flag: the buffer holds an indirect descriptor table.
+    pub const DESC_F_INDIRECT: u16 = 4;
+    /// Available ring flag: the driver does not want interrupts.
+    pub const AVAIL_F_NO_INTERRUPT: u16 = 1;
+    /// Used ring flag: the device does not want notifications.
+    pub const USED_F_NO_NOTIFY: u16 = 1;
+
+    /// The maximum queue size.
+    pub const MAX_SIZE: u16 = 32768;
+
+    const DESC_SIZE: usize = 16;
+
+    /// Creates a queue of `size` entries with the descriptor table, available
+    /// ring and used ring at the given guest addresses.
+    ///
+    /// Returns `Err(AxError::InvalidInput)` if `size` is not a power of two
+    /// no larger than [`Self::MAX_SIZE`].
+    pub fn new(
+        size: u16,
+        desc: GuestPhysAddr,
+        avail: GuestPhysAddr,
+        used: GuestPhysAddr,
+    ) -> AxResult<Self> {
+        if !size.is_power_of_two() || size > Self::MAX_SIZE {
+            return ax_err!(InvalidInput, "invalid virtqueue size");
+        }
+        Ok(Self {
+            size,
+            desc,
+            avail,
+            used,
+            event_idx: false,
+            last_avail_idx: 0,
+            used_idx: 0,
+            signalled_used: 0,
+        })
+    }
+
+    /// Enables or disables `VIRTIO_F_EVENT_IDX` handling, according to the
+    /// negotiated features.
+    pub fn set_event_idx(&mut self, enabled: bool) {
+        self.event_idx = enabled;
+    }
+
+    /// Returns the size of the queue.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the index of the next available ring entry to be consumed.
+    pub fn last_avail_idx(&self) -> u16 {
+        self.last_avail_idx
+    }
+
+    /// Returns the index of the next used ring entry to be produced.
+    pub fn used_idx(&self) -> u16 {
+        self.used_idx
+    }
+
+    /// Takes the next descriptor chain from the available ring, or returns
+    /// `None` if the ring is empty.
+    ///
+    /// Returns `Err(AxError::InvalidData)` if the chain is malformed: an out of
+    /// range descriptor index, a loop, a nested indirect table, or a readable
+    /// buffer after a writable one.
+    pub fn pop(&mut self, mem: &dyn GuestMemoryAccessor) -> AxResult<Option<DescChain>> {
+        let avail_idx = mem.read_u16(self.avail + 2)?;
+        if avail_idx == self.last_avail_idx {
+            return Ok(None);
+        }
+        if avail_idx.wrapping_sub(self.last_avail_idx) > self.size {
+            return ax_err!(InvalidData, "virtqueue available index out of range");
+        }
+        // Read the ring entry only after observing the index update.
+        fence(Ordering::Acquire);
+        let slot = (self.last_avail_idx % self.size) as usize;
+        let head = mem.read_u16(self.avail + 4 + 2 * slot)?;
+        let chain = self.walk(mem, head)?;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+        if self.event_idx {
+            mem.write_u16(self.avail_event_addr(), self.last_avail_idx)?;
+        }
+        Ok(Some(chain))
+    }
+
+    /// Returns the chain with head `head` to the driver, with `len` bytes
+    /// written to its writable buffers.
+    pub fn push_used(&mut self, mem: &dyn GuestMemoryAccessor, head: u16, len: u32) -> AxResult {
+        let slot = (self.used_idx % self.size) as usize;
+        let elem = self.used + 4 + 8 * slot;
+        mem.write_u32(elem, head as u32)?;
+        mem.write_u32(elem + 4, len)?;
+        // Publish the ring entry before the index.
+        fence(Ordering::Release);
+        self.used_idx = self.used_idx.wrapping_add(1);
+        mem.write_u16(self.used + 2, self.used_idx)
+    }
+
+    /// Returns whether the driver should be interrupted for the used entries
+    /// pushed since the last call.
+    pub fn needs_notification(&mut self, mem: &dyn GuestMemoryAccessor) -> AxResult<bool> {
+        // Order the used index update before reading the suppression fields.
+        fence(Ordering::SeqCst);
+        let old = core::mem::replace(&mut self.signalled_used, self.used_idx);
+        let new = self.used_idx;
+        if old == new {
+            return Ok(false);
+        }
+        if self.event_idx {
+            let used_event = mem.read_u16(self.avail + 4 + 2 * self.size as usize)?;
+            Ok(new.wrapping_sub(used_event).wrapping_sub(1) < new.wrapping_sub(old))
+        } else {
+            let flags = mem.read_u16(self.avail)?;
+            Ok(flags & Self::AVAIL_F_NO_INTERRUPT == 0)
+        }
+    }
+
+    /// Enables or disables notifications from the driver.
+    ///
+    /// With `VIRTIO_F_EVENT_IDX`, enabling requests a notification for the next
+    /// available entry; otherwise the `VIRTQ_USED_F_NO_NOTIFY` flag is updated.
+    /// After enabling, the device must check the ring again to avoid missing
+    /// entries made available in the meantime.
+    pub fn set_notification(&mut self, mem: &dyn GuestMemoryAccessor, enable: bool) -> AxResult {
+        if self.event_idx {
+            if enable {
+                mem.write_u16(self.avail_event_addr(), self.last_avail_idx)?;
+            }
+        } else {
+            let flags = mem.read_u16(self.used)?;
+            let flags = if enable {
+                flags & !Self::USED_F_NO_NOTIFY
+            } else {
+                flags | Self::USED_F_NO_NOTIFY
+            };
+            mem.write_u16(self.used, flags)?;
+        }
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Forgets all progress, as on a device or queue reset.
+    pub fn reset(&mut self) {
+        self.last_avail_idx = 0;
+        self.used_idx = 0;
+        self.signalled_used = 0;
+    }
+
+    fn avail_event_addr(&self) -> GuestPhysAddr {
+        self.used + 4 + 8 * self.size as usize
+    }
+
+    fn walk(&self, mem: &dyn GuestMemoryAccessor, head: u16) -> AxResult<DescChain> {
+        let mut chain = DescChain {
+            head,
+            readable: GuestBufferList::new(),
+            writable: GuestBufferList::new(),
+        };
+        let (mut table, mut count) = (self.desc, self.size);
+        let mut index = head;
+        let mut indirect = false;
+        // Zero-length buffers are not added to the lists, so ordering is
+        // checked against the descriptors rather than against `writable`.
+        let mut seen_writable = false;
+        let mut budget = count;
+        loop {
+            if index >= count || budget == 0 {
+                return ax_err!(InvalidData, "malformed virtqueue descriptor chain");
+            }
+            budget -= 1;
+            let desc = table + Self::DESC_SIZE * index as usize;
+            let addr = GuestPhysAddr::from(mem.read_u64(desc)? as usize);
+            let len = mem.read_u32(desc + 8)? as usize;
+            let flags = mem.read_u16(desc + 12)?;
+            let next = mem.read_u16(desc + 14)?;
+
+            if flags & Self::DESC_F_INDIRECT != 0 {
+                if indirect
+                    || flags & Self::DESC_F_NEXT != 0
+                    || !len.is_multiple_of(Self::DESC_SIZE)
+                {
+                    return ax_err!(InvalidData, "malformed indirect descriptor");
+                }
+                let entries = len / Self::DESC_SIZE;
+                if entries == 0 || entries > Self::MAX_SIZE as usize {
+                    return ax_err!(InvalidData, "malformed indirect descriptor");
+                }
+                (table, count, budget, index) = (addr, entries as u16, entries as u16, 0);
+                indirect = true;
+                continue;
+            }
+
+            if flags & Self::DESC_F_WRITE != 0 {
+                seen_writable = true;
+                chain.writable.push(addr, len)?;
+            } else if !seen_writable {
+                chain.readable.push(addr, len)?;
+            } else {
+                return ax_err!(InvalidData, "readable descriptor after writable one");
+            }
+            if flags & Self::DESC_F_NEXT == 0 {
+                return Ok(chain);
+            }
+            index = next;
+        }
+    }
+}
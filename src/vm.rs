@@ -0,0 +1,261 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The device managers of all VMs on the host.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt;
+
+use axerrno::{AxResult, ax_err};
+use spin::{Mutex, RwLock};
+
+use crate::{AccessStats, BaseDeviceOps, DeviceAddrRangeExt, DeviceManager, EmuDeviceType};
+
+/// The identity of a VM on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VmId(pub usize);
+
+impl fmt::Display for VmId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VM[{}]", self.0)
+    }
+}
+
+/// The [`DeviceManager`]s of all VMs on the host, keyed by [`VmId`], for
+/// operations spanning VMs: finding devices of a type, collecting statistics,
+/// and quiescing all devices while the host suspends.
+///
+/// # Locking
+///
+/// Guest accesses never go through this table. The hypervisor looks up the
+/// manager of a VM with [`vm`](Self::vm) when the VM is created, keeps the
+/// returned `Arc` in its VM structure and dispatches accesses to it directly,
+/// so per-VM operations only take the locks of that VM's manager and never
+/// contend with other VMs or with global operations.
+///
+/// The table itself is guarded by a read-write lock, held only to insert,
+/// remove or clone the managers: global operations first take a snapshot of
+/// the managers and then visit them with the lock released. No device is ever
+/// called with the table locked, so the order between the table lock and the
+/// locks of a per-VM manager is always table first, and devices may look up
+/// VMs from their handlers.
+///
+/// [`quiesce_all`](Self::quiesce_all), [`resume_all`](Self::resume_all),
+/// [`add_vm`](Self::add_vm) and [`remove_vm`](Self::remove_vm) are further
+/// serialized by a quiesce lock, held while devices are paused or resumed, so
+/// that a VM added while the host is quiesced is paused before it becomes
+/// visible and no VM is resumed behind the host's back. Device
+/// [`pause`](BaseDeviceOps::pause) and [`resume`](BaseDeviceOps::resume) hooks
+/// must therefore not add or remove VMs. The lock order is quiesce lock, then
+/// table lock, then per-VM locks.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use axdevice_base::{EmuDeviceType, HostDeviceManager, VmId};
+///
+/// let host = HostDeviceManager::new();
+/// host.add_vm(VmId(1), vm1.devices.clone())?;
+/// host.add_vm(VmId(2), vm2.devices.clone())?;
+/// for (vm, uart) in host.devices_of_type(EmuDeviceType::Console) {
+///     info!("{vm}: UART at {:?}", uart.address_range());
+/// }
+/// host.quiesce_all()?;
+/// // ... suspend the host ...
+/// host.resume_all()?;
+/// ```
+pub struct HostDeviceManager<R> {
+    vms: RwLock<BTreeMap<VmId, Arc<DeviceManager<R>>>>,
+    /// Whether the devices of all VMs are paused.
+    quiesced: Mutex<bool>,
+}
+
+impl<R: DeviceAddrRangeExt + 'static> HostDeviceManager<R> {
+    /// Creates a host device manager without VMs.
+    pub const fn new() -> Self {
+        Self {
+            vms: RwLock::new(BTreeMap::new()),
+            quiesced: Mutex::new(false),
+        }
+    }
+
+    /// Adds the device manager of VM `id`.
+    ///
+    /// If the host is quiesced, the devices of the VM are paused before it is
+    /// added, and resumed with the others by [`resume_all`](Self::resume_all).
+    /// Returns `Err(AxError::AlreadyExists)` if a VM with the same identity
+    /// exists, or the error of pausing its devices.
+    pub fn add_vm(&self, id: VmId, manager: Arc<DeviceManager<R>>) -> AxResult {
+        let quiesced = self.quiesced.lock();
+        if self.vms.read().contains_key(&id) {
+            return ax_err!(AlreadyExists, "VM already has a device manager");
+        }
+        if *quiesced {
+            manager.pause_all()?;
+        }
+        self.vms.write().insert(id, manager);
+        Ok(())
+    }
+
+    /// Removes the device manager of VM `id`, returning it.
+    ///
+    /// The devices are left as they are: if the host is quiesced, they stay
+    /// paused until the caller resumes or shuts them down.
+    pub fn remove_vm(&self, id: VmId) -> Option<Arc<DeviceManager<R>>> {
+        let _quiesced = self.quiesced.lock();
+        self.vms.write().remove(&id)
+    }
+
+    /// Returns the device manager of VM `id`.
+    pub fn vm(&self, id: VmId) -> Option<Arc<DeviceManager<R>>> {
+        self.vms.read().get(&id).cloned()
+    }
+
+    /// Returns the identities of all VMs, in ascending order.
+    pub fn vm_ids(&self) -> Vec<VmId> {
+        self.vms.read().keys().copied().collect()
+    }
+
+    /// Returns the number of VMs.
+    pub fn len(&self) -> usize {
+        self.vms.read().len()
+    }
+
+    /// Returns `true` if there are no VMs.
+    pub fn is_empty(&self) -> bool {
+        self.vms.read().is_empty()
+    }
+
+    /// Returns the devices of type `emu_type` in all VMs, e.g. all UARTs with
+    /// [`EmuDeviceType::Console`], by VM and then in address order.
+    pub fn devices_of_type(
+        &self,
+        emu_type: EmuDeviceType,
+    ) -> Vec<(VmId, Arc<dyn BaseDeviceOps<R>>)> {
+        self.snapshot()
+            .into_iter()
+            .flat_map(|(id, manager)| {
+                manager
+                    .devices()
+                    .into_iter()
+                    .filter(move |device| device.emu_type() == emu_type)
+                    .map(move |device| (id, device))
+            })
+            .collect()
+    }
+
+    /// Returns the access statistics of each VM, summed over its devices
+    /// maintaining statistics (see [`BaseDeviceOps::stats`]).
+    pub fn stats(&self) -> Vec<(VmId, AccessStats)> {
+        self.snapshot()
+            .into_iter()
+            .map(|(id, manager)| {
+                let stats = manager
+                    .devices()
+                    .iter()
+                    .filter_map(|device| device.stats())
+                    .fold(AccessStats::default(), |sum, stats| {
+                        add_stats(sum, &stats.total)
+                    });
+                (id, stats)
+            })
+            .collect()
+    }
+
+    /// Returns the access statistics of all VMs together.
+    pub fn total_stats(&self) -> AccessStats {
+        self.stats()
+            .iter()
+            .fold(AccessStats::default(), |sum, (_, stats)| {
+                add_stats(sum, stats)
+            })
+    }
+
+    /// Pauses the devices of all VMs, e.g. before the host suspends, in
+    /// ascending VM order.
+    ///
+    /// Either all VMs are paused or none is: if pausing a VM fails, the VMs
+    /// already paused, and the one that failed, are resumed and the error is
+    /// returned. Does nothing if the host is already quiesced.
+    pub fn quiesce_all(&self) -> AxResult {
+        let mut quiesced = self.quiesced.lock();
+        if *quiesced {
+            return Ok(());
+        }
+        let vms = self.snapshot();
+        for (index, (id, manager)) in vms.iter().enumerate() {
+            if let Err(err) = manager.pause_all() {
+                warn!("failed to quiesce the devices of {id}: {err:?}");
+                for (id, manager) in vms[..=index].iter().rev() {
+                    if let Err(err) = manager.resume_all() {
+                        warn!("failed to resume the devices of {id}: {err:?}");
+                    }
+                }
+                return Err(err);
+            }
+        }
+        *quiesced = true;
+        Ok(())
+    }
+
+    /// Resumes the devices of all VMs paused by
+    /// [`quiesce_all`](Self::quiesce_all), in ascending VM order.
+    ///
+    /// Every VM is resumed even if an earlier one fails; the first error is
+    /// returned. Does nothing if the host is not quiesced.
+    pub fn resume_all(&self) -> AxResult {
+        let mut quiesced = self.quiesced.lock();
+        if !*quiesced {
+            return Ok(());
+        }
+        *quiesced = false;
+        let mut result = Ok(());
+        for (_, manager) in self.snapshot() {
+            result = result.and(manager.resume_all());
+        }
+        result
+    }
+
+    /// Returns `true` if the devices of all VMs are paused by
+    /// [`quiesce_all`](Self::quiesce_all).
+    pub fn is_quiesced(&self) -> bool {
+        *self.quiesced.lock()
+    }
+
+    /// Clones the managers so that they can be visited with the table unlocked.
+    fn snapshot(&self) -> Vec<(VmId, Arc<DeviceManager<R>>)> {
+        self.vms
+            .read()
+            .iter()
+            .map(|(id, manager)| (*id, manager.clone()))
+            .collect()
+    }
+}
+
+impl<R: DeviceAddrRangeExt + 'static> Default for HostDeviceManager<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn add_stats(sum: AccessStats, stats: &AccessStats) -> AccessStats {
+    AccessStats {
+        reads: sum.reads + stats.reads,
+        writes: sum.writes + stats.writes,
+        bytes_read: sum.bytes_read + stats.bytes_read,
+        bytes_written: sum.bytes_written + stats.bytes_written,
+        errors: sum.errors + stats.errors,
+        last_access_ns: sum.last_access_ns.max(stats.last_access_ns),
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access-size emulation for devices implementing a single register width.
+
+use alloc::collections::BTreeMap;
+
+use axaddrspace::device::AccessWidth;
+use axerrno::{AxResult, ax_err};
+
+use crate::{
+    AccessContext, AccessOutcome, BaseDeviceOps, DeviceAddrRangeExt, RawDeviceAddr,
+    forward::forward_base_device_ops, read_bulk_each, write_bulk_each,
+};
+
+/// Returns a mask covering the low `len` bytes of a `usize`.
+fn bytes_mask(len: usize) -> usize {
+    1usize
+        .checked_shl((len * 8) as u32)
+        .map_or(usize::MAX, |bit| bit - 1)
+}
+
+/// A wrapper that lets a device handle only accesses of its natural register
+/// width.
+///
+/// Guest accesses of any other width, or not aligned to the natural width, are
+/// split into the registers they touch. Registers fully covered by the access
+/// are read or written directly; partially covered registers are read and the
+/// touched bytes merged in (read-modify-write). Bits registered with
+/// [`with_w1c`](Self::with_w1c) are written as zero outside the bytes the
+/// guest wrote, so that a narrow write does not clear write-1-to-clear status
+/// bits it did not target.
+///
+/// Partial writes read the register first, so they are unsuitable for
+/// registers with read side effects (e.g. FIFOs or read-to-clear status).
+pub struct NaturalWidthAdapter<R, D> {
+    inner: D,
+    range: R,
+    natural: AccessWidth,
+    w1c: BTreeMap<usize, usize>,
+}
+
+impl<R: DeviceAddrRangeExt, D: BaseDeviceOps<R>> NaturalWidthAdapter<R, D> {
+    /// Wraps `inner`, which only handles aligned `natural`-width accesses.
+    pub fn new(inner: D, natural: AccessWidth) -> Self {
+        Self {
+            range: inner.address_range(),
+            inner,
+            natural,
+            w1c: BTreeMap::new(),
+        }
+    }
+
+    /// Declares the write-1-to-clear bits of the register at `offset` from the
+    /// start of the device range.
+    pub fn with_w1c(mut self, offset: usize, mask: usize) -> Self {
+        self.w1c.insert(offset, mask);
+        self
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns the natural register width of the wrapped device.
+    pub fn natural_width(&self) -> AccessWidth {
+        self.natural
+    }
+
+    /// Calls `f(reg_offset, byte_in_reg, byte_in_access, len)` for each
+    /// register touched by the access, in ascending order.
+    fn for_each_reg(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        mut f: impl FnMut(usize, usize, usize, usize) -> AxResult,
+    ) -> AxResult {
+        if !self.range.contains_access(addr, width) {
+            return ax_err!(BadAddress);
+        }
+        let size = self.natural.size();
+        let start = self.range.offset_of(addr).unwrap();
+        let end = start + width.size();
+        let mut reg = start & !(size - 1);
+        while reg < end {
+            let lo = start.max(reg);
+            let hi = end.min(reg + size);
+            f(reg, lo - reg, lo - start, hi - lo)?;
+            reg += size;
+        }
+        Ok(())
+    }
+
+    fn reg_addr(&self, offset: usize) -> AxResult<R::Addr> {
+        let (start, _) = self.range.raw_bounds();
+        match R::Addr::from_raw(start + offset) {
+            Some(addr) => Ok(addr),
+            None => ax_err!(BadAddress),
+        }
+    }
+
+    fn is_natural(&self, addr: R::Addr, width: AccessWidth) -> bool {
+        width == self.natural
+            && self
+                .range
+                .offset_of(addr)
+                .is_some_and(|offset| offset % width.size() == 0)
+    }
+
+    /// Returns whether all elements of a bulk access are aligned accesses of
+    /// the natural width, which the wrapped device handles directly.
+    fn is_natural_bulk(&self, addr: R::Addr, width: AccessWidth, stride: isize) -> bool {
+        self.is_natural(addr, width) && stride % width.size() as isize == 0
+    }
+
+    fn split_read(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        read: impl Fn(R::Addr, AccessWidth) -> AxResult<usize>,
+    ) -> AxResult<usize> {
+        if self.is_natural(addr, width) {
+            return read(addr, width);
+        }
+        let mut val = 0;
+        self.for_each_reg(addr, width, |reg, in_reg, in_access, len| {
+            let reg_val = read(self.reg_addr(reg)?, self.natural)?;
+            let part = (reg_val >> (in_reg * 8)) & bytes_mask(len);
+            val |= part << (in_access * 8);
+            Ok(())
+        })?;
+        Ok(val)
+    }
+
+    fn split_write(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        read: impl Fn(R::Addr, AccessWidth) -> AxResult<usize>,
+        write: impl Fn(R::Addr, AccessWidth, usize) -> AxResult,
+    ) -> AxResult {
+        if self.is_natural(addr, width) {
+            return write(addr, width, val);
+        }
+        let size = self.natural.size();
+        self.for_each_reg(addr, width, |reg, in_reg, in_access, len| {
+            let reg_addr = self.reg_addr(reg)?;
+            let part = (val >> (in_access * 8)) & bytes_mask(len);
+            if len == size {
+                return write(reg_addr, self.natural, part);
+            }
+            let written = bytes_mask(len) << (in_reg * 8);
+            let old = read(reg_addr, self.natural)?;
+            let mut new = (old & !written) | (part << (in_reg * 8));
+            if let Some(w1c) = self.w1c.get(&reg) {
+                new &= !(w1c & !written);
+            }
+            write(reg_addr, self.natural, new)
+        })
+    }
+}
+
+impl<R: DeviceAddrRangeExt + 'static, D: BaseDeviceOps<R>> BaseDeviceOps<R>
+    for NaturalWidthAdapter<R, D>
+{
+    forward_base_device_ops!(R, inner => identity, handle_call, lifecycle, services, introspection);
+
+    fn handle_read(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.split_read(addr, width, |addr, width| {
+            self.inner.handle_read(addr, width)
+        })
+    }
+
+    fn handle_write(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.split_write(
+            addr,
+            width,
+            val,
+            |addr, width| self.inner.handle_read(addr, width),
+            |addr, width, val| self.inner.handle_write(addr, width, val),
+        )
+    }
+
+    fn handle_read_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        ctx: AccessContext,
+    ) -> AxResult<usize> {
+        self.split_read(addr, width, |addr, width| {
+            self.inner.handle_read_ctx(addr, width, ctx)
+        })
+    }
+
+    fn handle_write_ctx(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+        ctx: AccessContext,
+    ) -> AxResult {
+        self.split_write(
+            addr,
+            width,
+            val,
+            |addr, width| self.inner.handle_read_ctx(addr, width, ctx),
+            |addr, width, val| self.inner.handle_write_ctx(addr, width, val, ctx),
+        )
+    }
+
+    /// Only natural accesses may complete asynchronously: split accesses are
+    /// handled synchronously, register by register.
+    fn handle_read_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &mut [u8],
+    ) -> AxResult {
+        if self.is_natural_bulk(addr, width, stride) {
+            return self.inner.handle_read_bulk(addr, width, count, stride, buf);
+        }
+        read_bulk_each(addr, width, count, stride, buf, |addr, width| {
+            self.handle_read(addr, width)
+        })
+    }
+
+    fn handle_write_bulk(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        count: usize,
+        stride: isize,
+        buf: &[u8],
+    ) -> AxResult {
+        if self.is_natural_bulk(addr, width, stride) {
+            return self
+                .inner
+                .handle_write_bulk(addr, width, count, stride, buf);
+        }
+        write_bulk_each(addr, width, count, stride, buf, |addr, width, val| {
+            self.handle_write(addr, width, val)
+        })
+    }
+
+    fn handle_read_async(&self, addr: R::Addr, width: AccessWidth) -> AxResult<AccessOutcome> {
+        if self.is_natural(addr, width) {
+            return self.inner.handle_read_async(addr, width);
+        }
+        self.handle_read(addr, width).map(AccessOutcome::Completed)
+    }
+
+    fn handle_write_async(
+        &self,
+        addr: R::Addr,
+        width: AccessWidth,
+        val: usize,
+    ) -> AxResult<AccessOutcome> {
+        if self.is_natural(addr, width) {
+            return self.inner.handle_write_async(addr, width, val);
+        }
+        self.handle_write(addr, width, val)
+            .map(|()| AccessOutcome::Completed(0))
+    }
+
+    fn peek(&self, addr: R::Addr, width: AccessWidth) -> AxResult<usize> {
+        self.split_read(addr, width, |addr, width| self.inner.peek(addr, width))
+    }
+
+    fn poke(&self, addr: R::Addr, width: AccessWidth, val: usize) -> AxResult {
+        self.split_write(
+            addr,
+            width,
+            val,
+            |addr, width| self.inner.peek(addr, width),
+            |addr, width, val| self.inner.poke(addr, width, val),
+        )
+    }
+}